@@ -1,4 +1,10 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+
+use crate::color::ColorChoice;
+use crate::data::{ArrayMerge, DataFormat, MergeStrategy};
+use crate::error::ErrorFormat;
+use crate::sandbox::{Sandbox, SymlinkPolicy};
+use crate::template::MissingPolicy;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -6,27 +12,306 @@ use clap::Parser;
     version,
     about = "Template engine with variable substitution and include functionality",
     long_about = "A minimal template engine that renders templates with variable substitution ({{ var }}) \
-                  and include directives ({{> file }}). Supports YAML and JSON data sources."
+                  and include directives ({{> file }}). Supports YAML, JSON, JSONL and CSV/TSV data sources. \
+                  Without a subcommand, `rp -t TEMPLATE ...` renders the template, like `rp render`.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 pub struct Cli {
-    /// Template file path
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Template and data options of a bare invocation (absent with a subcommand)
+    #[command(flatten)]
+    pub input: Option<InputArgs>,
+
+    #[command(flatten)]
+    pub render: RenderArgs,
+
+    /// Show what is being done on stderr: -v lists the data files merged and the includes
+    /// resolved with timings, -vv adds more detail
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Show on stderr, as the template renders, the tree of files it includes and each
+    /// substitution (expression → value, truncated)
+    #[arg(long = "trace", global = true)]
+    pub trace: bool,
+
+    /// How errors and warnings are reported on stderr: text (default) or json (one object
+    /// per line with code, file, line, column and message)
+    #[arg(
+        long = "error-format",
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true
+    )]
+    pub error_format: ErrorFormat,
+
+    /// Color errors and warnings on stderr: auto (default; when stderr is a terminal and
+    /// NO_COLOR is not set), always or never
+    #[arg(
+        long = "color",
+        value_name = "WHEN",
+        default_value = "auto",
+        global = true
+    )]
+    pub color: ColorChoice,
+
+    /// Suppress warnings (errors are still reported)
+    #[arg(short = 'q', long = "quiet", global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print dependency tree (all template files) and exit (same as `rp deps`)
+    #[arg(long = "print-deps", conflicts_with_all = ["watch", "dry_run"])]
+    pub print_deps: bool,
+
+    /// Check the template and all includes for syntax errors and missing files without
+    /// rendering it; exits non-zero if any problem is found (same as `rp check`)
+    #[arg(
+        long = "check",
+        conflicts_with_all = ["print_deps", "watch", "dry_run"]
+    )]
+    pub check: bool,
+
+    /// Print every variable the template and its includes refer to and exit, one per line
+    /// or as a JSON array with `--list-vars=json` (same as `rp vars`)
+    #[arg(
+        long = "list-vars",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text",
+        conflicts_with_all = ["print_deps", "check", "watch", "dry_run"]
+    )]
+    pub list_vars: Option<ListFormat>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render a template (the default when no subcommand is given)
+    Render {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        render: RenderArgs,
+    },
+
+    /// Check the template and all includes for syntax errors and missing files without
+    /// rendering it; exits non-zero if any problem is found
+    Check {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Print the dependency tree (the template and every file it includes or extends)
+    Deps {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Print every variable the template and its includes refer to
+    Vars {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Output format: text (one variable per line) or json (an array)
+        #[arg(long = "format", value_name = "FORMAT", default_value = "text")]
+        format: ListFormat,
+    },
+
+    /// Serve the rendered template over HTTP, re-rendering whenever the template, an
+    /// included file or a data file changes; open pages reload automatically
+    Serve {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        render: RenderArgs,
+
+        /// Port to listen on
+        #[arg(long = "port", value_name = "PORT", default_value = "8080")]
+        port: u16,
+
+        /// Address to listen on; use 0.0.0.0 to let others on the network see the preview
+        #[arg(long = "host", value_name = "ADDR", default_value = "127.0.0.1")]
+        host: std::net::IpAddr,
+    },
+
+    /// Run every render job of a manifest (render.yaml), with paths relative to it
+    Build {
+        /// Manifest listing the jobs, each with the options of `rp render`
+        #[arg(
+            short = 'f',
+            long = "file",
+            value_name = "PATH",
+            default_value = "render.yaml"
+        )]
+        file: String,
+
+        /// Render every job but write nothing; print the files that would be read and
+        /// written instead
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+
+        /// Compare every job's output with the existing files instead of writing them;
+        /// exits with code 1 and prints a diff if any differs
+        #[arg(long = "check-output", conflicts_with = "dry_run")]
+        check_output: bool,
+    },
+}
+
+/// Where the template and its data come from; shared by every command
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// Template file path, or `-` to read the template from stdin
+    /// (includes then require --root). With --output-dir, a glob pattern is allowed.
+    /// Repeat to render several templates, with the same root and data, into one output.
     #[arg(short = 't', long = "template", required = true, value_name = "PATH")]
-    pub template: String,
+    pub templates: Vec<String>,
 
-    /// Data files (YAML/JSON). Can be specified multiple times.
+    /// Data files (YAML/JSON/JSONL/CSV/TSV). Can be specified multiple times.
     /// Multiple files will be deep-merged with later files taking precedence.
-    #[arg(short = 'd', long = "data", value_name = "PATH")]
+    /// Use `NAME=PATH` to mount a file's contents under the key NAME.
+    #[arg(short = 'd', long = "data", value_name = "[NAME=]PATH")]
     pub data: Vec<String>,
 
-    /// Output file path. If not specified, output goes to stdout.
-    #[arg(short = 'o', long = "out", value_name = "PATH")]
-    pub output: Option<String>,
-
     /// Root directory for include resolution.
     /// If not specified, uses the template file's directory.
     #[arg(short = 'r', long = "root", value_name = "DIR")]
     pub root: Option<String>,
 
+    /// Run a shell command and use its stdout (JSON or YAML) as data, merged after the
+    /// data files. Can be specified multiple times.
+    #[arg(long = "data-exec", value_name = "COMMAND")]
+    pub data_exec: Vec<String>,
+
+    /// Override a data value, e.g. `--set user.name=Alice` (applied after all data files).
+    /// Can be specified multiple times.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_assignment)]
+    pub set: Vec<(String, String)>,
+
+    /// Override a data value with parsed JSON, e.g. `--set-json 'tags=["a","b"]'`
+    /// (applied after `--set`). Can be specified multiple times.
+    #[arg(long = "set-json", value_name = "KEY=JSON", value_parser = parse_json_assignment)]
+    pub set_json: Vec<(String, serde_json::Value)>,
+
+    /// JSON Schema (JSON or YAML) that the merged data must satisfy
+    #[arg(long = "schema", value_name = "PATH")]
+    pub schema: Option<String>,
+
+    /// Field delimiter for .csv data files: a single character, or `tab`
+    #[arg(long = "csv-delimiter", value_name = "CHAR", value_parser = parse_delimiter)]
+    pub csv_delimiter: Option<u8>,
+
+    /// Key that records of .jsonl data files are loaded under (default: records)
+    #[arg(long = "jsonl-key", value_name = "KEY")]
+    pub jsonl_key: Option<String>,
+
+    /// Parse every data file given with --data as this format (yaml, json, jsonl, csv,
+    /// tsv or auto), regardless of its extension
+    #[arg(long = "data-format", value_name = "FORMAT")]
+    pub data_format: Option<DataFormat>,
+
+    /// How data files are merged: deep (default) merges objects recursively, shallow lets
+    /// a later file replace whole top-level sections
+    #[arg(
+        long = "merge-strategy",
+        value_name = "STRATEGY",
+        default_value = "deep"
+    )]
+    pub merge_strategy: MergeStrategy,
+
+    /// How arrays are combined when data files are merged: replace (default), concat,
+    /// or merge-by-key=<field> to merge objects with the same field value
+    #[arg(
+        long = "array-merge",
+        value_name = "STRATEGY",
+        default_value = "replace"
+    )]
+    pub array_merge: ArrayMerge,
+
+    /// Let an explicit `null` in a later data file remove the key instead of setting it
+    /// to null (so --strict reports its use)
+    #[arg(long = "null-deletes")]
+    pub null_deletes: bool,
+
+    /// Expose process environment variables to templates under the `env` key
+    #[arg(long = "allow-env")]
+    pub allow_env: bool,
+
+    /// Expose only the matching environment variables under `env`, e.g. `--env 'CI_*'`
+    /// or `--env MODEL`. Can be specified multiple times.
+    #[arg(long = "env", value_name = "PATTERN", value_parser = parse_env_pattern)]
+    pub env: Vec<glob::Pattern>,
+
+    /// Read environment variables prefixed with `RP_VAR_` as data, e.g. `RP_VAR_USER_NAME`
+    /// as `user_name` (merged after data files and --data-exec, before --set)
+    #[arg(long = "env-vars")]
+    pub env_vars: bool,
+
+    /// Maximum include depth to prevent infinite recursion
+    #[arg(long = "max-include-depth", value_name = "N", default_value = "20")]
+    pub max_include_depth: usize,
+
+    /// Also allow includes from this directory, outside the root. Can be specified multiple
+    /// times.
+    #[arg(long = "allow-root", value_name = "DIR")]
+    pub allow_root: Vec<String>,
+
+    /// Reject included files that are symlinks
+    #[arg(long = "no-symlinks")]
+    pub no_symlinks: bool,
+
+    /// Reject templates, included files and data files larger than this, in bytes or with
+    /// a K, M or G suffix (e.g. `1M`)
+    #[arg(long = "max-file-size", value_name = "SIZE", value_parser = parse_size)]
+    pub max_file_size: Option<usize>,
+
+    /// Abort (exit code 7) if rendering a template reads more than this many included files
+    #[arg(long = "max-includes", value_name = "N")]
+    pub max_includes: Option<usize>,
+
+    /// Reject data that could come from the network: --data-exec commands and data sources
+    /// registered by a library embedder
+    #[arg(long = "no-network", conflicts_with = "data_exec")]
+    pub no_network: bool,
+}
+
+/// Options that only affect rendering
+#[derive(Args, Debug, Default)]
+pub struct RenderArgs {
+    /// Output file path. If not specified, output goes to stdout. A path containing
+    /// `{{ }}` (e.g. `out/{{ name }}.md`) writes one file per record of array data.
+    #[arg(short = 'o', long = "out", value_name = "PATH")]
+    pub output: Option<String>,
+
+    /// Render every template matching --template, which may be a glob pattern such as
+    /// `prompts/**/*.txt`, into this directory, keeping the directory structure below the
+    /// pattern's fixed leading directories
+    #[arg(
+        long = "output-dir",
+        value_name = "DIR",
+        conflicts_with_all = ["output", "watch"]
+    )]
+    pub output_dir: Option<String>,
+
+    /// Append to the output files instead of overwriting them (records of a templated
+    /// output path that share a file accumulate in it)
+    #[arg(long = "append", conflicts_with_all = ["check_output", "watch"])]
+    pub append: bool,
+
+    /// Leave an output file untouched (keeping its modification time) when the render is
+    /// identical to its contents, so make-style build tools see nothing to rebuild
+    #[arg(long = "only-if-changed", conflicts_with = "append")]
+    pub only_if_changed: bool,
+
+    /// Also print what is written to the output files to stdout, so a pipeline can keep
+    /// the output and pass it on
+    #[arg(long = "tee", conflicts_with = "check_output")]
+    pub tee: bool,
+
     /// Strict mode: treat undefined variables as errors
     #[arg(long = "strict")]
     pub strict: bool,
@@ -35,29 +320,467 @@ pub struct Cli {
     #[arg(long = "warn-undefined")]
     pub warn_undefined: bool,
 
-    /// Maximum include depth to prevent infinite recursion
-    #[arg(long = "max-include-depth", value_name = "N", default_value = "20")]
-    pub max_include_depth: usize,
+    /// What undefined variables in `{{ }}` render as: error (like --strict), empty (the
+    /// default), keep to leave the tag as written for a second-stage renderer, or warn
+    /// (like --warn-undefined)
+    #[arg(
+        long = "missing",
+        value_name = "POLICY",
+        conflicts_with_all = ["strict", "warn_undefined"]
+    )]
+    pub missing: Option<MissingPolicy>,
 
-    /// Print dependency tree (all template files) and exit
-    #[arg(long = "print-deps")]
-    pub print_deps: bool,
+    /// Render undefined variables as this text instead of nothing, e.g. `N/A` or
+    /// `<MISSING:{name}>` (`{name}` is replaced by the variable's path)
+    #[arg(
+        long = "default-undefined",
+        value_name = "VALUE",
+        conflicts_with = "strict"
+    )]
+    pub default_undefined: Option<String>,
+
+    /// Abort (exit code 7) if the rendered output grows past this size, in bytes or with
+    /// a K, M or G suffix (e.g. `10M`)
+    #[arg(long = "max-output-size", value_name = "SIZE", value_parser = parse_size)]
+    pub max_output_size: Option<usize>,
+
+    /// Abort (exit code 7) if loading the data and rendering take longer than this, e.g.
+    /// `30s`, `2m` or `500ms` (a bare number is seconds)
+    #[arg(
+        long = "timeout",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        conflicts_with = "watch"
+    )]
+    pub timeout: Option<std::time::Duration>,
+
+    /// Re-render whenever the template, an included file or a data file changes
+    #[arg(long = "watch")]
+    pub watch: bool,
+
+    /// Render and compare the result with the existing output files instead of writing
+    /// them; exits with code 1 and prints a diff if any differs (requires -o or --output-dir)
+    #[arg(long = "check-output", conflicts_with_all = ["watch", "dry_run"])]
+    pub check_output: bool,
+
+    /// Fail (exit code 6) without writing output if any warning was reported, such as an
+    /// undefined variable; implies --warn-undefined
+    #[arg(long = "warn-as-error")]
+    pub warn_as_error: bool,
+
+    /// Run the whole pipeline but write nothing; print the files that would be read and
+    /// written instead
+    #[arg(long = "dry-run", conflicts_with = "watch")]
+    pub dry_run: bool,
+
+    /// Print the rendered output exactly as rendered, without appending a newline (a
+    /// trailing newline of the template itself is kept)
+    #[arg(long = "no-trailing-newline")]
+    pub no_trailing_newline: bool,
+
+    /// Line endings of the output: lf, crlf, or preserve (default) to keep those of the
+    /// template, its includes and the data as they are
+    #[arg(long = "newline", value_name = "STYLE", default_value = "preserve")]
+    pub newline: Newline,
+
+    /// Collapse runs of blank (or whitespace-only) lines in the output into one
+    #[arg(long = "squeeze-blank-lines")]
+    pub squeeze_blank_lines: bool,
+
+    /// Remove spaces and tabs at the end of every line of the output
+    #[arg(long = "trim-trailing-whitespace")]
+    pub trim_trailing_whitespace: bool,
+
+    /// After rendering, print the size of the output (bytes, lines, words) and the number
+    /// of includes, substituted and undefined variables to stderr, as text or json
+    #[arg(
+        long = "stats",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    pub stats: Option<ListFormat>,
+
+    /// Disable the `now` helper so output does not depend on the current time
+    #[arg(long = "no-now")]
+    pub no_now: bool,
+
+    /// HTML-escape substituted values (template text itself is not escaped)
+    #[arg(long = "escape-html")]
+    pub escape_html: bool,
+
+    /// Create missing parent directories of the output file, as `rp build` does
+    #[arg(skip)]
+    pub create_dirs: bool,
+}
+
+impl InputArgs {
+    /// Whether one of the templates is read from stdin (`-t -`)
+    pub fn reads_stdin(&self) -> bool {
+        self.templates.iter().any(|template| template == "-")
+    }
+
+    /// The limits set by --allow-root, --no-symlinks, --max-file-size, --max-includes and
+    /// --no-network
+    pub fn sandbox(&self) -> Sandbox {
+        let mut sandbox = Sandbox::new().network(!self.no_network);
+        for root in &self.allow_root {
+            sandbox = sandbox.allow_root(root);
+        }
+        if self.no_symlinks {
+            sandbox = sandbox.symlinks(SymlinkPolicy::Deny);
+        }
+        if let Some(limit) = self.max_file_size {
+            sandbox = sandbox.max_file_size(limit);
+        }
+        if let Some(limit) = self.max_includes {
+            sandbox = sandbox.max_includes(limit);
+        }
+        sandbox
+    }
+}
+
+impl RenderArgs {
+    /// Apply the output post-processing options to a rendered template: whitespace
+    /// cleanup first, then line ending conversion
+    pub fn finish(&self, output: String) -> String {
+        let output = if self.trim_trailing_whitespace || self.squeeze_blank_lines {
+            let mut result = String::with_capacity(output.len());
+            let mut previous_blank = false;
+            for line in output.split_inclusive('\n') {
+                let ending = if line.ends_with("\r\n") {
+                    "\r\n"
+                } else if line.ends_with('\n') {
+                    "\n"
+                } else {
+                    ""
+                };
+                let content = &line[..line.len() - ending.len()];
+                let blank = content.trim().is_empty();
+                if self.squeeze_blank_lines && blank && previous_blank {
+                    continue;
+                }
+                previous_blank = blank;
+                if self.trim_trailing_whitespace {
+                    result.push_str(content.trim_end_matches([' ', '\t']));
+                } else {
+                    result.push_str(content);
+                }
+                result.push_str(ending);
+            }
+            result
+        } else {
+            output
+        };
+        self.newline.apply(output)
+    }
+}
+
+/// What a command line asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Render,
+    Check,
+    Deps,
+    Vars(ListFormat),
+    /// Serve a live preview at this address
+    Serve(std::net::SocketAddr),
+}
+
+/// A parsed command line, with a bare invocation's flags mapped to the equivalent command
+#[derive(Debug)]
+pub struct Invocation {
+    pub mode: Mode,
+    pub input: InputArgs,
+    pub render: RenderArgs,
+    pub verbose: u8,
+    pub quiet: bool,
+    pub error_format: ErrorFormat,
+}
+
+/// How a listing such as --list-vars (or a summary such as --stats) is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One entry per line
+    Text,
+    /// A JSON array (or object)
+    Json,
+}
+
+impl std::str::FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format '{}' (expected text or json)", s)),
+        }
+    }
+}
+
+/// Line endings written to the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Newline {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    Crlf,
+    /// Leave line endings as rendered
+    #[default]
+    Preserve,
+}
+
+impl Newline {
+    /// Convert every line ending of `text` to this style
+    pub fn apply(self, text: String) -> String {
+        match self {
+            Self::Preserve => text,
+            Self::Lf => text.replace("\r\n", "\n"),
+            Self::Crlf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+        }
+    }
+
+    /// The line ending appended after output printed to stdout
+    pub fn terminator(self) -> &'static str {
+        match self {
+            Self::Crlf => "\r\n",
+            Self::Lf | Self::Preserve => "\n",
+        }
+    }
+}
+
+impl std::str::FromStr for Newline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::Crlf),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(format!(
+                "unknown newline style '{}' (expected lf, crlf or preserve)",
+                s
+            )),
+        }
+    }
+}
+
+/// Parse `key=value`, where the key is a dot-separated path without empty segments
+fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", s))?;
+    if key.split('.').any(|segment| segment.trim().is_empty()) {
+        return Err(format!("invalid key '{}' in '{}'", key, s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse `key=json`, with the value parsed as JSON
+fn parse_json_assignment(s: &str) -> Result<(String, serde_json::Value), String> {
+    let (key, value) = parse_assignment(s)?;
+    let value =
+        serde_json::from_str(&value).map_err(|e| format!("invalid JSON for '{}': {}", key, e))?;
+    Ok((key, value))
+}
+
+/// Parse an environment variable name, optionally with `*` wildcards
+fn parse_env_pattern(s: &str) -> Result<glob::Pattern, String> {
+    if s.is_empty() {
+        return Err("expected an environment variable name or pattern".to_string());
+    }
+    glob::Pattern::new(s).map_err(|e| format!("invalid pattern '{}': {}", s, e))
+}
+
+/// Parse a size in bytes, optionally with a binary K, M or G suffix
+fn parse_size(s: &str) -> Result<usize, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, unit) = match digits.char_indices().last() {
+        Some((i, 'K')) => (&digits[..i], 1 << 10),
+        Some((i, 'M')) => (&digits[..i], 1 << 20),
+        Some((i, 'G')) => (&digits[..i], 1 << 30),
+        _ => (digits, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| {
+            format!(
+                "expected a positive size such as 65536, 512K or 10M, got '{}'",
+                s
+            )
+        })
+}
+
+/// Parse a duration such as `30`, `30s`, `2m` or `500ms` (a bare number is seconds)
+fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let seconds = match (number.parse::<f64>(), unit) {
+        (Ok(n), "" | "s") => n,
+        (Ok(n), "ms") => n / 1000.0,
+        (Ok(n), "m") => n * 60.0,
+        (Ok(n), "h") => n * 3600.0,
+        _ => f64::NAN,
+    };
+    if seconds.is_finite() && seconds > 0.0 {
+        Ok(std::time::Duration::from_secs_f64(seconds))
+    } else {
+        Err(format!(
+            "expected a positive duration such as 30s, 2m or 500ms, got '{}'",
+            s
+        ))
+    }
+}
+
+/// Parse a delimiter given as a single ASCII character, `tab` or `\t`
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!(
+            "expected a single ASCII character or 'tab', got '{}'",
+            s
+        )),
+    }
 }
 
 impl Cli {
+    /// Resolve the subcommand, or the flags of a bare invocation, into what to run
+    pub fn into_invocation(self) -> Invocation {
+        let (mode, input, render) = match self.command {
+            Some(Command::Render { input, render }) => (Mode::Render, input, render),
+            Some(Command::Check { input }) => (Mode::Check, input, RenderArgs::default()),
+            Some(Command::Deps { input }) => (Mode::Deps, input, RenderArgs::default()),
+            Some(Command::Vars { input, format }) => {
+                (Mode::Vars(format), input, RenderArgs::default())
+            }
+            Some(Command::Serve {
+                input,
+                render,
+                port,
+                host,
+            }) => (
+                Mode::Serve(std::net::SocketAddr::new(host, port)),
+                input,
+                render,
+            ),
+            Some(Command::Build { .. }) => {
+                unreachable!("`rp build` runs its jobs' own invocations")
+            }
+            None => {
+                let mode = if self.check {
+                    Mode::Check
+                } else if self.print_deps {
+                    Mode::Deps
+                } else if let Some(format) = self.list_vars {
+                    Mode::Vars(format)
+                } else {
+                    Mode::Render
+                };
+                let input = self
+                    .input
+                    .expect("--template is required without a subcommand");
+                (mode, input, self.render)
+            }
+        };
+        Invocation {
+            mode,
+            input,
+            render,
+            verbose: self.verbose,
+            quiet: self.quiet,
+            error_format: self.error_format,
+        }
+    }
+}
+
+impl Invocation {
     /// Validate CLI arguments
     pub fn validate(&self) -> Result<(), String> {
         // Check if template file path is provided (already enforced by required = true)
 
         // Check max_include_depth is reasonable
-        if self.max_include_depth == 0 {
+        if self.input.max_include_depth == 0 {
             return Err("max-include-depth must be at least 1".to_string());
         }
 
-        if self.max_include_depth > 1000 {
+        if self.input.max_include_depth > 1000 {
             return Err("max-include-depth is too large (max: 1000)".to_string());
         }
 
+        if self.render.watch && self.input.reads_stdin() {
+            return Err("--watch cannot be used with a template read from stdin".to_string());
+        }
+
+        if matches!(self.mode, Mode::Serve(_)) {
+            let render = &self.render;
+            if render.output.is_some()
+                || render.output_dir.is_some()
+                || render.append
+                || render.watch
+                || render.dry_run
+                || render.check_output
+                || render.timeout.is_some()
+            {
+                return Err(
+                    "`rp serve` cannot be used with -o, --output-dir, --append, --watch, \
+                     --dry-run, --check-output or --timeout"
+                        .to_string(),
+                );
+            }
+            if self.input.reads_stdin() {
+                return Err("`rp serve` cannot be used with a template read from stdin".to_string());
+            }
+        }
+
+        if self.render.check_output
+            && self.render.output.is_none()
+            && self.render.output_dir.is_none()
+        {
+            return Err("--check-output requires -o or --output-dir".to_string());
+        }
+
+        if self.render.append && self.render.output.is_none() && self.render.output_dir.is_none() {
+            return Err("--append requires -o or --output-dir".to_string());
+        }
+
+        if self.render.tee && self.render.output.is_none() && self.render.output_dir.is_none() {
+            return Err("--tee requires -o or --output-dir".to_string());
+        }
+
+        if self.render.default_undefined.is_some()
+            && matches!(
+                self.render.missing,
+                Some(MissingPolicy::Error | MissingPolicy::Keep)
+            )
+        {
+            return Err(
+                "--default-undefined cannot be used with --missing error or --missing keep"
+                    .to_string(),
+            );
+        }
+
+        if self.render.output_dir.is_some() && self.input.reads_stdin() {
+            return Err("--output-dir cannot be used with a template read from stdin".to_string());
+        }
+
+        if self.render.output_dir.is_some() && self.input.templates.len() > 1 {
+            return Err("--output-dir takes a single template pattern".to_string());
+        }
+
+        if self.input.templates.iter().filter(|t| *t == "-").count() > 1 {
+            return Err("stdin (`-t -`) can only be read once".to_string());
+        }
+
         Ok(())
     }
 }
@@ -66,51 +789,168 @@ impl Cli {
 mod tests {
     use super::*;
 
+    fn parse(args: &[&str]) -> Invocation {
+        Cli::try_parse_from(args).unwrap().into_invocation()
+    }
+
     #[test]
     fn test_validate_max_depth_zero() {
-        let cli = Cli {
-            template: "test.txt".to_string(),
-            data: vec![],
-            output: None,
-            root: None,
-            strict: false,
-            warn_undefined: false,
-            max_include_depth: 0,
-            print_deps: false,
-        };
+        let cli = parse(&["rp", "-t", "test.txt", "--max-include-depth", "0"]);
 
         assert!(cli.validate().is_err());
     }
 
     #[test]
     fn test_validate_max_depth_too_large() {
-        let cli = Cli {
-            template: "test.txt".to_string(),
-            data: vec![],
-            output: None,
-            root: None,
-            strict: false,
-            warn_undefined: false,
-            max_include_depth: 1001,
-            print_deps: false,
-        };
+        let cli = parse(&["rp", "-t", "test.txt", "--max-include-depth", "1001"]);
 
         assert!(cli.validate().is_err());
     }
 
+    #[test]
+    fn test_validate_multiple_templates() {
+        let cli = parse(&["rp", "-t", "system.txt", "-t", "-", "-t", "examples.txt"]);
+        assert_eq!(cli.input.templates, ["system.txt", "-", "examples.txt"]);
+        assert!(cli.validate().is_ok());
+
+        assert!(parse(&["rp", "-t", "-", "-t", "-"]).validate().is_err());
+        assert!(
+            parse(&["rp", "-t", "a/*.txt", "-t", "b.txt", "--output-dir", "out"])
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_assignment() {
+        assert_eq!(
+            parse_assignment("user.name=Alice=B"),
+            Ok(("user.name".to_string(), "Alice=B".to_string()))
+        );
+        assert_eq!(
+            parse_assignment("empty="),
+            Ok(("empty".to_string(), String::new()))
+        );
+        assert!(parse_assignment("novalue").is_err());
+        assert!(parse_assignment("=x").is_err());
+        assert!(parse_assignment("user..name=x").is_err());
+    }
+
+    #[test]
+    fn test_parse_json_assignment() {
+        assert_eq!(
+            parse_json_assignment(r#"config={"a": [1, 2]}"#),
+            Ok(("config".to_string(), serde_json::json!({"a": [1, 2]})))
+        );
+        assert_eq!(
+            parse_json_assignment("n=3"),
+            Ok(("n".to_string(), serde_json::json!(3)))
+        );
+        assert!(parse_json_assignment("name=Alice").is_err());
+        assert!(parse_json_assignment("{\"a\":1}").is_err());
+    }
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter(";"), Ok(b';'));
+        assert_eq!(parse_delimiter("tab"), Ok(b'\t'));
+        assert_eq!(parse_delimiter("\\t"), Ok(b'\t'));
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter(";;").is_err());
+        assert!(parse_delimiter("、").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("65536"), Ok(65536));
+        assert_eq!(parse_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_size("10m"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1T").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        use std::time::Duration;
+
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("3d").is_err());
+    }
+
     #[test]
     fn test_validate_ok() {
-        let cli = Cli {
-            template: "test.txt".to_string(),
-            data: vec![],
-            output: None,
-            root: None,
-            strict: false,
-            warn_undefined: false,
-            max_include_depth: 20,
-            print_deps: false,
-        };
+        let cli = parse(&["rp", "-t", "test.txt", "--max-include-depth", "20"]);
 
         assert!(cli.validate().is_ok());
     }
+
+    #[test]
+    fn test_newline() {
+        let text = "a\r\nb\nc".to_string();
+        assert_eq!(Newline::Lf.apply(text.clone()), "a\nb\nc");
+        assert_eq!(Newline::Crlf.apply(text.clone()), "a\r\nb\r\nc");
+        assert_eq!(Newline::Preserve.apply(text.clone()), text);
+        assert_eq!("CRLF".parse(), Ok(Newline::Crlf));
+        assert!("cr".parse::<Newline>().is_err());
+    }
+
+    #[test]
+    fn test_finish_output() {
+        let text = "a  \n\n \n\t\nb\t\r\n\r\n\r\nc \n\n".to_string();
+        let mut render = RenderArgs::default();
+        assert_eq!(render.finish(text.clone()), text);
+
+        render.trim_trailing_whitespace = true;
+        assert_eq!(render.finish(text.clone()), "a\n\n\n\nb\r\n\r\n\r\nc\n\n");
+
+        render.squeeze_blank_lines = true;
+        assert_eq!(render.finish(text.clone()), "a\n\nb\r\n\r\nc\n\n");
+
+        render.trim_trailing_whitespace = false;
+        render.newline = Newline::Lf;
+        assert_eq!(render.finish(text), "a  \n\nb\t\n\nc \n\n");
+    }
+
+    #[test]
+    fn test_subcommands_and_bare_flags() {
+        assert_eq!(parse(&["rp", "-t", "a.txt"]).mode, Mode::Render);
+        assert_eq!(parse(&["rp", "render", "-t", "a.txt"]).mode, Mode::Render);
+        assert_eq!(parse(&["rp", "-t", "a.txt", "--check"]).mode, Mode::Check);
+        assert_eq!(parse(&["rp", "check", "-t", "a.txt"]).mode, Mode::Check);
+        assert_eq!(
+            parse(&["rp", "-t", "a.txt", "--print-deps"]).mode,
+            Mode::Deps
+        );
+        assert_eq!(parse(&["rp", "deps", "-t", "a.txt"]).mode, Mode::Deps);
+        assert_eq!(
+            parse(&["rp", "-t", "a.txt", "--list-vars=json"]).mode,
+            Mode::Vars(ListFormat::Json)
+        );
+        assert_eq!(
+            parse(&["rp", "vars", "-t", "a.txt"]).mode,
+            Mode::Vars(ListFormat::Text)
+        );
+        assert_eq!(
+            parse(&["rp", "serve", "-t", "a.txt", "--port", "3000"]).mode,
+            Mode::Serve("127.0.0.1:3000".parse().unwrap())
+        );
+
+        let render = parse(&["rp", "render", "-t", "a.txt", "-o", "out.txt", "--strict"]);
+        assert_eq!(render.render.output.as_deref(), Some("out.txt"));
+        assert!(render.render.strict);
+
+        // Render-only options belong to `render`, and options go after the subcommand
+        assert!(Cli::try_parse_from(["rp", "check", "-t", "a.txt", "--strict"]).is_err());
+        assert!(Cli::try_parse_from(["rp", "-t", "a.txt", "check"]).is_err());
+        assert!(Cli::try_parse_from(["rp"]).is_err());
+    }
 }
@@ -0,0 +1,154 @@
+//! ANSI colors for errors and warnings on stderr (`--color`)
+
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When diagnostics are colored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When stderr is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color, given whether stderr is a terminal and the value of `NO_COLOR`
+    ///
+    /// An explicit `always` wins over `NO_COLOR`, as https://no-color.org asks.
+    fn resolve(self, terminal: bool, no_color: Option<&str>) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => terminal && no_color.is_none_or(str::is_empty),
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "unknown color choice '{}' (expected auto, always or never)",
+                s
+            )),
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decide once whether diagnostics are colored
+pub fn init(choice: ColorChoice) {
+    let no_color = std::env::var("NO_COLOR").ok();
+    let enabled = choice.resolve(std::io::stderr().is_terminal(), no_color.as_deref());
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+fn paint(style: &str, text: &str) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// `Error`, `ERROR` and other words starting an error
+pub fn error(text: &str) -> String {
+    paint(RED, text)
+}
+
+/// `Warning` and other words starting a warning
+pub fn warning(text: &str) -> String {
+    paint(YELLOW, text)
+}
+
+/// The message of an error
+pub fn message(text: &str) -> String {
+    paint(BOLD, text)
+}
+
+/// Machine-readable error lines (`ERROR code=...`), with the leading `ERROR` colored
+pub fn error_lines(text: &str) -> String {
+    text.lines()
+        .map(|line| match line.strip_prefix("ERROR") {
+            Some(rest) => format!("{}{}", error("ERROR"), rest),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A [`RenderError::snippet`](render_prompt::error::RenderError::snippet), with the
+/// gutter in blue and the carets in red
+pub fn snippet(snippet: &str) -> String {
+    let lines: Vec<&str> = snippet.lines().collect();
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            // The gutter ends at `-->` on the first line and at the first `|` on the others
+            let end = match (index, line.find("-->"), line.find('|')) {
+                (0, Some(at), _) => at + 3,
+                (_, _, Some(at)) => at + 1,
+                _ => 0,
+            };
+            let (gutter, rest) = line.split_at(end);
+            let rest = match rest.find('^') {
+                Some(at) if index == lines.len() - 1 => {
+                    format!("{}{}", &rest[..at], error(&rest[at..]))
+                }
+                _ => rest.to_string(),
+            };
+            format!("{}{}", paint(BLUE, gutter), rest)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert!(ColorChoice::Auto.resolve(true, None));
+        assert!(ColorChoice::Auto.resolve(true, Some("")));
+        assert!(!ColorChoice::Auto.resolve(false, None));
+        assert!(!ColorChoice::Auto.resolve(true, Some("1")));
+        assert!(ColorChoice::Always.resolve(false, Some("1")));
+        assert!(!ColorChoice::Never.resolve(true, None));
+        assert_eq!("ALWAYS".parse(), Ok(ColorChoice::Always));
+        assert!("yes".parse::<ColorChoice>().is_err());
+    }
+
+    #[test]
+    fn test_snippet() {
+        let plain = " --> main.txt:3:7\n  |\n3 | a | {{ b }}\n  |       ^^^^^";
+        ENABLED.store(false, Ordering::Relaxed);
+        assert_eq!(snippet(plain), plain);
+
+        ENABLED.store(true, Ordering::Relaxed);
+        let colored = snippet(plain);
+        ENABLED.store(false, Ordering::Relaxed);
+        assert_eq!(
+            colored,
+            "\x1b[1;34m -->\x1b[0m main.txt:3:7\n\
+             \x1b[1;34m  |\x1b[0m\n\
+             \x1b[1;34m3 |\x1b[0m a | {{ b }}\n\
+             \x1b[1;34m  |\x1b[0m       \x1b[1;31m^^^^^\x1b[0m"
+        );
+    }
+}
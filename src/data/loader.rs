@@ -1,57 +1,433 @@
 use crate::error::RenderError;
+use crate::sandbox::Sandbox;
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use super::merger::DataMerger;
+use super::source::{DataParser, DataSource};
 
-pub struct DataLoader;
+/// Key that JSONL records are loaded under unless overridden
+const DEFAULT_JSONL_KEY: &str = "records";
+
+/// Prefix of the environment variables `--env-vars` reads as data
+pub const ENV_VAR_PREFIX: &str = "RP_VAR_";
+
+/// How a data file is parsed, normally chosen from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    Yaml,
+    Json,
+    Jsonl,
+    Csv,
+    Tsv,
+    /// Detect JSON or YAML from the content
+    Auto,
+}
+
+impl DataFormat {
+    /// Format for a (lowercase) file extension; unknown extensions are detected from content
+    fn from_extension(extension: &str) -> Self {
+        match extension {
+            "yaml" | "yml" => DataFormat::Yaml,
+            "json" => DataFormat::Json,
+            "jsonl" | "ndjson" => DataFormat::Jsonl,
+            "csv" => DataFormat::Csv,
+            "tsv" => DataFormat::Tsv,
+            _ => DataFormat::Auto,
+        }
+    }
+}
+
+impl std::str::FromStr for DataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(DataFormat::Auto),
+            other => match DataFormat::from_extension(other) {
+                DataFormat::Auto => Err(format!(
+                    "unknown data format '{}' (expected yaml, json, jsonl, csv, tsv or auto)",
+                    s
+                )),
+                format => Ok(format),
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DataLoader {
+    csv_delimiter: Option<u8>,
+    jsonl_key: Option<String>,
+    format: Option<DataFormat>,
+    merger: DataMerger,
+    sources: BTreeMap<String, Rc<dyn DataSource>>,
+    parsers: BTreeMap<String, Rc<dyn DataParser>>,
+    sandbox: Sandbox,
+}
 
 impl DataLoader {
-    /// Load a single data file (YAML or JSON)
-    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Value, RenderError> {
-        let path = path.as_ref();
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Override the delimiter of `.csv` files (default `,`; `.tsv` files always use tabs)
+    pub fn with_csv_delimiter(mut self, delimiter: Option<u8>) -> Self {
+        self.csv_delimiter = delimiter;
+        self
+    }
+
+    /// Set the key that `.jsonl` records are loaded under (default `records`)
+    pub fn with_jsonl_key(mut self, key: Option<String>) -> Self {
+        self.jsonl_key = key;
+        self
+    }
+
+    /// Set how multiple data files (and the files of a data directory) are merged
+    pub fn with_merger(mut self, merger: DataMerger) -> Self {
+        self.merger = merger;
+        self
+    }
+
+    /// Parse every data file given directly with the given format, ignoring its extension
+    /// (files found in a data directory are still recognized by extension)
+    pub fn with_format(mut self, format: Option<DataFormat>) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Load data given as `scheme://location` from a custom source
+    ///
+    /// Arguments with a scheme that has no registered source are still read as paths.
+    pub fn with_source(mut self, scheme: &str, source: impl DataSource + 'static) -> Self {
+        self.sources.insert(scheme.to_string(), Rc::new(source));
+        self
+    }
+
+    /// Parse files with the given extension (without the dot) with a custom parser,
+    /// instead of detecting JSON or YAML from their content
+    pub fn with_parser(mut self, extension: &str, parser: impl DataParser + 'static) -> Self {
+        self.parsers
+            .insert(extension.to_lowercase(), Rc::new(parser));
+        self
+    }
+
+    /// Enforce the file size limit of `sandbox` on data files, and reject data sources and
+    /// commands when it denies network access
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Load a single data file (YAML, JSON, JSONL, CSV, TSV or a registered extension), a
+    /// directory of them, or `scheme://location` from a registered source
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<Value, RenderError> {
+        self.load_path(path.as_ref(), self.format)
+    }
+
+    /// The registered source and its location, if `path` is `scheme://location`
+    fn source_for<'a>(&self, path: &'a Path) -> Option<(&dyn DataSource, &'a str)> {
+        let (scheme, location) = path.to_str()?.split_once("://")?;
+        Some((self.sources.get(scheme)?.as_ref(), location))
+    }
+
+    fn load_path(&self, path: &Path, format: Option<DataFormat>) -> Result<Value, RenderError> {
         let path_str = path.display().to_string();
 
+        if let Some((source, location)) = self.source_for(path) {
+            self.sandbox
+                .check_network("a data source")
+                .map_err(|e| RenderError::DataFileRead {
+                    path: path_str.clone(),
+                    source: e,
+                })?;
+            return source
+                .load(location)
+                .map_err(|e| RenderError::DataFileRead {
+                    path: path_str,
+                    source: io::Error::other(e),
+                });
+        }
+
+        if path.is_dir() {
+            return self.load_dir(path);
+        }
+
         // Read file content
-        let content = fs::read_to_string(path).map_err(|e| RenderError::DataFileRead {
-            path: path_str.clone(),
-            source: e,
-        })?;
+        let content = fs::read_to_string(path)
+            .and_then(|content| self.sandbox.check_size(&content).map(|_| content))
+            .map_err(|e| RenderError::DataFileRead {
+                path: path_str.clone(),
+                source: e,
+            })?;
 
-        // Determine format from extension
+        // Determine format from extension unless it was given explicitly
         let extension = path
             .extension()
             .and_then(|e| e.to_str())
-            .unwrap_or("");
+            .unwrap_or("")
+            .to_lowercase();
+        let explicit = format.is_some();
+        if let Some(parser) = self.parsers.get(&extension).filter(|_| !explicit) {
+            return parser
+                .parse(&content)
+                .map_err(|e| RenderError::DataFileParse {
+                    path: path_str,
+                    source: e,
+                });
+        }
+        let format = format.unwrap_or_else(|| DataFormat::from_extension(&extension));
 
-        match extension.to_lowercase().as_str() {
-            "yaml" | "yml" => {
+        match format {
+            DataFormat::Yaml => {
                 // Parse as YAML
-                serde_yaml::from_str(&content).map_err(|e| RenderError::DataFileParse {
+                Self::parse_yaml(&content).map_err(|e| RenderError::DataFileParse {
                     path: path_str,
-                    source: anyhow::Error::new(e),
+                    source: e,
                 })
             }
-            "json" => {
+            DataFormat::Json => {
                 // Parse as JSON
                 serde_json::from_str(&content).map_err(|e| RenderError::DataFileParse {
                     path: path_str,
                     source: anyhow::Error::new(e),
                 })
             }
-            _ => Err(RenderError::DataFileParse {
-                path: path_str,
-                source: anyhow::anyhow!(
-                    "Unsupported file extension: '{}'. Expected .yaml, .yml, or .json",
-                    extension
-                ),
+            DataFormat::Jsonl => {
+                // Parse one JSON value per line, as an array under the JSONL key
+                let records =
+                    Self::parse_jsonl(&content).map_err(|e| RenderError::DataFileParse {
+                        path: path_str,
+                        source: e,
+                    })?;
+                let key = self.jsonl_key.as_deref().unwrap_or(DEFAULT_JSONL_KEY);
+                Ok(serde_json::json!({ key: records }))
+            }
+            DataFormat::Csv | DataFormat::Tsv => {
+                // Parse as CSV/TSV (array of row objects)
+                let delimiter = match format {
+                    DataFormat::Tsv => b'\t',
+                    _ => self.csv_delimiter.unwrap_or(b','),
+                };
+                Self::parse_csv(&content, delimiter).map_err(|e| RenderError::DataFileParse {
+                    path: path_str,
+                    source: anyhow::Error::new(e),
+                })
+            }
+            // Unknown or missing extension: decide between JSON and YAML from the content
+            DataFormat::Auto => Self::parse_sniffed(&content).map_err(|e| {
+                let source = if explicit {
+                    e
+                } else {
+                    anyhow::anyhow!(
+                        "{} (unrecognized extension '{}', so the content was read as JSON or YAML)",
+                        e,
+                        extension
+                    )
+                };
+                RenderError::DataFileParse {
+                    path: path_str,
+                    source,
+                }
             }),
         }
     }
 
+    /// Load every data file in a directory under a key named after the file
+    /// (`context/users.yaml` → `users`); subdirectories become nested objects
+    ///
+    /// Hidden entries and files with unsupported extensions are skipped.
+    fn load_dir(&self, dir: &Path) -> Result<Value, RenderError> {
+        let read_error = |e| RenderError::DataFileRead {
+            path: dir.display().to_string(),
+            source: e,
+        };
+        let mut entries = fs::read_dir(dir)
+            .map_err(read_error)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(read_error)?;
+        entries.sort();
+
+        let mut result = Value::Object(serde_json::Map::new());
+        for path in entries {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let key = if path.is_dir() {
+                name
+            } else {
+                let extension = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("")
+                    .to_lowercase();
+                if DataFormat::from_extension(&extension) == DataFormat::Auto
+                    && !self.parsers.contains_key(&extension)
+                {
+                    continue;
+                }
+                path.file_stem().and_then(|s| s.to_str()).unwrap_or(name)
+            };
+            let value = self.load_path(&path, None)?;
+            self.merger
+                .merge_into(&mut result, &serde_json::json!({ key: value }));
+        }
+        Ok(result)
+    }
+
+    /// Run a shell command and parse its stdout as JSON, or as YAML if it is not JSON
+    pub fn load_exec(&self, command: &str) -> Result<Value, RenderError> {
+        let source = format!("exec: {}", command);
+        self.sandbox
+            .check_network("running a command")
+            .map_err(|e| RenderError::DataFileRead {
+                path: source.clone(),
+                source: e,
+            })?;
+
+        #[cfg(windows)]
+        let output = std::process::Command::new("cmd")
+            .args(["/C", command])
+            .output();
+        #[cfg(not(windows))]
+        let output = std::process::Command::new("sh")
+            .args(["-c", command])
+            .output();
+
+        let output = output.map_err(|e| RenderError::DataFileRead {
+            path: source.clone(),
+            source: e,
+        })?;
+        let parse_error = |e: anyhow::Error| RenderError::DataFileParse {
+            path: source.clone(),
+            source: e,
+        };
+        if !output.status.success() {
+            return Err(parse_error(anyhow::anyhow!(
+                "command failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let stdout = String::from_utf8(output.stdout).map_err(|e| parse_error(e.into()))?;
+        Self::parse_sniffed(&stdout).map_err(parse_error)
+    }
+
+    /// Parse content of unknown format: JSON if it is valid JSON, YAML otherwise
+    ///
+    /// Plain text also parses as a YAML string, so only an object or array is accepted.
+    fn parse_sniffed(content: &str) -> Result<Value, anyhow::Error> {
+        let value = match serde_json::from_str(content) {
+            Ok(value) => value,
+            Err(_) => Self::parse_yaml(content)?,
+        };
+        match value {
+            Value::Object(_) | Value::Array(_) => Ok(value),
+            _ => anyhow::bail!("content is neither a JSON nor a YAML document"),
+        }
+    }
+
+    /// Parse YAML, resolving YAML 1.1 merge keys (`<<: *base`) that serde_yaml leaves as-is
+    pub(crate) fn parse_yaml(content: &str) -> Result<Value, anyhow::Error> {
+        let mut yaml: serde_yaml::Value = serde_yaml::from_str(content)?;
+        if !Self::apply_merge_keys(&mut yaml)? {
+            return Ok(serde_yaml::from_str(content)?);
+        }
+        // Re-parse the resolved document so non-string keys become strings as usual
+        Ok(serde_yaml::from_str(&serde_yaml::to_string(&yaml)?)?)
+    }
+
+    /// Merge `<<` entries into their mapping, innermost first so merge chains resolve,
+    /// returning whether any were found
+    ///
+    /// Keys already present in the mapping win, and earlier sources in a `<<: [*a, *b]`
+    /// list win over later ones.
+    fn apply_merge_keys(value: &mut serde_yaml::Value) -> Result<bool, anyhow::Error> {
+        use serde_yaml::Value as Yaml;
+
+        let mut merged = false;
+        match value {
+            Yaml::Mapping(mapping) => {
+                for child in mapping.values_mut() {
+                    merged |= Self::apply_merge_keys(child)?;
+                }
+                let sources = match mapping.remove("<<") {
+                    None => return Ok(merged),
+                    Some(Yaml::Mapping(source)) => vec![source],
+                    Some(Yaml::Sequence(sources)) => sources
+                        .into_iter()
+                        .map(|source| match source {
+                            Yaml::Mapping(source) => Ok(source),
+                            _ => Err(anyhow::anyhow!("'<<' list entries must be mappings")),
+                        })
+                        .collect::<Result<_, _>>()?,
+                    Some(_) => anyhow::bail!("'<<' must be a mapping or a list of mappings"),
+                };
+                for source in sources {
+                    for (key, value) in source {
+                        mapping.entry(key).or_insert(value);
+                    }
+                }
+                merged = true;
+            }
+            Yaml::Sequence(sequence) => {
+                for child in sequence {
+                    merged |= Self::apply_merge_keys(child)?;
+                }
+            }
+            Yaml::Tagged(tagged) => merged = Self::apply_merge_keys(&mut tagged.value)?,
+            _ => {}
+        }
+        Ok(merged)
+    }
+
+    /// Parse newline-delimited JSON into an array, skipping blank lines
+    fn parse_jsonl(content: &str) -> Result<Value, anyhow::Error> {
+        let mut records = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("line {}: {}", index + 1, e))?;
+            records.push(record);
+        }
+        Ok(Value::Array(records))
+    }
+
+    /// Parse delimited text with a header row into an array of objects keyed by column name
+    ///
+    /// Every cell is kept as a string.
+    fn parse_csv(content: &str, delimiter: u8) -> Result<Value, csv::Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .from_reader(content.as_bytes());
+        let headers = reader.headers()?.clone();
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let row = headers
+                .iter()
+                .zip(record?.iter())
+                .map(|(key, cell)| (key.to_string(), Value::String(cell.to_string())))
+                .collect();
+            rows.push(Value::Object(row));
+        }
+        Ok(Value::Array(rows))
+    }
+
     /// Load multiple data files and merge them (later files override earlier ones)
-    pub fn load_multiple<P: AsRef<Path>>(paths: &[P]) -> Result<Value, RenderError> {
+    pub fn load_multiple<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Value, RenderError> {
         if paths.is_empty() {
             // Return empty object if no data files provided
             return Ok(Value::Object(serde_json::Map::new()));
@@ -59,11 +435,114 @@ impl DataLoader {
 
         let mut values = Vec::new();
         for path in paths {
-            let value = Self::load_file(path)?;
-            values.push(value);
+            let (namespace, path) = Self::split_namespace(path.as_ref());
+            let paths = match self.source_for(path) {
+                Some(_) => vec![path.to_path_buf()],
+                None => Self::expand(path)?,
+            };
+            for path in paths {
+                let value = self.load_file(&path)?;
+                tracing::info!("merged data file {}", path.display());
+                values.push(match namespace {
+                    Some(namespace) => DataMerger::at_path(namespace, value),
+                    None => value,
+                });
+            }
+        }
+
+        Ok(self.merger.merge_multiple(values))
+    }
+
+    /// The files and directories that `load_multiple` would read for these arguments
+    ///
+    /// Glob patterns are expanded; a pattern without matches is returned as-is.
+    pub fn source_paths<P: AsRef<Path>>(paths: &[P]) -> Vec<PathBuf> {
+        paths
+            .iter()
+            .flat_map(|path| {
+                let (_, path) = Self::split_namespace(path.as_ref());
+                Self::expand(path).unwrap_or_else(|_| vec![path.to_path_buf()])
+            })
+            .collect()
+    }
+
+    /// Split `namespace=path` into the key the file's contents are mounted under and the path
+    ///
+    /// A path that exists as given is never split, so file names containing `=` still work.
+    fn split_namespace(path: &Path) -> (Option<&str>, &Path) {
+        let split = path
+            .to_str()
+            .filter(|_| !path.exists())
+            .and_then(|s| s.split_once('='))
+            .filter(|(namespace, _)| {
+                namespace.split('.').all(|segment| {
+                    !segment.is_empty()
+                        && segment
+                            .chars()
+                            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                })
+            });
+        match split {
+            Some((namespace, rest)) => (Some(namespace), Path::new(rest)),
+            None => (None, path),
+        }
+    }
+
+    /// Expand a glob pattern (`configs/*.yaml`) into the matching paths in lexical order
+    ///
+    /// Paths without wildcards, or that exist as given, are returned unchanged.
+    fn expand(path: &Path) -> Result<Vec<PathBuf>, RenderError> {
+        let pattern = path.to_string_lossy();
+        if path.exists() || !pattern.contains(['*', '?', '[']) {
+            return Ok(vec![path.to_path_buf()]);
+        }
+
+        let error = |message: String| RenderError::DataFileRead {
+            path: pattern.to_string(),
+            source: io::Error::new(io::ErrorKind::NotFound, message),
+        };
+        let mut paths: Vec<PathBuf> = glob::glob(&pattern)
+            .map_err(|e| error(format!("invalid pattern: {}", e)))?
+            .filter_map(Result::ok)
+            .collect();
+        if paths.is_empty() {
+            return Err(error("no files match the pattern".to_string()));
         }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Collect the process environment as an object of strings
+    ///
+    /// Variables whose name or value is not valid UTF-8 are skipped.
+    pub fn load_env() -> Value {
+        Self::load_selected_env(&[glob::Pattern::new("*").unwrap()])
+    }
 
-        Ok(DataMerger::merge_multiple(values))
+    /// Collect only the environment variables whose name matches one of the patterns
+    /// (e.g. `CI_*` or an exact `MODEL`)
+    pub fn load_selected_env(patterns: &[glob::Pattern]) -> Value {
+        let vars = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(key, _)| patterns.iter().any(|pattern| pattern.matches(key)))
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect();
+        Value::Object(vars)
+    }
+
+    /// Collect the environment variables named `<prefix>NAME` as data, keyed by the
+    /// lowercased `NAME` (`RP_VAR_USER_NAME` becomes `user_name`)
+    ///
+    /// Variables whose name or value is not valid UTF-8 are skipped.
+    pub fn load_prefixed_env(prefix: &str) -> Value {
+        let vars = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .filter_map(|(key, value)| {
+                let name = key.strip_prefix(prefix).filter(|name| !name.is_empty())?;
+                Some((name.to_lowercase(), Value::String(value)))
+            })
+            .collect();
+        Value::Object(vars)
     }
 }
 
@@ -79,7 +558,7 @@ mod tests {
         let mut file = NamedTempFile::with_suffix(".json").unwrap();
         writeln!(file, r#"{{"name": "Alice", "age": 30}}"#).unwrap();
 
-        let result = DataLoader::load_file(file.path()).unwrap();
+        let result = DataLoader::new().load_file(file.path()).unwrap();
         assert_eq!(result, json!({"name": "Alice", "age": 30}));
     }
 
@@ -89,25 +568,228 @@ mod tests {
         writeln!(file, "name: Bob").unwrap();
         writeln!(file, "age: 25").unwrap();
 
-        let result = DataLoader::load_file(file.path()).unwrap();
+        let result = DataLoader::new().load_file(file.path()).unwrap();
         assert_eq!(result, json!({"name": "Bob", "age": 25}));
     }
 
+    #[test]
+    fn test_load_yaml_anchors_and_merge_keys() {
+        let mut file = NamedTempFile::with_suffix(".yaml").unwrap();
+        write!(
+            file,
+            "\
+base: &base
+  model: small
+  temperature: 0.2
+tuned: &tuned
+  <<: *base
+  model: large
+extra: &extra
+  top_p: 0.9
+prod:
+  <<: [*tuned, *extra]
+  temperature: 0.0
+alias: *extra
+"
+        )
+        .unwrap();
+
+        let result = DataLoader::new().load_file(file.path()).unwrap();
+        assert_eq!(
+            result["tuned"],
+            json!({"model": "large", "temperature": 0.2})
+        );
+        assert_eq!(
+            result["prod"],
+            json!({"model": "large", "temperature": 0.0, "top_p": 0.9})
+        );
+        assert_eq!(result["alias"], json!({"top_p": 0.9}));
+    }
+
+    #[test]
+    fn test_load_yaml_invalid_merge_key() {
+        let mut file = NamedTempFile::with_suffix(".yaml").unwrap();
+        writeln!(file, "a:\n  <<: 3").unwrap();
+
+        let result = DataLoader::new().load_file(file.path());
+        assert!(matches!(result, Err(RenderError::DataFileParse { .. })));
+    }
+
     #[test]
     fn test_load_yml_extension() {
         let mut file = NamedTempFile::with_suffix(".yml").unwrap();
         writeln!(file, "key: value").unwrap();
 
-        let result = DataLoader::load_file(file.path()).unwrap();
+        let result = DataLoader::new().load_file(file.path()).unwrap();
         assert_eq!(result, json!({"key": "value"}));
     }
 
+    #[test]
+    fn test_load_csv() {
+        let mut file = NamedTempFile::with_suffix(".csv").unwrap();
+        writeln!(file, "input,expected").unwrap();
+        writeln!(file, "2+2,4").unwrap();
+        writeln!(file, "\"Hello, world\",\"greeting \"\"quoted\"\"\"").unwrap();
+
+        let result = DataLoader::new().load_file(file.path()).unwrap();
+        assert_eq!(
+            result,
+            json!([
+                {"input": "2+2", "expected": "4"},
+                {"input": "Hello, world", "expected": "greeting \"quoted\""}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_load_tsv_and_custom_delimiter() {
+        let mut tsv = NamedTempFile::with_suffix(".tsv").unwrap();
+        writeln!(tsv, "name\tnote").unwrap();
+        writeln!(tsv, "Ann\ta, b; c").unwrap();
+
+        let mut csv = NamedTempFile::with_suffix(".csv").unwrap();
+        writeln!(csv, "name;note").unwrap();
+        writeln!(csv, "Ann;a, b").unwrap();
+
+        let loader = DataLoader::new().with_csv_delimiter(Some(b';'));
+        assert_eq!(
+            loader.load_file(tsv.path()).unwrap(),
+            json!([{"name": "Ann", "note": "a, b; c"}])
+        );
+        assert_eq!(
+            loader.load_file(csv.path()).unwrap(),
+            json!([{"name": "Ann", "note": "a, b"}])
+        );
+    }
+
+    #[test]
+    fn test_load_jsonl() {
+        let mut file = NamedTempFile::with_suffix(".jsonl").unwrap();
+        writeln!(file, r#"{{"q": "2+2", "a": 4}}"#).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, r#"{{"q": "capital of France", "a": "Paris"}}"#).unwrap();
+
+        let result = DataLoader::new().load_file(file.path()).unwrap();
+        assert_eq!(
+            result,
+            json!({"records": [{"q": "2+2", "a": 4}, {"q": "capital of France", "a": "Paris"}]})
+        );
+
+        let result = DataLoader::new()
+            .with_jsonl_key(Some("cases".to_string()))
+            .load_file(file.path())
+            .unwrap();
+        assert_eq!(result["cases"][1]["a"], json!("Paris"));
+    }
+
+    #[test]
+    fn test_load_invalid_jsonl() {
+        let mut file = NamedTempFile::with_suffix(".jsonl").unwrap();
+        writeln!(file, r#"{{"ok": true}}"#).unwrap();
+        writeln!(file, "{{broken").unwrap();
+
+        let err = DataLoader::new().load_file(file.path()).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("users.yaml"), "- name: Ann\n- name: Bob").unwrap();
+        fs::write(dir.path().join("settings.json"), r#"{"model": "gpt-4o"}"#).unwrap();
+        fs::write(dir.path().join("README.md"), "# not data").unwrap();
+        fs::write(dir.path().join(".hidden.yaml"), "secret: true").unwrap();
+        fs::create_dir(dir.path().join("team")).unwrap();
+        fs::write(dir.path().join("team").join("lead.yml"), "name: Cy").unwrap();
+
+        let result = DataLoader::new().load_file(dir.path()).unwrap();
+        assert_eq!(
+            result,
+            json!({
+                "users": [{"name": "Ann"}, {"name": "Bob"}],
+                "settings": {"model": "gpt-4o"},
+                "team": {"lead": {"name": "Cy"}}
+            })
+        );
+    }
+
+    #[test]
+    fn test_custom_source() {
+        let loader = DataLoader::new().with_source("vault", |location: &str| match location {
+            "secret/app" => Ok(json!({"api_key": "abc"})),
+            _ => Err(anyhow::anyhow!("no secret at {}", location)),
+        });
+
+        let result = loader
+            .load_multiple(&["creds=vault://secret/app", "vault://secret/app"])
+            .unwrap();
+        assert_eq!(
+            result,
+            json!({"creds": {"api_key": "abc"}, "api_key": "abc"})
+        );
+
+        let err = loader.load_file("vault://secret/other").unwrap_err();
+        assert!(matches!(err, RenderError::DataFileRead { .. }));
+        assert!(
+            err.to_string().contains("no secret at secret/other"),
+            "{}",
+            err
+        );
+
+        // Schemes without a source are paths as before
+        let err = loader.load_file("kv://settings").unwrap_err();
+        assert!(matches!(err, RenderError::DataFileRead { .. }));
+    }
+
+    #[test]
+    fn test_custom_parser() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("settings.ini"), "model=gpt-4o\nretries=3").unwrap();
+        let parse_ini = |content: &str| -> Result<Value, anyhow::Error> {
+            let pairs = content
+                .lines()
+                .map(|line| {
+                    let (key, value) = line
+                        .split_once('=')
+                        .ok_or_else(|| anyhow::anyhow!("missing '=' in '{}'", line))?;
+                    Ok((key.to_string(), json!(value)))
+                })
+                .collect::<Result<_, anyhow::Error>>()?;
+            Ok(Value::Object(pairs))
+        };
+        let loader = DataLoader::new().with_parser("INI", parse_ini);
+
+        let expected = json!({"model": "gpt-4o", "retries": "3"});
+        let path = dir.path().join("settings.ini");
+        assert_eq!(loader.load_file(&path).unwrap(), expected);
+        // Files of the extension are no longer skipped in data directories
+        assert_eq!(
+            loader.load_file(dir.path()).unwrap(),
+            json!({ "settings": expected })
+        );
+
+        fs::write(&path, "broken").unwrap();
+        let err = loader.load_file(&path).unwrap_err();
+        assert!(matches!(err, RenderError::DataFileParse { .. }));
+        assert!(err.to_string().contains("missing '='"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_invalid_csv() {
+        let mut file = NamedTempFile::with_suffix(".csv").unwrap();
+        writeln!(file, "a,b").unwrap();
+        writeln!(file, "1,2,3").unwrap();
+
+        let result = DataLoader::new().load_file(file.path());
+        assert!(matches!(result, Err(RenderError::DataFileParse { .. })));
+    }
+
     #[test]
     fn test_load_invalid_extension() {
         let mut file = NamedTempFile::with_suffix(".txt").unwrap();
         writeln!(file, "some text").unwrap();
 
-        let result = DataLoader::load_file(file.path());
+        let result = DataLoader::new().load_file(file.path());
         assert!(result.is_err());
         match result {
             Err(RenderError::DataFileParse { .. }) => {}
@@ -115,18 +797,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_load_unknown_extension_sniffed() {
+        let mut file = NamedTempFile::with_suffix(".dat").unwrap();
+        writeln!(file, r#"{{"name": "Alice", "tags": ["a"]}}"#).unwrap();
+        let result = DataLoader::new().load_file(file.path()).unwrap();
+        assert_eq!(result, json!({"name": "Alice", "tags": ["a"]}));
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "name: Bob\nitems:\n  - 1").unwrap();
+        let result = DataLoader::new().load_file(file.path()).unwrap();
+        assert_eq!(result, json!({"name": "Bob", "items": [1]}));
+
+        // Invalid JSON is still tried as YAML, whose error is reported
+        let mut file = NamedTempFile::with_suffix(".dat").unwrap();
+        writeln!(file, "key: [unclosed").unwrap();
+        let result = DataLoader::new().load_file(file.path());
+        assert!(matches!(result, Err(RenderError::DataFileParse { .. })));
+    }
+
+    #[test]
+    fn test_load_with_format_override() {
+        // A YAML file delivered with a misleading name
+        let mut file = NamedTempFile::with_suffix(".json").unwrap();
+        writeln!(file, "name: Alice").unwrap();
+        assert!(DataLoader::new().load_file(file.path()).is_err());
+
+        let loader = DataLoader::new().with_format(Some(DataFormat::Yaml));
+        assert_eq!(
+            loader.load_file(file.path()).unwrap(),
+            json!({"name": "Alice"})
+        );
+
+        let mut file = NamedTempFile::with_suffix(".txt").unwrap();
+        writeln!(file, "a\tb\n1\t2").unwrap();
+        let loader = DataLoader::new().with_format(Some(DataFormat::Tsv));
+        assert_eq!(
+            loader.load_file(file.path()).unwrap(),
+            json!([{"a": "1", "b": "2"}])
+        );
+    }
+
+    #[test]
+    fn test_parse_data_format() {
+        assert_eq!("yml".parse::<DataFormat>(), Ok(DataFormat::Yaml));
+        assert_eq!("JSON".parse::<DataFormat>(), Ok(DataFormat::Json));
+        assert_eq!("ndjson".parse::<DataFormat>(), Ok(DataFormat::Jsonl));
+        assert_eq!("auto".parse::<DataFormat>(), Ok(DataFormat::Auto));
+        assert!("toml".parse::<DataFormat>().is_err());
+    }
+
     #[test]
     fn test_load_invalid_json() {
         let mut file = NamedTempFile::with_suffix(".json").unwrap();
         writeln!(file, "{{invalid json}}").unwrap();
 
-        let result = DataLoader::load_file(file.path());
+        let result = DataLoader::new().load_file(file.path());
         assert!(result.is_err());
     }
 
     #[test]
     fn test_load_nonexistent_file() {
-        let result = DataLoader::load_file("/nonexistent/path/file.json");
+        let result = DataLoader::new().load_file("/nonexistent/path/file.json");
         assert!(result.is_err());
         match result {
             Err(RenderError::DataFileRead { .. }) => {}
@@ -137,7 +869,7 @@ mod tests {
     #[test]
     fn test_load_multiple_empty() {
         let paths: Vec<String> = vec![];
-        let result = DataLoader::load_multiple(&paths).unwrap();
+        let result = DataLoader::new().load_multiple(&paths).unwrap();
         assert_eq!(result, json!({}));
     }
 
@@ -150,12 +882,133 @@ mod tests {
         writeln!(file2, r#"{{"b": 3, "c": 4}}"#).unwrap();
 
         let paths = vec![file1.path(), file2.path()];
-        let result = DataLoader::load_multiple(&paths).unwrap();
+        let result = DataLoader::new().load_multiple(&paths).unwrap();
 
         // Later file wins on conflict (b: 3, not 2)
         assert_eq!(result, json!({"a": 1, "b": 3, "c": 4}));
     }
 
+    #[test]
+    fn test_load_multiple_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("20-env.yaml"), "a: env\nb: env").unwrap();
+        fs::write(dir.path().join("10-base.yaml"), "a: base\nb: base\nc: base").unwrap();
+        fs::write(dir.path().join("30-local.yaml"), "a: local").unwrap();
+
+        let pattern = dir.path().join("*.yaml");
+        let result = DataLoader::new().load_multiple(&[pattern]).unwrap();
+        assert_eq!(result, json!({"a": "local", "b": "env", "c": "base"}));
+
+        let missing = dir.path().join("*.json");
+        let result = DataLoader::new().load_multiple(&[missing]);
+        assert!(matches!(result, Err(RenderError::DataFileRead { .. })));
+    }
+
+    #[test]
+    fn test_load_multiple_namespaced() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("profile.yaml"), "name: Ann").unwrap();
+        fs::write(dir.path().join("settings.yaml"), "name: prod").unwrap();
+        fs::write(dir.path().join("a=b.yaml"), "literal: true").unwrap();
+
+        let paths = [
+            format!("profile={}", dir.path().join("profile.yaml").display()),
+            format!("config.env={}", dir.path().join("settings.yaml").display()),
+            dir.path().join("a=b.yaml").display().to_string(),
+        ];
+        let result = DataLoader::new().load_multiple(&paths).unwrap();
+        assert_eq!(
+            result,
+            json!({
+                "profile": {"name": "Ann"},
+                "config": {"env": {"name": "prod"}},
+                "literal": true
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_exec() {
+        let loader = DataLoader::new();
+        assert_eq!(
+            loader
+                .load_exec(r#"printf '{"pods": [{"name": "web"}]}'"#)
+                .unwrap(),
+            json!({"pods": [{"name": "web"}]})
+        );
+        assert_eq!(
+            loader.load_exec("printf 'status: ok\\ncount: 2'").unwrap(),
+            json!({"status": "ok", "count": 2})
+        );
+
+        let err = loader.load_exec("echo boom >&2; exit 3").unwrap_err();
+        assert!(matches!(err, RenderError::DataFileParse { .. }));
+        assert!(err.to_string().contains("boom"), "{}", err);
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("small.yaml"), "a: 1").unwrap();
+        fs::write(dir.path().join("large.yaml"), "a: 1\nb: 2\nc: 3").unwrap();
+        let loader = DataLoader::new()
+            .with_source("vault", |_: &str| Ok(json!({"secret": true})))
+            .with_sandbox(Sandbox::new().max_file_size(8).network(false));
+
+        assert_eq!(
+            loader.load_file(dir.path().join("small.yaml")).unwrap(),
+            json!({"a": 1})
+        );
+        let err = loader.load_file(dir.path().join("large.yaml")).unwrap_err();
+        assert!(matches!(err, RenderError::DataFileRead { .. }));
+        assert!(err.to_string().contains("--max-file-size"), "{}", err);
+
+        let err = loader.load_file("vault://secret/app").unwrap_err();
+        assert!(err.to_string().contains("--no-network"), "{}", err);
+        let err = loader.load_exec("echo '{}'").unwrap_err();
+        assert!(matches!(err, RenderError::DataFileRead { .. }));
+        assert!(err.to_string().contains("--no-network"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_env() {
+        let env = DataLoader::load_env();
+        let vars = env.as_object().unwrap();
+        for (key, value) in std::env::vars() {
+            assert_eq!(vars.get(&key), Some(&Value::String(value)));
+        }
+    }
+
+    #[test]
+    fn test_load_selected_env() {
+        let patterns = [
+            glob::Pattern::new("CARGO_PKG_*").unwrap(),
+            glob::Pattern::new("CARGO_MANIFEST_DIR").unwrap(),
+        ];
+        let env = DataLoader::load_selected_env(&patterns);
+        let vars = env.as_object().unwrap();
+
+        assert!(vars.contains_key("CARGO_PKG_NAME"));
+        assert!(vars.contains_key("CARGO_MANIFEST_DIR"));
+        assert!(vars
+            .keys()
+            .all(|key| key.starts_with("CARGO_PKG_") || key == "CARGO_MANIFEST_DIR"));
+    }
+
+    #[test]
+    fn test_load_prefixed_env() {
+        let env = DataLoader::load_prefixed_env("CARGO_PKG_");
+        let vars = env.as_object().unwrap();
+
+        assert_eq!(
+            vars.get("name"),
+            Some(&Value::String(env!("CARGO_PKG_NAME").to_string()))
+        );
+        assert!(vars.keys().all(|key| *key == key.to_lowercase()));
+        assert!(!vars.contains_key("CARGO_MANIFEST_DIR"));
+    }
+
     #[test]
     fn test_load_multiple_yaml_and_json() {
         let mut file1 = NamedTempFile::with_suffix(".yaml").unwrap();
@@ -165,7 +1018,7 @@ mod tests {
         writeln!(file2, r#"{{"y": 20}}"#).unwrap();
 
         let paths = vec![file1.path(), file2.path()];
-        let result = DataLoader::load_multiple(&paths).unwrap();
+        let result = DataLoader::new().load_multiple(&paths).unwrap();
 
         assert_eq!(result, json!({"x": 10, "y": 20}));
     }
@@ -1,48 +1,172 @@
 use serde_json::Value;
 
+/// How arrays present in both values are combined
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ArrayMerge {
+    /// The later array replaces the earlier one
+    #[default]
+    Replace,
+    /// The later array is appended to the earlier one
+    Concat,
+    /// Objects with the same value for the field are merged; other items are appended
+    ByKey(String),
+}
+
+impl std::str::FromStr for ArrayMerge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(ArrayMerge::Replace),
+            "concat" => Ok(ArrayMerge::Concat),
+            _ => match s.strip_prefix("merge-by-key=") {
+                Some(field) if !field.is_empty() => Ok(ArrayMerge::ByKey(field.to_string())),
+                _ => Err(format!(
+                    "unknown array merge strategy '{}' (expected replace, concat or merge-by-key=<field>)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// How deep objects present in both values are merged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Objects are merged recursively
+    #[default]
+    Deep,
+    /// Top-level keys of the later value replace the earlier ones entirely
+    Shallow,
+}
+
+impl std::str::FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deep" => Ok(MergeStrategy::Deep),
+            "shallow" => Ok(MergeStrategy::Shallow),
+            _ => Err(format!(
+                "unknown merge strategy '{}' (expected deep or shallow)",
+                s
+            )),
+        }
+    }
+}
+
 /// Deep merge two JSON values
 /// Later values take precedence over earlier values (last-wins)
-/// Arrays are replaced entirely, not merged
-pub struct DataMerger;
+/// Arrays are replaced entirely unless another `ArrayMerge` strategy is configured
+#[derive(Debug, Clone, Default)]
+pub struct DataMerger {
+    strategy: MergeStrategy,
+    arrays: ArrayMerge,
+    null_deletes: bool,
+}
 
 impl DataMerger {
-    /// Merge `overlay` into `base`, modifying `base` in place
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether objects are merged recursively or only at the top level (default: deep)
+    pub fn with_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Make an explicit `null` in the overlay remove the key instead of setting it to null
+    pub fn with_null_deletes(mut self, enabled: bool) -> Self {
+        self.null_deletes = enabled;
+        self
+    }
+
+    /// Set how arrays present in both values are combined (default: replace)
+    pub fn with_array_merge(mut self, arrays: ArrayMerge) -> Self {
+        self.arrays = arrays;
+        self
+    }
+
+    /// Merge `overlay` into `base` with the default rules, modifying `base` in place
     ///
     /// Rules:
     /// - If both are objects: recursively merge keys (overlay wins on conflict)
     /// - If both are arrays: overlay completely replaces base
     /// - Otherwise: overlay replaces base
     pub fn merge(base: &mut Value, overlay: &Value) {
+        Self::default().merge_into(base, overlay)
+    }
+
+    /// Merge `overlay` into `base` with this merger's rules, modifying `base` in place
+    pub fn merge_into(&self, base: &mut Value, overlay: &Value) {
         match (base, overlay) {
             // Both are objects: deep merge
             (Value::Object(base_map), Value::Object(overlay_map)) => {
                 for (key, overlay_value) in overlay_map {
-                    if let Some(base_value) = base_map.get_mut(key) {
+                    if self.null_deletes && overlay_value.is_null() {
+                        base_map.remove(key);
+                    } else if self.strategy == MergeStrategy::Shallow {
+                        // Shallow: the whole top-level section is replaced
+                        base_map.insert(key.clone(), overlay_value.clone());
+                    } else if let Some(base_value) = base_map.get_mut(key) {
                         // Key exists in both: recursively merge
-                        Self::merge(base_value, overlay_value);
+                        self.merge_into(base_value, overlay_value);
                     } else {
                         // Key only in overlay: insert it
                         base_map.insert(key.clone(), overlay_value.clone());
                     }
                 }
             }
-            // Not both objects: overlay wins
+            // Both are arrays: combine them unless they are replaced
+            (Value::Array(base_items), Value::Array(overlay_items))
+                if self.arrays != ArrayMerge::Replace =>
+            {
+                self.merge_arrays(base_items, overlay_items);
+            }
+            // Otherwise: overlay wins
             (base, overlay) => {
                 *base = overlay.clone();
             }
         }
     }
 
+    fn merge_arrays(&self, base: &mut Vec<Value>, overlay: &[Value]) {
+        for item in overlay {
+            let existing = match &self.arrays {
+                ArrayMerge::ByKey(field) => item.get(field).and_then(|key| {
+                    base.iter_mut()
+                        .find(|existing| existing.get(field) == Some(key))
+                }),
+                _ => None,
+            };
+            match existing {
+                Some(existing) => self.merge_into(existing, item),
+                None => base.push(item.clone()),
+            }
+        }
+    }
+
+    /// Build a value that holds `value` at a dot-separated path, e.g. `user.name`
+    /// becomes `{"user": {"name": value}}`, ready to be merged over other data
+    pub fn at_path(path: &str, value: Value) -> Value {
+        path.rsplit('.').fold(value, |inner, key| {
+            let mut map = serde_json::Map::new();
+            map.insert(key.to_string(), inner);
+            Value::Object(map)
+        })
+    }
+
     /// Merge multiple values from left to right
     /// Returns the merged result
-    pub fn merge_multiple(values: Vec<Value>) -> Value {
+    pub fn merge_multiple(&self, values: Vec<Value>) -> Value {
         if values.is_empty() {
             return Value::Object(serde_json::Map::new());
         }
 
         let mut result = values[0].clone();
         for value in values.iter().skip(1) {
-            Self::merge(&mut result, value);
+            self.merge_into(&mut result, value);
         }
         result
     }
@@ -53,6 +177,18 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn test_at_path() {
+        assert_eq!(
+            DataMerger::at_path("name", json!("Ann")),
+            json!({"name": "Ann"})
+        );
+
+        let mut base = json!({"user": {"name": "Bob", "age": 25}});
+        DataMerger::merge(&mut base, &DataMerger::at_path("user.name", json!("Ann")));
+        assert_eq!(base, json!({"user": {"name": "Ann", "age": 25}}));
+    }
+
     #[test]
     fn test_merge_simple() {
         let mut base = json!({ "a": 1 });
@@ -104,6 +240,85 @@ mod tests {
         assert_eq!(base, json!({ "items": [4, 5] }));
     }
 
+    #[test]
+    fn test_merge_array_concat() {
+        let merger = DataMerger::new().with_array_merge(ArrayMerge::Concat);
+        let mut base = json!({ "examples": [1, 2], "meta": {"tags": ["a"]} });
+        let overlay = json!({ "examples": [3], "meta": {"tags": ["b"]} });
+        merger.merge_into(&mut base, &overlay);
+        assert_eq!(
+            base,
+            json!({ "examples": [1, 2, 3], "meta": {"tags": ["a", "b"]} })
+        );
+    }
+
+    #[test]
+    fn test_merge_array_by_key() {
+        let merger = DataMerger::new().with_array_merge(ArrayMerge::ByKey("id".to_string()));
+        let mut base = json!({ "examples": [
+            {"id": "greet", "input": "hi", "output": "hello"},
+            {"id": "bye", "input": "bye", "output": "goodbye"},
+        ]});
+        let overlay = json!({ "examples": [
+            {"id": "bye", "output": "see you"},
+            {"id": "thanks", "input": "thanks", "output": "welcome"},
+            "no key",
+        ]});
+        merger.merge_into(&mut base, &overlay);
+        assert_eq!(
+            base,
+            json!({ "examples": [
+                {"id": "greet", "input": "hi", "output": "hello"},
+                {"id": "bye", "input": "bye", "output": "see you"},
+                {"id": "thanks", "input": "thanks", "output": "welcome"},
+                "no key",
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_merge_shallow() {
+        let merger = DataMerger::new().with_strategy(MergeStrategy::Shallow);
+        let mut base = json!({ "model": {"name": "a", "temperature": 0.2}, "keep": 1 });
+        let overlay = json!({ "model": {"name": "b"} });
+        merger.merge_into(&mut base, &overlay);
+        assert_eq!(base, json!({ "model": {"name": "b"}, "keep": 1 }));
+
+        assert_eq!("shallow".parse(), Ok(MergeStrategy::Shallow));
+        assert_eq!("deep".parse(), Ok(MergeStrategy::Deep));
+        assert!("none".parse::<MergeStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_merge_null_deletes() {
+        let mut base =
+            json!({ "user": {"name": "Alice", "email": "a@example.com"}, "debug": true });
+        let overlay = json!({ "user": {"email": null}, "debug": null, "extra": null });
+
+        let mut kept = base.clone();
+        DataMerger::merge(&mut kept, &overlay);
+        assert_eq!(
+            kept,
+            json!({ "user": {"name": "Alice", "email": null}, "debug": null, "extra": null })
+        );
+
+        let merger = DataMerger::new().with_null_deletes(true);
+        merger.merge_into(&mut base, &overlay);
+        assert_eq!(base, json!({ "user": {"name": "Alice"} }));
+    }
+
+    #[test]
+    fn test_parse_array_merge() {
+        assert_eq!("replace".parse(), Ok(ArrayMerge::Replace));
+        assert_eq!("concat".parse(), Ok(ArrayMerge::Concat));
+        assert_eq!(
+            "merge-by-key=name".parse(),
+            Ok(ArrayMerge::ByKey("name".to_string()))
+        );
+        assert!("merge-by-key=".parse::<ArrayMerge>().is_err());
+        assert!("append".parse::<ArrayMerge>().is_err());
+    }
+
     #[test]
     fn test_merge_type_change() {
         let mut base = json!({ "value": "string" });
@@ -151,19 +366,19 @@ mod tests {
 
     #[test]
     fn test_merge_multiple_empty() {
-        let result = DataMerger::merge_multiple(vec![]);
+        let result = DataMerger::new().merge_multiple(vec![]);
         assert_eq!(result, json!({}));
     }
 
     #[test]
     fn test_merge_multiple_single() {
-        let result = DataMerger::merge_multiple(vec![json!({"a": 1})]);
+        let result = DataMerger::new().merge_multiple(vec![json!({"a": 1})]);
         assert_eq!(result, json!({"a": 1}));
     }
 
     #[test]
     fn test_merge_multiple_three() {
-        let result = DataMerger::merge_multiple(vec![
+        let result = DataMerger::new().merge_multiple(vec![
             json!({"a": 1, "b": 2}),
             json!({"b": 3, "c": 4}),
             json!({"c": 5, "d": 6}),
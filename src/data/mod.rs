@@ -1,5 +1,9 @@
 pub mod loader;
 pub mod merger;
+pub mod schema;
+pub mod source;
 
-pub use loader::DataLoader;
-pub use merger::DataMerger;
+pub use loader::{DataFormat, DataLoader, ENV_VAR_PREFIX};
+pub use merger::{ArrayMerge, DataMerger, MergeStrategy};
+pub use schema::SchemaValidator;
+pub use source::{DataParser, DataSource};
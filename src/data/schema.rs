@@ -0,0 +1,97 @@
+use crate::error::RenderError;
+use serde_json::Value;
+use std::path::Path;
+
+use super::loader::DataLoader;
+
+/// Validates merged data against a JSON Schema (the schema file may be JSON or YAML)
+pub struct SchemaValidator;
+
+impl SchemaValidator {
+    /// Check `data` against the schema at `schema_path`, reporting every violation
+    pub fn validate<P: AsRef<Path>>(schema_path: P, data: &Value) -> Result<(), RenderError> {
+        let schema_path = schema_path.as_ref();
+        let schema = DataLoader::new().load_file(schema_path)?;
+
+        let validator =
+            jsonschema::validator_for(&schema).map_err(|e| RenderError::DataFileParse {
+                path: schema_path.display().to_string(),
+                source: anyhow::anyhow!("invalid schema: {}", e),
+            })?;
+
+        let violations: Vec<String> = validator
+            .iter_errors(data)
+            .map(|e| {
+                let path = e.instance_path().to_string();
+                if path.is_empty() {
+                    e.to_string()
+                } else {
+                    format!("{}: {}", path, e)
+                }
+            })
+            .collect();
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(RenderError::SchemaValidation {
+                schema: schema_path.display().to_string(),
+                violations,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn schema_file(dir: &Path) -> std::path::PathBuf {
+        let path = dir.join("schema.yaml");
+        fs::write(
+            &path,
+            "type: object\nrequired: [name]\nproperties:\n  age:\n    type: integer",
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        let dir = tempdir().unwrap();
+        let schema = schema_file(dir.path());
+        assert!(SchemaValidator::validate(&schema, &json!({"name": "Ann", "age": 3})).is_ok());
+    }
+
+    #[test]
+    fn test_validate_lists_violations() {
+        let dir = tempdir().unwrap();
+        let schema = schema_file(dir.path());
+
+        match SchemaValidator::validate(&schema, &json!({"age": "old"})) {
+            Err(RenderError::SchemaValidation { violations, .. }) => {
+                assert_eq!(
+                    violations,
+                    vec![
+                        "\"name\" is a required property".to_string(),
+                        "/age: \"old\" is not of type \"integer\"".to_string(),
+                    ]
+                );
+            }
+            other => panic!("Expected SchemaValidation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        let dir = tempdir().unwrap();
+        let schema = dir.path().join("schema.json");
+        fs::write(&schema, r#"{"type": 12}"#).unwrap();
+
+        let result = SchemaValidator::validate(&schema, &json!({}));
+        assert!(matches!(result, Err(RenderError::DataFileParse { .. })));
+    }
+}
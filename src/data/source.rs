@@ -0,0 +1,39 @@
+use serde_json::Value;
+
+/// A provider of data addressed by a URL-like location (`vault://secret/app`)
+///
+/// Register one with [`DataLoader::with_source`](super::DataLoader::with_source) under its
+/// scheme; the loader then hands it everything after `scheme://` of a matching data
+/// argument, and the value it returns is merged like a data file. Closures taking the
+/// location implement this trait.
+pub trait DataSource {
+    fn load(&self, location: &str) -> Result<Value, anyhow::Error>;
+}
+
+impl<F> DataSource for F
+where
+    F: Fn(&str) -> Result<Value, anyhow::Error>,
+{
+    fn load(&self, location: &str) -> Result<Value, anyhow::Error> {
+        self(location)
+    }
+}
+
+/// A parser for data files of a format the loader does not know (`.toml`, `.ini`, ...)
+///
+/// Register one with [`DataLoader::with_parser`](super::DataLoader::with_parser) under a
+/// file extension; files with that extension, given directly or found in a data directory,
+/// are read and their content handed to it. Closures taking the content implement this
+/// trait.
+pub trait DataParser {
+    fn parse(&self, content: &str) -> Result<Value, anyhow::Error>;
+}
+
+impl<F> DataParser for F
+where
+    F: Fn(&str) -> Result<Value, anyhow::Error>,
+{
+    fn parse(&self, content: &str) -> Result<Value, anyhow::Error> {
+        self(content)
+    }
+}
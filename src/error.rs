@@ -2,15 +2,42 @@ use std::fmt;
 
 /// Exit codes as defined in the specification
 pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_OUTPUT_DIFFERS: i32 = 1;
 pub const EXIT_USAGE_ERROR: i32 = 2;
 pub const EXIT_TEMPLATE_ERROR: i32 = 3;
 pub const EXIT_DATA_ERROR: i32 = 4;
 pub const EXIT_INCLUDE_ERROR: i32 = 5;
 pub const EXIT_VARIABLE_ERROR: i32 = 6;
 pub const EXIT_CIRCULAR_OR_DEPTH_ERROR: i32 = 7;
+pub const EXIT_DELIMITER_ERROR: i32 = 8;
+
+/// How errors and warnings are written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// A machine-readable `ERROR code=...` line followed by the message
+    #[default]
+    Text,
+    /// One JSON object per diagnostic (see `RenderError::to_json`)
+    Json,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown error format '{}' (expected text or json)",
+                s
+            )),
+        }
+    }
+}
 
 /// Location information for error reporting
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Location {
     pub file: String,
     pub line: usize,
@@ -66,14 +93,17 @@ pub enum RenderError {
     },
 
     #[error("Failed to parse data file '{path}': {source}")]
-    DataFileParse {
-        path: String,
-        source: anyhow::Error,
-    },
+    DataFileParse { path: String, source: anyhow::Error },
 
     #[error("Data merge error: {0}")]
     DataMerge(String),
 
+    #[error("Data does not match schema '{schema}':\n  - {}", violations.join("\n  - "))]
+    SchemaValidation {
+        schema: String,
+        violations: Vec<String>,
+    },
+
     // Template loading errors
     #[error("Failed to read template file '{path}': {source}")]
     TemplateFileRead {
@@ -81,31 +111,73 @@ pub enum RenderError {
         source: std::io::Error,
     },
 
+    #[error("Template syntax error at {location}: {message}")]
+    TemplateSyntax { message: String, location: Location },
+
+    /// An unclosed `{{`, a stray `}}` or a broken include tag, which would otherwise be
+    /// rendered as text
+    #[error("Malformed delimiter at {location}: {message}")]
+    MalformedDelimiter { message: String, location: Location },
+
     // Variable errors
     #[error("Undefined variable '{name}' at {location}")]
     UndefinedVariable { name: String, location: Location },
 
+    /// Every undefined variable of a strict render that found more than one
+    #[error("{} undefined variables: {}", .undefined.len(), list_undefined(.undefined))]
+    UndefinedVariables { undefined: Vec<(String, Location)> },
+
     #[error("Variable resolution error at {location}: {message}")]
     VariableResolution { message: String, location: Location },
 
-    // Include errors
-    #[error("Failed to read included file '{path}': {source}")]
+    // Include errors. `included_from` is the chain of include directives that led to the
+    // error, innermost first
+    #[error("Failed to read included file '{path}': {source}{}", list_included_from(.included_from))]
     IncludeFileRead {
         path: String,
         source: std::io::Error,
+        included_from: Vec<Location>,
+    },
+
+    #[error("Include not found: '{path}' referenced from {from}{}", list_included_from(.included_from))]
+    IncludeNotFound {
+        path: String,
+        from: String,
+        included_from: Vec<Location>,
+    },
+
+    #[error("Path traversal attempt detected: '{path}' is outside root directory{}", list_included_from(.included_from))]
+    PathTraversal {
+        path: String,
+        included_from: Vec<Location>,
+    },
+
+    #[error("Circular include detected: {path}{}", list_included_from(.included_from))]
+    CircularInclude {
+        path: String,
+        included_from: Vec<Location>,
+    },
+
+    #[error("Include depth limit exceeded (max: {max_depth}){}", list_included_from(.included_from))]
+    IncludeDepthExceeded {
+        max_depth: usize,
+        included_from: Vec<Location>,
     },
 
-    #[error("Include not found: '{path}' referenced from {from}")]
-    IncludeNotFound { path: String, from: String },
+    #[error("Template reads more than {max_includes} included files (--max-includes)")]
+    TooManyIncludes { max_includes: usize },
 
-    #[error("Path traversal attempt detected: '{path}' is outside root directory")]
-    PathTraversal { path: String },
+    #[error("Rendered output exceeds the maximum size of {limit} bytes (--max-output-size)")]
+    OutputTooLarge { limit: usize },
 
-    #[error("Circular include detected: {path}")]
-    CircularInclude { path: String },
+    #[error("Rendering did not finish within {timeout:?} (--timeout)")]
+    Timeout { timeout: std::time::Duration },
 
-    #[error("Include depth limit exceeded (max: {max_depth})")]
-    IncludeDepthExceeded { max_depth: usize },
+    #[error("{count} warning(s) treated as errors (--warn-as-error)")]
+    WarningsAsErrors { count: usize },
+
+    #[error("Output is out of date: {}", paths.join(", "))]
+    OutputOutOfDate { paths: Vec<String> },
 
     // Generic I/O error
     #[error("I/O error: {0}")]
@@ -121,21 +193,182 @@ impl RenderError {
     pub fn exit_code(&self) -> i32 {
         match self {
             RenderError::Usage(_) => EXIT_USAGE_ERROR,
-            RenderError::TemplateFileRead { .. } => EXIT_TEMPLATE_ERROR,
-            RenderError::DataFileRead { .. } | RenderError::DataFileParse { .. } | RenderError::DataMerge(_) => {
-                EXIT_DATA_ERROR
+            RenderError::TemplateFileRead { .. } | RenderError::TemplateSyntax { .. } => {
+                EXIT_TEMPLATE_ERROR
             }
+            RenderError::DataFileRead { .. }
+            | RenderError::DataFileParse { .. }
+            | RenderError::DataMerge(_)
+            | RenderError::SchemaValidation { .. } => EXIT_DATA_ERROR,
             RenderError::IncludeFileRead { .. }
             | RenderError::IncludeNotFound { .. }
             | RenderError::PathTraversal { .. } => EXIT_INCLUDE_ERROR,
-            RenderError::UndefinedVariable { .. } | RenderError::VariableResolution { .. } => {
-                EXIT_VARIABLE_ERROR
+            RenderError::UndefinedVariable { .. }
+            | RenderError::UndefinedVariables { .. }
+            | RenderError::VariableResolution { .. }
+            | RenderError::WarningsAsErrors { .. } => EXIT_VARIABLE_ERROR,
+            RenderError::CircularInclude { .. }
+            | RenderError::IncludeDepthExceeded { .. }
+            | RenderError::TooManyIncludes { .. }
+            | RenderError::OutputTooLarge { .. }
+            | RenderError::Timeout { .. } => EXIT_CIRCULAR_OR_DEPTH_ERROR,
+            RenderError::MalformedDelimiter { .. } => EXIT_DELIMITER_ERROR,
+            RenderError::OutputOutOfDate { .. } => EXIT_OUTPUT_DIFFERS,
+            RenderError::Io(_) => EXIT_INCLUDE_ERROR,
+        }
+    }
+
+    /// Stable identifier of the kind of error, as used by `--error-format json`
+    pub fn code(&self) -> &'static str {
+        match self {
+            RenderError::DataFileRead { .. } => "DATA_READ_ERROR",
+            RenderError::DataFileParse { .. } => "DATA_PARSE_ERROR",
+            RenderError::DataMerge(_) => "DATA_MERGE_ERROR",
+            RenderError::SchemaValidation { .. } => "SCHEMA_VIOLATION",
+            RenderError::TemplateFileRead { .. } => "TEMPLATE_READ_ERROR",
+            RenderError::TemplateSyntax { .. } => "SYNTAX_ERROR",
+            RenderError::MalformedDelimiter { .. } => "MALFORMED_DELIMITER",
+            RenderError::UndefinedVariable { .. } | RenderError::UndefinedVariables { .. } => {
+                "UNDEFINED_VAR"
             }
-            RenderError::CircularInclude { .. } | RenderError::IncludeDepthExceeded { .. } => {
-                EXIT_CIRCULAR_OR_DEPTH_ERROR
+            RenderError::VariableResolution { .. } => "VARIABLE_ERROR",
+            RenderError::IncludeFileRead { .. } => "INCLUDE_READ_ERROR",
+            RenderError::IncludeNotFound { .. } => "INCLUDE_NOT_FOUND",
+            RenderError::PathTraversal { .. } => "PATH_TRAVERSAL",
+            RenderError::CircularInclude { .. } => "CIRCULAR_INCLUDE",
+            RenderError::IncludeDepthExceeded { .. } => "DEPTH_EXCEEDED",
+            RenderError::TooManyIncludes { .. } => "TOO_MANY_INCLUDES",
+            RenderError::OutputTooLarge { .. } => "OUTPUT_TOO_LARGE",
+            RenderError::Timeout { .. } => "TIMEOUT",
+            RenderError::WarningsAsErrors { .. } => "WARNINGS_AS_ERRORS",
+            RenderError::OutputOutOfDate { .. } => "OUTPUT_OUT_OF_DATE",
+            RenderError::Io(_) => "IO_ERROR",
+            RenderError::Usage(_) => "USAGE_ERROR",
+        }
+    }
+
+    /// Position in a template the error points at, if it has one
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            RenderError::TemplateSyntax { location, .. }
+            | RenderError::MalformedDelimiter { location, .. }
+            | RenderError::UndefinedVariable { location, .. }
+            | RenderError::VariableResolution { location, .. } => Some(location),
+            RenderError::UndefinedVariables { undefined } => {
+                undefined.first().map(|(_, location)| location)
             }
-            RenderError::Io(_) => EXIT_INCLUDE_ERROR,
+            _ => None,
+        }
+    }
+
+    /// The file the error is about, if any
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            RenderError::DataFileRead { path, .. }
+            | RenderError::DataFileParse { path, .. }
+            | RenderError::TemplateFileRead { path, .. }
+            | RenderError::IncludeFileRead { path, .. }
+            | RenderError::CircularInclude { path, .. } => Some(path),
+            RenderError::SchemaValidation { schema, .. } => Some(schema),
+            // The file containing the broken include directive
+            RenderError::IncludeNotFound { from, .. } => Some(from),
+            _ => self.location().map(|location| location.file.as_str()),
+        }
+    }
+
+    /// The include directives that led to an include error, innermost first
+    pub fn included_from(&self) -> &[Location] {
+        match self {
+            RenderError::IncludeFileRead { included_from, .. }
+            | RenderError::IncludeNotFound { included_from, .. }
+            | RenderError::PathTraversal { included_from, .. }
+            | RenderError::CircularInclude { included_from, .. }
+            | RenderError::IncludeDepthExceeded { included_from, .. } => included_from,
+            _ => &[],
+        }
+    }
+
+    /// Record the include directives that led to an include error, innermost first,
+    /// unless a deeper include already did
+    pub(crate) fn with_included_from(mut self, chain: impl FnOnce() -> Vec<Location>) -> Self {
+        match &mut self {
+            RenderError::IncludeFileRead { included_from, .. }
+            | RenderError::IncludeNotFound { included_from, .. }
+            | RenderError::PathTraversal { included_from, .. }
+            | RenderError::CircularInclude { included_from, .. }
+            | RenderError::IncludeDepthExceeded { included_from, .. }
+                if included_from.is_empty() =>
+            {
+                *included_from = chain();
+            }
+            _ => {}
         }
+        self
+    }
+
+    /// Format error as a JSON diagnostic with stable fields
+    ///
+    /// `file`, `line` and `column` are null when the error has no such position.
+    pub fn to_json(&self) -> serde_json::Value {
+        let position = self.location().filter(|l| l.line > 0);
+        serde_json::json!({
+            "severity": "error",
+            "code": self.code(),
+            "file": self.file(),
+            "line": position.map(|l| l.line),
+            "column": position.map(|l| l.column),
+            "message": self.to_string(),
+        })
+    }
+
+    /// The line of `source` (the content of [`file`](Self::file)) the error points at,
+    /// with a `^` caret under the offending tag, in the style of rustc:
+    ///
+    /// ```text
+    ///   --> template.txt:12:7
+    ///    |
+    /// 12 | Hello {{ user.email }}
+    ///    |          ^^^^^^^^^^
+    /// ```
+    ///
+    /// None when the error has no position, or the position is not in `source`.
+    pub fn snippet(&self, source: &str) -> Option<String> {
+        let location = self.location().filter(|l| l.line > 0 && l.column > 0)?;
+        let line = source.lines().nth(location.line - 1)?;
+        let start = location.column - 1;
+        let rest = line.get(start..)?;
+
+        // Underline the whole tag, or just the undefined variable in it
+        let mut underline = start..start + rest.chars().next().map_or(1, char::len_utf8);
+        if rest.starts_with("}}") {
+            underline = start..start + 2;
+        }
+        if let Some(inner) = rest.strip_prefix("{{") {
+            // An unclosed `{{` (not closed before the next one) is underlined on its own
+            let next = inner.find("{{").map_or(rest.len(), |next| next + 2);
+            let end = match rest.find("}}") {
+                Some(close) if close < next => close + 2,
+                _ => 2,
+            };
+            underline = start..start + end;
+            if let RenderError::UndefinedVariable { name, .. } = self {
+                if let Some(at) = line[underline.clone()].find(name.as_str()) {
+                    underline = underline.start + at..underline.start + at + name.len();
+                }
+            }
+        }
+
+        // Keep tabs so the caret lines up with the text above it
+        let indent: String = line[..underline.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(line[underline].chars().count().max(1));
+        let number = location.line.to_string();
+        let gutter = " ".repeat(number.len());
+        Some(format!(
+            "{gutter}--> {location}\n{gutter} |\n{number} | {line}\n{gutter} | {indent}{carets}"
+        ))
     }
 
     /// Format error for machine-readable output
@@ -147,22 +380,231 @@ impl RenderError {
                     name, location.file, location.line, location.column
                 )
             }
-            RenderError::IncludeNotFound { path, from } => {
+            RenderError::UndefinedVariables { undefined } => undefined
+                .iter()
+                .map(|(name, location)| {
+                    RenderError::UndefinedVariable {
+                        name: name.clone(),
+                        location: location.clone(),
+                    }
+                    .format_machine_readable()
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            RenderError::TemplateSyntax { message, location } => {
+                format!(
+                    "ERROR code=SYNTAX_ERROR template=\"{}\" line={} col={} message=\"{}\"",
+                    location.file, location.line, location.column, message
+                )
+            }
+            RenderError::MalformedDelimiter { message, location } => {
+                format!(
+                    "ERROR code=MALFORMED_DELIMITER template=\"{}\" line={} col={} message=\"{}\"",
+                    location.file, location.line, location.column, message
+                )
+            }
+            RenderError::IncludeNotFound {
+                path,
+                from,
+                included_from,
+            } => {
+                format!(
+                    "ERROR code=INCLUDE_NOT_FOUND file=\"{}\" from=\"{}\"{}",
+                    path,
+                    from,
+                    included_from_field(included_from)
+                )
+            }
+            RenderError::CircularInclude {
+                path,
+                included_from,
+            } => {
+                format!(
+                    "ERROR code=CIRCULAR_INCLUDE path=\"{}\"{}",
+                    path,
+                    included_from_field(included_from)
+                )
+            }
+            RenderError::SchemaValidation { schema, violations } => {
+                format!(
+                    "ERROR code=SCHEMA_VIOLATION schema=\"{}\" count={}",
+                    schema,
+                    violations.len()
+                )
+            }
+            RenderError::PathTraversal {
+                path,
+                included_from,
+            } => {
+                format!(
+                    "ERROR code=PATH_TRAVERSAL path=\"{}\"{}",
+                    path,
+                    included_from_field(included_from)
+                )
+            }
+            RenderError::IncludeDepthExceeded {
+                max_depth,
+                included_from,
+            } => {
                 format!(
-                    "ERROR code=INCLUDE_NOT_FOUND file=\"{}\" from=\"{}\"",
-                    path, from
+                    "ERROR code=DEPTH_EXCEEDED max={}{}",
+                    max_depth,
+                    included_from_field(included_from)
                 )
             }
-            RenderError::CircularInclude { path } => {
-                format!("ERROR code=CIRCULAR_INCLUDE path=\"{}\"", path)
+            RenderError::TooManyIncludes { max_includes } => {
+                format!("ERROR code=TOO_MANY_INCLUDES max={}", max_includes)
             }
-            RenderError::PathTraversal { path } => {
-                format!("ERROR code=PATH_TRAVERSAL path=\"{}\"", path)
+            RenderError::OutputTooLarge { limit } => {
+                format!("ERROR code=OUTPUT_TOO_LARGE max={}", limit)
             }
-            RenderError::IncludeDepthExceeded { max_depth } => {
-                format!("ERROR code=DEPTH_EXCEEDED max={}", max_depth)
+            RenderError::OutputOutOfDate { paths } => {
+                format!("ERROR code=OUTPUT_OUT_OF_DATE count={}", paths.len())
             }
             _ => format!("ERROR: {}", self),
         }
     }
 }
+
+/// `, included from file:line` for each include directive that led to an include error
+fn list_included_from(included_from: &[Location]) -> String {
+    included_from
+        .iter()
+        .map(|location| format!(", included from {}:{}", location.file, location.line))
+        .collect()
+}
+
+/// ` included_from="file:line,..."` for the machine-readable line of an include error, or
+/// nothing when it happened in the template itself
+fn included_from_field(included_from: &[Location]) -> String {
+    if included_from.is_empty() {
+        return String::new();
+    }
+    let chain: Vec<String> = included_from
+        .iter()
+        .map(|location| format!("{}:{}", location.file, location.line))
+        .collect();
+    format!(" included_from=\"{}\"", chain.join(","))
+}
+
+/// `'name' at file:line:column` for each undefined variable
+fn list_undefined(undefined: &[(String, Location)]) -> String {
+    undefined
+        .iter()
+        .map(|(name, location)| format!("'{}' at {}", name, location))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let error = RenderError::UndefinedVariable {
+            name: "user.name".to_string(),
+            location: Location::new("main.txt".to_string(), 3, 7),
+        };
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({
+                "severity": "error",
+                "code": "UNDEFINED_VAR",
+                "file": "main.txt",
+                "line": 3,
+                "column": 7,
+                "message": "Undefined variable 'user.name' at main.txt:3:7",
+            })
+        );
+
+        let error = RenderError::Usage("bad option".to_string());
+        let json = error.to_json();
+        assert_eq!(json["code"], "USAGE_ERROR");
+        assert!(json["file"].is_null() && json["line"].is_null() && json["column"].is_null());
+    }
+
+    #[test]
+    fn test_undefined_variables() {
+        let error = RenderError::UndefinedVariables {
+            undefined: vec![
+                ("a".to_string(), Location::new("main.txt".to_string(), 1, 1)),
+                ("b".to_string(), Location::new("part.txt".to_string(), 2, 5)),
+            ],
+        };
+        assert_eq!(
+            error.to_string(),
+            "2 undefined variables: 'a' at main.txt:1:1, 'b' at part.txt:2:5"
+        );
+        assert_eq!(
+            error.format_machine_readable(),
+            "ERROR code=UNDEFINED_VAR var=\"a\" template=\"main.txt\" line=1 col=1\n\
+             ERROR code=UNDEFINED_VAR var=\"b\" template=\"part.txt\" line=2 col=5"
+        );
+        assert_eq!(error.exit_code(), EXIT_VARIABLE_ERROR);
+    }
+
+    #[test]
+    fn test_included_from() {
+        let error = RenderError::PathTraversal {
+            path: "../secret.txt".to_string(),
+            included_from: Vec::new(),
+        }
+        .with_included_from(|| {
+            vec![
+                Location::new("a.txt".to_string(), 3, 1),
+                Location::new("main.txt".to_string(), 10, 5),
+            ]
+        });
+        assert_eq!(
+            error.to_string(),
+            "Path traversal attempt detected: '../secret.txt' is outside root directory, \
+             included from a.txt:3, included from main.txt:10"
+        );
+        assert_eq!(
+            error.format_machine_readable(),
+            "ERROR code=PATH_TRAVERSAL path=\"../secret.txt\" included_from=\"a.txt:3,main.txt:10\""
+        );
+
+        // The innermost include that failed records the chain; outer ones keep it
+        let error = error.with_included_from(|| vec![Location::new("x.txt".to_string(), 1, 1)]);
+        assert_eq!(error.included_from().len(), 2);
+        let error = RenderError::Usage("bad option".to_string())
+            .with_included_from(|| vec![Location::new("x.txt".to_string(), 1, 1)]);
+        assert!(error.included_from().is_empty());
+    }
+
+    #[test]
+    fn test_snippet() {
+        let source = "# Title\n\nHello {{ user.email }}!\n";
+        let error = RenderError::UndefinedVariable {
+            name: "user.email".to_string(),
+            location: Location::new("main.txt".to_string(), 3, 7),
+        };
+        assert_eq!(
+            error.snippet(source).unwrap(),
+            " --> main.txt:3:7\n  |\n3 | Hello {{ user.email }}!\n  |          ^^^^^^^^^^"
+        );
+
+        let error = RenderError::TemplateSyntax {
+            message: "unclosed block".to_string(),
+            location: Location::new("main.txt".to_string(), 2, 4),
+        };
+        let snippet = error.snippet("x\n\tö{{#if a}}").unwrap();
+        assert!(
+            snippet.ends_with("2 | \tö{{#if a}}\n  | \t ^^^^^^^^^"),
+            "{}",
+            snippet
+        );
+
+        // Positions outside the source (or errors without one) have no snippet
+        let error = RenderError::TemplateSyntax {
+            message: "unclosed block".to_string(),
+            location: Location::new("main.txt".to_string(), 5, 1),
+        };
+        assert!(error.snippet(source).is_none());
+        assert!(RenderError::Usage("bad".to_string())
+            .snippet(source)
+            .is_none());
+    }
+}
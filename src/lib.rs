@@ -0,0 +1,31 @@
+//! render-prompt as a library: the template engine behind the `rp` command
+//!
+//! Load and merge data with [`DataLoader`] and [`DataMerger`], then render templates with
+//! [`TemplateEngine`]. Every failure is a [`RenderError`], whose
+//! [`exit_code`](RenderError::exit_code) is the one `rp` exits with.
+//!
+//! ```
+//! use render_prompt::TemplateEngine;
+//! use serde_json::json;
+//! use std::path::Path;
+//!
+//! let engine = TemplateEngine::new(".".into(), 20, true, false);
+//! let output = engine
+//!     .render_source("Hello, {{ user.name }}!", Path::new("greeting.txt"), &json!({
+//!         "user": { "name": "Alice" }
+//!     }))
+//!     .unwrap();
+//! assert_eq!(output, "Hello, Alice!");
+//! ```
+
+pub mod data;
+pub mod error;
+pub mod sandbox;
+pub mod template;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use data::{DataLoader, DataMerger};
+pub use error::RenderError;
+pub use sandbox::Sandbox;
+pub use template::TemplateEngine;
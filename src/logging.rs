@@ -0,0 +1,206 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::color;
+use crate::error::ErrorFormat;
+use crate::template::TRACE_TARGET;
+
+/// Fields locating a diagnostic, part of the JSON format but not shown in text form
+const POSITION_FIELDS: [&str; 4] = ["code", "file", "line", "column"];
+
+/// Install the stderr logger for the requested verbosity
+///
+/// Warnings are shown by default, `--quiet` hides them, and each `-v` adds a level of
+/// detail (info, then debug, then trace). The include and substitution trace of a render
+/// is only shown with `--trace`, whatever the verbosity.
+pub fn init(verbose: u8, quiet: bool, trace: bool, format: ErrorFormat) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::ERROR,
+        (false, 0) => Level::WARN,
+        (false, 1) => Level::INFO,
+        (false, 2) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+    let output = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .event_format(Diagnostics { format })
+        .with_filter(filter_fn(move |metadata| {
+            if metadata.target() == TRACE_TARGET {
+                trace
+            } else {
+                *metadata.level() <= level
+            }
+        }));
+    // Warnings are counted even when --quiet hides them, for --warn-as-error
+    let counter = WarningCounter.with_filter(LevelFilter::WARN);
+    tracing_subscriber::registry()
+        .with(output)
+        .with(counter)
+        .init();
+}
+
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of warnings emitted since the last call
+pub fn take_warnings() -> usize {
+    WARNINGS.swap(0, Ordering::Relaxed)
+}
+
+struct WarningCounter;
+
+impl<S: Subscriber> Layer<S> for WarningCounter {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() == Level::WARN {
+            WARNINGS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// One line per event
+///
+/// Text: `Warning: message` for warnings (as before logging existed) and
+/// `info: message key=value` for diagnostics. JSON: an object with the same stable fields
+/// as errors (`severity`, `code`, `file`, `line`, `column`, `message`).
+struct Diagnostics {
+    format: ErrorFormat,
+}
+
+impl<S, N> FormatEvent<S, N> for Diagnostics
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let level = *event.metadata().level();
+
+        match self.format {
+            ErrorFormat::Text if event.metadata().target() == TRACE_TARGET => {
+                writeln!(writer, "trace: {}", fields.trace_line())
+            }
+            ErrorFormat::Text => {
+                let prefix = match level {
+                    Level::ERROR => color::error("Error"),
+                    Level::WARN => color::warning("Warning"),
+                    Level::INFO => "info".to_string(),
+                    Level::DEBUG => "debug".to_string(),
+                    Level::TRACE => "trace".to_string(),
+                };
+                write!(writer, "{}: {}", prefix, fields.message)?;
+                for (name, value) in &fields.values {
+                    if POSITION_FIELDS.contains(&name.as_str()) {
+                        continue;
+                    }
+                    match value {
+                        Value::String(s) => write!(writer, " {}={}", name, s)?,
+                        other => write!(writer, " {}={}", name, other)?,
+                    }
+                }
+                writeln!(writer)
+            }
+            ErrorFormat::Json => {
+                let severity = match level {
+                    Level::ERROR => "error",
+                    Level::WARN => "warning",
+                    Level::INFO => "info",
+                    Level::DEBUG => "debug",
+                    Level::TRACE => "trace",
+                };
+                let mut object = Map::new();
+                object.insert("severity".to_string(), severity.into());
+                for name in POSITION_FIELDS {
+                    let value = fields.values.get(name).cloned().unwrap_or(Value::Null);
+                    object.insert(name.to_string(), value);
+                }
+                object.insert("message".to_string(), fields.message.into());
+                for (name, value) in fields.values {
+                    object.entry(name).or_insert(value);
+                }
+                writeln!(writer, "{}", Value::Object(object))
+            }
+        }
+    }
+}
+
+/// The message and other fields of an event
+#[derive(Default)]
+struct Fields {
+    message: String,
+    values: Map<String, Value>,
+}
+
+impl Fields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.values.insert(field.name().to_string(), value);
+    }
+
+    fn text(&self, name: &str) -> String {
+        match self.values.get(name) {
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// A trace event in text form: an included file, indented by its depth, or a
+    /// substitution with where its tag is
+    fn trace_line(&self) -> String {
+        match self.values.get("depth").and_then(Value::as_u64) {
+            Some(depth) => format!("{}{}", "  ".repeat(depth as usize), self.text("path")),
+            None => format!(
+                "{{{{ {} }}}} → {} at {}:{}:{}",
+                self.text("expression"),
+                self.text("value"),
+                self.text("file"),
+                self.text("line"),
+                self.text("column")
+            ),
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.insert(field, value.into());
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.insert(field, format!("{:?}", value).into());
+        }
+    }
+}
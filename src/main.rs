@@ -1,74 +1,1093 @@
 mod cli;
-mod data;
-mod error;
-mod template;
+mod color;
+mod logging;
+mod manifest;
+mod serve;
+mod watch;
 
 use clap::Parser;
-use cli::Cli;
-use error::{RenderError, EXIT_SUCCESS};
+use cli::{Cli, Command, Invocation, Mode};
+use render_prompt::error::{ErrorFormat, RenderError, EXIT_SUCCESS};
+use render_prompt::{data, error, sandbox, template};
+use serde_json::Value;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use template::TemplateCache;
 
 fn main() {
     // Parse CLI arguments
-    let cli = Cli::parse();
+    let args = Cli::parse();
+    color::init(args.color);
+    logging::init(args.verbose, args.quiet, args.trace, args.error_format);
+
+    if let Some(Command::Build {
+        file,
+        dry_run,
+        check_output,
+    }) = &args.command
+    {
+        std::process::exit(build(&args, file, *dry_run, *check_output));
+    }
+    let cli = args.into_invocation();
 
     // Validate arguments
     if let Err(e) = cli.validate() {
-        eprintln!("Error: {}", e);
+        match cli.error_format {
+            ErrorFormat::Text => eprintln!("{}: {}", color::error("Error"), e),
+            ErrorFormat::Json => eprintln!("{}", RenderError::Usage(e).to_json()),
+        }
         std::process::exit(error::EXIT_USAGE_ERROR);
     }
 
+    if cli.mode == Mode::Check {
+        std::process::exit(check(&cli));
+    }
+
+    // Serving watches the files itself
+    if let Mode::Serve(addr) = cli.mode {
+        serve_loop(&cli, addr);
+    }
+
+    if cli.render.watch {
+        watch_loop(&cli);
+    }
+
+    if let Some(timeout) = cli.render.timeout {
+        start_timeout(timeout, cli.error_format);
+    }
+
     // Run the main logic
-    match run(cli) {
+    match run(&cli, &Arc::default()) {
         Ok(output) => {
-            println!("{}", output);
+            print_output(&cli, &output);
             std::process::exit(EXIT_SUCCESS);
         }
         Err(e) => {
-            // Print machine-readable error message to stderr
-            eprintln!("{}", e.format_machine_readable());
-            eprintln!("{}", e);
+            report_error(cli.error_format, &e);
             std::process::exit(e.exit_code());
         }
     }
 }
 
-fn run(cli: Cli) -> Result<String, RenderError> {
-    use data::DataLoader;
-    use std::path::PathBuf;
-    use template::TemplateEngine;
+/// Print the result of a run to stdout, followed by a line ending unless
+/// --no-trailing-newline asks for it byte for byte
+fn print_output(cli: &Invocation, output: &str) {
+    if cli.render.no_trailing_newline {
+        let mut stdout = std::io::stdout();
+        let _ = stdout
+            .write_all(output.as_bytes())
+            .and_then(|_| stdout.flush());
+    } else {
+        print!("{}{}", output, cli.render.newline.terminator());
+    }
+}
+
+/// Held while an output file is written, so that exiting on --timeout waits for the write
+/// to finish instead of leaving a temporary or half-appended file behind
+static WRITING: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Set once --timeout has run out, so that a write finishing after it leaves the exit to
+/// the timeout instead of carrying on with the render
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+/// Exit with a timeout error if the process is still running after `timeout`, even if it
+/// is blocked (e.g. waiting for a --data-exec command)
+fn start_timeout(timeout: std::time::Duration, format: ErrorFormat) {
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        TIMED_OUT.store(true, Ordering::SeqCst);
+        let _writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+        let e = RenderError::Timeout { timeout };
+        report_error(format, &e);
+        std::process::exit(e.exit_code());
+    });
+}
+
+/// Print machine-readable error message to stderr, followed by the offending line of the
+/// template when the error points into one
+fn report_error(format: ErrorFormat, e: &RenderError) {
+    // Each undefined variable of a strict render is a diagnostic of its own
+    if let RenderError::UndefinedVariables { undefined } = e {
+        for (name, location) in undefined {
+            let e = RenderError::UndefinedVariable {
+                name: name.clone(),
+                location: location.clone(),
+            };
+            report_error(format, &e);
+        }
+        if format == ErrorFormat::Text {
+            eprintln!("{} undefined variables", undefined.len());
+        }
+        return;
+    }
+
+    match format {
+        ErrorFormat::Text => {
+            eprintln!("{}", color::error_lines(&e.format_machine_readable()));
+            eprintln!("{}", color::message(&e.to_string()));
+            let snippet = e
+                .location()
+                .and_then(|location| std::fs::read_to_string(&location.file).ok())
+                .and_then(|source| e.snippet(&source));
+            if let Some(snippet) = snippet {
+                eprintln!("{}", color::snippet(&snippet));
+            }
+        }
+        ErrorFormat::Json => eprintln!("{}", e.to_json()),
+    }
+}
+
+/// Report every problem in the template and its includes, returning the exit code
+///
+/// The exit code is that of the first problem, so CI can tell syntax errors (3) from
+/// broken includes (5).
+fn check(cli: &Invocation) -> i32 {
+    let prepared = match prepare(cli, &Arc::default()) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            report_error(cli.error_format, &e);
+            return e.exit_code();
+        }
+    };
+
+    let problems: Vec<_> = prepared
+        .templates
+        .iter()
+        .flat_map(|source| {
+            prepared
+                .engine
+                .check(&source.content, &source.path, &prepared.data)
+        })
+        .collect();
+    for problem in &problems {
+        report_error(cli.error_format, problem);
+    }
+
+    // JSON output is for tools, which only need the diagnostics
+    let summary = cli.error_format == ErrorFormat::Text;
+    match problems.first() {
+        Some(first) => {
+            if summary {
+                eprintln!("{} problem(s) found", problems.len());
+            }
+            first.exit_code()
+        }
+        None => {
+            if summary {
+                let paths: Vec<_> = prepared
+                    .templates
+                    .iter()
+                    .map(|source| source.path.display().to_string())
+                    .collect();
+                eprintln!("{}: no problems found", paths.join(", "));
+            }
+            EXIT_SUCCESS
+        }
+    }
+}
+
+/// Run every job of a manifest, returning the exit code
+///
+/// Every job runs even if an earlier one failed, so a single build reports all problems;
+/// the exit code is that of the first failure. Jobs share one cache, as they usually share
+/// includes.
+fn build(args: &Cli, file: &str, dry_run: bool, check_output: bool) -> i32 {
+    let fail = |e: RenderError| {
+        report_error(args.error_format, &e);
+        e.exit_code()
+    };
+
+    let manifest_path = std::path::Path::new(file);
+    let manifest = match manifest::Manifest::load(manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => return fail(e),
+    };
+    // Paths in the manifest are relative to it
+    if let Some(dir) = manifest_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        if let Err(e) = std::env::set_current_dir(dir) {
+            return fail(RenderError::Io(e));
+        }
+    }
+
+    let cache = Arc::default();
+    let mut exit_code = EXIT_SUCCESS;
+    let mut failed = 0;
+    let progress = Progress::new(manifest.jobs.len(), args.quiet, args.error_format);
+    for (index, job) in manifest.jobs.iter().enumerate() {
+        let label = job.label(index);
+        progress.step(index, &label);
+
+        let result = job_invocation(args, job, dry_run, check_output)
+            .map_err(RenderError::Usage)
+            .and_then(|cli| run(&cli, &cache).map(|output| (cli, output)));
+        match result {
+            Ok((cli, output)) => {
+                if !output.is_empty() {
+                    print_output(&cli, &output);
+                }
+            }
+            Err(e) => {
+                if args.error_format == ErrorFormat::Text {
+                    eprintln!("{} failed:", label);
+                }
+                report_error(args.error_format, &e);
+                if failed == 0 {
+                    exit_code = e.exit_code();
+                }
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 && args.error_format == ErrorFormat::Text {
+        eprintln!("{} of {} job(s) failed", failed, manifest.jobs.len());
+    }
+    exit_code
+}
+
+/// The invocation of one manifest job, with the options given to `rp build` applied
+fn job_invocation(
+    args: &Cli,
+    job: &manifest::Job,
+    dry_run: bool,
+    check_output: bool,
+) -> Result<Invocation, String> {
+    let parsed = Cli::try_parse_from(job.args()?).map_err(|e| {
+        // Only the message itself; the usage that follows is that of `rp render`
+        let message = e.to_string();
+        let first_line = message.lines().next().unwrap_or_default();
+        first_line.trim_start_matches("error: ").to_string()
+    })?;
+
+    let mut cli = parsed.into_invocation();
+    cli.verbose = args.verbose;
+    cli.quiet = args.quiet;
+    cli.error_format = args.error_format;
+    cli.render.create_dirs = true;
+    if dry_run {
+        cli.render.dry_run = true;
+        cli.render.check_output = false;
+    }
+    if check_output {
+        cli.render.check_output = true;
+        cli.render.dry_run = false;
+    }
+
+    if cli.render.watch || cli.render.timeout.is_some() {
+        return Err("watch and timeout cannot be used in a manifest job".to_string());
+    }
+    cli.validate()?;
+    Ok(cli)
+}
+
+/// Re-render whenever the template, an included file or a data file changes
+///
+/// Errors are reported without exiting, so a broken intermediate edit can be fixed.
+fn watch_loop(cli: &Invocation) -> ! {
+    loop {
+        match run(cli, &Arc::default()) {
+            Ok(output) if cli.render.output.is_none() || cli.render.tee => {
+                print_output(cli, &output)
+            }
+            Ok(_) => {}
+            Err(e) => report_error(cli.error_format, &e),
+        }
+
+        let files = watched_files(cli);
+        eprintln!(
+            "Watching {} file(s) for changes (press Ctrl+C to stop)",
+            files.len()
+        );
+        report_changes(cli, &watch::wait_for_change(&files));
+    }
+}
+
+/// Name the files whose change triggers a re-render, unless --quiet is given
+fn report_changes(cli: &Invocation, changed: &[std::path::PathBuf]) {
+    if !cli.quiet && cli.error_format == ErrorFormat::Text {
+        let names: Vec<_> = changed
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        eprintln!("Changed: {}", names.join(", "));
+    }
+}
+
+/// Per-item status lines on stderr (`[2/10] dist/b.txt`), printed as each item of a batch
+/// starts, so long runs are not a black box
+///
+/// Shown for text output of batches with more than one item, unless --quiet is given.
+struct Progress {
+    total: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    fn new(total: usize, quiet: bool, format: ErrorFormat) -> Self {
+        Progress {
+            total,
+            enabled: total > 1 && !quiet && format == ErrorFormat::Text,
+        }
+    }
+
+    fn step(&self, index: usize, item: impl std::fmt::Display) {
+        if self.enabled {
+            eprintln!("[{}/{}] {}", index + 1, self.total, item);
+        }
+    }
+}
+
+/// Serve the latest render over HTTP, re-rendering whenever a file changes
+fn serve_loop(cli: &Invocation, addr: std::net::SocketAddr) -> ! {
+    let title = cli.input.templates.join(", ");
+    let preview = match serve::Preview::start(addr, &title) {
+        Ok(preview) => preview,
+        Err(e) => {
+            let e = RenderError::Usage(format!("cannot listen on {}: {}", addr, e));
+            report_error(cli.error_format, &e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    eprintln!(
+        "Serving a preview of {} at http://{}/ (press Ctrl+C to stop)",
+        title,
+        preview.addr()
+    );
+
+    loop {
+        match run(cli, &Arc::default()) {
+            Ok(output) => preview.publish(Ok(output)),
+            Err(e) => {
+                report_error(cli.error_format, &e);
+                preview.publish(Err(e.to_string()));
+            }
+        }
+        report_changes(cli, &watch::wait_for_change(&watched_files(cli)));
+    }
+}
+
+/// The templates, their data files and every file they currently include
+fn watched_files(cli: &Invocation) -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = cli
+        .input
+        .templates
+        .iter()
+        .map(std::path::PathBuf::from)
+        .collect();
+    files.extend(data::DataLoader::source_paths(&cli.input.data));
+    files.extend(cli.input.schema.iter().map(std::path::PathBuf::from));
+    if let Ok(prepared) = prepare(cli, &Arc::default()) {
+        for source in &prepared.templates {
+            let dependencies =
+                prepared
+                    .engine
+                    .dependencies(&source.content, &source.path, &prepared.data);
+            files.extend(dependencies.into_iter().flatten().map(|d| d.path));
+        }
+    }
+    files
+}
+
+/// Everything needed to render: the configured engine, the templates and the merged data
+struct Prepared {
+    engine: template::TemplateEngine,
+    /// Rendered one after another into the same output (usually just one)
+    templates: Vec<Source>,
+    data: serde_json::Value,
+    /// --max-output-size, which applies to the concatenated output as a whole
+    max_output_size: Option<usize>,
+}
+
+/// A template and its path (`<root>/<stdin>` for a template read from stdin)
+struct Source {
+    content: String,
+    path: std::path::PathBuf,
+}
+
+impl Prepared {
+    /// Render every template with `data`, concatenating the outputs
+    fn render(&self, data: &Value) -> Result<String, RenderError> {
+        let mut output = String::new();
+        for source in &self.templates {
+            output.push_str(
+                &self
+                    .engine
+                    .render_source(&source.content, &source.path, data)?,
+            );
+            // Each render stops at the limit by itself, but together they can exceed it
+            if let Some(limit) = self.max_output_size.filter(|&limit| output.len() > limit) {
+                return Err(RenderError::OutputTooLarge { limit });
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Render (or list the dependencies or variables of) a template, sharing `cache` with
+/// other runs of the same batch
+fn run(cli: &Invocation, cache: &Arc<TemplateCache>) -> Result<String, RenderError> {
+    // Only warnings from this render count (--watch renders repeatedly)
+    logging::take_warnings();
+
+    if let Some(out_dir) = &cli.render.output_dir {
+        return render_tree(cli, out_dir, cache);
+    }
+
+    let prepared = prepare(cli, cache)?;
+    let (engine, data) = (&prepared.engine, &prepared.data);
+
+    if cli.mode == Mode::Deps {
+        // One file per line, indented by include depth, one tree per template
+        let mut trees = Vec::new();
+        for source in &prepared.templates {
+            let dependencies = engine.dependencies(&source.content, &source.path, data)?;
+            let mut tree = source.path.display().to_string();
+            for dependency in dependencies {
+                tree.push_str(&format!(
+                    "\n{}{}",
+                    "  ".repeat(dependency.depth),
+                    dependency.path.display()
+                ));
+            }
+            trees.push(tree);
+        }
+        return Ok(trees.join("\n"));
+    }
+
+    if let Mode::Vars(format) = cli.mode {
+        let mut variables = std::collections::BTreeSet::new();
+        for source in &prepared.templates {
+            variables.extend(engine.variables(&source.content, &source.path, data)?);
+        }
+        let variables: Vec<_> = variables.into_iter().collect();
+        return Ok(match format {
+            cli::ListFormat::Text => variables.join("\n"),
+            cli::ListFormat::Json => serde_json::to_string_pretty(&variables)
+                .expect("a list of strings always serializes"),
+        });
+    }
+
+    // 5. Render template, once per record for a templated output path
+    let targets = output_targets(cli.render.output.as_deref(), data, cli.render.append)?;
+    let mut outputs = Vec::with_capacity(targets.len());
+    let progress = Progress::new(targets.len(), cli.quiet, cli.error_format);
+    for (index, (path, record)) in targets.iter().enumerate() {
+        if let Some(path) = path {
+            progress.step(index, path.display());
+        }
+        outputs.push((path, cli.render.finish(prepared.render(record)?)));
+    }
+    fail_on_warnings(cli)?;
+    if let Some(format) = cli.render.stats {
+        let texts = outputs.iter().map(|(_, output)| output.as_str());
+        report_stats(format, texts, prepared.engine.stats());
+    }
+
+    if cli.render.dry_run {
+        return dry_run_report(cli, &prepared, &targets);
+    }
+
+    if cli.render.check_output {
+        return check_outputs(
+            outputs
+                .iter()
+                .filter_map(|(path, output)| Some((path.as_deref()?, output.as_str()))),
+        );
+    }
+
+    // 6. Write output, returning what goes to stdout
+    let mut stdout = String::new();
+    for (path, output) in outputs {
+        match path {
+            Some(path) => {
+                // Batch output may go to directories that do not exist yet
+                if cli.render.create_dirs || cli.render.output.as_deref().is_some_and(is_templated)
+                {
+                    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                        std::fs::create_dir_all(parent).map_err(RenderError::Io)?;
+                    }
+                }
+                write_output(cli, path, &output)?;
+                if cli.render.tee {
+                    stdout.push_str(&output);
+                }
+            }
+            None => stdout = output,
+        }
+    }
+    Ok(stdout)
+}
+
+/// Compare rendered outputs with the files on disk, printing a unified diff of each one
+/// that is missing or differs
+fn check_outputs<'a>(
+    outputs: impl IntoIterator<Item = (&'a std::path::Path, &'a str)>,
+) -> Result<String, RenderError> {
+    let mut out_of_date = Vec::new();
+    for (path, rendered) in outputs {
+        let current = match std::fs::read_to_string(path) {
+            Ok(current) => Some(current),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(RenderError::Io(e)),
+        };
+        if current.as_deref() == Some(rendered) {
+            continue;
+        }
+
+        let name = path.display().to_string();
+        let diff = similar::TextDiff::from_lines(current.as_deref().unwrap_or(""), rendered);
+        print!(
+            "{}",
+            diff.unified_diff()
+                .header(&name, &format!("{} (rendered)", name))
+        );
+        out_of_date.push(name);
+    }
+
+    if out_of_date.is_empty() {
+        Ok(String::new())
+    } else {
+        Err(RenderError::OutputOutOfDate { paths: out_of_date })
+    }
+}
+
+/// With --warn-as-error, turn the warnings reported while rendering into an error
+fn fail_on_warnings(cli: &Invocation) -> Result<(), RenderError> {
+    let count = logging::take_warnings();
+    if cli.render.warn_as_error && count > 0 {
+        return Err(RenderError::WarningsAsErrors { count });
+    }
+    Ok(())
+}
+
+/// Print --stats for a render: the size of its outputs and what the engine did
+fn report_stats<'a>(
+    format: cli::ListFormat,
+    outputs: impl Iterator<Item = &'a str>,
+    stats: &template::RenderStats,
+) {
+    let (mut bytes, mut lines, mut words) = (0, 0, 0);
+    for output in outputs {
+        bytes += output.len();
+        lines += output.lines().count();
+        words += output.split_whitespace().count();
+    }
+    match format {
+        cli::ListFormat::Text => eprintln!(
+            "Stats: {} bytes, {} lines, {} words, {} includes, {} variables substituted, \
+             {} undefined",
+            bytes,
+            lines,
+            words,
+            stats.includes(),
+            stats.substituted(),
+            stats.undefined()
+        ),
+        cli::ListFormat::Json => eprintln!(
+            "{}",
+            serde_json::json!({
+                "bytes": bytes,
+                "lines": lines,
+                "words": words,
+                "includes": stats.includes(),
+                "substituted": stats.substituted(),
+                "undefined": stats.undefined(),
+            })
+        ),
+    }
+}
+
+/// Whether an output path contains `{{ }}` and is rendered per record
+fn is_templated(output: &str) -> bool {
+    output.contains("{{")
+}
+
+/// Where each rendered output goes (None for stdout) and the data it is rendered with
+///
+/// A templated output path renders the template once per record: when the data is an
+/// array (e.g. a CSV file) each element is a record, otherwise the whole data is one.
+/// Records may only share a path when appending.
+fn output_targets<'d>(
+    output: Option<&str>,
+    data: &'d Value,
+    append: bool,
+) -> Result<Vec<(Option<std::path::PathBuf>, &'d Value)>, RenderError> {
+    use std::collections::HashMap;
+
+    let pattern = match output {
+        Some(pattern) if is_templated(pattern) => pattern,
+        output => return Ok(vec![(output.map(std::path::PathBuf::from), data)]),
+    };
+    let records = match data {
+        Value::Array(items) => items.iter().collect(),
+        _ => vec![data],
+    };
+
+    // Every path is rendered first, so nothing is written if two records collide
+    let substitutor = template::VariableSubstitutor::new(true, false);
+    let mut targets = Vec::with_capacity(records.len());
+    let mut seen = HashMap::new();
+    for (index, record) in records.into_iter().enumerate() {
+        let path = substitutor.substitute(pattern, record).map_err(|e| {
+            RenderError::Usage(format!(
+                "cannot render output path '{}' for record {}: {}",
+                pattern, index, e
+            ))
+        })?;
+        // Appending, records that share a path accumulate in it
+        if let Some(previous) = seen.insert(path.clone(), index).filter(|_| !append) {
+            return Err(RenderError::Usage(format!(
+                "records {} and {} both render to output path '{}'",
+                previous, index, path
+            )));
+        }
+        targets.push((Some(path.into()), record));
+    }
+    Ok(targets)
+}
+
+/// Write (or with --append, append) rendered output to a file
+///
+/// With --only-if-changed, a file that already holds exactly this output is not rewritten.
+fn write_output(cli: &Invocation, path: &std::path::Path, output: &str) -> Result<(), RenderError> {
+    let writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = write_file(cli, path, output);
+    drop(writing);
+    if TIMED_OUT.load(Ordering::SeqCst) {
+        // The timeout thread exits as soon as it gets the lock
+        loop {
+            std::thread::park();
+        }
+    }
+    result
+}
+
+fn write_file(cli: &Invocation, path: &std::path::Path, output: &str) -> Result<(), RenderError> {
+    if cli.render.append {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(output.as_bytes()))
+            .map_err(RenderError::Io)
+    } else if cli.render.only_if_changed
+        && std::fs::read(path).is_ok_and(|existing| existing == output.as_bytes())
+    {
+        tracing::info!("{} is unchanged", path.display());
+        Ok(())
+    } else {
+        write_atomically(path, output).map_err(RenderError::Io)
+    }
+}
+
+/// Write a file by renaming a complete temporary file over it, so that an interrupted or
+/// failed write never leaves a truncated file behind
+///
+/// The temporary file is created next to the target (a rename cannot cross file systems)
+/// and gets the permissions of the file it replaces. A symlink is followed, not replaced.
+fn write_atomically(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{}' is not a file path", path.display()),
+        )
+    })?;
+    let temp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            std::fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        std::fs::rename(&temp, &path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// List the files a render reads and writes, one per line
+fn dry_run_report(
+    cli: &Invocation,
+    prepared: &Prepared,
+    targets: &[(Option<std::path::PathBuf>, &Value)],
+) -> Result<String, RenderError> {
+    let mut lines = Vec::new();
+    for (template, source) in cli.input.templates.iter().zip(&prepared.templates) {
+        if template == "-" {
+            lines.push("read: <stdin>".to_string());
+        } else {
+            lines.push(format!("read: {}", source.path.display()));
+        }
+    }
+    lines.extend(data_reads(cli));
+
+    // Dynamic include paths can differ between records
+    let mut included = std::collections::HashSet::new();
+    for (_, record) in targets {
+        for source in &prepared.templates {
+            let dependencies =
+                prepared
+                    .engine
+                    .dependencies(&source.content, &source.path, record)?;
+            for dependency in dependencies {
+                if included.insert(dependency.path.clone()) {
+                    lines.push(format!("read: {}", dependency.path.display()));
+                }
+            }
+        }
+    }
+
+    let write = if cli.render.append { "append" } else { "write" };
+    for (path, _) in targets {
+        match path {
+            Some(path) => lines.push(format!("{}: {}", write, path.display())),
+            None => lines.push("write: <stdout>".to_string()),
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+/// The data files, commands and schema a render reads, as --dry-run lines
+fn data_reads(cli: &Invocation) -> Vec<String> {
+    let mut lines = Vec::new();
+    for path in data::DataLoader::source_paths(&cli.input.data) {
+        lines.push(format!("read: {}", path.display()));
+    }
+    for command in &cli.input.data_exec {
+        lines.push(format!("run: {}", command));
+    }
+    if let Some(schema) = &cli.input.schema {
+        lines.push(format!("read: {}", schema));
+    }
+    lines
+}
+
+/// Render every template matching the template pattern into `out_dir`
+///
+/// Each output keeps its path relative to the pattern's fixed leading directories, so
+/// `-t 'prompts/**/*.txt' --output-dir dist` renders `prompts/a/b.txt` to `dist/a/b.txt`.
+/// Nothing is written unless every template renders.
+fn render_tree(
+    cli: &Invocation,
+    out_dir: &str,
+    cache: &Arc<TemplateCache>,
+) -> Result<String, RenderError> {
+    use std::path::Path;
+
+    let data = load_data(cli)?;
+    let (base, templates) = expand_templates(&cli.input.templates[0])?;
+
+    let mut outputs = Vec::with_capacity(templates.len());
+    let mut reads = Vec::new();
+    let stats = template::RenderStats::default();
+    let progress = Progress::new(templates.len(), cli.quiet, cli.error_format);
+    for (index, template) in templates.into_iter().enumerate() {
+        progress.step(index, template.display());
+        let prepared = prepare_templates(
+            cli,
+            &[template.to_string_lossy().into_owned()],
+            data.clone(),
+            cache,
+        )?;
+        let output = cli.render.finish(prepared.render(&prepared.data)?);
+        stats.add(prepared.engine.stats());
+        if cli.render.dry_run {
+            reads.push(format!("read: {}", template.display()));
+            let source = &prepared.templates[0];
+            let dependencies =
+                prepared
+                    .engine
+                    .dependencies(&source.content, &source.path, &prepared.data)?;
+            // Templates often share includes; list each file once
+            for dependency in dependencies {
+                let line = format!("read: {}", dependency.path.display());
+                if !reads.contains(&line) {
+                    reads.push(line);
+                }
+            }
+        }
+        let relative = template.strip_prefix(&base).unwrap_or(&template);
+        outputs.push((Path::new(out_dir).join(relative), output));
+    }
+
+    fail_on_warnings(cli)?;
+    if let Some(format) = cli.render.stats {
+        let texts = outputs.iter().map(|(_, output)| output.as_str());
+        report_stats(format, texts, &stats);
+    }
+
+    if cli.render.dry_run {
+        let mut lines = data_reads(cli);
+        lines.extend(reads);
+        let write = if cli.render.append { "append" } else { "write" };
+        lines.extend(
+            outputs
+                .iter()
+                .map(|(path, _)| format!("{}: {}", write, path.display())),
+        );
+        return Ok(lines.join("\n"));
+    }
+
+    if cli.render.check_output {
+        return check_outputs(
+            outputs
+                .iter()
+                .map(|(path, output)| (path.as_path(), output.as_str())),
+        );
+    }
+
+    let mut stdout = String::new();
+    for (path, output) in outputs {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(RenderError::Io)?;
+        }
+        write_output(cli, &path, &output)?;
+        if cli.render.tee {
+            stdout.push_str(&output);
+        }
+    }
+    Ok(stdout)
+}
+
+/// The template files a template argument names, and the directory outputs are relative to
+///
+/// A plain path names one file, relative to its own directory. A glob pattern names every
+/// matching file (in sorted order), relative to the leading directories without wildcards.
+fn expand_templates(
+    pattern: &str,
+) -> Result<(std::path::PathBuf, Vec<std::path::PathBuf>), RenderError> {
+    use std::path::{Path, PathBuf};
+
+    let is_glob = |s: &str| s.contains(['*', '?', '[']);
+    if !is_glob(pattern) || Path::new(pattern).exists() {
+        let path = PathBuf::from(pattern);
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        return Ok((base, vec![path]));
+    }
+
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect();
+    let mut templates: Vec<PathBuf> = glob::glob(pattern)
+        .map_err(|e| RenderError::Usage(format!("invalid template pattern '{}': {}", pattern, e)))?
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    templates.sort();
+    if templates.is_empty() {
+        return Err(RenderError::Usage(format!(
+            "no template files match '{}'",
+            pattern
+        )));
+    }
+    Ok((base, templates))
+}
+
+fn prepare(cli: &Invocation, cache: &Arc<TemplateCache>) -> Result<Prepared, RenderError> {
+    let data = load_data(cli)?;
+    prepare_templates(cli, &cli.input.templates, data, cache)
+}
+
+/// Load, merge and validate the data every template is rendered with
+fn load_data(cli: &Invocation) -> Result<Value, RenderError> {
+    use data::{DataLoader, DataMerger, SchemaValidator};
 
     // 1. Load and merge data files
-    let data = if cli.data.is_empty() {
+    let started = std::time::Instant::now();
+    let merger = DataMerger::new()
+        .with_strategy(cli.input.merge_strategy)
+        .with_array_merge(cli.input.array_merge.clone())
+        .with_null_deletes(cli.input.null_deletes);
+    let loader = DataLoader::new()
+        .with_csv_delimiter(cli.input.csv_delimiter)
+        .with_jsonl_key(cli.input.jsonl_key.clone())
+        .with_format(cli.input.data_format)
+        .with_merger(merger.clone())
+        .with_sandbox(cli.input.sandbox());
+    let mut data = if cli.input.data.is_empty() {
         serde_json::json!({})
     } else {
-        DataLoader::load_multiple(&cli.data)?
+        loader.load_multiple(&cli.input.data)?
+    };
+
+    // Command output is only used when explicitly requested
+    for command in &cli.input.data_exec {
+        merge_overrides(&merger, &mut data, &loader.load_exec(command)?)?;
+        tracing::info!("merged output of command {}", command);
+    }
+
+    // Environment variables are only exposed when explicitly requested
+    let env = if cli.input.allow_env {
+        Some(DataLoader::load_env())
+    } else if !cli.input.env.is_empty() {
+        Some(DataLoader::load_selected_env(&cli.input.env))
+    } else {
+        None
     };
+    if let Some(env) = env {
+        merge_overrides(
+            &DataMerger::default(),
+            &mut data,
+            &serde_json::json!({ "env": env }),
+        )?;
+    }
+
+    // Prefixed variables are top-level data, for configuring containers without files
+    if cli.input.env_vars {
+        merge_overrides(
+            &merger,
+            &mut data,
+            &DataLoader::load_prefixed_env(data::ENV_VAR_PREFIX),
+        )?;
+    }
+
+    // Command-line overrides are applied last
+    let overrides = cli
+        .input
+        .set
+        .iter()
+        .map(|(key, value)| (key, Value::from(value.as_str())))
+        .chain(
+            cli.input
+                .set_json
+                .iter()
+                .map(|(key, value)| (key, value.clone())),
+        );
+    for (key, value) in overrides {
+        merge_overrides(
+            &DataMerger::default(),
+            &mut data,
+            &DataMerger::at_path(key, value),
+        )?;
+    }
+
+    // Validate the fully merged data before rendering anything
+    if let Some(schema) = &cli.input.schema {
+        SchemaValidator::validate(schema, &data)?;
+        tracing::info!("validated data against schema {}", schema);
+    }
+    tracing::debug!(elapsed = ?started.elapsed(), "loaded data");
 
-    // 2. Determine root directory
-    let template_path = PathBuf::from(&cli.template);
-    let root_dir = if let Some(root) = cli.root {
+    Ok(data)
+}
+
+/// Merge data given on the command line (`--set`, `--env`, `--data-exec`, ...) into the
+/// loaded data
+///
+/// When the data is an array of records (CSV rows, say), an object is merged into each
+/// record rather than replacing the array.
+fn merge_overrides(
+    merger: &data::DataMerger,
+    data: &mut Value,
+    overlay: &Value,
+) -> Result<(), RenderError> {
+    match data {
+        Value::Array(records) if overlay.is_object() => {
+            if records.iter().any(|record| !record.is_object()) {
+                return Err(RenderError::Usage(
+                    "cannot merge --set, --env or --data-exec values into data whose root is an \
+                     array of non-object values"
+                        .to_string(),
+                ));
+            }
+            for record in records {
+                merger.merge_into(record, overlay);
+            }
+        }
+        data => merger.merge_into(data, overlay),
+    }
+    Ok(())
+}
+
+/// Set up the engine for templates rendered into one output and read them
+fn prepare_templates(
+    cli: &Invocation,
+    templates: &[String],
+    data: Value,
+    cache: &Arc<TemplateCache>,
+) -> Result<Prepared, RenderError> {
+    use std::path::PathBuf;
+    use template::{MissingPolicy, TemplateEngine};
+
+    // 2. Determine root directory, shared by all templates. A template read from stdin
+    // (`-t -`) has no directory of its own, so it can only include files when --root is
+    // given
+    let first = &templates[0];
+    let includes_allowed = first != "-" || cli.input.root.is_some();
+    let root_dir = if let Some(root) = &cli.input.root {
         PathBuf::from(root)
     } else {
-        // Use template's parent directory as root
-        template_path
+        // Use the first template's parent directory as root (`.` for a bare file name,
+        // whose parent is empty)
+        std::path::Path::new(first)
             .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
             .unwrap_or_else(|| std::path::Path::new("."))
             .to_path_buf()
     };
 
     // 3. Create template engine
-    let engine = TemplateEngine::new(root_dir, cli.max_include_depth, cli.strict, cli.warn_undefined);
-
-    // 4. Render template
-    let output = engine.render(&template_path, &data)?;
+    let missing = match cli.render.missing {
+        Some(missing) => missing,
+        None if cli.render.strict => MissingPolicy::Error,
+        None => MissingPolicy::Empty,
+    };
+    let engine = TemplateEngine::new(
+        root_dir.clone(),
+        cli.input.max_include_depth,
+        cli.render.strict,
+        cli.render.warn_undefined || cli.render.warn_as_error,
+    )
+    .with_missing(missing)
+    .with_placeholder(cli.render.default_undefined.clone())
+    .with_max_output_size(cli.render.max_output_size)
+    .with_now_helper(!cli.render.no_now)
+    .with_escape_html(cli.render.escape_html)
+    .with_includes_allowed(includes_allowed)
+    .with_sandbox(cli.input.sandbox())
+    .with_cache(Arc::clone(cache));
 
-    // 5. Write output
-    if let Some(out_path) = cli.output {
-        std::fs::write(&out_path, &output).map_err(|e| RenderError::Io(e))?;
-        // Return empty string to avoid printing to stdout
-        Ok(String::new())
-    } else {
-        // Return output for stdout
-        Ok(output)
+    // 4. Read templates
+    let mut sources = Vec::with_capacity(templates.len());
+    for template in templates {
+        sources.push(if template == "-" {
+            let content = std::io::read_to_string(std::io::stdin()).map_err(|e| {
+                RenderError::TemplateFileRead {
+                    path: "<stdin>".to_string(),
+                    source: e,
+                }
+            })?;
+            Source {
+                content,
+                path: root_dir.join("<stdin>"),
+            }
+        } else {
+            let path = PathBuf::from(template);
+            Source {
+                content: engine.read_source(&path)?,
+                path,
+            }
+        });
     }
+
+    Ok(Prepared {
+        engine,
+        templates: sources,
+        data,
+        max_output_size: cli.render.max_output_size,
+    })
 }
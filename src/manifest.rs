@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::RenderError;
+
+/// A project file (`render.yaml`) listing the renders `rp build` runs, in order
+///
+/// ```yaml
+/// jobs:
+///   - name: system
+///     template: prompts/system.txt
+///     data: [data/base.yaml, data/prod.yaml]
+///     out: dist/system.txt
+///     strict: true
+///   - template: "prompts/**/*.md"
+///     output-dir: dist/docs
+///     set: { model: gpt-4o }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub jobs: Vec<Job>,
+}
+
+/// One render, with the options of `rp render` under their long names
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    /// Shown in messages instead of the job's position
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(flatten)]
+    pub options: BTreeMap<String, Value>,
+}
+
+impl Manifest {
+    /// Read and parse a manifest file
+    pub fn load(path: &Path) -> Result<Self, RenderError> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            RenderError::Usage(format!("cannot read manifest '{}': {}", path.display(), e))
+        })?;
+        Self::parse(&content).map_err(|e| {
+            RenderError::Usage(format!("invalid manifest '{}': {}", path.display(), e))
+        })
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let manifest: Self = serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+        if manifest.jobs.is_empty() {
+            return Err("no jobs defined".to_string());
+        }
+        Ok(manifest)
+    }
+}
+
+impl Job {
+    /// The command line of `rp render` equivalent to this job
+    ///
+    /// `true` switches a flag on, a list repeats the option, and a `set` mapping becomes
+    /// one `--set-json` per key, so data values keep their YAML types.
+    pub fn args(&self) -> Result<Vec<String>, String> {
+        let mut args = vec!["rp".to_string(), "render".to_string()];
+        for (key, value) in &self.options {
+            let flag = format!("--{}", key.replace('_', "-"));
+            match (key.as_str(), value) {
+                ("set", Value::Object(values)) => {
+                    for (path, value) in values {
+                        args.push(format!("--set-json={}={}", path, value));
+                    }
+                }
+                (_, Value::Bool(true)) => args.push(flag),
+                (_, Value::Bool(false) | Value::Null) => {}
+                (_, Value::Array(items)) => {
+                    for item in items {
+                        args.push(format!("{}={}", flag, scalar(key, item)?));
+                    }
+                }
+                (_, value) => args.push(format!("{}={}", flag, scalar(key, value)?)),
+            }
+        }
+        Ok(args)
+    }
+
+    /// The job's name, or its 1-based position in the manifest
+    pub fn label(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("job {}", index + 1),
+        }
+    }
+}
+
+/// An option value as it would be written on the command line
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!("option '{}' expects a string, number or list", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_args() {
+        let manifest = Manifest::parse(
+            "jobs:\n\
+             \x20 - name: system\n\
+             \x20   template: system.txt\n\
+             \x20   data: [base.yaml, prod.yaml]\n\
+             \x20   out: dist/system.txt\n\
+             \x20   strict: true\n\
+             \x20   escape_html: false\n\
+             \x20   max-include-depth: 5\n\
+             \x20   set: { model: gpt-4o, tags: [a, b] }\n",
+        )
+        .unwrap();
+        let job = &manifest.jobs[0];
+
+        assert_eq!(job.label(0), "system");
+        assert_eq!(
+            job.args().unwrap(),
+            vec![
+                "rp",
+                "render",
+                "--data=base.yaml",
+                "--data=prod.yaml",
+                "--max-include-depth=5",
+                "--out=dist/system.txt",
+                "--set-json=model=\"gpt-4o\"",
+                "--set-json=tags=[\"a\",\"b\"]",
+                "--strict",
+                "--template=system.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        assert!(Manifest::parse("jobs: []").is_err());
+        assert!(Manifest::parse("job:\n  - template: a.txt").is_err());
+
+        let manifest = Manifest::parse("jobs:\n  - template: { path: a.txt }").unwrap();
+        assert_eq!(manifest.jobs[0].label(0), "job 1");
+        assert!(manifest.jobs[0].args().is_err());
+    }
+}
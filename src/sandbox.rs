@@ -0,0 +1,151 @@
+//! Limits on what rendering may read, shared by the `rp` flags and the library
+//!
+//! A [`Sandbox`] is given to
+//! [`TemplateEngine::with_sandbox`](crate::TemplateEngine::with_sandbox) for the template
+//! and the files it includes, and to [`DataLoader::with_sandbox`](crate::DataLoader::with_sandbox)
+//! for its data. The default sandbox allows everything `rp` allows without flags, so
+//! embedders only tighten the limits they care about:
+//!
+//! ```
+//! use render_prompt::sandbox::{Sandbox, SymlinkPolicy};
+//! use render_prompt::TemplateEngine;
+//!
+//! let sandbox = Sandbox::new()
+//!     .allow_root("shared/partials")
+//!     .symlinks(SymlinkPolicy::Deny)
+//!     .max_file_size(1 << 20)
+//!     .max_includes(100)
+//!     .network(false);
+//! let engine = TemplateEngine::builder()
+//!     .root("prompts")
+//!     .sandbox(sandbox)
+//!     .build();
+//! ```
+
+use std::io;
+use std::path::PathBuf;
+
+/// Whether included files may be symlinks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Follow symlinks, as long as their target stays inside an allowed root
+    #[default]
+    Follow,
+    /// Reject included files that are symlinks
+    Deny,
+}
+
+/// What templates and data may read
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    pub(crate) allowed_roots: Vec<PathBuf>,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) max_file_size: Option<usize>,
+    pub(crate) max_includes: Option<usize>,
+    pub(crate) network: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self {
+            allowed_roots: Vec::new(),
+            symlinks: SymlinkPolicy::Follow,
+            max_file_size: None,
+            max_includes: None,
+            network: true,
+        }
+    }
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also let includes read files below `dir`, besides the engine's root directory
+    pub fn allow_root(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.allowed_roots.push(dir.into());
+        self
+    }
+
+    /// Whether included files may be symlinks
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Reject templates, included files and data files larger than `limit` bytes
+    pub fn max_file_size(mut self, limit: usize) -> Self {
+        self.max_file_size = Some(limit);
+        self
+    }
+
+    /// Reject a render that reads more than `limit` included (or extended) files
+    pub fn max_includes(mut self, limit: usize) -> Self {
+        self.max_includes = Some(limit);
+        self
+    }
+
+    /// Allow or reject data that does not come from files: `scheme://` data sources and
+    /// the output of commands, either of which may reach the network
+    pub fn network(mut self, allowed: bool) -> Self {
+        self.network = allowed;
+        self
+    }
+
+    /// Fail with [`io::ErrorKind::InvalidData`] if `content` is over the file size limit
+    pub(crate) fn check_size(&self, content: &str) -> io::Result<()> {
+        match self.max_file_size {
+            Some(limit) if content.len() > limit => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "file is {} bytes, larger than the limit of {} bytes (--max-file-size)",
+                    content.len(),
+                    limit
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail with [`io::ErrorKind::PermissionDenied`] unless data may come from `what`
+    pub(crate) fn check_network(&self, what: &str) -> io::Result<()> {
+        if self.network {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not allowed without network access (--no-network)",
+                what
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_allows_everything() {
+        let sandbox = Sandbox::new();
+        assert!(sandbox.check_size(&"x".repeat(1 << 20)).is_ok());
+        assert!(sandbox.check_network("command output").is_ok());
+        assert_eq!(sandbox.symlinks, SymlinkPolicy::Follow);
+    }
+
+    #[test]
+    fn test_limits() {
+        let sandbox = Sandbox::new()
+            .allow_root("shared")
+            .max_file_size(4)
+            .network(false);
+        assert!(sandbox.check_size("1234").is_ok());
+        let error = sandbox.check_size("12345").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = sandbox.check_network("command output").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(sandbox.allowed_roots, [PathBuf::from("shared")]);
+    }
+}
@@ -0,0 +1,427 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::template::filters::escape_html;
+
+/// How often an idle live-reload connection is pinged, so closed pages are noticed
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// How long a client may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most bytes read of a request line and its headers together
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+
+/// How many connections are served at once; more are turned away with 503. Each open page
+/// holds one for its live-reload stream
+const MAX_CONNECTIONS: usize = 64;
+
+/// The page showing the latest render; it reloads itself when a new render is published
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title} - render-prompt preview</title>
+<style>
+body { margin: 0; font-family: sans-serif; }
+header { padding: 0.5em 1em; background: #eee; font-size: 0.9em; }
+pre { margin: 0; padding: 1em; white-space: pre-wrap; word-wrap: break-word; }
+pre.error { color: #b00; }
+</style>
+</head>
+<body>
+<header>{title} &middot; <a href="/raw">raw</a></header>
+<pre class="{class}">{body}</pre>
+<script>
+new EventSource("/events?version={version}").onmessage = () => location.reload();
+</script>
+</body>
+</html>
+"#;
+
+/// The latest render of a template, served over HTTP with live reload
+///
+/// `GET /` shows the output (or the error) in a page that reloads on every new render,
+/// `GET /raw` returns the output as plain text, and `GET /events` is the server-sent event
+/// stream the page listens to.
+pub struct Preview {
+    title: String,
+    addr: SocketAddr,
+    state: Mutex<Render>,
+    changed: Condvar,
+    /// Connections being served
+    connections: AtomicUsize,
+}
+
+/// The parts of a request the server looks at
+struct Request {
+    line: String,
+    host: Option<String>,
+}
+
+/// The output or error of the latest render, numbered so pages can tell they are stale
+struct Render {
+    version: u64,
+    result: Result<String, String>,
+}
+
+impl Preview {
+    /// Listen on `addr` and serve requests in the background, each on its own thread (up
+    /// to `MAX_CONNECTIONS` at once)
+    pub fn start(addr: SocketAddr, title: &str) -> io::Result<Arc<Self>> {
+        let listener = TcpListener::bind(addr)?;
+        let preview = Arc::new(Preview {
+            title: title.to_string(),
+            addr: listener.local_addr()?,
+            state: Mutex::new(Render {
+                version: 0,
+                result: Ok(String::new()),
+            }),
+            changed: Condvar::new(),
+            connections: AtomicUsize::new(0),
+        });
+
+        let server = Arc::clone(&preview);
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    server.connections.fetch_sub(1, Ordering::SeqCst);
+                    // The request is read first, as closing with it unread resets the
+                    // connection before the client sees the response
+                    let _ = stream
+                        .set_read_timeout(Some(Duration::from_secs(1)))
+                        .and_then(|_| read_request(&stream));
+                    let _ = respond(
+                        &mut stream,
+                        "503 Service Unavailable",
+                        "text/plain; charset=utf-8",
+                        "too many connections\n",
+                    );
+                    continue;
+                }
+                let preview = Arc::clone(&server);
+                std::thread::spawn(move || {
+                    // A client that went away is not an error worth reporting
+                    let _ = preview.handle(stream);
+                    preview.connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        Ok(preview)
+    }
+
+    /// The address the server listens on (with the actual port if 0 was requested)
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Serve a new render, reloading every open page
+    pub fn publish(&self, result: Result<String, String>) {
+        let mut state = self.state();
+        state.version += 1;
+        state.result = result;
+        self.changed.notify_all();
+    }
+
+    fn state(&self) -> MutexGuard<'_, Render> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let request = match read_request(&stream) {
+            Ok(request) => request,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let message = format!("{}\n", e);
+                respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain; charset=utf-8",
+                    &message,
+                )?;
+                // Closing with the rest of the request unread would reset the connection
+                // before the client sees the response, so a bounded amount more is read
+                stream.shutdown(std::net::Shutdown::Write)?;
+                io::copy(&mut (&stream).take(MAX_REQUEST_SIZE), &mut io::sink())?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        // Pages of other sites can reach the server through a name that resolves to it (DNS
+        // rebinding); their requests carry that name as the host
+        if !request
+            .host
+            .as_deref()
+            .is_some_and(|host| self.is_own_host(host))
+        {
+            return respond(
+                &mut stream,
+                "403 Forbidden",
+                "text/plain; charset=utf-8",
+                "unknown host\n",
+            );
+        }
+        let target = request.line.split_whitespace().nth(1).unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match path {
+            "/" => self.page(&mut stream),
+            "/raw" => self.raw(&mut stream),
+            "/events" => {
+                let seen = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("version="))
+                    .and_then(|version| version.parse().ok())
+                    .unwrap_or(0);
+                self.events(&mut stream, seen)
+            }
+            _ => respond(
+                &mut stream,
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                "not found\n",
+            ),
+        }
+    }
+
+    /// Whether `host` (a Host header) names this server: `localhost`, or the address it
+    /// listens on (any IP address when it listens on all of them)
+    fn is_own_host(&self, host: &str) -> bool {
+        let name = match host.strip_prefix('[') {
+            Some(bracketed) => bracketed.split_once(']').map_or(bracketed, |(ip, _)| ip),
+            None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+        };
+        if name.eq_ignore_ascii_case("localhost") {
+            return true;
+        }
+        name.parse::<IpAddr>()
+            .is_ok_and(|ip| ip == self.addr.ip() || self.addr.ip().is_unspecified())
+    }
+
+    fn page(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let html = {
+            let state = self.state();
+            let (class, text) = match &state.result {
+                Ok(output) => ("output", output),
+                Err(message) => ("error", message),
+            };
+            fill(
+                PAGE,
+                &[
+                    ("title", &escape_html(&self.title)),
+                    ("class", class),
+                    ("version", &state.version.to_string()),
+                    ("body", &escape_html(text)),
+                ],
+            )
+        };
+        respond(stream, "200 OK", "text/html; charset=utf-8", &html)
+    }
+
+    fn raw(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let (status, body) = match &self.state().result {
+            Ok(output) => ("200 OK", output.clone()),
+            Err(message) => ("500 Internal Server Error", format!("{}\n", message)),
+        };
+        respond(stream, status, "text/plain; charset=utf-8", &body)
+    }
+
+    /// Tell the page to reload once a render newer than `seen` is published
+    fn events(&self, stream: &mut TcpStream, seen: u64) -> io::Result<()> {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+        )?;
+        loop {
+            let version = {
+                let state = self.state();
+                let (state, _) = self
+                    .changed
+                    .wait_timeout_while(state, KEEPALIVE, |state| state.version == seen)
+                    .unwrap_or_else(PoisonError::into_inner);
+                state.version
+            };
+            if version != seen {
+                return stream.write_all(b"data: reload\n\n");
+            }
+            // Fails once the page is closed, ending this connection
+            stream.write_all(b": keepalive\n\n")?;
+        }
+    }
+}
+
+/// Read a request up to the end of its headers
+///
+/// A request whose headers do not end within `MAX_REQUEST_SIZE` bytes fails with
+/// [`io::ErrorKind::InvalidData`].
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_SIZE));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut host = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request header too large or incomplete",
+            ));
+        }
+        if header.trim().is_empty() {
+            return Ok(Request { line, host });
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+        }
+    }
+}
+
+/// Replace the `{name}` placeholders of `page` in one pass, so that placeholders in the
+/// values themselves (a rendered `{body}`, say) are left as they are
+fn fill(page: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(page.len());
+    let mut rest = page;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = values.iter().find(|(name, _)| {
+            rest[1..]
+                .strip_prefix(name)
+                .is_some_and(|after| after.starts_with('}'))
+        });
+        match placeholder {
+            Some((name, value)) => {
+                filled.push_str(value);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(preview: &Preview, target: &str) -> String {
+        let mut stream = TcpStream::connect(preview.addr()).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serves_latest_render() {
+        let preview = Preview::start("127.0.0.1:0".parse().unwrap(), "main.txt").unwrap();
+
+        preview.publish(Ok("1 < 2".to_string()));
+        let page = get(&preview, "/");
+        assert!(page.starts_with("HTTP/1.1 200 OK"));
+        assert!(page.contains("<pre class=\"output\">1 &lt; 2</pre>"));
+        assert!(page.contains("/events?version=1"));
+        assert!(get(&preview, "/raw").ends_with("\r\n\r\n1 < 2"));
+
+        preview.publish(Err("Include not found".to_string()));
+        assert!(get(&preview, "/").contains("<pre class=\"error\">Include not found</pre>"));
+        assert!(get(&preview, "/raw").starts_with("HTTP/1.1 500"));
+        assert!(get(&preview, "/missing").starts_with("HTTP/1.1 404"));
+
+        // A page showing an older render is told to reload right away
+        assert!(get(&preview, "/events?version=1").ends_with("data: reload\n\n"));
+    }
+
+    #[test]
+    fn test_placeholders_in_values() {
+        let preview = Preview::start("127.0.0.1:0".parse().unwrap(), "{body}.txt").unwrap();
+        preview.publish(Ok("{class} {title}".to_string()));
+        let page = get(&preview, "/");
+        assert!(page.contains("<title>{body}.txt - render-prompt preview</title>"));
+        assert!(page.contains("<pre class=\"output\">{class} {title}</pre>"));
+        assert!(page.contains("body { margin: 0;"));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let preview = Preview::start("127.0.0.1:0".parse().unwrap(), "main.txt").unwrap();
+        // Live-reload streams of pages that are up to date stay open
+        let streams: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| {
+                let mut stream = TcpStream::connect(preview.addr()).unwrap();
+                write!(
+                    stream,
+                    "GET /events?version=0 HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                )
+                .unwrap();
+                let mut header = [0; 12];
+                stream.read_exact(&mut header).unwrap();
+                stream
+            })
+            .collect();
+        assert!(get(&preview, "/").starts_with("HTTP/1.1 503"));
+
+        drop(streams);
+        preview.publish(Ok(String::new()));
+        let served = (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            get(&preview, "/").starts_with("HTTP/1.1 200")
+        });
+        assert!(served);
+    }
+
+    fn send(preview: &Preview, request: &str) -> String {
+        let mut stream = TcpStream::connect(preview.addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_rejects_other_hosts() {
+        let preview = Preview::start("127.0.0.1:0".parse().unwrap(), "main.txt").unwrap();
+        let request = |host: &str| send(&preview, &format!("GET /raw HTTP/1.1\r\n{}\r\n", host));
+        let port = preview.addr().port();
+        assert!(request(&format!("Host: localhost:{}\r\n", port)).starts_with("HTTP/1.1 200"));
+        assert!(request(&format!("host: 127.0.0.1:{}\r\n", port)).starts_with("HTTP/1.1 200"));
+        assert!(request("Host: attacker.example:80\r\n").starts_with("HTTP/1.1 403"));
+        assert!(request("Host: 10.0.0.1\r\n").starts_with("HTTP/1.1 403"));
+        assert!(request("").starts_with("HTTP/1.1 403"));
+    }
+
+    #[test]
+    fn test_rejects_large_requests() {
+        let preview = Preview::start("127.0.0.1:0".parse().unwrap(), "main.txt").unwrap();
+        let header = "a".repeat(MAX_REQUEST_SIZE as usize);
+        let response = send(
+            &preview,
+            &format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nX-Large: {}\r\n\r\n",
+                header
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+}
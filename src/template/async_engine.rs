@@ -0,0 +1,323 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use futures_util::future::join_all;
+
+use crate::error::RenderError;
+use crate::sandbox::SymlinkPolicy;
+
+use super::engine::TemplateEngine;
+use super::source::{lexically_within, IncludeSource};
+
+/// Where [`TemplateEngine::render_async`] reads the template and its includes from, for
+/// sources that are awaited (remote stores, HTTP, async databases)
+///
+/// Paths are joined and cleaned as for [`IncludeSource`]. Glob includes are not supported,
+/// since the files cannot be listed ahead of time.
+pub trait AsyncIncludeSource: Sync {
+    /// The content of the file at `path`
+    ///
+    /// A missing file must be reported as [`io::ErrorKind::NotFound`], so that optional
+    /// includes (`{{>? file }}`) can render nothing.
+    fn read(&self, path: &Path) -> impl Future<Output = io::Result<String>> + Send;
+
+    /// Whether `path` lies inside `root`
+    ///
+    /// The default compares the paths as written, which suits sources without symlinks.
+    fn is_within(&self, path: &Path, root: &Path) -> io::Result<bool> {
+        Ok(lexically_within(path, root))
+    }
+
+    /// Whether the file at `path` is a symlink, for sandboxes that deny them
+    ///
+    /// The default suits sources without symlinks.
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// Files read with `tokio::fs`, with symlinks resolved before the root check
+#[cfg(feature = "fs")]
+impl AsyncIncludeSource for super::source::FileSystemSource {
+    async fn read(&self, path: &Path) -> io::Result<String> {
+        tokio::fs::read_to_string(path).await
+    }
+
+    fn is_within(&self, path: &Path, root: &Path) -> io::Result<bool> {
+        IncludeSource::is_within(self, path, root)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        IncludeSource::is_symlink(self, path)
+    }
+}
+
+/// A file fetched from the async source: its content or how reading it failed
+type Fetched = Result<String, (io::ErrorKind, String)>;
+
+/// What is known about a file the engine asked for
+struct File {
+    content: Fetched,
+    /// Whether it lies inside the root or another root the sandbox allows
+    within: bool,
+    /// Whether it is a symlink, only checked when the sandbox denies them
+    symlink: bool,
+}
+
+/// The files fetched so far, handed to the synchronous engine
+///
+/// Files the engine asks for that have not been fetched yet are recorded and reported as
+/// missing, so a render attempt runs through to collect everything still to fetch.
+#[derive(Default)]
+struct Prefetched {
+    files: Mutex<BTreeMap<PathBuf, File>>,
+    pending: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl Prefetched {
+    fn get<T>(&self, path: &Path, fetched: impl FnOnce(&File) -> T) -> Option<T> {
+        let files = lock(&self.files);
+        let file = files.get(path);
+        if file.is_none() {
+            lock(&self.pending).insert(path.to_path_buf());
+        }
+        file.map(fetched)
+    }
+}
+
+/// Lock one of the sets of files, which are only ever extended with whole entries
+fn lock<T>(files: &Mutex<T>) -> MutexGuard<'_, T> {
+    files.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl IncludeSource for Prefetched {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        match self.get(path, |file| file.content.clone()) {
+            Some(Ok(content)) => Ok(content),
+            Some(Err((kind, message))) => Err(io::Error::new(kind, message)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "not fetched yet")),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.get(path, |file| file.content.is_ok()).unwrap_or(false)
+    }
+
+    fn is_within(&self, path: &Path, _root: &Path) -> io::Result<bool> {
+        // Unknown paths pass, so that the engine goes on to read them and they are
+        // recorded as pending. Fetching checks them before reading anything
+        Ok(self.get(path, |file| file.within).unwrap_or(true))
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.get(path, |file| file.symlink).unwrap_or(false)
+    }
+}
+
+impl TemplateEngine {
+    /// Render a template read from an async source, fetching its includes concurrently
+    ///
+    /// Includes are discovered level by level: every include the template needs that has
+    /// not been fetched yet is awaited at once, then the template is expanded again, until
+    /// nothing new is needed. The render itself is synchronous. The returned future is
+    /// `Send`, so it can be spawned on a multi-threaded runtime.
+    pub async fn render_async(
+        &self,
+        template_path: &Path,
+        data: &Value,
+        source: &impl AsyncIncludeSource,
+    ) -> Result<String, RenderError> {
+        let content = source
+            .read(template_path)
+            .await
+            .and_then(|content| self.sandbox().check_size(&content).map(|_| content))
+            .map_err(|e| RenderError::TemplateFileRead {
+                path: template_path.display().to_string(),
+                source: e,
+            })?;
+
+        let prefetched = Arc::new(Prefetched::default());
+        let engine = self
+            .clone()
+            .with_shared_source(Arc::clone(&prefetched) as Arc<dyn IncludeSource>);
+        loop {
+            // Checking expands every include, recording those still to fetch, without
+            // stopping at the first one that is missing
+            engine.check(&content, template_path, data);
+            let pending = std::mem::take(&mut *lock(&prefetched.pending));
+            if pending.is_empty() {
+                return engine.render_source(&content, template_path, data);
+            }
+
+            let roots: Vec<&Path> = std::iter::once(engine.root_dir())
+                .chain(self.sandbox().allowed_roots.iter().map(PathBuf::as_path))
+                .collect();
+            let roots = &roots;
+            let deny_symlinks = self.sandbox().symlinks == SymlinkPolicy::Deny;
+            let fetched = join_all(pending.into_iter().map(|path| async move {
+                let within = roots
+                    .iter()
+                    .any(|root| source.is_within(&path, root).unwrap_or(false));
+                let symlink = deny_symlinks && source.is_symlink(&path);
+                // Files the engine will refuse are never fetched
+                let content = if within && !symlink {
+                    source
+                        .read(&path)
+                        .await
+                        .map_err(|e| (e.kind(), e.to_string()))
+                } else {
+                    Err((io::ErrorKind::PermissionDenied, "not fetched".to_string()))
+                };
+                let file = File {
+                    content,
+                    within,
+                    symlink,
+                };
+                (path, file)
+            }))
+            .await;
+            lock(&prefetched.files).extend(fetched);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    #[cfg(feature = "fs")]
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    #[cfg(feature = "fs")]
+    use tempfile::tempdir;
+
+    /// Templates by name, counting the reads that are in flight at once
+    struct SlowSource {
+        files: BTreeMap<PathBuf, String>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        read: Mutex<Vec<PathBuf>>,
+    }
+
+    impl AsyncIncludeSource for SlowSource {
+        async fn read(&self, path: &Path) -> io::Result<String> {
+            self.read.lock().unwrap().push(path.to_path_buf());
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such template"))
+        }
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_render_async() {
+        let files = [
+            ("main.txt", "{{> a.txt }}{{> b.txt }}{{>? optional.txt }}"),
+            ("a.txt", "A{{> parts/c.txt }}"),
+            ("b.txt", "B({{ name }})"),
+            ("parts/c.txt", "C"),
+            ("escape.txt", "{{> ../secret.txt }}"),
+            ("../secret.txt", "secret"),
+        ];
+        let source = SlowSource {
+            files: files
+                .iter()
+                .map(|(name, content)| (PathBuf::from(name), content.to_string()))
+                .collect(),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+            read: Mutex::default(),
+        };
+        let engine = TemplateEngine::new(PathBuf::from("."), 20, true, false);
+        let data = json!({"name": "x"});
+
+        let output = block_on(engine.render_async(Path::new("main.txt"), &data, &source));
+        assert_eq!(output.unwrap(), "ACB(x)");
+        // a.txt, b.txt and optional.txt were awaited together
+        assert_eq!(source.max_in_flight.load(Ordering::SeqCst), 3);
+
+        let result = block_on(engine.render_async(Path::new("escape.txt"), &data, &source));
+        assert!(matches!(result, Err(RenderError::PathTraversal { .. })));
+        // Paths outside the root are rejected without being fetched
+        assert!(!source
+            .read
+            .lock()
+            .unwrap()
+            .contains(&PathBuf::from("../secret.txt")));
+        let result = block_on(engine.render_async(Path::new("missing.txt"), &data, &source));
+        assert!(matches!(result, Err(RenderError::TemplateFileRead { .. })));
+    }
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn test_render_async_is_send() {
+        let source = SlowSource {
+            files: BTreeMap::new(),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+            read: Mutex::default(),
+        };
+        let engine = TemplateEngine::builder().build();
+        let data = json!({});
+        assert_send(&engine.render_async(Path::new("main.txt"), &data, &source));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_render_async_from_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "=== {{ title }} ===").unwrap();
+        let template = dir.path().join("template.txt");
+        fs::write(&template, "{{> header.txt }}\n{{ body }}").unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, true, false);
+        let data = json!({"title": "T", "body": "text"});
+        let output =
+            block_on(engine.render_async(&template, &data, &super::super::FileSystemSource));
+        assert_eq!(output.unwrap(), "=== T ===\ntext");
+    }
+
+    #[cfg(all(unix, feature = "fs"))]
+    #[test]
+    fn test_render_async_denies_symlinks() {
+        use crate::sandbox::Sandbox;
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "A").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("a.txt"), dir.path().join("link.txt")).unwrap();
+        let template = dir.path().join("template.txt");
+        fs::write(&template, "{{> link.txt }}").unwrap();
+
+        let render = |policy| {
+            let engine = TemplateEngine::builder()
+                .root(dir.path())
+                .sandbox(Sandbox::new().symlinks(policy))
+                .build();
+            block_on(engine.render_async(&template, &json!({}), &super::super::FileSystemSource))
+        };
+        assert_eq!(render(SymlinkPolicy::Follow).unwrap(), "A");
+        match render(SymlinkPolicy::Deny) {
+            Err(RenderError::IncludeFileRead { source, .. }) => {
+                assert_eq!(source.kind(), io::ErrorKind::PermissionDenied)
+            }
+            other => panic!("Expected IncludeFileRead error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::error::RenderError;
+
+use super::parser::{self, Node};
+use super::source::IncludeSource;
+
+/// Included files and parsed templates, shared by every render of a batch
+///
+/// Rendering many templates that share partials, or one template once per record, would
+/// otherwise read and parse the same files again for each render. Nothing is ever
+/// invalidated, so a cache must not outlive the batch it was made for (e.g. across
+/// `--watch` re-renders).
+#[derive(Default)]
+pub struct TemplateCache {
+    files: Mutex<HashMap<PathBuf, String>>,
+    /// Node trees by the expanded template content they were parsed from
+    parsed: Mutex<HashMap<String, Arc<Vec<Node>>>>,
+}
+
+impl TemplateCache {
+    /// The content of a file, read from `source` on first use
+    pub fn read(&self, path: &Path, source: &dyn IncludeSource) -> io::Result<String> {
+        if let Some(content) = lock(&self.files).get(path) {
+            return Ok(content.clone());
+        }
+        let content = source.read(path)?;
+        lock(&self.files).insert(path.to_path_buf(), content.clone());
+        Ok(content)
+    }
+
+    /// The node tree of expanded template content, parsed on first use
+    pub fn parse(&self, content: &str) -> Result<Arc<Vec<Node>>, RenderError> {
+        if let Some(nodes) = lock(&self.parsed).get(content) {
+            return Ok(Arc::clone(nodes));
+        }
+        let nodes = Arc::new(parser::parse(content)?);
+        lock(&self.parsed).insert(content.to_string(), Arc::clone(&nodes));
+        Ok(nodes)
+    }
+}
+
+/// Lock one of the maps; a render that panicked while holding it left it consistent, as
+/// entries are only ever inserted whole
+fn lock<T>(map: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    map.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_files_are_read_once() {
+        use crate::template::source::FileSystemSource;
+        use std::fs;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("partial.txt");
+        fs::write(&file, "first").unwrap();
+
+        let cache = TemplateCache::default();
+        assert_eq!(cache.read(&file, &FileSystemSource).unwrap(), "first");
+        fs::write(&file, "second").unwrap();
+        assert_eq!(cache.read(&file, &FileSystemSource).unwrap(), "first");
+        assert!(cache
+            .read(&dir.path().join("missing.txt"), &FileSystemSource)
+            .is_err());
+    }
+
+    #[test]
+    fn test_templates_are_parsed_once() {
+        let cache = TemplateCache::default();
+        let first = cache.parse("Hello, {{ name }}!").unwrap();
+        let second = cache.parse("Hello, {{ name }}!").unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(cache.parse("{{#if x}}").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::{Location, RenderError};
+
+use super::expr::Expr;
+use super::filters::FilterRegistry;
+use super::include::blank_directives;
+use super::lexer::check_delimiters;
+use super::parser::{self, Node};
+use super::source_map::MappedText;
+
+/// Check the syntax of a single template file without rendering it
+///
+/// Include and `{{#extends}}` directives are skipped, as they are checked by resolving
+/// them, and every filter used must be in `filters`. Problems are reported with their
+/// position in the file `content` came from.
+pub fn check_syntax(content: &MappedText, filters: &FilterRegistry) -> Vec<RenderError> {
+    let mut problems = check_delimiters(content.as_str(), |offset| content.location(offset));
+
+    // Blanking keeps every offset, so the parser's positions map back into the file
+    let mut blanked = blank_directives(content.as_str());
+    loop {
+        let error = match parser::parse(&blanked) {
+            Ok(nodes) => {
+                let mut unknown = Vec::new();
+                unknown_filters(&nodes, filters, &mut unknown);
+                problems.extend(unknown.into_iter().map(|(name, offset)| {
+                    RenderError::VariableResolution {
+                        message: format!("unknown filter '{}'", name),
+                        location: content.location(offset),
+                    }
+                }));
+                break;
+            }
+            Err(error) => error,
+        };
+
+        // Blank the tag the error is about and parse again, to find the problems after it
+        let offset = match &error {
+            RenderError::TemplateSyntax { location, .. } => offset_of(&blanked, location),
+            _ => None,
+        };
+        let error = content.relocate(error);
+        // An unclosed `{{` usually also makes the parser fail at the same place
+        if !problems.iter().any(|p| position(p) == position(&error)) {
+            problems.push(error);
+        }
+        let Some(range) = offset
+            .map(|offset| offset..offset + parser::tag_source(&blanked, offset).len())
+            .filter(|range| !range.is_empty())
+        else {
+            break;
+        };
+        let blank: String = blanked[range.clone()]
+            .bytes()
+            .map(|b| if b == b'\n' { '\n' } else { ' ' })
+            .collect();
+        blanked.replace_range(range, &blank);
+    }
+
+    problems.sort_by_key(|problem| position(problem).unwrap_or((usize::MAX, usize::MAX)));
+    problems
+}
+
+/// The offset in `content` of a location found by the parser
+fn offset_of(content: &str, location: &Location) -> Option<usize> {
+    let line_start = if location.line <= 1 {
+        0
+    } else {
+        content.match_indices('\n').nth(location.line - 2)?.0 + 1
+    };
+    Some(line_start + location.column.checked_sub(1)?).filter(|&offset| offset <= content.len())
+}
+
+/// Collect the filters used in `nodes` that are not in `filters`, with the offset of the tag
+/// using them
+fn unknown_filters<'n>(
+    nodes: &'n [Node],
+    filters: &FilterRegistry,
+    unknown: &mut Vec<(&'n str, usize)>,
+) {
+    for node in nodes {
+        let mut check = |expr: &'n Expr, offset: usize| {
+            let names = expr.filter_names().into_iter();
+            unknown.extend(
+                names
+                    .filter(|name| !filters.contains(name))
+                    .map(|name| (name, offset)),
+            );
+        };
+        match node {
+            Node::Text(_) | Node::FragmentRef { .. } => {}
+            Node::Output { expr, offset } | Node::Set { expr, offset, .. } => check(expr, *offset),
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                for branch in branches {
+                    check(&branch.condition, branch.offset);
+                }
+                for branch in branches {
+                    unknown_filters(&branch.body, filters, unknown);
+                }
+                unknown_filters(otherwise, filters, unknown);
+            }
+            Node::With {
+                expr,
+                offset,
+                body,
+                otherwise,
+            } => {
+                check(expr, *offset);
+                unknown_filters(body, filters, unknown);
+                unknown_filters(otherwise, filters, unknown);
+            }
+            Node::Let {
+                bindings,
+                offset,
+                body,
+            } => {
+                for (_, expr) in bindings {
+                    check(expr, *offset);
+                }
+                unknown_filters(body, filters, unknown);
+            }
+            Node::Block { body, .. } | Node::Macro { body, .. } | Node::Fragment { body, .. } => {
+                unknown_filters(body, filters, unknown)
+            }
+        }
+    }
+}
+
+/// Line and column of a problem found in a template
+fn position(error: &RenderError) -> Option<(usize, usize)> {
+    match error {
+        RenderError::TemplateSyntax { location, .. }
+        | RenderError::MalformedDelimiter { location, .. }
+        | RenderError::VariableResolution { location, .. } => {
+            Some((location.line, location.column))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_syntax(content: &str) -> Vec<RenderError> {
+        super::check_syntax(
+            &MappedText::new(content, "main.txt"),
+            &FilterRegistry::with_builtins(),
+        )
+    }
+
+    fn locations(problems: &[RenderError]) -> Vec<(usize, usize)> {
+        problems
+            .iter()
+            .map(|problem| position(problem).expect("problem without a position"))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_valid_template() {
+        let content = "{{> header.txt }}\n{{#if user}}Hi {{ user.name }}{{/if}}\n\\{{ literal";
+        assert!(check_syntax(content).is_empty());
+        assert!(check_syntax("{{#raw}}{{ not closed{{/raw}}").is_empty());
+    }
+
+    #[test]
+    fn test_check_unclosed_tag() {
+        let problems = check_syntax("Hello {{ name\nand {{ other }}");
+        assert_eq!(locations(&problems), vec![(1, 7)]);
+        assert!(problems[0].to_string().contains("main.txt:1:7"));
+    }
+
+    #[test]
+    fn test_check_block_errors_point_into_file() {
+        let problems = check_syntax("{{> partial.txt }}\n\n  {{#unknown}}");
+        assert_eq!(locations(&problems), vec![(3, 3)]);
+        assert!(problems[0].to_string().contains("unknown block"));
+
+        let problems = check_syntax("{{#extends base.txt}}\n{{#if x}}");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].to_string().contains("unclosed"));
+    }
+
+    #[test]
+    fn test_check_reports_every_bad_tag() {
+        let content = "{{#nope}}\n{{ a + }}\nok {{ b }}\n{{/if}}";
+        let problems = check_syntax(content);
+        assert_eq!(locations(&problems), vec![(1, 1), (2, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn test_check_unknown_filters() {
+        let content = "{{ a | upper }}\n{{#if b | nofilter}}{{ c | trim | other }}{{/if}}";
+        let problems = check_syntax(content);
+        assert_eq!(locations(&problems), vec![(2, 1), (2, 21)]);
+        assert!(problems[0]
+            .to_string()
+            .contains("unknown filter 'nofilter'"));
+        assert!(problems[1].to_string().contains("unknown filter 'other'"));
+    }
+}
@@ -0,0 +1,267 @@
+use serde_json::Value;
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::RenderError;
+
+use super::engine::{front_matter, mapped_body, with_defaults, TemplateEngine};
+use super::include::Dependency;
+use super::parser::Node;
+use super::source_map::SourceMap;
+use super::variable::VariableSubstitutor;
+
+/// A template whose includes were resolved and whose syntax was parsed ahead of time, for
+/// rendering it many times with different data
+///
+/// Made by [`TemplateEngine::compile`]; renders with the settings of that engine. The
+/// included files are read once, so changes to them after compiling are not seen.
+/// Templates with dynamic include paths (`{{> (partials[model]) }}`) depend on the data
+/// to resolve their includes, so those are resolved again on every render.
+///
+/// ```
+/// use render_prompt::template::MemoryTemplateStore;
+/// use render_prompt::TemplateEngine;
+/// use serde_json::json;
+/// use std::path::Path;
+///
+/// let mut store = MemoryTemplateStore::new();
+/// store.insert("greeting.txt", "Hello, {{> name.txt }}!");
+/// store.insert("name.txt", "{{ name }}");
+///
+/// let engine = TemplateEngine::builder().include_source(store).build();
+/// let template = engine.compile(Path::new("greeting.txt")).unwrap();
+/// for name in ["Alice", "Bob"] {
+///     let output = template.render(&json!({ "name": name })).unwrap();
+///     assert_eq!(output, format!("Hello, {}!", name));
+/// }
+/// ```
+pub struct CompiledTemplate {
+    engine: TemplateEngine,
+    path: PathBuf,
+    /// The template as written, rendered from scratch when its includes depend on the data
+    content: String,
+    defaults: Option<Value>,
+    expanded: Option<Expanded>,
+}
+
+/// A template with its includes and layouts resolved
+struct Expanded {
+    text: String,
+    source_map: Arc<SourceMap>,
+    nodes: Arc<Vec<Node>>,
+    dependencies: Vec<Dependency>,
+}
+
+impl TemplateEngine {
+    /// Read a template and resolve its includes and syntax once, for rendering it many
+    /// times
+    pub fn compile(&self, template_path: &Path) -> Result<CompiledTemplate, RenderError> {
+        let content = self.read_source(template_path)?;
+        self.compile_source(content, template_path)
+    }
+
+    /// Compile template text that did not come from a file (see
+    /// [`render_source`](Self::render_source))
+    pub fn compile_source(
+        &self,
+        content: impl Into<String>,
+        template_path: &Path,
+    ) -> Result<CompiledTemplate, RenderError> {
+        let content = content.into();
+        let (body, defaults) = front_matter(&content, template_path)?;
+        let empty = Value::Object(Default::default());
+        let include_resolver = self.include_resolver(defaults.as_ref().unwrap_or(&empty));
+        let body = mapped_body(&content, body, template_path, self.delimiters());
+        let expanded = Self::expand_with(&include_resolver, &body, template_path);
+
+        // Includes chosen by the data cannot be resolved ahead of time
+        let expanded = if include_resolver.used_data() {
+            None
+        } else {
+            let expanded = expanded?;
+            self.check_strict_delimiters(&expanded)?;
+            let (text, source_map) = expanded.into_parts();
+            Some(Expanded {
+                nodes: self
+                    .cache()
+                    .parse(&text)
+                    .map_err(|e| source_map.relocate(&text, e))?,
+                text,
+                source_map: Arc::new(source_map),
+                dependencies: include_resolver.dependencies(),
+            })
+        };
+        tracing::info!("compiled {}", template_path.display());
+
+        Ok(CompiledTemplate {
+            engine: self.clone(),
+            path: template_path.to_path_buf(),
+            defaults,
+            expanded,
+            content,
+        })
+    }
+}
+
+impl CompiledTemplate {
+    /// Render the template with the given data
+    pub fn render(&self, data: &Value) -> Result<String, RenderError> {
+        match &self.expanded {
+            Some(expanded) => {
+                let data = self.data_for(data, expanded);
+                let output = self.substitutor(expanded).substitute_parsed(
+                    &expanded.nodes,
+                    &expanded.text,
+                    &data,
+                )?;
+                tracing::info!("rendered {}", self.path.display());
+                Ok(output)
+            }
+            None => self.engine.render_source(&self.content, &self.path, data),
+        }
+    }
+
+    /// Render the template with the given data, writing the output to `writer` as it is
+    /// rendered (see [`TemplateEngine::render_to_writer`])
+    pub fn render_to_writer(
+        &self,
+        data: &Value,
+        writer: &mut impl io::Write,
+    ) -> Result<(), RenderError> {
+        match &self.expanded {
+            Some(expanded) => {
+                let data = self.data_for(data, expanded);
+                self.substitutor(expanded).substitute_parsed_to_writer(
+                    &expanded.nodes,
+                    &expanded.text,
+                    &data,
+                    writer,
+                )?;
+                tracing::info!("rendered {}", self.path.display());
+                Ok(writer.flush()?)
+            }
+            None => self
+                .engine
+                .render_source_to_writer(&self.content, &self.path, data, writer),
+        }
+    }
+
+    /// The path the template was compiled from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The files the template includes or extends, or None when they depend on the data
+    pub fn dependencies(&self) -> Option<&[Dependency]> {
+        self.expanded
+            .as_ref()
+            .map(|expanded| expanded.dependencies.as_slice())
+    }
+
+    /// A variable substitutor reporting positions in the files the template was expanded
+    /// from
+    fn substitutor(&self, expanded: &Expanded) -> VariableSubstitutor {
+        self.engine
+            .substitutor()
+            .with_source_map(Some(Arc::clone(&expanded.source_map)))
+    }
+
+    /// The data of a render over the front matter defaults, counting the includes the
+    /// render uses as if they had been read
+    fn data_for<'d>(&self, data: &'d Value, expanded: &Expanded) -> Cow<'d, Value> {
+        self.engine
+            .stats()
+            .add_includes(expanded.dependencies.len());
+        with_defaults(self.defaults.as_ref(), data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "fs")]
+    use serde_json::json;
+    #[cfg(feature = "fs")]
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compile_renders_many_times() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "=== {{ title }} ===").unwrap();
+        let path = dir.path().join("template.txt");
+        fs::write(
+            &path,
+            "---\ntitle: Default\n---\n{{> header.txt }}\n{{ body }}",
+        )
+        .unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, true, false);
+        let template = engine.compile(&path).unwrap();
+        assert_eq!(template.dependencies().unwrap().len(), 1);
+
+        // Includes were read once: later changes are not seen
+        fs::write(dir.path().join("header.txt"), "changed").unwrap();
+        assert_eq!(
+            template.render(&json!({"body": "one"})).unwrap(),
+            "=== Default ===\none"
+        );
+        assert_eq!(
+            template
+                .render(&json!({"title": "T", "body": "two"}))
+                .unwrap(),
+            "=== T ===\ntwo"
+        );
+        assert!(matches!(
+            template.render(&json!({})),
+            Err(RenderError::UndefinedVariable { .. })
+        ));
+
+        let mut output = Vec::new();
+        template
+            .render_to_writer(&json!({"body": "three"}), &mut output)
+            .unwrap();
+        assert_eq!(output, b"=== Default ===\nthree");
+        assert_eq!(engine.stats().includes(), 4);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_compile_dynamic_include() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "A {{ name }}").unwrap();
+        fs::write(dir.path().join("b.txt"), "B {{ name }}").unwrap();
+        let path = dir.path().join("template.txt");
+        fs::write(&path, "{{> (partials[model]) }}").unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        let template = engine.compile(&path).unwrap();
+        assert!(template.dependencies().is_none());
+
+        let data = json!({"partials": {"x": "a.txt", "y": "b.txt"}, "name": "n"});
+        let render = |model| {
+            let mut data = data.clone();
+            data["model"] = json!(model);
+            template.render(&data).unwrap()
+        };
+        assert_eq!(render("x"), "A n");
+        assert_eq!(render("y"), "B n");
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let dir = tempdir().unwrap();
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+
+        let path = dir.path().join("template.txt");
+        let result = engine.compile_source("{{> missing.txt }}", &path);
+        assert!(matches!(result, Err(RenderError::IncludeFileRead { .. })));
+        let result = engine.compile_source("{{#if a}}", &path);
+        assert!(matches!(result, Err(RenderError::TemplateSyntax { .. })));
+        let result = engine.compile(&dir.path().join("missing.txt"));
+        assert!(matches!(result, Err(RenderError::TemplateFileRead { .. })));
+    }
+}
@@ -0,0 +1,218 @@
+use std::ops::Range;
+
+use crate::error::RenderError;
+
+use super::source_map::MappedText;
+
+/// The markers around template tags, `{{` and `}}` unless configured otherwise
+///
+/// Templates written with other delimiters are translated to the `{{ }}` syntax before
+/// anything else sees them: `<% name %>` becomes `{{ name }}`, an escaped `\<% name %>` is
+/// output as written, and text that happens to contain `{{` or `}}` is kept verbatim.
+/// Errors are reported at their position in the file as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiters {
+    open: String,
+    close: String,
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            open: "{{".to_string(),
+            close: "}}".to_string(),
+        }
+    }
+}
+
+impl Delimiters {
+    /// Tags opened with `open` and closed with `close`
+    ///
+    /// Fails with a usage error if either delimiter is empty or contains whitespace.
+    pub fn new(open: impl Into<String>, close: impl Into<String>) -> Result<Self, RenderError> {
+        let (open, close) = (open.into(), close.into());
+        for delimiter in [&open, &close] {
+            if delimiter.is_empty() || delimiter.contains(char::is_whitespace) {
+                return Err(RenderError::Usage(format!(
+                    "invalid template delimiter '{}': delimiters must be non-empty and \
+                     contain no whitespace",
+                    delimiter
+                )));
+            }
+        }
+        Ok(Self { open, close })
+    }
+
+    pub fn open(&self) -> &str {
+        &self.open
+    }
+
+    pub fn close(&self) -> &str {
+        &self.close
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        self.open == "{{" && self.close == "}}"
+    }
+
+    /// Rewrite `content` from these delimiters to `{{ }}`
+    ///
+    /// Text and tag contents keep their position in the file; the `{{ }}` written in place
+    /// of a delimiter is reported where that delimiter is.
+    pub(crate) fn translate(&self, content: MappedText) -> MappedText {
+        if self.is_default() {
+            return content;
+        }
+
+        let text = content.as_str();
+        let mut translated = MappedText::default();
+        let mut pos = 0;
+        while let Some(found) = text[pos..].find(&self.open) {
+            let start = pos + found;
+            let after = start + self.open.len();
+            if start > pos && text[..start].ends_with('\\') {
+                // An escaped tag is output as written, without the backslash
+                push_text(&mut translated, &content, pos..start - 1);
+                let end = text[after..]
+                    .find(&self.close)
+                    .map_or(after, |end| after + end + self.close.len());
+                push_raw(&mut translated, &content, start..end);
+                pos = end;
+                continue;
+            }
+            push_text(&mut translated, &content, pos..start);
+
+            let Some(end) = text[after..].find(&self.close).map(|end| after + end) else {
+                // Left for the parser (or --strict) to report
+                translated.push_generated("{{", &content.location(start));
+                pos = after;
+                continue;
+            };
+            pos = end + self.close.len();
+            if text[after..end].trim() == "#raw" {
+                if let Some((raw_end, resume)) = self.find_raw_end(text, pos) {
+                    push_raw(&mut translated, &content, pos..raw_end);
+                    pos = resume;
+                    continue;
+                }
+            }
+            translated.push_generated("{{", &content.location(start));
+            translated.push_mapped(&content, after..end);
+            translated.push_generated("}}", &content.location(end));
+        }
+        push_text(&mut translated, &content, pos..text.len());
+        translated
+    }
+
+    /// Where the raw block whose content starts at `pos` ends, and where the text after its
+    /// closing `/raw` tag starts
+    fn find_raw_end(&self, text: &str, mut pos: usize) -> Option<(usize, usize)> {
+        while let Some(found) = text[pos..].find(&self.open) {
+            let start = pos + found;
+            let after = start + self.open.len();
+            let end = after + text[after..].find(&self.close)?;
+            if text[after..end].trim() == "/raw" {
+                return Some((start, end + self.close.len()));
+            }
+            pos = after;
+        }
+        None
+    }
+}
+
+/// Append text between tags, keeping anything `{{ }}` syntax would read as a tag verbatim
+fn push_text(translated: &mut MappedText, content: &MappedText, range: Range<usize>) {
+    let text = &content.as_str()[range.clone()];
+    if text.contains("{{") || text.contains("}}") || text.ends_with(['{', '\\']) {
+        push_raw(translated, content, range);
+    } else {
+        translated.push_mapped(content, range);
+    }
+}
+
+/// Append text in `{{#raw}}` blocks, so it is output verbatim
+///
+/// A `{{/raw` inside the text would end the block early, so it is written as a string
+/// literal between two blocks.
+fn push_raw(translated: &mut MappedText, content: &MappedText, range: Range<usize>) {
+    let mut start = range.start;
+    for (offset, _) in content.as_str()[range.clone()].match_indices("{{/raw") {
+        let end = range.start + offset;
+        push_raw_block(translated, content, start..end);
+        translated.push_generated("{{ '{{/raw' }}", &content.location(end));
+        start = end + "{{/raw".len();
+    }
+    push_raw_block(translated, content, start..range.end);
+}
+
+fn push_raw_block(translated: &mut MappedText, content: &MappedText, range: Range<usize>) {
+    translated.push_generated("{{#raw}}", &content.location(range.start));
+    translated.push_mapped(content, range.clone());
+    translated.push_generated("{{/raw}}", &content.location(range.end));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(content: &str) -> String {
+        Delimiters::new("<%", "%>")
+            .unwrap()
+            .translate(MappedText::new(content, "main.txt"))
+            .as_str()
+            .to_string()
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate("Hello, <% name %>!"), "Hello, {{ name }}!");
+        assert_eq!(
+            translate("<%#if a %>x<%else%>y<%/if%>"),
+            "{{#if a }}x{{else}}y{{/if}}"
+        );
+        assert_eq!(translate("<%> partial.md %>"), "{{> partial.md }}");
+        assert_eq!(translate("no tags\n"), "no tags\n");
+    }
+
+    #[test]
+    fn test_translate_keeps_braces_as_text() {
+        assert_eq!(
+            translate("{{ a }} <% b %>"),
+            "{{#raw}}{{ a }} {{/raw}}{{ b }}"
+        );
+        assert_eq!(translate("{<% b %>"), "{{#raw}}{{{/raw}}{{ b }}");
+        assert_eq!(translate(r"\<% b %> c"), "{{#raw}}<% b %>{{/raw}} c");
+        assert_eq!(
+            translate("<%#raw%><% a %> {{ b }}<%/raw%>"),
+            "{{#raw}}<% a %> {{ b }}{{/raw}}"
+        );
+        assert_eq!(translate("<% a"), "{{ a");
+    }
+
+    #[test]
+    fn test_default_is_unchanged() {
+        let content = r"{{ a }} \{{ b }} <% c %>";
+        let translated = Delimiters::default().translate(MappedText::new(content, "main.txt"));
+        assert_eq!(translated.as_str(), content);
+    }
+
+    #[test]
+    fn test_translate_keeps_positions() {
+        let translated = Delimiters::new("<%", "%>")
+            .unwrap()
+            .translate(MappedText::new("{{ a }}\n  <% b %>", "main.txt"));
+        assert_eq!(translated.as_str(), "{{#raw}}{{ a }}\n  {{/raw}}{{ b }}");
+        let b = translated.as_str().rfind('b').unwrap();
+        let location = translated.location(b);
+        assert_eq!((location.line, location.column), (2, 6));
+        let open = translated.location(translated.as_str().rfind("{{ b").unwrap());
+        assert_eq!((open.line, open.column), (2, 3));
+    }
+
+    #[test]
+    fn test_invalid_delimiters() {
+        assert!(Delimiters::new("", "%>").is_err());
+        assert!(Delimiters::new("<% ", "%>").is_err());
+        assert!(Delimiters::new("[[", "]]").is_ok());
+    }
+}
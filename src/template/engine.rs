@@ -1,17 +1,70 @@
-use crate::error::RenderError;
+use crate::data::{DataLoader, DataMerger};
+use crate::error::{Location, RenderError};
+use crate::sandbox::Sandbox;
 use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashSet;
+#[cfg(feature = "fs")]
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
 
-use super::include::IncludeResolver;
-use super::variable::VariableSubstitutor;
+use super::cache::TemplateCache;
+use super::check::check_syntax;
+use super::delimiters::Delimiters;
+use super::filters::FilterRegistry;
+use super::include::{Dependency, IncludeResolver};
+use super::lexer::check_delimiters;
+use super::report::{RenderReport, TRACE_TARGET};
+use super::source::{default_source, IncludeSource};
+use super::source_map::MappedText;
+use super::stats::RenderStats;
+use super::variable::{MissingFn, MissingPolicy, VariableSubstitutor};
+use super::{parser, vars};
 
+/// Include depth allowed unless configured otherwise (the default of --max-include-depth)
+pub const DEFAULT_MAX_DEPTH: usize = 20;
+
+/// Stand-in for `std::time::Instant`, which panics in the browser: render timings are
+/// reported as zero there
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+struct Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Instant {
+    fn now() -> Self {
+        Instant
+    }
+
+    fn elapsed(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
+
+/// Clones share the filters, include source, cache and stats of the original
+#[derive(Clone)]
 pub struct TemplateEngine {
     root_dir: PathBuf,
     max_depth: usize,
-    strict: bool,
+    missing: MissingPolicy,
     warn_undefined: bool,
+    now_helper: bool,
+    escape_html: bool,
+    placeholder: Option<String>,
+    max_output_size: Option<usize>,
+    includes_allowed: bool,
+    filters: Arc<FilterRegistry>,
+    source: Arc<dyn IncludeSource>,
+    cache: Arc<TemplateCache>,
+    stats: Arc<RenderStats>,
+    missing_fn: Option<Arc<MissingFn>>,
+    sandbox: Arc<Sandbox>,
+    delimiters: Arc<Delimiters>,
 }
 
 impl TemplateEngine {
@@ -19,48 +72,679 @@ impl TemplateEngine {
         Self {
             root_dir,
             max_depth,
-            strict,
+            missing: if strict {
+                MissingPolicy::Error
+            } else {
+                MissingPolicy::Empty
+            },
             warn_undefined,
+            now_helper: true,
+            escape_html: false,
+            placeholder: None,
+            max_output_size: None,
+            includes_allowed: true,
+            filters: Arc::default(),
+            source: default_source(),
+            cache: Arc::default(),
+            stats: Arc::default(),
+            missing_fn: None,
+            sandbox: Arc::default(),
+            delimiters: Arc::default(),
+        }
+    }
+
+    /// Configure an engine with named settings instead of positional parameters
+    ///
+    /// ```
+    /// use render_prompt::TemplateEngine;
+    ///
+    /// let engine = TemplateEngine::builder()
+    ///     .root("prompts")
+    ///     .max_depth(5)
+    ///     .strict(true)
+    ///     .build();
+    /// ```
+    pub fn builder() -> TemplateEngineBuilder {
+        TemplateEngineBuilder {
+            engine: Self::new(PathBuf::from("."), DEFAULT_MAX_DEPTH, false, false),
         }
     }
 
+    /// Set what undefined variables render as, in place of the `strict` flag
+    pub fn with_missing(mut self, missing: MissingPolicy) -> Self {
+        self.missing = missing;
+        self
+    }
+
+    /// Enable or disable the `now` helper (disable for deterministic output)
+    pub fn with_now_helper(mut self, enabled: bool) -> Self {
+        self.now_helper = enabled;
+        self
+    }
+
+    /// HTML-escape substituted values
+    pub fn with_escape_html(mut self, enabled: bool) -> Self {
+        self.escape_html = enabled;
+        self
+    }
+
+    /// Render undefined variables as this text (`{name}` is replaced by the variable's path)
+    pub fn with_placeholder(mut self, placeholder: Option<String>) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    /// Abort rendering once the output grows past `limit` bytes
+    pub fn with_max_output_size(mut self, limit: Option<usize>) -> Self {
+        self.max_output_size = limit;
+        self
+    }
+
+    /// Make the filters of `registry` available to templates, in place of the built-in set
+    pub fn with_filters(mut self, registry: FilterRegistry) -> Self {
+        self.filters = Arc::new(registry);
+        self
+    }
+
+    /// Ask `callback` for the value of each undefined variable, with its name and where it
+    /// is referenced, e.g. to look up secrets on demand
+    ///
+    /// A value returned is used as a string (filters and conditions see it too); None leaves
+    /// the variable to the missing variable policy.
+    pub fn with_missing_fn(
+        mut self,
+        callback: impl Fn(&str, &Location) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.missing_fn = Some(Arc::new(callback));
+        self
+    }
+
+    /// Read included files from `source` instead of the filesystem
+    pub fn with_include_source(mut self, source: impl IncludeSource + 'static) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    /// Enforce the limits of `sandbox` on the template and the files it includes
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Arc::new(sandbox);
+        self
+    }
+
+    /// Directory includes are resolved from and confined to
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    pub(crate) fn delimiters(&self) -> &Delimiters {
+        &self.delimiters
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn sandbox(&self) -> &Sandbox {
+        &self.sandbox
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn with_shared_source(mut self, source: Arc<dyn IncludeSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Share read files and parsed templates with other engines rendering the same batch
+    pub fn with_cache(mut self, cache: Arc<TemplateCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// What the renders of this engine did so far
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    /// Allow or reject includes, e.g. for a template read from stdin without a root directory
+    pub fn with_includes_allowed(mut self, allowed: bool) -> Self {
+        self.includes_allowed = allowed;
+        self
+    }
+
     /// Render a template with the given data
     ///
     /// Processing order (as specified):
-    /// 1. Load template
-    /// 2. Resolve includes (recursively)
+    /// 1. Load template, taking default data from its front matter
+    /// 2. Resolve includes (recursively), then `{{#extends}}` layouts
     /// 3. Substitute variables (once)
     /// 4. Unescape \{{ -> {{
     pub fn render(&self, template_path: &Path, data: &Value) -> Result<String, RenderError> {
-        // 1. Load template
-        let content =
-            fs::read_to_string(template_path).map_err(|e| RenderError::TemplateFileRead {
+        let content = self.read_source(template_path)?;
+        self.render_source(&content, template_path, data)
+    }
+
+    /// Render a template with the given data, writing the output to `writer` as it is
+    /// rendered
+    ///
+    /// Unlike [`render`](Self::render), the output is never held in memory as a whole, so
+    /// `writer` should be buffered. If rendering fails, the output up to the error has
+    /// already been written; in strict mode that is the whole output, with undefined
+    /// variables left empty, as the render goes on to find all of them.
+    pub fn render_to_writer(
+        &self,
+        template_path: &Path,
+        data: &Value,
+        writer: &mut impl io::Write,
+    ) -> Result<(), RenderError> {
+        let content = self.read_source(template_path)?;
+        self.render_source_to_writer(&content, template_path, data, writer)
+    }
+
+    /// Load a template, from the same source as its includes and within the size limit
+    /// of the sandbox
+    pub fn read_source(&self, template_path: &Path) -> Result<String, RenderError> {
+        self.source
+            .read(template_path)
+            .and_then(|content| self.sandbox.check_size(&content).map(|_| content))
+            .map_err(|e| RenderError::TemplateFileRead {
                 path: template_path.display().to_string(),
                 source: e,
-            })?;
+            })
+    }
+
+    /// Render template text that did not come from a file (e.g. stdin)
+    ///
+    /// `template_path` names the template in error messages, and relative includes are
+    /// resolved from its directory.
+    pub fn render_source(
+        &self,
+        content: &str,
+        template_path: &Path,
+        data: &Value,
+    ) -> Result<String, RenderError> {
+        self.render_with(
+            content,
+            template_path,
+            data,
+            None,
+            |substitutor, expanded, data| substitutor.substitute(expanded, data),
+        )
+    }
+
+    /// Render a template with the given data, also reporting the files it included and
+    /// the variables it substituted or found undefined
+    pub fn render_with_report(
+        &self,
+        template_path: &Path,
+        data: &Value,
+    ) -> Result<(String, RenderReport), RenderError> {
+        let content = self.read_source(template_path)?;
+        self.render_source_with_report(&content, template_path, data)
+    }
+
+    /// Render template text that did not come from a file, also reporting what the render
+    /// used (see [`render_with_report`](Self::render_with_report))
+    pub fn render_source_with_report(
+        &self,
+        content: &str,
+        template_path: &Path,
+        data: &Value,
+    ) -> Result<(String, RenderReport), RenderError> {
+        let report = Rc::new(RefCell::new(RenderReport::default()));
+        let output = self.render_with(
+            content,
+            template_path,
+            data,
+            Some(Rc::clone(&report)),
+            |substitutor, expanded, data| substitutor.substitute(expanded, data),
+        )?;
+        Ok((output, report.take()))
+    }
+
+    /// Render template text that did not come from a file, writing the output to `writer`
+    /// as it is rendered (see [`render_to_writer`](Self::render_to_writer))
+    pub fn render_source_to_writer(
+        &self,
+        content: &str,
+        template_path: &Path,
+        data: &Value,
+        writer: &mut impl io::Write,
+    ) -> Result<(), RenderError> {
+        self.render_with(
+            content,
+            template_path,
+            data,
+            None,
+            |substitutor, expanded, data| {
+                substitutor.substitute_to_writer(expanded, data, writer)?;
+                Ok(writer.flush()?)
+            },
+        )
+    }
+
+    /// Expand includes, then substitute variables with `substitute`, recording what the
+    /// render used in `report`
+    fn render_with<T>(
+        &self,
+        content: &str,
+        template_path: &Path,
+        data: &Value,
+        report: Option<Rc<RefCell<RenderReport>>>,
+        substitute: impl FnOnce(&VariableSubstitutor, &str, &Value) -> Result<T, RenderError>,
+    ) -> Result<T, RenderError> {
+        let started = Instant::now();
+        tracing::trace!(target: TRACE_TARGET, depth = 0, path = %template_path.display(), "include");
+        let (body, data) = with_front_matter(content, template_path, data)?;
+        let data = data.as_ref();
 
         // 2. Resolve includes
-        let include_resolver = IncludeResolver::new(&self.root_dir, self.max_depth);
-        let mut visited = HashSet::new();
-        let expanded = include_resolver.resolve(&content, template_path, &mut visited, 0)?;
+        let body = mapped_body(content, body, template_path, &self.delimiters);
+        let (expanded, dependencies) = self.expand(&body, template_path, data)?;
+        self.check_strict_delimiters(&expanded)?;
+        self.stats.add_includes(dependencies.len());
+        tracing::debug!(elapsed = ?started.elapsed(), "resolved includes");
+        if let Some(report) = &report {
+            report.borrow_mut().includes = dependencies;
+        }
 
-        // 3. Substitute variables
-        let variable_substitutor = VariableSubstitutor::new(self.strict, self.warn_undefined);
-        let substituted = variable_substitutor.substitute(&expanded, data)?;
+        // 3. Substitute variables, reporting positions in the file each region came from
+        let (expanded, source_map) = expanded.into_parts();
+        let substitutor = self
+            .substitutor()
+            .with_report(report)
+            .with_source_map(Some(Arc::new(source_map)));
+        let substituted = substitute(&substitutor, &expanded, data)?;
+        tracing::info!(elapsed = ?started.elapsed(), "rendered {}", template_path.display());
 
         // 4. Unescape \{{ -> {{
         // This is already handled in the VariableSubstitutor, so we just return
         Ok(substituted)
     }
+
+    /// List the files a template includes or extends, directly or indirectly, without
+    /// rendering it
+    ///
+    /// The data is only needed to evaluate dynamic include paths.
+    pub fn dependencies(
+        &self,
+        content: &str,
+        template_path: &Path,
+        data: &Value,
+    ) -> Result<Vec<Dependency>, RenderError> {
+        let (body, data) = with_front_matter(content, template_path, data)?;
+        let body = mapped_body(content, body, template_path, &self.delimiters);
+        let (_, dependencies) = self.expand(&body, template_path, &data)?;
+        Ok(dependencies)
+    }
+
+    /// List the data paths a template and everything it includes refer to, without
+    /// rendering it
+    ///
+    /// The data is only needed to evaluate dynamic include paths.
+    pub fn variables(
+        &self,
+        content: &str,
+        template_path: &Path,
+        data: &Value,
+    ) -> Result<Vec<String>, RenderError> {
+        let (body, data) = with_front_matter(content, template_path, data)?;
+        let body = mapped_body(content, body, template_path, &self.delimiters);
+        let (expanded, _) = self.expand(&body, template_path, &data)?;
+        let (expanded, source_map) = expanded.into_parts();
+        let nodes = parser::parse(&expanded).map_err(|e| source_map.relocate(&expanded, e))?;
+        Ok(vars::referenced_variables(&nodes))
+    }
+
+    /// Check a template and everything it includes without rendering it
+    ///
+    /// Reports the syntax problems of every file and every include that cannot be resolved,
+    /// instead of stopping at the first problem. An empty list means the template is valid.
+    pub fn check(&self, content: &str, template_path: &Path, data: &Value) -> Vec<RenderError> {
+        let mut problems = Vec::new();
+
+        let (body, data) = match with_front_matter(content, template_path, data) {
+            Ok(result) => result,
+            Err(e) => {
+                problems.push(e);
+                (content, Cow::Borrowed(data))
+            }
+        };
+
+        // Resolve includes, recording every broken one
+        let include_resolver = IncludeResolver::new(&self.root_dir, self.max_depth)
+            .with_data(data.into_owned())
+            .with_includes_allowed(self.includes_allowed)
+            .with_source(Arc::clone(&self.source))
+            .with_sandbox(Arc::clone(&self.sandbox))
+            .with_delimiters(Arc::clone(&self.delimiters))
+            .with_filters(Arc::clone(&self.filters))
+            .with_errors_collected(true);
+        let body = mapped_body(content, body, template_path, &self.delimiters);
+        let mut visited = HashSet::new();
+        let resolved = include_resolver
+            .resolve_mapped(&body, template_path, &mut visited, 0)
+            .and_then(|expanded| {
+                include_resolver.resolve_extends_mapped(&expanded, template_path, &mut visited, 0)
+            });
+        problems.extend(include_resolver.take_errors());
+        if let Err(e) = resolved {
+            problems.push(e);
+        }
+
+        // Check the syntax of each file on its own, so problems point into the right file
+        problems.extend(check_syntax(&body, &self.filters));
+
+        let mut checked = HashSet::new();
+        for dependency in include_resolver.dependencies() {
+            if dependency.verbatim || !checked.insert(dependency.path.clone()) {
+                continue;
+            }
+            if let Ok(content) = self.source.read(&dependency.path) {
+                let content = MappedText::new(&content, &dependency.path.display().to_string());
+                problems.extend(check_syntax(
+                    &self.delimiters.translate(content),
+                    &self.filters,
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Read a template file
+    #[cfg(feature = "fs")]
+    pub fn read_template(template_path: &Path) -> Result<String, RenderError> {
+        fs::read_to_string(template_path).map_err(|e| RenderError::TemplateFileRead {
+            path: template_path.display().to_string(),
+            source: e,
+        })
+    }
+
+    /// Resolve includes, then `{{#extends}}` layouts, returning the files that were read
+    fn expand(
+        &self,
+        content: &MappedText,
+        template_path: &Path,
+        data: &Value,
+    ) -> Result<(MappedText, Vec<Dependency>), RenderError> {
+        let include_resolver = self.include_resolver(data);
+        let expanded = Self::expand_with(&include_resolver, content, template_path)?;
+        Ok((expanded, include_resolver.dependencies()))
+    }
+
+    /// In strict mode, fail on the first malformed delimiter of an expanded template,
+    /// reported in the file it is in
+    pub(crate) fn check_strict_delimiters(&self, expanded: &MappedText) -> Result<(), RenderError> {
+        if self.missing != MissingPolicy::Error {
+            return Ok(());
+        }
+        let problems = check_delimiters(expanded.as_str(), |offset| expanded.location(offset));
+        match problems.into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// An include resolver with this engine's settings, evaluating dynamic paths against
+    /// `data`
+    pub(crate) fn include_resolver(&self, data: &Value) -> IncludeResolver {
+        IncludeResolver::new(&self.root_dir, self.max_depth)
+            .with_data(data.clone())
+            .with_includes_allowed(self.includes_allowed)
+            .with_source(Arc::clone(&self.source))
+            .with_sandbox(Arc::clone(&self.sandbox))
+            .with_delimiters(Arc::clone(&self.delimiters))
+            .with_filters(Arc::clone(&self.filters))
+            .with_cache(Arc::clone(&self.cache))
+    }
+
+    /// Resolve includes, then `{{#extends}}` layouts, with `include_resolver`
+    pub(crate) fn expand_with(
+        include_resolver: &IncludeResolver,
+        content: &MappedText,
+        template_path: &Path,
+    ) -> Result<MappedText, RenderError> {
+        let mut visited = HashSet::new();
+        let expanded = include_resolver.resolve_mapped(content, template_path, &mut visited, 0)?;
+        include_resolver.resolve_extends_mapped(&expanded, template_path, &mut visited, 0)
+    }
+
+    /// A variable substitutor with this engine's settings
+    pub(crate) fn substitutor(&self) -> VariableSubstitutor {
+        VariableSubstitutor::new(false, self.warn_undefined)
+            .with_missing(self.missing)
+            .with_now_helper(self.now_helper)
+            .with_escape_html(self.escape_html)
+            .with_placeholder(self.placeholder.clone())
+            .with_max_output_size(self.max_output_size)
+            .with_filters(Arc::clone(&self.filters))
+            .with_cache(Arc::clone(&self.cache))
+            .with_stats(Arc::clone(&self.stats))
+            .with_missing_fn(self.missing_fn.clone())
+    }
+
+    /// Files read and templates parsed by this engine
+    pub(crate) fn cache(&self) -> &TemplateCache {
+        &self.cache
+    }
+}
+
+/// Named settings for a [`TemplateEngine`], from [`TemplateEngine::builder`]
+///
+/// Anything not set keeps the default of [`TemplateEngine::new`]: includes resolved from
+/// the current directory up to [`DEFAULT_MAX_DEPTH`] deep, undefined variables rendered
+/// empty without warnings, and the built-in filters.
+pub struct TemplateEngineBuilder {
+    engine: TemplateEngine,
+}
+
+impl TemplateEngineBuilder {
+    /// Directory includes are resolved from and confined to
+    pub fn root(mut self, root_dir: impl Into<PathBuf>) -> Self {
+        self.engine.root_dir = root_dir.into();
+        self
+    }
+
+    /// Maximum include depth
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.engine.max_depth = max_depth;
+        self
+    }
+
+    /// Treat undefined variables as errors (the same as `missing(MissingPolicy::Error)`)
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.engine.missing = if strict {
+            MissingPolicy::Error
+        } else {
+            MissingPolicy::Empty
+        };
+        self
+    }
+
+    /// What undefined variables render as
+    pub fn missing(mut self, missing: MissingPolicy) -> Self {
+        self.engine.missing = missing;
+        self
+    }
+
+    /// Warn about undefined variables that are rendered anyway
+    pub fn warn_undefined(mut self, warn: bool) -> Self {
+        self.engine.warn_undefined = warn;
+        self
+    }
+
+    /// Render undefined variables as this text (`{name}` is replaced by the variable's path)
+    pub fn placeholder(mut self, placeholder: impl Into<String>) -> Self {
+        self.engine.placeholder = Some(placeholder.into());
+        self
+    }
+
+    /// Enable or disable the `now` helper (disable for deterministic output)
+    pub fn now_helper(mut self, enabled: bool) -> Self {
+        self.engine.now_helper = enabled;
+        self
+    }
+
+    /// HTML-escape substituted values
+    pub fn escape_html(mut self, enabled: bool) -> Self {
+        self.engine.escape_html = enabled;
+        self
+    }
+
+    /// Abort rendering once the output grows past `limit` bytes
+    pub fn max_output_size(mut self, limit: usize) -> Self {
+        self.engine.max_output_size = Some(limit);
+        self
+    }
+
+    /// Allow or reject includes
+    pub fn includes_allowed(mut self, allowed: bool) -> Self {
+        self.engine.includes_allowed = allowed;
+        self
+    }
+
+    /// Filters available to templates, in place of the built-in set
+    pub fn filters(mut self, registry: FilterRegistry) -> Self {
+        self.engine.filters = Arc::new(registry);
+        self
+    }
+
+    /// Callback asked for the value of undefined variables (see
+    /// [`TemplateEngine::with_missing_fn`])
+    pub fn missing_fn(
+        mut self,
+        callback: impl Fn(&str, &Location) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.engine = self.engine.with_missing_fn(callback);
+        self
+    }
+
+    /// Limits on the template and the files it includes (see [`Sandbox`])
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.engine = self.engine.with_sandbox(sandbox);
+        self
+    }
+
+    /// Where included files are read from, in place of the filesystem
+    pub fn include_source(mut self, source: impl IncludeSource + 'static) -> Self {
+        self.engine.source = Arc::new(source);
+        self
+    }
+
+    /// Share read files and parsed templates with other engines
+    pub fn cache(mut self, cache: Arc<TemplateCache>) -> Self {
+        self.engine.cache = cache;
+        self
+    }
+
+    /// Mark tags with `delimiters` instead of `{{ }}`, in the template and everything it
+    /// includes
+    pub fn delimiters(mut self, delimiters: Delimiters) -> Self {
+        self.engine.delimiters = Arc::new(delimiters);
+        self
+    }
+
+    /// The configured engine
+    pub fn build(self) -> TemplateEngine {
+        self.engine
+    }
+}
+
+/// The body of a template after its front matter, in `{{ }}` syntax, mapped to where it
+/// starts in the file
+pub(crate) fn mapped_body(
+    content: &str,
+    body: &str,
+    template_path: &Path,
+    delimiters: &Delimiters,
+) -> MappedText {
+    let start = Location::from_offset(
+        content,
+        content.len() - body.len(),
+        &template_path.display().to_string(),
+    );
+    delimiters.translate(MappedText::starting_at(body, &start))
+}
+
+/// Strip the template's front matter, merging its defaults under the data
+///
+/// Front matter defaults have the lowest precedence.
+fn with_front_matter<'c, 'd>(
+    content: &'c str,
+    template_path: &Path,
+    data: &'d Value,
+) -> Result<(&'c str, Cow<'d, Value>), RenderError> {
+    let (body, defaults) = front_matter(content, template_path)?;
+    Ok((body, with_defaults(defaults.as_ref(), data)))
+}
+
+/// Split the template body from the default data of its front matter, if it has any
+pub(crate) fn front_matter<'c>(
+    content: &'c str,
+    template_path: &Path,
+) -> Result<(&'c str, Option<Value>), RenderError> {
+    if let Some((front_matter, body)) = split_front_matter(content) {
+        if let Some(defaults) = parse_front_matter(front_matter, template_path)? {
+            return Ok((body, Some(defaults)));
+        }
+    }
+    Ok((content, None))
+}
+
+/// The data merged over the defaults of a template's front matter
+pub(crate) fn with_defaults<'d>(defaults: Option<&Value>, data: &'d Value) -> Cow<'d, Value> {
+    match defaults {
+        Some(defaults) => {
+            let mut merged = defaults.clone();
+            DataMerger::merge(&mut merged, data);
+            Cow::Owned(merged)
+        }
+        None => Cow::Borrowed(data),
+    }
+}
+
+/// Split a leading `---` front matter block from the template body
+fn split_front_matter(content: &str) -> Option<(&str, &str)> {
+    let rest = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']) == "---" {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Parse front matter as default data
+///
+/// Returns None when the block is not a YAML mapping (e.g. a template that merely starts
+/// with a `---` rule followed by prose), in which case it is rendered as ordinary text.
+fn parse_front_matter(
+    front_matter: &str,
+    template_path: &Path,
+) -> Result<Option<Value>, RenderError> {
+    let value = DataLoader::parse_yaml(front_matter).map_err(|e| RenderError::DataFileParse {
+        path: format!("{} (front matter)", template_path.display()),
+        source: e,
+    })?;
+    match value {
+        Value::Object(_) => Ok(Some(value)),
+        Value::Null => Ok(Some(Value::Object(serde_json::Map::new()))),
+        _ => Ok(None),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::template::source::MemoryTemplateStore;
     use serde_json::json;
     use std::fs;
+    use std::sync::Mutex;
     use tempfile::tempdir;
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_simple_render() {
         let dir = tempdir().unwrap();
@@ -74,6 +758,7 @@ mod tests {
         assert_eq!(result, "Hello, World!");
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_with_include() {
         let dir = tempdir().unwrap();
@@ -91,6 +776,378 @@ mod tests {
         assert_eq!(result, "=== My Title ===\nContent: My Content");
     }
 
+    #[test]
+    fn test_engine_is_send_and_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<TemplateEngine>();
+        assert_sync::<TemplateEngine>();
+        assert_send::<crate::template::CompiledTemplate>();
+        assert_sync::<crate::template::CompiledTemplate>();
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_builder() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "<{{ title }}>").unwrap();
+        let path = dir.path().join("template.txt");
+
+        let mut filters = FilterRegistry::new();
+        filters.register("shout", |value, _| {
+            Ok(Value::String(format!(
+                "{}!",
+                value.as_str().unwrap_or_default()
+            )))
+        });
+        let engine = TemplateEngine::builder()
+            .root(dir.path())
+            .escape_html(true)
+            .placeholder("?")
+            .filters(filters)
+            .build();
+        let result = engine.render_source(
+            "{{> header.txt }} {{ name | shout }} {{ missing }}",
+            &path,
+            &json!({"title": "a & b", "name": "hi"}),
+        );
+        assert_eq!(result.unwrap(), "<a &amp; b> hi! ?");
+
+        // The built-in filters are replaced, not extended
+        let result = engine.render_source("{{ name | upper }}", &path, &json!({"name": "hi"}));
+        assert!(result.is_err());
+
+        let engine = TemplateEngine::builder()
+            .root(dir.path())
+            .max_depth(1)
+            .strict(true)
+            .build();
+        let result = engine.render_source("{{ missing }}", &path, &json!({}));
+        assert!(matches!(result, Err(RenderError::UndefinedVariable { .. })));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_builder_delimiters() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("main.txt");
+        fs::write(
+            &path,
+            "<%> part.txt %> {\"a\": {\"b\": <% n %>}} {{ name }}\n<%>raw code.txt %>",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("part.txt"),
+            "<%#if name %>[<% name | upper %>]<%/if%>",
+        )
+        .unwrap();
+        fs::write(dir.path().join("code.txt"), "<% name %>").unwrap();
+
+        let engine = TemplateEngine::builder()
+            .root(dir.path())
+            .delimiters(Delimiters::new("<%", "%>").unwrap())
+            .strict(true)
+            .build();
+        let data = json!({"name": "x", "n": 1});
+        let expected = "[X] {\"a\": {\"b\": 1}} {{ name }}\n<% name %>";
+        assert_eq!(engine.render(&path, &data).unwrap(), expected);
+        let compiled = engine.compile(&path).unwrap();
+        assert_eq!(compiled.render(&data).unwrap(), expected);
+
+        let problems = engine.check("ok\n<%#if name %>", &path, &data);
+        assert!(matches!(
+            problems.as_slice(),
+            [RenderError::TemplateSyntax { location, .. }] if location.line == 2
+        ));
+
+        let result = engine.render_source("{{ a }} <% missing %>", &path, &data);
+        assert!(matches!(
+            result,
+            Err(RenderError::UndefinedVariable { location, .. })
+                if (location.line, location.column) == (1, 9)
+        ));
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let mut store = MemoryTemplateStore::new();
+        store.insert(
+            "main.txt",
+            "{{#extends \"layouts/base.txt\"}}{{#block body}}{{> parts/*.txt }}{{/block}}",
+        );
+        store.insert("layouts/base.txt", "<{{#block body}}{{/block}}>");
+        store.insert("parts/a.txt", "{{ a }}");
+        store.insert("parts/b.txt", "{{> ../parts/./c.txt }}");
+        store.insert("parts/c.txt", "C");
+        store.insert("escape.txt", "{{> ../outside.txt }}");
+
+        let engine = TemplateEngine::builder().include_source(store).build();
+        let data = json!({"a": "A"});
+        assert_eq!(
+            engine.render(Path::new("main.txt"), &data).unwrap(),
+            "<ACC>"
+        );
+        assert!(matches!(
+            engine.render(Path::new("escape.txt"), &data),
+            Err(RenderError::PathTraversal { .. })
+        ));
+        assert!(matches!(
+            engine.render(Path::new("missing.txt"), &data),
+            Err(RenderError::TemplateFileRead { .. })
+        ));
+    }
+
+    #[test]
+    fn test_custom_filter_in_include_path() {
+        let mut store = MemoryTemplateStore::new();
+        store.insert("main.txt", "{{> (model | part) }}");
+        store.insert("parts/gpt.txt", "GPT");
+
+        let mut filters = FilterRegistry::with_builtins();
+        filters.register("part", |value, _| {
+            Ok(Value::String(format!(
+                "parts/{}.txt",
+                value.as_str().unwrap_or_default()
+            )))
+        });
+        let engine = TemplateEngine::builder()
+            .include_source(store)
+            .filters(filters)
+            .build();
+        let data = json!({"model": "gpt"});
+        assert_eq!(engine.render(Path::new("main.txt"), &data).unwrap(), "GPT");
+        assert!(engine
+            .check("{{> (model | part) }}", Path::new("main.txt"), &data)
+            .is_empty());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_render_stats() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "=== {{ title }} ===").unwrap();
+
+        let template = dir.path().join("template.txt");
+        fs::write(
+            &template,
+            "{{> header.txt }}\n{{ content }} {{ missing }}{{set x = other}}",
+        )
+        .unwrap();
+
+        let data = json!({"title": "My Title", "content": "My Content"});
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        engine.render(&template, &data).unwrap();
+        engine.render(&template, &data).unwrap();
+
+        let stats = engine.stats();
+        assert_eq!(stats.includes(), 2);
+        assert_eq!(stats.substituted(), 4);
+        assert_eq!(stats.undefined(), 4);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_render_with_report() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "=== {{ title }} ===").unwrap();
+        let template = dir.path().join("template.txt");
+        fs::write(
+            &template,
+            "{{> header.txt }}\n{{ name | upper }} {{ missing }}{{#if other}}x{{/if}}",
+        )
+        .unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        let (output, report) = engine
+            .render_with_report(&template, &json!({"title": "T", "name": "ann"}))
+            .unwrap();
+        assert_eq!(output, "=== T ===\nANN ");
+        assert_eq!(report.includes.len(), 1);
+        assert_eq!(report.includes[0].path, dir.path().join("header.txt"));
+        assert_eq!(report.substituted, ["title", "name | upper"]);
+        let undefined: Vec<_> = report.undefined.iter().map(|(name, _)| name).collect();
+        assert_eq!(undefined, ["missing"]);
+        assert_eq!(report.undefined[0].1.line, 2);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_locations_across_includes() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("base.txt"),
+            "{{#block head}}{{/block}}\n{{> card.txt title=a }}{{#block body}}{{/block}}",
+        )
+        .unwrap();
+        fs::write(dir.path().join("card.txt"), "[{{ title }}]\n  {{ b }}").unwrap();
+        let template = dir.path().join("page.txt");
+        fs::write(
+            &template,
+            "{{#extends base.txt}}\n{{#block body}}\n  x {{ c }}{{/block}}",
+        )
+        .unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        let (_, report) = engine.render_with_report(&template, &json!({})).unwrap();
+        let undefined: Vec<_> = report
+            .undefined
+            .iter()
+            .map(|(name, location)| (name.as_str(), location.to_string()))
+            .collect();
+        let at = |file: &str, position: &str| {
+            format!("{}:{}", dir.path().join(file).display(), position)
+        };
+        assert_eq!(
+            undefined,
+            [
+                // The argument is evaluated where the include directive is
+                ("a", at("base.txt", "2:1")),
+                ("b", at("card.txt", "2:3")),
+                ("c", at("page.txt", "3:5")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_fn() {
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&asked);
+        let engine = TemplateEngine::builder()
+            .strict(true)
+            .missing_fn(move |name, location| {
+                log.lock()
+                    .unwrap()
+                    .push(format!("{} at {}", name, location.line));
+                name.strip_prefix("secret.").map(|key| format!("<{}>", key))
+            })
+            .build();
+        let path = Path::new("template.txt");
+
+        let result = engine.render_source(
+            "{{ user }}\n{{ secret.key | upper }}{{#if secret.flag}} on{{/if}}",
+            path,
+            &json!({"user": "ann"}),
+        );
+        assert_eq!(result.unwrap(), "ann\n<KEY> on");
+        assert_eq!(
+            *asked.lock().unwrap(),
+            ["secret.key at 2", "secret.flag at 2"]
+        );
+
+        // Names the callback does not know are still undefined
+        let result = engine.render_source("{{ other }}", path, &json!({}));
+        assert!(matches!(result, Err(RenderError::UndefinedVariable { .. })));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_render_to_writer() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "=== {{ title }} ===").unwrap();
+        let template = dir.path().join("template.txt");
+        fs::write(&template, "{{> header.txt }}\n{{ body }}\n{{ more }}").unwrap();
+
+        let data = json!({"title": "T", "body": "x".repeat(100), "more": "y".repeat(100)});
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, true, false);
+        let mut output = Vec::new();
+        engine
+            .render_to_writer(&template, &data, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            engine.render(&template, &data).unwrap()
+        );
+
+        // Output before a failure has already been written
+        let engine = engine.with_max_output_size(Some(150));
+        let mut output = Vec::new();
+        let result = engine.render_to_writer(&template, &data, &mut output);
+        assert!(matches!(
+            result,
+            Err(RenderError::OutputTooLarge { limit: 150 })
+        ));
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("=== T ===\nxxx"));
+
+        // Write errors are reported as I/O errors
+        let mut full = [0u8; 4];
+        let result = engine.render_to_writer(&template, &data, &mut &mut full[..]);
+        assert!(matches!(result, Err(RenderError::Io(_))));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_render_source_without_includes() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("header.txt"), "=== {{ title }} ===").unwrap();
+
+        let data = json!({"title": "My Title"});
+        let path = dir.path().join("<stdin>");
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        let result = engine.render_source("{{> header.txt }}", &path, &data);
+        assert_eq!(result.unwrap(), "=== My Title ===");
+
+        let engine = engine.with_includes_allowed(false);
+        let result = engine.render_source("Title: {{ title }}", &path, &data);
+        assert_eq!(result.unwrap(), "Title: My Title");
+        let result = engine.render_source("{{> header.txt }}", &path, &data);
+        assert!(matches!(result, Err(RenderError::Usage(_))));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_front_matter_defaults() {
+        let dir = tempdir().unwrap();
+        let template = dir.path().join("template.txt");
+        fs::write(
+            &template,
+            "---\nmodel: gpt-4o\nuser:\n  name: Guest\n  role: viewer\n---\n{{ user.name }} ({{ user.role }}) on {{ model }}",
+        )
+        .unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        let result = engine.render(&template, &json!({})).unwrap();
+        assert_eq!(result, "Guest (viewer) on gpt-4o");
+
+        // Data files take precedence over the front matter
+        let data = json!({"user": {"name": "Alice"}});
+        let result = engine.render(&template, &data).unwrap();
+        assert_eq!(result, "Alice (viewer) on gpt-4o");
+    }
+
+    #[test]
+    fn test_split_front_matter() {
+        assert_eq!(
+            split_front_matter("---\na: 1\n---\nbody"),
+            Some(("a: 1\n", "body"))
+        );
+        assert_eq!(
+            split_front_matter("---\r\na: 1\r\n---\r\nbody"),
+            Some(("a: 1\r\n", "body"))
+        );
+        assert_eq!(split_front_matter("---\n---\n"), Some(("", "")));
+        assert_eq!(split_front_matter("---\na: 1\nbody"), None);
+        assert_eq!(split_front_matter("body\n---\na: 1\n---\n"), None);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_front_matter_not_a_mapping() {
+        let dir = tempdir().unwrap();
+        let template = dir.path().join("template.txt");
+        let content = "---\nJust a horizontal rule\n---\nHello";
+        fs::write(&template, content).unwrap();
+
+        let engine = TemplateEngine::new(dir.path().to_path_buf(), 20, false, false);
+        assert_eq!(engine.render(&template, &json!({})).unwrap(), content);
+
+        fs::write(&template, "---\nkey: [unclosed\n---\nHello").unwrap();
+        let result = engine.render(&template, &json!({}));
+        assert!(matches!(result, Err(RenderError::DataFileParse { .. })));
+    }
+
+    #[cfg(feature = "fs")]
     #[test]
     fn test_nested_include_with_variables() {
         let dir = tempdir().unwrap();
@@ -116,6 +1173,7 @@ mod tests {
         assert_eq!(result, "Header: Top\nBody: Middle\nFooter: Bottom");
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_escape_in_included_file() {
         let dir = tempdir().unwrap();
@@ -146,6 +1204,7 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_undefined_variable_non_strict() {
         let dir = tempdir().unwrap();
@@ -159,17 +1218,32 @@ mod tests {
         assert_eq!(result, "Hello, !");
     }
 
+    #[test]
+    fn test_deep_nesting() {
+        // As deep as the parsers allow, which must render without overflowing the stack
+        let condition = format!("{}a{}", "(".repeat(63), ")".repeat(63));
+        let template = format!(
+            "{}{{{{ a }}}}{}",
+            format!("{{{{#if {}}}}}", condition).repeat(64),
+            "{{/if}}".repeat(64)
+        );
+        let engine = TemplateEngine::builder().build();
+        let output = engine.render_source(&template, Path::new("t.txt"), &json!({"a": 1}));
+        assert_eq!(output.unwrap(), "1");
+
+        let template = format!("{{{{ {}a }}}}", "!".repeat(2000));
+        let output = engine.render_source(&template, Path::new("t.txt"), &json!({}));
+        assert!(matches!(output, Err(RenderError::TemplateSyntax { .. })));
+    }
+
+    #[cfg(feature = "fs")]
     #[test]
     fn test_complex_scenario() {
         let dir = tempdir().unwrap();
 
         // Create a partial for segments
         let segment = dir.path().join("segment.txt");
-        fs::write(
-            &segment,
-            "- {{ name }}: {{ description }}",
-        )
-        .unwrap();
+        fs::write(&segment, "- {{ name }}: {{ description }}").unwrap();
 
         // Create main template
         let template = dir.path().join("template.txt");
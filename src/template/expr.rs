@@ -0,0 +1,1162 @@
+use crate::error::{Location, RenderError};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+use super::filters::FilterRegistry;
+use super::variable::{MissingFn, VariableSubstitutor};
+
+/// Parsed form of the text inside `{{ ... }}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Literal value (`"text"`, `42`, `true`, `null`)
+    Literal(Value),
+    /// Variable path (`user.name`, `items.0`)
+    Path(String),
+    /// Dynamic lookup (`greetings[lang]`, `items[0]`)
+    Index { target: Box<Expr>, key: Box<Expr> },
+    /// Dotted path following a dynamic lookup (`greetings[lang].text`)
+    Member { target: Box<Expr>, path: String },
+    /// Macro call (`card("Intro", summary)`)
+    Call { name: String, args: Vec<Expr> },
+    /// Filter application (`value | name(args)`)
+    Filter {
+        input: Box<Expr>,
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// Logical negation (`!value`)
+    Not(Box<Expr>),
+    /// Inline conditional (`cond ? a : b`)
+    Conditional {
+        condition: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// Comparison, logical or concatenation operator (`a == b`, `a && b`, `a ~ b`)
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Concat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Str(String),
+    Word(String),
+    Pipe,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Not,
+    Question,
+    Colon,
+    Op(BinaryOp),
+}
+
+impl Expr {
+    /// Parse the content of an output tag (`{{ ... }}`)
+    ///
+    /// A lone token is always a variable path, so `{{ 123 }}` keeps looking up the key "123",
+    /// and so are words with nothing but spaces between them (`{{ first name }}`).
+    pub fn parse_output(source: &str) -> Result<Expr, String> {
+        let tokens = tokenize(source)?;
+        match tokens.as_slice() {
+            [] => Ok(Expr::Path(String::new())),
+            [Token::Word(word)] => Ok(Expr::Path(word.clone())),
+            words if words.iter().all(is_key_word) => Ok(Expr::Path(source.trim().to_string())),
+            _ => Self::from_tokens(tokens),
+        }
+    }
+
+    /// Parse an expression such as `name | replace("a", "b") | upper`
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        Self::from_tokens(tokenize(source)?)
+    }
+
+    fn from_tokens(tokens: Vec<Token>) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.expression()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {}", describe(token))),
+        }
+    }
+
+    /// Evaluate the expression in the given context
+    pub fn eval(&self, ctx: &EvalContext) -> Result<Value, RenderError> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Path(path) => match ctx.lookup(path) {
+                Some(value) => Ok(value.into_owned()),
+                None => match ctx.helper(path) {
+                    Some(value) => Ok(value),
+                    None => ctx.undefined(path.clone()),
+                },
+            },
+            Expr::Index { target, key } => {
+                let target_value = target.eval(ctx)?;
+                let key = match key.eval(ctx)? {
+                    Value::String(s) => s,
+                    Value::Number(n) => n.to_string(),
+                    other => {
+                        return Err(RenderError::VariableResolution {
+                            message: format!(
+                                "cannot use {} as a key",
+                                VariableSubstitutor::value_to_string(&other)
+                            ),
+                            location: ctx.location.clone(),
+                        })
+                    }
+                };
+                match VariableSubstitutor::step(&target_value, &key) {
+                    Some(value) => Ok(value.clone()),
+                    None => ctx.undefined(format!("{}[{}]", target.name(), key)),
+                }
+            }
+            Expr::Member { target, path } => {
+                let target_value = target.eval(ctx)?;
+                match VariableSubstitutor::lookup(path, &target_value) {
+                    Some(value) => Ok(value.into_owned()),
+                    None => ctx.undefined(format!("{}.{}", target.name(), path)),
+                }
+            }
+            Expr::Call { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(ctx))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ctx.functions
+                    .and_then(|functions| functions.call(name, &args, ctx))
+                    .unwrap_or_else(|| {
+                        Err(RenderError::VariableResolution {
+                            message: format!("unknown macro '{}'", name),
+                            location: ctx.location.clone(),
+                        })
+                    })
+            }
+            Expr::Filter { input, name, args } => {
+                let input = input.eval(ctx)?;
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(ctx))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ctx.filters.apply(name, &input, &args).map_err(|message| {
+                    RenderError::VariableResolution {
+                        message,
+                        location: ctx.location.clone(),
+                    }
+                })
+            }
+            Expr::Not(inner) => Ok(Value::Bool(!is_truthy(&inner.eval(ctx)?))),
+            Expr::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                // Only the chosen branch is evaluated
+                if condition.eval_condition(ctx)? {
+                    then.eval(ctx)
+                } else {
+                    otherwise.eval(ctx)
+                }
+            }
+            Expr::Binary { op, left, right } => {
+                let left = left.eval(ctx)?;
+                let result = match op {
+                    // Short-circuit: the right side is only evaluated when needed
+                    BinaryOp::And => is_truthy(&left) && is_truthy(&right.eval(ctx)?),
+                    BinaryOp::Or => is_truthy(&left) || is_truthy(&right.eval(ctx)?),
+                    BinaryOp::Concat => {
+                        let mut text = VariableSubstitutor::value_to_string(&left);
+                        text.push_str(&VariableSubstitutor::value_to_string(&right.eval(ctx)?));
+                        return Ok(Value::String(text));
+                    }
+                    _ => compare(*op, &left, &right.eval(ctx)?),
+                };
+                Ok(Value::Bool(result))
+            }
+        }
+    }
+
+    /// Short name used in error messages for the value this expression refers to
+    fn name(&self) -> String {
+        match self {
+            Expr::Path(path) => path.clone(),
+            Expr::Member { target, path } => format!("{}.{}", target.name(), path),
+            Expr::Index { target, key } => match key.as_ref() {
+                Expr::Literal(key) => format!(
+                    "{}[{}]",
+                    target.name(),
+                    VariableSubstitutor::value_to_string(key)
+                ),
+                _ => format!("{}[...]", target.name()),
+            },
+            _ => "(expression)".to_string(),
+        }
+    }
+
+    /// Names of the filters the expression applies, in the order they are written
+    pub fn filter_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_filter_names(&mut names);
+        names
+    }
+
+    fn collect_filter_names<'e>(&'e self, names: &mut Vec<&'e str>) {
+        match self {
+            Expr::Literal(_) | Expr::Path(_) => {}
+            Expr::Index { target, key } => {
+                target.collect_filter_names(names);
+                key.collect_filter_names(names);
+            }
+            Expr::Member { target, .. } | Expr::Not(target) => target.collect_filter_names(names),
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    arg.collect_filter_names(names);
+                }
+            }
+            Expr::Filter { input, name, args } => {
+                input.collect_filter_names(names);
+                names.push(name);
+                for arg in args {
+                    arg.collect_filter_names(names);
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                condition.collect_filter_names(names);
+                then.collect_filter_names(names);
+                otherwise.collect_filter_names(names);
+            }
+            Expr::Binary { left, right, .. } => {
+                left.collect_filter_names(names);
+                right.collect_filter_names(names);
+            }
+        }
+    }
+
+    /// Evaluate as a condition: undefined variables count as null instead of failing
+    pub fn eval_condition(&self, ctx: &EvalContext) -> Result<bool, RenderError> {
+        let lenient = EvalContext {
+            undefined_as_null: true,
+            ..*ctx
+        };
+        Ok(is_truthy(&self.eval(&lenient)?))
+    }
+}
+
+/// Truthiness used by conditions: null, false, 0, "", [] and {} are false
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Compare two values; numbers compare numerically and strings lexicographically
+///
+/// Ordering between values of different types is always false.
+fn compare(op: BinaryOp, left: &Value, right: &Value) -> bool {
+    use std::cmp::Ordering;
+
+    let ordering = match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+
+    match op {
+        BinaryOp::Eq => ordering.map_or(left == right, |o| o == Ordering::Equal),
+        BinaryOp::Ne => ordering.map_or(left != right, |o| o != Ordering::Equal),
+        BinaryOp::Lt => ordering == Some(Ordering::Less),
+        BinaryOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        BinaryOp::Gt => ordering == Some(Ordering::Greater),
+        BinaryOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Concat => {
+            unreachable!("'{}' is not a comparison", op.symbol())
+        }
+    }
+}
+
+/// A data context together with the contexts enclosing it
+///
+/// Blocks such as `{{#with}}` push a new scope; `../` in a path walks back out.
+pub struct Scope<'a> {
+    pub data: &'a Value,
+    pub parent: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    /// The top-level scope holding the merged data
+    pub fn root(data: &'a Value) -> Self {
+        Self { data, parent: None }
+    }
+
+    /// A scope nested inside this one
+    pub fn nested(&'a self, data: &'a Value) -> Self {
+        Self {
+            data,
+            parent: Some(self),
+        }
+    }
+}
+
+/// Resolves calls such as `card("Intro")` made from expressions
+pub trait Functions {
+    /// Call the named function, or return None if no such function exists
+    fn call(
+        &self,
+        name: &str,
+        args: &[Value],
+        ctx: &EvalContext,
+    ) -> Option<Result<Value, RenderError>>;
+}
+
+/// Everything an expression can refer to while being evaluated
+pub struct EvalContext<'a> {
+    pub scope: &'a Scope<'a>,
+    /// Values assigned with `{{set}}`, consulted before the data
+    pub locals: &'a Map<String, Value>,
+    pub filters: &'a FilterRegistry,
+    /// Macros callable from expressions, if any
+    pub functions: Option<&'a dyn Functions>,
+    /// Value of the `now` helper, or None when it is disabled
+    pub now: Option<&'a Value>,
+    pub location: &'a Location,
+    /// Resolve undefined variables to null instead of failing (used for conditions)
+    pub undefined_as_null: bool,
+    /// Asked for the value of undefined variables before they count as undefined
+    pub missing: Option<&'a MissingFn>,
+}
+
+impl<'a> EvalContext<'a> {
+    /// Resolve a path, letting locals shadow data keys with the same first segment
+    ///
+    /// Each leading `../` resolves the rest of the path in the enclosing scope instead.
+    fn lookup(&self, path: &str) -> Option<Cow<'a, Value>> {
+        if path.starts_with("../") {
+            let mut scope = self.scope;
+            let mut rest = path;
+            while let Some(tail) = rest.strip_prefix("../") {
+                scope = scope.parent?;
+                rest = tail;
+            }
+            return VariableSubstitutor::lookup(rest, scope.data);
+        }
+
+        let (head, tail) = match path.split_once('.') {
+            Some((head, tail)) => (head, Some(tail)),
+            None => (path, None),
+        };
+
+        match (self.locals.get(head), tail) {
+            (Some(local), None) => Some(Cow::Borrowed(local)),
+            (Some(local), Some(tail)) => VariableSubstitutor::lookup(tail, local),
+            (None, _) => VariableSubstitutor::lookup(path, self.scope.data),
+        }
+    }
+
+    /// Result for a name that resolved to nothing
+    fn undefined(&self, name: String) -> Result<Value, RenderError> {
+        if let Some(value) = self
+            .missing
+            .and_then(|missing| missing(&name, self.location))
+        {
+            Ok(Value::String(value))
+        } else if self.undefined_as_null {
+            Ok(Value::Null)
+        } else {
+            Err(RenderError::UndefinedVariable {
+                name,
+                location: self.location.clone(),
+            })
+        }
+    }
+
+    /// Built-in helpers, consulted only when the data does not define the name
+    fn helper(&self, name: &str) -> Option<Value> {
+        match name {
+            "now" => self.now.cloned(),
+            _ => None,
+        }
+    }
+}
+
+/// How deeply parentheses, brackets, arguments and `!` may nest in an expression, so that
+/// a template cannot overflow the stack of the recursive parser. Each level takes a dozen
+/// stack frames: with blocks nested as deep as the template parser allows, this still
+/// fits in a 2 MiB thread stack in a debug build.
+const MAX_NESTING: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How many nested expressions are being parsed
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!(
+                "expected {}, found {}",
+                describe(&expected),
+                describe(&token)
+            )),
+            None => Err(format!("expected {}", describe(&expected))),
+        }
+    }
+
+    /// Parse a nested part of the expression, up to `MAX_NESTING` levels deep
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        if self.depth >= MAX_NESTING {
+            return Err(format!(
+                "expression nested more than {} levels deep",
+                MAX_NESTING
+            ));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    /// expression := or [ '?' expression ':' expression ]
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.nested(Self::conditional)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.or()?;
+        if self.peek() != Some(&Token::Question) {
+            return Ok(condition);
+        }
+
+        self.next();
+        let then = self.expression()?;
+        self.expect(Token::Colon)?;
+        let otherwise = self.expression()?;
+        Ok(Expr::Conditional {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
+    }
+
+    /// or := and ( '||' and )*
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Op(BinaryOp::Or)) {
+            self.next();
+            expr = binary(BinaryOp::Or, expr, self.and()?);
+        }
+        Ok(expr)
+    }
+
+    /// and := comparison ( '&&' comparison )*
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+        while self.peek() == Some(&Token::Op(BinaryOp::And)) {
+            self.next();
+            expr = binary(BinaryOp::And, expr, self.comparison()?);
+        }
+        Ok(expr)
+    }
+
+    /// comparison := concat [ ( '==' | '!=' | '<' | '<=' | '>' | '>=' ) concat ]
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let expr = self.concat()?;
+        match self.peek() {
+            Some(Token::Op(op))
+                if !matches!(op, BinaryOp::And | BinaryOp::Or | BinaryOp::Concat) =>
+            {
+                let op = *op;
+                self.next();
+                Ok(binary(op, expr, self.concat()?))
+            }
+            _ => Ok(expr),
+        }
+    }
+
+    /// concat := unary ( '~' unary )*
+    fn concat(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::Op(BinaryOp::Concat)) {
+            self.next();
+            expr = binary(BinaryOp::Concat, expr, self.unary()?);
+        }
+        Ok(expr)
+    }
+
+    /// postfix := primary ( '[' expression ']' [ '.path' ] )*
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+
+        while self.peek() == Some(&Token::LBracket) {
+            self.next();
+            let key = self.expression()?;
+            self.expect(Token::RBracket)?;
+            expr = Expr::Index {
+                target: Box::new(expr),
+                key: Box::new(key),
+            };
+
+            // `.rest` directly after `]` continues the path in the looked-up value
+            if let Some(Token::Word(word)) = self.peek() {
+                if let Some(path) = word.strip_prefix('.') {
+                    let path = path.to_string();
+                    self.next();
+                    expr = Expr::Member {
+                        target: Box::new(expr),
+                        path,
+                    };
+                }
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// unary := '!' unary | pipeline
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.nested(Self::unary)?)));
+        }
+        self.pipeline()
+    }
+
+    /// pipeline := postfix ( '|' name [ '(' args ')' ] )*
+    fn pipeline(&mut self) -> Result<Expr, String> {
+        let mut expr = self.postfix()?;
+
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            let name = match self.next() {
+                Some(Token::Word(name)) => name,
+                Some(token) => {
+                    return Err(format!("expected filter name, found {}", describe(&token)))
+                }
+                None => return Err("expected filter name after '|'".to_string()),
+            };
+
+            let mut args = Vec::new();
+            if self.peek() == Some(&Token::LParen) {
+                self.next();
+                args = self.arguments()?;
+            }
+
+            expr = Expr::Filter {
+                input: Box::new(expr),
+                name,
+                args,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    /// args := [ expression ( ',' expression )* ] ')'   (after the opening '(')
+    fn arguments(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::RParen) {
+            args.push(self.expression()?);
+            while self.peek() == Some(&Token::Comma) {
+                self.next();
+                args.push(self.expression()?);
+            }
+        }
+        self.expect(Token::RParen)?;
+        Ok(args)
+    }
+
+    /// primary := string | number | true | false | null | name '(' args | path | '(' expression ')'
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Word(word)) => Ok(match word.as_str() {
+                "true" => Expr::Literal(Value::Bool(true)),
+                "false" => Expr::Literal(Value::Bool(false)),
+                "null" => Expr::Literal(Value::Null),
+                _ => match serde_json::from_str::<serde_json::Number>(&word) {
+                    Ok(n) => Expr::Literal(Value::Number(n)),
+                    Err(_) if self.peek() == Some(&Token::LParen) => {
+                        self.next();
+                        Expr::Call {
+                            name: word,
+                            args: self.arguments()?,
+                        }
+                    }
+                    Err(_) => Expr::Path(word),
+                },
+            }),
+            Some(Token::LParen) => {
+                let expr = self.expression()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("unexpected {}", describe(&token))),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Str(s) => format!("string \"{}\"", s),
+        Token::Word(w) => format!("'{}'", w),
+        Token::Pipe => "'|'".to_string(),
+        Token::LParen => "'('".to_string(),
+        Token::RParen => "')'".to_string(),
+        Token::LBracket => "'['".to_string(),
+        Token::RBracket => "']'".to_string(),
+        Token::Comma => "','".to_string(),
+        Token::Not => "'!'".to_string(),
+        Token::Question => "'?'".to_string(),
+        Token::Colon => "':'".to_string(),
+        Token::Op(op) => format!("'{}'", op.symbol()),
+    }
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Concat => "~",
+        }
+    }
+}
+
+/// Whether `token` can be part of a key with spaces: a word of letters, digits, `_`, `-`
+/// and `.`
+fn is_key_word(token: &Token) -> bool {
+    matches!(token, Token::Word(word)
+        if word.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')))
+}
+
+/// Characters that end a word (path, number, or filter name)
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '|' | '('
+                | ')'
+                | ','
+                | '"'
+                | '\''
+                | '!'
+                | '='
+                | '<'
+                | '>'
+                | '&'
+                | '~'
+                | '?'
+                | '['
+                | ']'
+        )
+}
+
+/// Whether `word` ends in the start bound of a slice segment (`items.` or `items.-2`)
+fn is_slice_start(word: &str) -> bool {
+    word.rsplit_once('.')
+        .is_some_and(|(_, start)| start.is_empty() || start.parse::<isize>().is_ok())
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    // Bracket depth, and the depths of the `?`s still waiting for their `:`
+    let mut depth = 0usize;
+    let mut pending = Vec::new();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '|' => {
+                chars.next();
+                if chars.next_if_eq(&'|').is_some() {
+                    tokens.push(Token::Op(BinaryOp::Or));
+                } else {
+                    tokens.push(Token::Pipe);
+                }
+            }
+            '&' => {
+                chars.next();
+                if chars.next_if_eq(&'&').is_none() {
+                    return Err("expected '&&'".to_string());
+                }
+                tokens.push(Token::Op(BinaryOp::And));
+            }
+            '!' => {
+                chars.next();
+                if chars.next_if_eq(&'=').is_some() {
+                    tokens.push(Token::Op(BinaryOp::Ne));
+                } else {
+                    tokens.push(Token::Not);
+                }
+            }
+            '=' => {
+                chars.next();
+                if chars.next_if_eq(&'=').is_none() {
+                    return Err("expected '==' (use '==' for comparison)".to_string());
+                }
+                tokens.push(Token::Op(BinaryOp::Eq));
+            }
+            '<' | '>' => {
+                chars.next();
+                let or_equal = chars.next_if_eq(&'=').is_some();
+                tokens.push(Token::Op(match (c, or_equal) {
+                    ('<', false) => BinaryOp::Lt,
+                    ('<', true) => BinaryOp::Le,
+                    ('>', false) => BinaryOp::Gt,
+                    _ => BinaryOp::Ge,
+                }));
+            }
+            '~' => {
+                chars.next();
+                tokens.push(Token::Op(BinaryOp::Concat));
+            }
+            '?' => {
+                chars.next();
+                pending.push(depth);
+                tokens.push(Token::Question);
+            }
+            ':' => {
+                chars.next();
+                if pending.last() == Some(&depth) {
+                    pending.pop();
+                }
+                tokens.push(Token::Colon);
+            }
+            '(' => {
+                chars.next();
+                depth += 1;
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                depth = depth.saturating_sub(1);
+                tokens.push(Token::RParen);
+            }
+            '[' => {
+                chars.next();
+                depth += 1;
+                tokens.push(Token::LBracket);
+            }
+            ']' => {
+                chars.next();
+                depth = depth.saturating_sub(1);
+                tokens.push(Token::RBracket);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '"' | '\'' => {
+                let quote = c;
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(c) if c == quote => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(c),
+                            None => return Err("unterminated string literal".to_string()),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err("unterminated string literal".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    // Inside a word ':' is part of a slice (`items.0:3`), unless it ends the
+                    // first branch of a conditional (`a ? 1:2`)
+                    let ends_branch =
+                        c == ':' && pending.last() == Some(&depth) && !is_slice_start(&word);
+                    if is_delimiter(c) || ends_branch {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_single_word_is_path() {
+        assert_eq!(
+            Expr::parse_output("user.name").unwrap(),
+            Expr::Path("user.name".into())
+        );
+        assert_eq!(Expr::parse_output("123").unwrap(), Expr::Path("123".into()));
+        assert_eq!(Expr::parse_output("").unwrap(), Expr::Path("".into()));
+        assert_eq!(
+            Expr::parse_output(" first name ").unwrap(),
+            Expr::Path("first name".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_single_word_literal_in_expression() {
+        assert_eq!(Expr::parse("123").unwrap(), Expr::Literal(json!(123)));
+        assert_eq!(Expr::parse("true").unwrap(), Expr::Literal(json!(true)));
+        assert!(Expr::parse("").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter_chain() {
+        let expr = Expr::parse(r#"name | replace("a", 'b') | upper"#).unwrap();
+        assert_eq!(
+            expr,
+            Expr::Filter {
+                input: Box::new(Expr::Filter {
+                    input: Box::new(Expr::Path("name".into())),
+                    name: "replace".into(),
+                    args: vec![Expr::Literal(json!("a")), Expr::Literal(json!("b")),],
+                }),
+                name: "upper".into(),
+                args: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_literal_arguments() {
+        let expr = Expr::parse("x | f(1, 2.5, true, null)").unwrap();
+        match expr {
+            Expr::Filter { args, .. } => assert_eq!(
+                args,
+                vec![
+                    Expr::Literal(json!(1)),
+                    Expr::Literal(json!(2.5)),
+                    Expr::Literal(json!(true)),
+                    Expr::Literal(Value::Null),
+                ]
+            ),
+            _ => panic!("Expected filter expression"),
+        }
+    }
+
+    fn eval(source: &str, data: &Value) -> Value {
+        let filters = FilterRegistry::with_builtins();
+        let location = Location::unknown();
+        let locals = Map::new();
+        let ctx = EvalContext {
+            scope: &Scope::root(data),
+            locals: &locals,
+            filters: &filters,
+            functions: None,
+            now: None,
+            location: &location,
+            undefined_as_null: false,
+            missing: None,
+        };
+        Expr::parse(source).unwrap().eval(&ctx).unwrap()
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let expr = Expr::parse("a || b && !c == d").unwrap();
+        assert_eq!(
+            expr,
+            binary(
+                BinaryOp::Or,
+                Expr::Path("a".into()),
+                binary(
+                    BinaryOp::And,
+                    Expr::Path("b".into()),
+                    binary(
+                        BinaryOp::Eq,
+                        Expr::Not(Box::new(Expr::Path("c".into()))),
+                        Expr::Path("d".into()),
+                    ),
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let data = json!({"score": 0.85, "lang": "en", "n": 3});
+        assert_eq!(eval("score >= 0.8", &data), json!(true));
+        assert_eq!(eval("score < 0.8", &data), json!(false));
+        assert_eq!(eval("n == 3.0", &data), json!(true));
+        assert_eq!(eval("n != 3", &data), json!(false));
+        assert_eq!(eval("lang == \"en\"", &data), json!(true));
+        assert_eq!(eval("lang < \"fr\"", &data), json!(true));
+        assert_eq!(eval("lang > 1", &data), json!(false));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        let data = json!({"score": 0.9, "lang": "en", "tags": []});
+        assert_eq!(eval("score >= 0.8 && lang == 'en'", &data), json!(true));
+        assert_eq!(eval("score >= 0.95 || lang == 'ja'", &data), json!(false));
+        assert_eq!(eval("!tags", &data), json!(true));
+        assert_eq!(eval("!(score > 0.5)", &data), json!(false));
+    }
+
+    #[test]
+    fn test_condition_treats_undefined_as_null() {
+        let filters = FilterRegistry::with_builtins();
+        let location = Location::unknown();
+        let data = json!({"b": true});
+        let locals = Map::new();
+        let ctx = EvalContext {
+            scope: &Scope::root(&data),
+            locals: &locals,
+            filters: &filters,
+            functions: None,
+            now: None,
+            location: &location,
+            undefined_as_null: false,
+            missing: None,
+        };
+        let expr = Expr::parse("missing || b").unwrap();
+        assert!(expr.eval_condition(&ctx).unwrap());
+        assert!(expr.eval(&ctx).is_err());
+    }
+
+    #[test]
+    fn test_concat() {
+        let data = json!({"user": {"name": "Ann"}, "n": 3});
+        assert_eq!(eval("\"Hello, \" ~ user.name", &data), json!("Hello, Ann"));
+        assert_eq!(eval("n ~ '/' ~ n", &data), json!("3/3"));
+        // Filters bind tighter than '~'
+        assert_eq!(eval("user.name ~ 'x' | upper", &data), json!("AnnX"));
+        assert_eq!(eval("(user.name ~ 'x') | upper", &data), json!("ANNX"));
+        assert_eq!(eval("'a' ~ 'b' == 'ab'", &data), json!(true));
+    }
+
+    #[test]
+    fn test_locals_shadow_data() {
+        let filters = FilterRegistry::with_builtins();
+        let location = Location::unknown();
+        let data = json!({"name": "data", "user": {"name": "Ann"}});
+        let mut locals = Map::new();
+        locals.insert("user".into(), json!({"name": "Local"}));
+        let ctx = EvalContext {
+            scope: &Scope::root(&data),
+            locals: &locals,
+            filters: &filters,
+            functions: None,
+            now: None,
+            location: &location,
+            undefined_as_null: false,
+            missing: None,
+        };
+        let eval = |source: &str| Expr::parse(source).unwrap().eval(&ctx).unwrap();
+        assert_eq!(eval("user.name"), json!("Local"));
+        assert_eq!(eval("name"), json!("data"));
+    }
+
+    #[test]
+    fn test_parent_scope_lookup() {
+        let filters = FilterRegistry::with_builtins();
+        let location = Location::unknown();
+        let locals = Map::new();
+        let data = json!({"title": "Top", "section": {"title": "Mid", "item": {"name": "x"}}});
+        let root = Scope::root(&data);
+        let section = root.nested(&data["section"]);
+        let item = section.nested(&data["section"]["item"]);
+        let ctx = EvalContext {
+            scope: &item,
+            locals: &locals,
+            filters: &filters,
+            functions: None,
+            now: None,
+            location: &location,
+            undefined_as_null: false,
+            missing: None,
+        };
+        let eval = |source: &str| Expr::parse_output(source).unwrap().eval(&ctx);
+        assert_eq!(eval("name").unwrap(), json!("x"));
+        assert_eq!(eval("../title").unwrap(), json!("Mid"));
+        assert_eq!(eval("../../title").unwrap(), json!("Top"));
+        assert!(eval("../../../title").is_err());
+    }
+
+    #[test]
+    fn test_bracket_lookup() {
+        let data = json!({
+            "lang": "en",
+            "n": 1,
+            "snippets": {"en": {"intro": "Hi"}, "gpt-4": "long"},
+            "items": ["a", "b", "c"]
+        });
+        assert_eq!(eval("snippets[lang].intro", &data), json!("Hi"));
+        assert_eq!(eval("snippets['gpt-4']", &data), json!("long"));
+        assert_eq!(eval("items[n]", &data), json!("b"));
+        assert_eq!(eval("items[-1] | upper", &data), json!("C"));
+        assert_eq!(eval("items[n ~ '']", &data), json!("b"));
+        assert!(Expr::parse("items[0").is_err());
+    }
+
+    #[test]
+    fn test_parse_call() {
+        assert_eq!(
+            Expr::parse_output("card('Intro', user.name)").unwrap(),
+            Expr::Call {
+                name: "card".into(),
+                args: vec![
+                    Expr::Literal(json!("Intro")),
+                    Expr::Path("user.name".into())
+                ],
+            }
+        );
+        let data = json!({});
+        let filters = FilterRegistry::with_builtins();
+        let location = Location::unknown();
+        let locals = Map::new();
+        let ctx = EvalContext {
+            scope: &Scope::root(&data),
+            locals: &locals,
+            filters: &filters,
+            functions: None,
+            now: None,
+            location: &location,
+            undefined_as_null: false,
+            missing: None,
+        };
+        assert!(Expr::parse("card()").unwrap().eval(&ctx).is_err());
+    }
+
+    #[test]
+    fn test_conditional() {
+        let data = json!({"premium": true, "n": 0, "items": [1, 2, 3]});
+        assert_eq!(
+            eval(r#"premium ? "Pro plan" : "Free plan""#, &data),
+            json!("Pro plan")
+        );
+        assert_eq!(eval("n > 0 ? 'some' : 'none'", &data), json!("none"));
+        assert_eq!(eval("missing ? 'yes' : 'no'", &data), json!("no"));
+        assert_eq!(eval("premium ? items.0:2 : items", &data), json!([1, 2]));
+        assert_eq!(eval("premium ? 1:2", &data), json!(1));
+        assert_eq!(eval("n ? premium:n", &data), json!(0));
+        assert_eq!(eval("premium ? (n ? 1:2):3", &data), json!(2));
+        // Right-associative, and the untaken branch is never evaluated
+        assert_eq!(eval("n ? undefined : premium ? 1 : 2", &data), json!(1));
+    }
+
+    #[test]
+    fn test_truthiness() {
+        for falsy in [
+            json!(null),
+            json!(false),
+            json!(0),
+            json!(""),
+            json!([]),
+            json!({}),
+        ] {
+            assert!(!is_truthy(&falsy));
+        }
+        for truthy in [
+            json!(true),
+            json!(1),
+            json!("0"),
+            json!([0]),
+            json!({"a": 1}),
+        ] {
+            assert!(is_truthy(&truthy));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("name |").is_err());
+        assert!(Expr::parse("name | replace(\"a\"").is_err());
+        assert!(Expr::parse("name | upper extra").is_err());
+        assert!(Expr::parse("\"unterminated").is_err());
+        assert!(Expr::parse("a = b").is_err());
+        assert!(Expr::parse("a & b").is_err());
+        assert!(Expr::parse("a ==").is_err());
+        assert!(Expr::parse("a ? b").is_err());
+        assert!(Expr::parse("a ? b : ").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let parens = |depth| format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expr::parse(&parens(MAX_NESTING - 1)).is_ok());
+        let error = Expr::parse(&parens(MAX_NESTING)).unwrap_err();
+        assert_eq!(error, "expression nested more than 64 levels deep");
+
+        // Deep enough to overflow the stack without the limit
+        assert!(Expr::parse(&format!("{}x", "!".repeat(2000))).is_err());
+        assert!(Expr::parse(&format!("{}x{}", "f(".repeat(2000), ")".repeat(2000))).is_err());
+        assert!(Expr::parse(&format!("a{}", "[b".repeat(2000))).is_err());
+    }
+}
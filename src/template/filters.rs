@@ -0,0 +1,833 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::variable::VariableSubstitutor;
+
+/// A filter receives the piped value and its evaluated arguments
+pub type FilterFn = dyn Fn(&Value, &[Value]) -> Result<Value, String> + Send + Sync;
+
+/// Named filters available through the pipe syntax (`{{ value | name(args) }}`)
+///
+/// Library users can add project-specific filters next to the built-in ones and hand the
+/// registry to the engine:
+///
+/// ```
+/// use render_prompt::template::FilterRegistry;
+/// use render_prompt::TemplateEngine;
+/// use serde_json::{json, Value};
+/// use std::path::Path;
+///
+/// let mut filters = FilterRegistry::with_builtins();
+/// let suffix = "!".to_string();
+/// filters.register("shout", move |value, args| {
+///     if !args.is_empty() {
+///         return Err("expected no arguments".to_string());
+///     }
+///     let text = value.as_str().ok_or("expected a string")?;
+///     Ok(Value::String(text.to_uppercase() + &suffix))
+/// });
+///
+/// let engine = TemplateEngine::builder().filters(filters).build();
+/// let output = engine.render_source("{{ name | shout }}", Path::new("t.txt"), &json!({"name": "hi"}));
+/// assert_eq!(output.unwrap(), "HI!");
+/// ```
+pub struct FilterRegistry {
+    filters: HashMap<String, Box<FilterFn>>,
+}
+
+impl FilterRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self {
+            filters: HashMap::new(),
+        }
+    }
+
+    /// Create a registry with all built-in filters registered
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register("upper", upper);
+        registry.register("lower", lower);
+        registry.register("trim", trim);
+        registry.register("replace", replace);
+        registry.register("date", date);
+        registry.register("json_pretty", json_pretty);
+        registry.register("to_yaml", to_yaml);
+        registry.register("escape_html", escape_html_filter);
+        registry.register("length", length);
+        registry.register("sort", sort);
+        registry.register("reverse", reverse);
+        registry.register("split", split);
+        registry.register("join", join);
+        registry.register("slugify", slugify);
+        registry.register("snake_case", snake_case);
+        registry.register("camel_case", camel_case);
+        registry.register("kebab_case", kebab_case);
+        registry.register("title_case", title_case);
+        registry.register("truncate", truncate);
+        registry.register("truncate_words", truncate_words);
+        registry
+    }
+
+    /// Register a filter, replacing any existing filter with the same name
+    ///
+    /// The error message of a failing filter is reported with the filter's name and the
+    /// location of the tag.
+    pub fn register(
+        &mut self,
+        name: &str,
+        filter: impl Fn(&Value, &[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) {
+        self.filters.insert(name.to_string(), Box::new(filter));
+    }
+
+    /// Whether a filter with this name is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.filters.contains_key(name)
+    }
+
+    /// The names of the registered filters, in sorted order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.filters.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Apply the named filter
+    pub fn apply(&self, name: &str, input: &Value, args: &[Value]) -> Result<Value, String> {
+        let filter = self
+            .filters
+            .get(name)
+            .ok_or_else(|| format!("unknown filter '{}'", name))?;
+        filter(input, args).map_err(|message| format!("filter '{}': {}", name, message))
+    }
+}
+
+impl Default for FilterRegistry {
+    fn default() -> Self {
+        Self::with_builtins()
+    }
+}
+
+/// Check the number of arguments passed to a filter
+fn expect_args(args: &[Value], min: usize, max: usize) -> Result<(), String> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(format!(
+            "expected {} argument(s), got {}",
+            expected,
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Render a value as text the same way variable substitution does
+fn as_text(value: &Value) -> String {
+    VariableSubstitutor::value_to_string(value)
+}
+
+fn upper(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    Ok(Value::String(as_text(input).to_uppercase()))
+}
+
+fn lower(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    Ok(Value::String(as_text(input).to_lowercase()))
+}
+
+fn trim(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    Ok(Value::String(as_text(input).trim().to_string()))
+}
+
+fn replace(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 2, 2)?;
+    let from = as_text(&args[0]);
+    if from.is_empty() {
+        return Err("search string must not be empty".to_string());
+    }
+    Ok(Value::String(
+        as_text(input).replace(&from, &as_text(&args[1])),
+    ))
+}
+
+/// Format a timestamp with a strftime-style pattern (default `%Y-%m-%d`)
+///
+/// Accepts RFC 3339 strings, `YYYY-MM-DD[ HH:MM:SS]` strings (taken as UTC),
+/// and numbers as Unix timestamps in seconds.
+fn date(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 1)?;
+    let format = args
+        .first()
+        .map(as_text)
+        .unwrap_or_else(|| "%Y-%m-%d".to_string());
+    let timestamp = parse_timestamp(input)?;
+
+    let mut formatted = String::new();
+    write!(formatted, "{}", timestamp.format(&format))
+        .map_err(|_| format!("invalid date format '{}'", format))?;
+    Ok(Value::String(formatted))
+}
+
+/// Serialize a value as indented JSON (default indent: 2 spaces)
+fn json_pretty(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 1)?;
+    let indent = match args.first() {
+        None => 2,
+        Some(arg) => arg
+            .as_u64()
+            .filter(|&n| n <= 16)
+            .ok_or_else(|| format!("indent must be an integer from 0 to 16, got {}", arg))?,
+    };
+
+    let indent = " ".repeat(indent as usize);
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut buffer = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buffer, formatter);
+    input
+        .serialize(&mut serializer)
+        .map_err(|e| e.to_string())?;
+    Ok(Value::String(
+        String::from_utf8(buffer).map_err(|e| e.to_string())?,
+    ))
+}
+
+/// Serialize a value as YAML, without the trailing newline so it fits inline
+fn to_yaml(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let yaml = serde_yaml::to_string(input).map_err(|e| e.to_string())?;
+    Ok(Value::String(yaml.trim_end_matches('\n').to_string()))
+}
+
+/// Number of characters in a string or elements in an array/object (null counts as empty)
+fn length(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let len = match input {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.len(),
+        Value::Object(map) => map.len(),
+        Value::Null => 0,
+        other => return Err(format!("{} has no length", as_text(other))),
+    };
+    Ok(Value::from(len))
+}
+
+/// Sort an array in ascending order (`sort("desc")` for descending)
+///
+/// Numbers compare numerically and strings lexicographically; mixed types are grouped
+/// in the order null, booleans, numbers, strings, arrays, objects. The sort is stable.
+fn sort(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 1)?;
+    let descending = match args.first().map(as_text).as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Err(format!(
+                "order must be \"asc\" or \"desc\", got \"{}\"",
+                other
+            ))
+        }
+    };
+
+    let mut items = expect_array(input)?.clone();
+    items.sort_by(|a, b| {
+        let ordering = sort_order(a, b);
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(Value::Array(items))
+}
+
+/// Reverse the order of an array, or the characters of a string
+fn reverse(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    match input {
+        Value::String(s) => Ok(Value::String(s.chars().rev().collect())),
+        other => Ok(Value::Array(
+            expect_array(other)?.iter().rev().cloned().collect(),
+        )),
+    }
+}
+
+fn split(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 1, 1)?;
+    let separator = as_text(&args[0]);
+    if separator.is_empty() {
+        return Err("separator must not be empty".to_string());
+    }
+    Ok(Value::Array(
+        as_text(input)
+            .split(&separator)
+            .map(|part| Value::String(part.to_string()))
+            .collect(),
+    ))
+}
+
+/// Join array elements as text (default separator: none)
+fn join(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 1)?;
+    let separator = args.first().map(as_text).unwrap_or_default();
+    let parts: Vec<String> = expect_array(input)?.iter().map(as_text).collect();
+    Ok(Value::String(parts.join(&separator)))
+}
+
+/// Lowercase the text and replace every run of non-alphanumeric characters with `-`
+///
+/// Non-ASCII letters (e.g. Japanese) are kept so such titles do not collapse to nothing.
+fn slugify(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let mut slug = String::new();
+    let mut pending_dash = false;
+
+    for c in as_text(input).chars() {
+        if c.is_alphanumeric() {
+            if pending_dash && !slug.is_empty() {
+                slug.push('-');
+            }
+            pending_dash = false;
+            slug.extend(c.to_lowercase());
+        } else {
+            pending_dash = true;
+        }
+    }
+    Ok(Value::String(slug))
+}
+
+/// Clip text to at most `length` characters, ellipsis included (default ellipsis: `...`)
+fn truncate(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 1, 2)?;
+    let length = expect_count(&args[0])?;
+    let ellipsis = args
+        .get(1)
+        .map(as_text)
+        .unwrap_or_else(|| "...".to_string());
+    let text = as_text(input);
+
+    if text.chars().count() <= length {
+        return Ok(Value::String(text));
+    }
+    // An ellipsis longer than the limit is itself cut to fit
+    let ellipsis_length = ellipsis.chars().count();
+    if ellipsis_length >= length {
+        return Ok(Value::String(ellipsis.chars().take(length).collect()));
+    }
+    let clipped: String = text.chars().take(length - ellipsis_length).collect();
+    Ok(Value::String(format!("{}{}", clipped.trim_end(), ellipsis)))
+}
+
+/// Keep the first `count` whitespace-separated words, appending an ellipsis if any were cut
+fn truncate_words(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 1, 2)?;
+    let count = expect_count(&args[0])?;
+    let ellipsis = args
+        .get(1)
+        .map(as_text)
+        .unwrap_or_else(|| "...".to_string());
+    let text = as_text(input);
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() <= count {
+        return Ok(Value::String(text));
+    }
+    Ok(Value::String(format!(
+        "{}{}",
+        words[..count].join(" "),
+        ellipsis
+    )))
+}
+
+fn expect_count(arg: &Value) -> Result<usize, String> {
+    arg.as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| format!("expected a non-negative integer, got {}", as_text(arg)))
+}
+
+fn snake_case(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let words: Vec<String> = words(&as_text(input))
+        .iter()
+        .map(|w| w.to_lowercase())
+        .collect();
+    Ok(Value::String(words.join("_")))
+}
+
+fn kebab_case(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let words: Vec<String> = words(&as_text(input))
+        .iter()
+        .map(|w| w.to_lowercase())
+        .collect();
+    Ok(Value::String(words.join("-")))
+}
+
+fn camel_case(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let camel = words(&as_text(input))
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            if i == 0 {
+                w.to_lowercase()
+            } else {
+                capitalize(w)
+            }
+        })
+        .collect();
+    Ok(Value::String(camel))
+}
+
+fn title_case(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    let words: Vec<String> = words(&as_text(input))
+        .iter()
+        .map(|w| capitalize(w))
+        .collect();
+    Ok(Value::String(words.join(" ")))
+}
+
+/// Split text into words at separators and case changes (`HTTPServer2Go` -> HTTP, Server2, Go)
+fn words(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        if c.is_uppercase() && !current.is_empty() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            // lower/digit -> Upper starts a word; so does the last capital of an acronym
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower) {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// Uppercase the first character and lowercase the rest
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn expect_array(input: &Value) -> Result<&Vec<Value>, String> {
+    input
+        .as_array()
+        .ok_or_else(|| format!("expected an array, got {}", as_text(input)))
+}
+
+fn sort_order(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Number(x), Value::Number(y)) => x
+            .as_f64()
+            .partial_cmp(&y.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn escape_html_filter(input: &Value, args: &[Value]) -> Result<Value, String> {
+    expect_args(args, 0, 0)?;
+    Ok(Value::String(escape_html(&as_text(input))))
+}
+
+/// Escape the characters that are significant in HTML text and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn parse_timestamp(input: &Value) -> Result<DateTime<FixedOffset>, String> {
+    let utc = |naive: NaiveDateTime| naive.and_utc().fixed_offset();
+
+    match input {
+        Value::Number(n) => n
+            .as_i64()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|dt| dt.fixed_offset())
+            .ok_or_else(|| format!("invalid Unix timestamp {}", n)),
+        Value::String(s) => {
+            let s = s.trim();
+            DateTime::parse_from_rfc3339(s)
+                .ok()
+                .or_else(|| {
+                    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+                        .iter()
+                        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+                        .map(utc)
+                })
+                .or_else(|| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|d| d.and_hms_opt(0, 0, 0))
+                        .map(utc)
+                })
+                .ok_or_else(|| format!("cannot parse '{}' as a date", s))
+        }
+        other => Err(format!("cannot parse {} as a date", as_text(other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(name: &str, input: Value, args: &[Value]) -> Result<Value, String> {
+        FilterRegistry::with_builtins().apply(name, &input, args)
+    }
+
+    #[test]
+    fn test_upper_lower() {
+        assert_eq!(apply("upper", json!("Hello"), &[]).unwrap(), json!("HELLO"));
+        assert_eq!(apply("lower", json!("Hello"), &[]).unwrap(), json!("hello"));
+        assert_eq!(apply("upper", json!(true), &[]).unwrap(), json!("TRUE"));
+    }
+
+    #[test]
+    fn test_trim() {
+        assert_eq!(apply("trim", json!("  a b \n"), &[]).unwrap(), json!("a b"));
+    }
+
+    #[test]
+    fn test_replace() {
+        let result = apply("replace", json!("a-b-c"), &[json!("-"), json!("_")]).unwrap();
+        assert_eq!(result, json!("a_b_c"));
+        assert!(apply("replace", json!("abc"), &[json!("a")]).is_err());
+        assert!(apply("replace", json!("abc"), &[json!(""), json!("x")]).is_err());
+    }
+
+    #[test]
+    fn test_date() {
+        let fmt = [json!("%Y/%m/%d %H:%M")];
+        assert_eq!(
+            apply("date", json!("2024-03-05T10:20:30+09:00"), &fmt).unwrap(),
+            json!("2024/03/05 10:20")
+        );
+        assert_eq!(
+            apply("date", json!("2024-03-05 10:20:30"), &fmt).unwrap(),
+            json!("2024/03/05 10:20")
+        );
+        assert_eq!(
+            apply("date", json!(0), &fmt).unwrap(),
+            json!("1970/01/01 00:00")
+        );
+        assert_eq!(
+            apply("date", json!("2024-03-05"), &[]).unwrap(),
+            json!("2024-03-05")
+        );
+    }
+
+    #[test]
+    fn test_date_errors() {
+        assert!(apply("date", json!("yesterday"), &[]).is_err());
+        assert!(apply("date", json!(true), &[]).is_err());
+        assert!(apply("date", json!("2024-03-05"), &[json!("%Q")]).is_err());
+    }
+
+    #[test]
+    fn test_json_pretty() {
+        let config = json!({"model": "x", "stop": ["a"]});
+        assert_eq!(
+            apply("json_pretty", config.clone(), &[]).unwrap(),
+            json!("{\n  \"model\": \"x\",\n  \"stop\": [\n    \"a\"\n  ]\n}")
+        );
+        assert_eq!(
+            apply("json_pretty", json!([1]), &[json!(4)]).unwrap(),
+            json!("[\n    1\n]")
+        );
+        assert_eq!(
+            apply("json_pretty", json!("s"), &[]).unwrap(),
+            json!("\"s\"")
+        );
+        assert!(apply("json_pretty", config.clone(), &[json!(-1)]).is_err());
+        assert!(apply("json_pretty", config, &[json!("two")]).is_err());
+    }
+
+    #[test]
+    fn test_to_yaml() {
+        let context = json!({"user": {"name": "Ann", "tags": ["a", "b"]}});
+        assert_eq!(
+            apply("to_yaml", context, &[]).unwrap(),
+            json!("user:\n  name: Ann\n  tags:\n  - a\n  - b")
+        );
+        assert_eq!(apply("to_yaml", json!("text"), &[]).unwrap(), json!("text"));
+        assert!(apply("to_yaml", json!([]), &[json!(2)]).is_err());
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            apply(
+                "escape_html",
+                json!(r#"<a href="x">Tom & Jerry's</a>"#),
+                &[]
+            )
+            .unwrap(),
+            json!("&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;")
+        );
+        assert_eq!(apply("escape_html", json!(42), &[]).unwrap(), json!("42"));
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(apply("length", json!("日本語"), &[]).unwrap(), json!(3));
+        assert_eq!(apply("length", json!([1, 2]), &[]).unwrap(), json!(2));
+        assert_eq!(apply("length", json!({"a": 1}), &[]).unwrap(), json!(1));
+        assert_eq!(apply("length", json!(null), &[]).unwrap(), json!(0));
+        assert!(apply("length", json!(5), &[]).is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!(
+            apply("sort", json!(["carol", "alice", "bob"]), &[]).unwrap(),
+            json!(["alice", "bob", "carol"])
+        );
+        assert_eq!(
+            apply("sort", json!([10, 2, 3.5]), &[json!("desc")]).unwrap(),
+            json!([10, 3.5, 2])
+        );
+        assert_eq!(
+            apply("sort", json!(["b", 1, null, "a", true]), &[]).unwrap(),
+            json!([null, true, 1, "a", "b"])
+        );
+        assert!(apply("sort", json!("abc"), &[]).is_err());
+        assert!(apply("sort", json!([1]), &[json!("up")]).is_err());
+    }
+
+    #[test]
+    fn test_reverse() {
+        assert_eq!(
+            apply("reverse", json!([1, 2, 3]), &[]).unwrap(),
+            json!([3, 2, 1])
+        );
+        assert_eq!(apply("reverse", json!("abc"), &[]).unwrap(), json!("cba"));
+        assert!(apply("reverse", json!(1), &[]).is_err());
+    }
+
+    #[test]
+    fn test_split_join() {
+        assert_eq!(
+            apply("split", json!("a,b,,c"), &[json!(",")]).unwrap(),
+            json!(["a", "b", "", "c"])
+        );
+        assert_eq!(
+            apply("join", json!(["a", 1, true]), &[json!(" / ")]).unwrap(),
+            json!("a / 1 / true")
+        );
+        assert_eq!(apply("join", json!(["x", "y"]), &[]).unwrap(), json!("xy"));
+        assert!(apply("split", json!("abc"), &[json!("")]).is_err());
+        assert!(apply("join", json!("abc"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(
+            apply("slugify", json!("  Hello, World! 2024 "), &[]).unwrap(),
+            json!("hello-world-2024")
+        );
+        assert_eq!(
+            apply("slugify", json!("API_v2 / Getting--Started"), &[]).unwrap(),
+            json!("api-v2-getting-started")
+        );
+        assert_eq!(
+            apply("slugify", json!("日本語 タイトル"), &[]).unwrap(),
+            json!("日本語-タイトル")
+        );
+        assert_eq!(apply("slugify", json!("!!!"), &[]).unwrap(), json!(""));
+    }
+
+    #[test]
+    fn test_case_conversion() {
+        let cases = [
+            ("user_id", ["user_id", "userId", "user-id", "User Id"]),
+            (
+                "HTTPServerError",
+                [
+                    "http_server_error",
+                    "httpServerError",
+                    "http-server-error",
+                    "Http Server Error",
+                ],
+            ),
+            (
+                "getUser2Name",
+                [
+                    "get_user2_name",
+                    "getUser2Name",
+                    "get-user2-name",
+                    "Get User2 Name",
+                ],
+            ),
+            (
+                "  list all-items ",
+                [
+                    "list_all_items",
+                    "listAllItems",
+                    "list-all-items",
+                    "List All Items",
+                ],
+            ),
+        ];
+        for (input, expected) in cases {
+            for (filter, want) in ["snake_case", "camel_case", "kebab_case", "title_case"]
+                .iter()
+                .zip(expected)
+            {
+                assert_eq!(
+                    apply(filter, json!(input), &[]).unwrap(),
+                    json!(want),
+                    "{} | {}",
+                    input,
+                    filter
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncate() {
+        let text = json!("The quick brown fox");
+        assert_eq!(
+            apply("truncate", text.clone(), &[json!(12)]).unwrap(),
+            json!("The quick...")
+        );
+        assert_eq!(
+            apply("truncate", text.clone(), &[json!(10), json!("…")]).unwrap(),
+            json!("The quick…")
+        );
+        assert_eq!(
+            apply("truncate", text.clone(), &[json!(100)]).unwrap(),
+            text
+        );
+        assert_eq!(
+            apply("truncate", json!("日本語のテキスト"), &[json!(5)]).unwrap(),
+            json!("日本...")
+        );
+        assert_eq!(
+            apply("truncate", text.clone(), &[json!(2)]).unwrap(),
+            json!("..")
+        );
+        assert_eq!(
+            apply("truncate", text.clone(), &[json!(3)]).unwrap(),
+            json!("...")
+        );
+        assert_eq!(
+            apply("truncate", text.clone(), &[json!(0)]).unwrap(),
+            json!("")
+        );
+        assert_eq!(
+            apply("truncate", json!("abc"), &[json!(2), json!("…")]).unwrap(),
+            json!("a…")
+        );
+        assert!(apply("truncate", text.clone(), &[]).is_err());
+        assert!(apply("truncate", text, &[json!(-1)]).is_err());
+    }
+
+    #[test]
+    fn test_truncate_words() {
+        let text = json!("one two  three four");
+        assert_eq!(
+            apply("truncate_words", text.clone(), &[json!(2)]).unwrap(),
+            json!("one two...")
+        );
+        assert_eq!(
+            apply("truncate_words", text.clone(), &[json!(3), json!(" [...]")]).unwrap(),
+            json!("one two three [...]")
+        );
+        assert_eq!(
+            apply("truncate_words", text.clone(), &[json!(4)]).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn test_unknown_filter() {
+        let err = apply("nope", json!("x"), &[]).unwrap_err();
+        assert!(err.contains("unknown filter 'nope'"));
+    }
+
+    #[test]
+    fn test_register_closure() {
+        let mut registry = FilterRegistry::with_builtins();
+        let prefix = String::from("> ");
+        registry.register("quote", move |input, args| {
+            expect_args(args, 0, 0)?;
+            Ok(Value::String(format!("{}{}", prefix, as_text(input))))
+        });
+
+        assert!(registry.contains("quote") && registry.contains("upper"));
+        assert_eq!(
+            registry.apply("quote", &json!("hi"), &[]).unwrap(),
+            json!("> hi")
+        );
+        let err = registry
+            .apply("quote", &json!("hi"), &[json!(1)])
+            .unwrap_err();
+        assert!(err.starts_with("filter 'quote': expected 0"), "{}", err);
+
+        assert!(FilterRegistry::new().names().is_empty());
+        assert_eq!(registry.names().first(), Some(&"camel_case"));
+    }
+
+    #[test]
+    fn test_wrong_argument_count() {
+        let err = apply("upper", json!("x"), &[json!(1)]).unwrap_err();
+        assert!(err.contains("expected 0 argument(s), got 1"));
+    }
+}
@@ -1,19 +1,80 @@
-use crate::error::RenderError;
+use crate::error::{Location, RenderError};
+use crate::sandbox::{Sandbox, SymlinkPolicy};
+use crate::template::cache::TemplateCache;
+use crate::template::delimiters::Delimiters;
+use crate::template::expr::{EvalContext, Expr, Scope};
+use crate::template::filters::FilterRegistry;
+use crate::template::report::TRACE_TARGET;
+use crate::template::source::{default_source, IncludeSource};
+use crate::template::source_map::MappedText;
 use lazy_static::lazy_static;
 use path_clean::PathClean;
 use regex::Regex;
+use serde_json::{Map, Value};
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
-use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 lazy_static! {
-    // Match {{> path/to/file }}
-    static ref INCLUDE_PATTERN: Regex = Regex::new(r"\{\{>\s*([^}]+?)\s*\}\}").unwrap();
+    // Match {{> path/to/file }}, {{>? optional }} and {{>raw verbatim }},
+    // skipping over {{#raw}}...{{/raw}} blocks
+    // Group 1: `?` marking an optional include
+    // Group 2: `raw` marking a verbatim include
+    // Group 3: include path (absent for raw blocks)
+    static ref INCLUDE_PATTERN: Regex = Regex::new(
+        r"(?s)\{\{#raw\s*\}\}.*?\{\{/raw\s*\}\}|\{\{>(\?)?(raw\s)?\s*([^}]+?)\s*\}\}"
+    )
+    .unwrap();
+
+    // Start of the `name=value` parameters after an include path
+    static ref PARAMS_START: Regex = Regex::new(r"\s[A-Za-z_][A-Za-z0-9_]*=").unwrap();
+
+    // Match {{#extends "base.txt"}}, {{#block name}} and {{/block}}, skipping raw blocks
+    // Group 1: extends path (quoted or bare)
+    // Group 2: block name
+    // Group 3: closing tag
+    static ref INHERIT_PATTERN: Regex = Regex::new(
+        r#"(?s)\{\{#raw\s*\}\}.*?\{\{/raw\s*\}\}|\{\{#extends\s+["']?([^"'}]+?)["']?\s*\}\}|\{\{#block\s+([^}\s]+)\s*\}\}|(\{\{/block\s*\}\})"#
+    )
+    .unwrap();
+}
+
+/// A `{{#block name}}...{{/block}}` region, as byte offsets into the template
+struct BlockSpan {
+    name: String,
+    start: usize,
+    inner_start: usize,
+    inner_end: usize,
+}
+
+/// A file read while resolving a template, `depth` levels below the main template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub depth: usize,
+    pub path: PathBuf,
+    /// Included with `{{>raw}}`, so its content is not template syntax
+    pub verbatim: bool,
 }
 
 pub struct IncludeResolver {
     root_dir: PathBuf,
     max_depth: usize,
+    data: Value,
+    includes_allowed: bool,
+    dependencies: RefCell<Vec<Dependency>>,
+    collect_errors: bool,
+    errors: RefCell<Vec<RenderError>>,
+    /// The include directives being expanded, outermost first
+    include_stack: RefCell<Vec<Location>>,
+    /// Whether an include path was computed from the data
+    used_data: Cell<bool>,
+    cache: Arc<TemplateCache>,
+    source: Arc<dyn IncludeSource>,
+    sandbox: Arc<Sandbox>,
+    delimiters: Arc<Delimiters>,
+    filters: Arc<FilterRegistry>,
 }
 
 impl IncludeResolver {
@@ -21,9 +82,86 @@ impl IncludeResolver {
         Self {
             root_dir: root_dir.as_ref().to_path_buf(),
             max_depth,
+            data: Value::Null,
+            includes_allowed: true,
+            dependencies: RefCell::new(Vec::new()),
+            collect_errors: false,
+            errors: RefCell::new(Vec::new()),
+            include_stack: RefCell::new(Vec::new()),
+            used_data: Cell::new(false),
+            cache: Arc::default(),
+            source: default_source(),
+            sandbox: Arc::default(),
+            delimiters: Arc::default(),
+            filters: Arc::default(),
         }
     }
 
+    /// Keep going after an include fails, recording the error and expanding the include
+    /// to nothing, so that every broken include can be reported at once
+    pub fn with_errors_collected(mut self, enabled: bool) -> Self {
+        self.collect_errors = enabled;
+        self
+    }
+
+    /// Take the errors recorded while collecting errors, in the order they occurred
+    pub fn take_errors(&self) -> Vec<RenderError> {
+        self.errors.take()
+    }
+
+    /// Every file read so far (includes and `{{#extends}}` parents), in depth-first order
+    pub fn dependencies(&self) -> Vec<Dependency> {
+        self.dependencies.borrow().clone()
+    }
+
+    /// Whether a dynamic include path (`{{> (partials[model]) }}`) was evaluated, so the
+    /// expansion depends on the data
+    pub fn used_data(&self) -> bool {
+        self.used_data.get()
+    }
+
+    /// Reject every include and `{{#extends}}`, for templates without a root directory
+    pub fn with_includes_allowed(mut self, allowed: bool) -> Self {
+        self.includes_allowed = allowed;
+        self
+    }
+
+    /// Read files through a cache shared with other renders
+    pub fn with_cache(mut self, cache: Arc<TemplateCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Read included files from `source` instead of the filesystem
+    pub fn with_source(mut self, source: Arc<dyn IncludeSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Confine includes to the roots, file sizes and include count allowed by `sandbox`
+    pub fn with_sandbox(mut self, sandbox: Arc<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Read tags in included files with `delimiters` instead of `{{ }}`
+    pub fn with_delimiters(mut self, delimiters: Arc<Delimiters>) -> Self {
+        self.delimiters = delimiters;
+        self
+    }
+
+    /// Filters available to dynamic include paths, in place of the built-in set
+    pub fn with_filters(mut self, filters: Arc<FilterRegistry>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Data used to evaluate dynamic include paths like `{{> (partials[model]) }}`
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = data;
+        self
+    }
+
     /// Resolve all includes in the content recursively
     pub fn resolve(
         &self,
@@ -32,78 +170,444 @@ impl IncludeResolver {
         visited: &mut HashSet<PathBuf>,
         depth: usize,
     ) -> Result<String, RenderError> {
+        let content = MappedText::new(content, &current_file.display().to_string());
+        let resolved = self.resolve_mapped(&content, current_file, visited, depth)?;
+        Ok(resolved.into_parts().0)
+    }
+
+    /// Resolve all includes in the content recursively, mapping each region of the result
+    /// to the file it came from
+    pub fn resolve_mapped(
+        &self,
+        content: &MappedText,
+        current_file: &Path,
+        visited: &mut HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<MappedText, RenderError> {
         // Check depth limit
         if depth > self.max_depth {
             return Err(RenderError::IncludeDepthExceeded {
                 max_depth: self.max_depth,
+                included_from: Vec::new(),
             });
         }
 
-        let mut result = String::with_capacity(content.len());
+        let mut result = MappedText::default();
         let mut last_end = 0;
 
-        for cap in INCLUDE_PATTERN.captures_iter(content) {
+        for cap in INCLUDE_PATTERN.captures_iter(content.as_str()) {
             let full_match = cap.get(0).unwrap();
-            let start = full_match.start();
-            let end = full_match.end();
 
             // Add text before this match
-            result.push_str(&content[last_end..start]);
+            result.push_mapped(content, last_end..full_match.start());
 
-            // Extract the include path
-            let include_path = cap.get(1).unwrap().as_str().trim();
+            let location = content.location(full_match.start());
+            self.include_stack.borrow_mut().push(location.clone());
+            let expanded =
+                self.expand_include(&cap, content, location, current_file, visited, depth);
+            // Errors of this include (rather than of a deeper one) get the directives
+            // that led here
+            let expanded = expanded.map_err(|e| {
+                e.with_included_from(|| self.include_stack.borrow().iter().rev().cloned().collect())
+            });
+            self.include_stack.borrow_mut().pop();
+            match expanded {
+                Ok(expanded) => result.push_all(&expanded),
+                // When collecting errors, a failed include expands to nothing
+                Err(e) if self.collect_errors => self.errors.borrow_mut().push(e),
+                Err(e) => return Err(e),
+            }
+
+            last_end = full_match.end();
+        }
 
-            // Resolve the path
-            let resolved_path = self.resolve_path(current_file, include_path)?;
+        // Add remaining text
+        result.push_mapped(content, last_end..content.len());
 
-            // Check for circular include
-            if visited.contains(&resolved_path) {
-                return Err(RenderError::CircularInclude {
-                    path: resolved_path.display().to_string(),
-                });
+        Ok(result)
+    }
+
+    /// Expand one include directive (or keep a raw block as-is)
+    fn expand_include(
+        &self,
+        cap: &regex::Captures,
+        content: &MappedText,
+        location: Location,
+        current_file: &Path,
+        visited: &mut HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<MappedText, RenderError> {
+        let full_match = cap.get(0).unwrap();
+        let mut result = MappedText::default();
+
+        // Raw blocks are kept as-is for the variable substitutor
+        let optional = cap.get(1).is_some();
+        let target = match cap.get(3) {
+            Some(target) => target.as_str().trim(),
+            None => {
+                result.push_mapped(content, full_match.range());
+                return Ok(result);
             }
+        };
+
+        if let Some(message) = target_problem(target) {
+            return Err(RenderError::MalformedDelimiter {
+                message: message.to_string(),
+                location,
+            });
+        }
+
+        // `(expr)` computes the path from the data at include time
+        let (dynamic, target) = match split_dynamic(target) {
+            Some((expr, rest)) => match self.eval_path(expr, &location, optional)? {
+                Some(path) => (Some(path), rest),
+                None => return Ok(result),
+            },
+            None => (None, target),
+        };
+
+        // Verbatim includes skip nested includes and variable substitution entirely
+        if cap.get(2).is_some() {
+            let include_path = dynamic.as_deref().unwrap_or(target);
+            match self.load_verbatim(current_file, include_path, visited, depth + 1) {
+                Ok((_, included_content)) => {
+                    result.push_generated(&raw_block(&included_content), &location)
+                }
+                Err(RenderError::IncludeFileRead { source, .. })
+                    if optional && source.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            return Ok(result);
+        }
+
+        let (target, params) = split_params(target);
+        let (include_path, context) = match &dynamic {
+            Some(path) => (path.as_str(), Some(target).filter(|t| !t.is_empty())),
+            None => self.split_context(current_file, target)?,
+        };
 
-            // Check path traversal (ensure it's within root)
-            if !self.is_within_root(&resolved_path)? {
-                return Err(RenderError::PathTraversal {
+        // Add expanded content, scoped to its context and parameters. Parameters wrap
+        // the context so their values are evaluated where the include appears
+        if !params.is_empty() {
+            result.push_generated(&format!("{{{{#let {}}}}}", params), &location);
+        }
+        if let Some(context) = context {
+            result.push_generated(&format!("{{{{#with {}}}}}", context), &location);
+        }
+        if is_glob(include_path) {
+            let paths = self.glob(current_file, include_path).map_err(|message| {
+                RenderError::TemplateSyntax {
+                    message,
+                    location: location.clone(),
+                }
+            })?;
+            if paths.is_empty() && !optional {
+                return Err(RenderError::IncludeNotFound {
                     path: include_path.to_string(),
+                    from: current_file.display().to_string(),
+                    included_from: Vec::new(),
                 });
             }
 
-            // Read the included file
-            let included_content =
-                fs::read_to_string(&resolved_path).map_err(|e| RenderError::IncludeFileRead {
-                    path: resolved_path.display().to_string(),
-                    source: e,
-                })?;
+            for path in paths {
+                result.push_all(&self.include(current_file, &path, visited, depth)?);
+            }
+        } else {
+            match self.include(current_file, include_path, visited, depth) {
+                Ok(expanded) => result.push_all(&expanded),
+                // A missing optional include renders nothing
+                Err(RenderError::IncludeFileRead { source, .. })
+                    if optional && source.kind() == io::ErrorKind::NotFound =>
+                {
+                    return Ok(MappedText::default());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if context.is_some() {
+            result.push_generated("{{/with}}", &location);
+        }
+        if !params.is_empty() {
+            result.push_generated("{{/let}}", &location);
+        }
+        Ok(result)
+    }
 
-            // Mark as visited
-            visited.insert(resolved_path.clone());
+    /// Apply `{{#extends}}`: render the parent template with this template's blocks
+    ///
+    /// Expects content whose includes are already resolved. Anything outside `{{#block}}`
+    /// regions of an extending template is dropped, as the parent provides the skeleton.
+    /// Parents may extend further templates; the `{{#block}}` tags are kept so that each
+    /// level can override the blocks of the level above.
+    pub fn resolve_extends(
+        &self,
+        content: &str,
+        current_file: &Path,
+        visited: &mut HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<String, RenderError> {
+        let content = MappedText::new(content, &current_file.display().to_string());
+        let resolved = self.resolve_extends_mapped(&content, current_file, visited, depth)?;
+        Ok(resolved.into_parts().0)
+    }
 
-            // Recursively resolve includes in the included content
-            let expanded = self.resolve(&included_content, &resolved_path, visited, depth + 1)?;
+    /// Apply `{{#extends}}` to mapped content (see [`resolve_extends`](Self::resolve_extends))
+    pub fn resolve_extends_mapped(
+        &self,
+        content: &MappedText,
+        current_file: &Path,
+        visited: &mut HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<MappedText, RenderError> {
+        if depth > self.max_depth {
+            return Err(RenderError::IncludeDepthExceeded {
+                max_depth: self.max_depth,
+                included_from: Vec::new(),
+            });
+        }
 
-            // Add expanded content
-            result.push_str(&expanded);
+        let parent_path = match INHERIT_PATTERN
+            .captures_iter(content.as_str())
+            .find_map(|cap| cap.get(1))
+        {
+            Some(path) => path.as_str().trim(),
+            None => return Ok(content.clone()),
+        };
 
-            // Unmark (allow including the same file from different branches)
-            visited.remove(&resolved_path);
+        let (resolved_path, parent) = self.load(current_file, parent_path, visited, depth + 1)?;
+        visited.insert(resolved_path.clone());
+        let parent = self.resolve_mapped(&parent, &resolved_path, visited, depth + 1)?;
+        let parent = self.resolve_extends_mapped(&parent, &resolved_path, visited, depth + 1)?;
+        visited.remove(&resolved_path);
 
-            last_end = end;
+        // The child's definition of each block (the first one wins if repeated)
+        let mut overrides = std::collections::HashMap::new();
+        for span in block_spans(content.as_str()) {
+            overrides
+                .entry(span.name)
+                .or_insert(span.inner_start..span.inner_end);
         }
 
-        // Add remaining text
-        result.push_str(&content[last_end..]);
+        let mut result = MappedText::default();
+        let mut last_end = 0;
+        for span in block_spans(parent.as_str()) {
+            // Blocks nested in an overridden block were replaced along with it
+            if span.start < last_end {
+                continue;
+            }
+            if let Some(inner) = overrides.get(span.name.as_str()) {
+                result.push_mapped(&parent, last_end..span.inner_start);
+                result.push_mapped(content, inner.clone());
+                last_end = span.inner_end;
+            }
+        }
+        result.push_mapped(&parent, last_end..parent.len());
 
         Ok(result)
     }
 
+    /// Load one included file and recursively resolve its own includes
+    fn include(
+        &self,
+        current_file: &Path,
+        include_path: &str,
+        visited: &mut HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<MappedText, RenderError> {
+        let (resolved_path, included) =
+            self.load(current_file, include_path, visited, depth + 1)?;
+
+        // Mark as visited
+        visited.insert(resolved_path.clone());
+
+        // Recursively resolve includes in the included content
+        let expanded = self.resolve_mapped(&included, &resolved_path, visited, depth + 1);
+
+        // Unmark (allow including the same file from different branches)
+        visited.remove(&resolved_path);
+
+        expanded
+    }
+
+    /// Expand a glob include pattern into the matching files, in sorted order
+    fn glob(&self, current_file: &Path, pattern: &str) -> Result<Vec<String>, String> {
+        let current_dir = current_file.parent().unwrap_or_else(|| Path::new("."));
+        let full_pattern = Path::new(&glob::Pattern::escape(&current_dir.to_string_lossy()))
+            .join(pattern)
+            .to_string_lossy()
+            .into_owned();
+
+        let paths = self
+            .source
+            .glob(&full_pattern)
+            .map_err(|e| format!("invalid include pattern '{}': {}", pattern, e))?;
+        Ok(paths
+            .into_iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Locate, check and read a file referenced from `current_file`, translated to `{{ }}`
+    /// syntax
+    fn load(
+        &self,
+        current_file: &Path,
+        include_path: &str,
+        visited: &HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<(PathBuf, MappedText), RenderError> {
+        let (resolved_path, content) =
+            self.read(current_file, include_path, visited, depth, false)?;
+        let content = MappedText::new(&content, &resolved_path.display().to_string());
+        Ok((resolved_path, self.delimiters.translate(content)))
+    }
+
+    /// Like `load`, for a file whose content is used verbatim
+    fn load_verbatim(
+        &self,
+        current_file: &Path,
+        include_path: &str,
+        visited: &HashSet<PathBuf>,
+        depth: usize,
+    ) -> Result<(PathBuf, String), RenderError> {
+        self.read(current_file, include_path, visited, depth, true)
+    }
+
+    fn read(
+        &self,
+        current_file: &Path,
+        include_path: &str,
+        visited: &HashSet<PathBuf>,
+        depth: usize,
+        verbatim: bool,
+    ) -> Result<(PathBuf, String), RenderError> {
+        if !self.includes_allowed {
+            return Err(RenderError::Usage(format!(
+                "cannot include '{}' from {}: a template read from stdin needs --root for includes",
+                include_path,
+                current_file.display()
+            )));
+        }
+
+        // Resolve the path
+        let resolved_path = self.resolve_path(current_file, include_path)?;
+
+        // Check for circular include
+        if visited.contains(&resolved_path) {
+            return Err(RenderError::CircularInclude {
+                path: resolved_path.display().to_string(),
+                included_from: Vec::new(),
+            });
+        }
+
+        // Check path traversal (ensure it's within root)
+        if !self.is_within_root(&resolved_path)? {
+            return Err(RenderError::PathTraversal {
+                path: include_path.to_string(),
+                included_from: Vec::new(),
+            });
+        }
+
+        if let Some(max_includes) = self.sandbox.max_includes {
+            if self.dependencies.borrow().len() >= max_includes {
+                return Err(RenderError::TooManyIncludes { max_includes });
+            }
+        }
+
+        // Read the included file
+        let read_error = |e| RenderError::IncludeFileRead {
+            path: resolved_path.display().to_string(),
+            source: e,
+            included_from: Vec::new(),
+        };
+        if self.sandbox.symlinks == SymlinkPolicy::Deny && self.source.is_symlink(&resolved_path) {
+            return Err(read_error(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "symlinks are not allowed (--no-symlinks)",
+            )));
+        }
+        let content = self
+            .cache
+            .read(&resolved_path, self.source.as_ref())
+            .and_then(|content| self.sandbox.check_size(&content).map(|_| content))
+            .map_err(read_error)?;
+
+        tracing::info!(depth, "resolved include {}", resolved_path.display());
+        tracing::trace!(target: TRACE_TARGET, depth, path = %resolved_path.display(), "include");
+        self.dependencies.borrow_mut().push(Dependency {
+            depth,
+            path: resolved_path.clone(),
+            verbatim,
+        });
+        Ok((resolved_path, content))
+    }
+
+    /// Evaluate a dynamic include path against the data
+    ///
+    /// Returns None when an optional include's path is undefined or null.
+    fn eval_path(
+        &self,
+        source: &str,
+        location: &Location,
+        optional: bool,
+    ) -> Result<Option<String>, RenderError> {
+        let invalid = |message: String| RenderError::VariableResolution {
+            message,
+            location: location.clone(),
+        };
+        let expr = Expr::parse(source).map_err(|m| invalid(format!("{} in '{}'", m, source)))?;
+        self.used_data.set(true);
+
+        let ctx = EvalContext {
+            scope: &Scope::root(&self.data),
+            locals: &Map::new(),
+            filters: &self.filters,
+            functions: None,
+            now: None,
+            location,
+            undefined_as_null: false,
+            missing: None,
+        };
+
+        match expr.eval(&ctx) {
+            Ok(Value::String(path)) => Ok(Some(path)),
+            Ok(Value::Null) | Err(RenderError::UndefinedVariable { .. }) if optional => Ok(None),
+            Ok(other) => Err(invalid(format!(
+                "include path '({})' must be a string, got {}",
+                source, other
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Split `path context` into the path and the optional context expression
+    ///
+    /// A path containing spaces is kept whole when it names an existing file.
+    fn split_context<'t>(
+        &self,
+        current_file: &Path,
+        target: &'t str,
+    ) -> Result<(&'t str, Option<&'t str>), RenderError> {
+        match target.rsplit_once(char::is_whitespace) {
+            Some((path, context))
+                if !self
+                    .source
+                    .is_file(&self.resolve_path(current_file, target)?) =>
+            {
+                Ok((path.trim_end(), Some(context)))
+            }
+            _ => Ok((target, None)),
+        }
+    }
+
     /// Resolve a relative include path to an absolute path
-    fn resolve_path(&self, current_file: &Path, relative_path: &str) -> Result<PathBuf, RenderError> {
+    fn resolve_path(
+        &self,
+        current_file: &Path,
+        relative_path: &str,
+    ) -> Result<PathBuf, RenderError> {
         // Get the directory of the current file
-        let current_dir = current_file
-            .parent()
-            .unwrap_or_else(|| Path::new("."));
+        let current_dir = current_file.parent().unwrap_or_else(|| Path::new("."));
 
         // Join with the relative path
         let joined = current_dir.join(relative_path);
@@ -114,34 +618,162 @@ impl IncludeResolver {
         Ok(cleaned)
     }
 
-    /// Check if a path is within the root directory
+    /// Check if a path is within the root directory or another root the sandbox allows
     fn is_within_root(&self, path: &Path) -> Result<bool, RenderError> {
-        // Canonicalize both paths to resolve symlinks and get absolute paths
-        let canonical_path = path
-            .canonicalize()
-            .map_err(|e| RenderError::IncludeFileRead {
+        let within = self.source.is_within(path, &self.root_dir).map_err(|e| {
+            RenderError::IncludeFileRead {
                 path: path.display().to_string(),
                 source: e,
-            })?;
+                included_from: Vec::new(),
+            }
+        })?;
+        // An allowed root that cannot be checked (e.g. does not exist) contains nothing
+        Ok(within
+            || self
+                .sandbox
+                .allowed_roots
+                .iter()
+                .any(|root| self.source.is_within(path, root).unwrap_or(false)))
+    }
+}
 
-        let canonical_root = self
-            .root_dir
-            .canonicalize()
-            .map_err(|e| RenderError::IncludeFileRead {
-                path: self.root_dir.display().to_string(),
-                source: e,
-            })?;
+/// Whether `tag` is a whole include directive, such as `{{> header.txt }}`
+pub(crate) fn is_include_tag(tag: &str) -> bool {
+    INCLUDE_PATTERN
+        .captures(tag)
+        .is_some_and(|cap| cap.get(0).unwrap().len() == tag.len() && cap.get(3).is_some())
+}
+
+/// What is wrong with the target of an include directive that can never be read
+fn target_problem(target: &str) -> Option<&'static str> {
+    if target.is_empty() {
+        Some("include tag without a file path")
+    } else if target.starts_with('>') {
+        Some("unexpected '>' after '{{>' in include tag")
+    } else if target.starts_with(['"', '\'']) {
+        Some("include paths are written without quotes")
+    } else if target.starts_with('(') && split_dynamic(target).is_none() {
+        Some("unclosed '(' in dynamic include path")
+    } else {
+        None
+    }
+}
+
+/// Replace include and `{{#extends}}` directives with spaces, so the rest of a file can be
+/// parsed on its own while byte offsets (and so line and column numbers) stay the same
+pub fn blank_directives(content: &str) -> String {
+    let mut result = content.to_string();
+    let include_tags = INCLUDE_PATTERN
+        .captures_iter(content)
+        .filter(|cap| cap.get(3).is_some())
+        .map(|cap| cap.get(0).unwrap().range());
+    let extends_tags = INHERIT_PATTERN
+        .captures_iter(content)
+        .filter(|cap| cap.get(1).is_some())
+        .map(|cap| cap.get(0).unwrap().range());
+    for range in include_tags.chain(extends_tags) {
+        // One space per byte, so multi-byte characters keep their length too
+        let blank: String = content[range.clone()]
+            .bytes()
+            .map(|b| if b == b'\n' { '\n' } else { ' ' })
+            .collect();
+        result.replace_range(range, &blank);
+    }
+    result
+}
+
+/// Wrap text in a `{{#raw}}` block so it is output verbatim
+///
+/// A `{{/raw` inside the text would end the block early, so it is emitted as a string literal.
+pub(crate) fn raw_block(text: &str) -> String {
+    let parts: Vec<String> = text
+        .split("{{/raw")
+        .map(|part| format!("{{{{#raw}}}}{}{{{{/raw}}}}", part))
+        .collect();
+    parts.join("{{ '{{/raw' }}")
+}
+
+/// Split `(expr) rest` into the dynamic path expression and the rest of the include
+fn split_dynamic(target: &str) -> Option<(&str, &str)> {
+    if !target.starts_with('(') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in target.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&target[1..i], &target[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether an include path is a glob pattern rather than a single file
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
 
-        Ok(canonical_path.starts_with(&canonical_root))
+/// Split `path name=value ...` into the path and the (possibly empty) parameter list
+///
+/// Parameters start at the first ` name=` so that paths containing spaces keep working.
+fn split_params(target: &str) -> (&str, &str) {
+    match PARAMS_START.find(target) {
+        Some(m) => (target[..m.start()].trim(), target[m.start()..].trim()),
+        None => (target, ""),
     }
 }
 
+/// Find all properly nested `{{#block}}` regions, ordered by their start
+///
+/// Unbalanced tags are ignored here; the template parser reports them later.
+fn block_spans(content: &str) -> Vec<BlockSpan> {
+    let mut spans = Vec::new();
+    let mut open: Vec<(String, usize, usize)> = Vec::new();
+
+    for cap in INHERIT_PATTERN.captures_iter(content) {
+        let full_match = cap.get(0).unwrap();
+        if let Some(name) = cap.get(2) {
+            open.push((
+                name.as_str().to_string(),
+                full_match.start(),
+                full_match.end(),
+            ));
+        } else if cap.get(3).is_some() {
+            if let Some((name, start, inner_start)) = open.pop() {
+                spans.push(BlockSpan {
+                    name,
+                    start,
+                    inner_start,
+                    inner_end: full_match.start(),
+                });
+            }
+        }
+    }
+
+    spans.sort_by_key(|span| span.start);
+    spans
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
-    use tempfile::{tempdir, NamedTempFile};
+    #[cfg(feature = "fs")]
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_simple_include() {
         let dir = tempdir().unwrap();
@@ -157,10 +789,13 @@ mod tests {
         let content = fs::read_to_string(&main_file).unwrap();
         let mut visited = HashSet::new();
 
-        let result = resolver.resolve(&content, &main_file, &mut visited, 0).unwrap();
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
         assert_eq!(result, "Start\nHello from include!\nEnd");
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_nested_include() {
         let dir = tempdir().unwrap();
@@ -179,10 +814,13 @@ mod tests {
         let content = fs::read_to_string(&main_file).unwrap();
         let mut visited = HashSet::new();
 
-        let result = resolver.resolve(&content, &main_file, &mut visited, 0).unwrap();
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
         assert_eq!(result, "Main\nContent A\nContent B");
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_circular_include() {
         let dir = tempdir().unwrap();
@@ -206,6 +844,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_depth_limit() {
         let dir = tempdir().unwrap();
@@ -264,6 +903,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_multiple_includes() {
         let dir = tempdir().unwrap();
@@ -280,10 +920,28 @@ mod tests {
         let content = fs::read_to_string(&main_file).unwrap();
         let mut visited = HashSet::new();
 
-        let result = resolver.resolve(&content, &main_file, &mut visited, 0).unwrap();
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
         assert_eq!(result, "Content A and Content B");
     }
 
+    #[test]
+    fn test_include_inside_raw_block() {
+        let dir = tempdir().unwrap();
+        let main_file = dir.path().join("main.txt");
+        fs::write(&main_file, "{{#raw}}{{> missing.txt }}{{/raw}}").unwrap();
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(result, "{{#raw}}{{> missing.txt }}{{/raw}}");
+    }
+
     #[test]
     fn test_no_includes() {
         let dir = tempdir().unwrap();
@@ -294,7 +952,547 @@ mod tests {
         let content = fs::read_to_string(&main_file).unwrap();
         let mut visited = HashSet::new();
 
-        let result = resolver.resolve(&content, &main_file, &mut visited, 0).unwrap();
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
         assert_eq!(result, "No includes here!");
     }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_include_with_params() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("card.txt"), "[{{ title }}]").unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(&main_file, r#"{{> card.txt title="Intro" n=3 }}"#).unwrap();
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(result, r#"{{#let title="Intro" n=3}}[{{ title }}]{{/let}}"#);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_include_with_context() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("card.txt"), "{{ name }}").unwrap();
+        fs::write(dir.path().join("file with spaces.txt"), "spaced").unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(
+            &main_file,
+            r#"{{> card.txt users.3 }}|{{> card.txt user note="hi" }}|{{> file with spaces.txt }}"#,
+        )
+        .unwrap();
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(
+            result,
+            r#"{{#with users.3}}{{ name }}{{/with}}|{{#let note="hi"}}{{#with user}}{{ name }}{{/with}}{{/let}}|spaced"#
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_optional_include() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("present.txt"), "here").unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(
+            &main_file,
+            "[{{>? present.txt }}][{{>? missing.txt }}][{{>? missing.txt user }}]",
+        )
+        .unwrap();
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(result, "[here][][]");
+
+        // Only a missing file is tolerated; escaping the root is still an error
+        fs::write(&main_file, "{{>? ../../../../etc/passwd }}").unwrap();
+        let content = fs::read_to_string(&main_file).unwrap();
+        let result = resolver.resolve(&content, &main_file, &mut visited, 0);
+        assert!(matches!(result, Err(RenderError::PathTraversal { .. })));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_glob_include() {
+        let dir = tempdir().unwrap();
+        let sections = dir.path().join("sections");
+        fs::create_dir(&sections).unwrap();
+        fs::write(sections.join("02-body.md"), "B{{> ../shared.txt }}").unwrap();
+        fs::write(sections.join("01-intro.md"), "A").unwrap();
+        fs::write(sections.join("notes.txt"), "X").unwrap();
+        fs::write(dir.path().join("shared.txt"), "s").unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(
+            &main_file,
+            "[{{> sections/*.md }}][{{>? none/*.md }}][{{> sections/*.md sec }}]",
+        )
+        .unwrap();
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(result, "[ABs][][{{#with sec}}ABs{{/with}}]");
+
+        // A pattern without matches is an error unless optional
+        fs::write(&main_file, "{{> none/*.md }}").unwrap();
+        let content = fs::read_to_string(&main_file).unwrap();
+        let result = resolver.resolve(&content, &main_file, &mut visited, 0);
+        assert!(matches!(result, Err(RenderError::IncludeNotFound { .. })));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_glob_include_outside_root() {
+        let outer = tempdir().unwrap();
+        let root = outer.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(outer.path().join("secret.txt"), "secret").unwrap();
+
+        let main_file = root.join("main.txt");
+        fs::write(&main_file, "{{> ../*.txt }}").unwrap();
+
+        let resolver = IncludeResolver::new(&root, 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver.resolve(&content, &main_file, &mut visited, 0);
+        assert!(matches!(result, Err(RenderError::PathTraversal { .. })));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_raw_include() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("example.txt"),
+            "{{ name }} {{> other.txt }}",
+        )
+        .unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(&main_file, "{{>raw example.txt }}{{>?raw missing.txt }}").unwrap();
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(result, "{{#raw}}{{ name }} {{> other.txt }}{{/raw}}");
+    }
+
+    #[test]
+    fn test_raw_block_closing_tag() {
+        assert_eq!(
+            raw_block("a {{/raw}} b"),
+            "{{#raw}}a {{/raw}}{{ '{{/raw' }}{{#raw}}}} b{{/raw}}"
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_dynamic_include() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("gpt.txt"), "GPT {{ x }}").unwrap();
+        fs::write(dir.path().join("claude.txt"), "Claude {{ x }}").unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(
+            &main_file,
+            "[{{> (partials[model]) }}][{{> (model ~ '.txt') user x=1 }}][{{>? (missing) }}]",
+        )
+        .unwrap();
+
+        let data =
+            json!({"model": "claude", "partials": {"claude": "claude.txt", "gpt": "gpt.txt"}});
+        let resolver = IncludeResolver::new(dir.path(), 20).with_data(data);
+        let content = fs::read_to_string(&main_file).unwrap();
+        let mut visited = HashSet::new();
+
+        let result = resolver
+            .resolve(&content, &main_file, &mut visited, 0)
+            .unwrap();
+        assert_eq!(
+            result,
+            "[Claude {{ x }}][{{#let x=1}}{{#with user}}Claude {{ x }}{{/with}}{{/let}}][]"
+        );
+
+        for (template, expected) in [
+            ("{{> (missing) }}", "UndefinedVariable"),
+            ("{{> (partials) }}", "VariableResolution"),
+            ("{{> ('../../etc/passwd') }}", "PathTraversal"),
+            ("{{> (model }}", "MalformedDelimiter"),
+            ("{{> }}", "MalformedDelimiter"),
+            ("{{>>  claude.txt }}", "MalformedDelimiter"),
+            ("{{>? 'claude.txt' }}", "MalformedDelimiter"),
+        ] {
+            fs::write(&main_file, template).unwrap();
+            let content = fs::read_to_string(&main_file).unwrap();
+            let err = resolver
+                .resolve(&content, &main_file, &mut visited, 0)
+                .unwrap_err();
+            assert!(format!("{:?}", err).starts_with(expected), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_split_dynamic() {
+        assert_eq!(split_dynamic("a.txt"), None);
+        assert_eq!(split_dynamic("(a[b]) ctx"), Some(("a[b]", " ctx")));
+        assert_eq!(
+            split_dynamic("(f(a, ')') ~ b) x=1"),
+            Some(("f(a, ')') ~ b", " x=1"))
+        );
+        assert_eq!(split_dynamic("(unclosed"), None);
+    }
+
+    #[test]
+    fn test_split_params() {
+        assert_eq!(split_params("a.txt"), ("a.txt", ""));
+        assert_eq!(
+            split_params("file with spaces.txt"),
+            ("file with spaces.txt", "")
+        );
+        assert_eq!(
+            split_params("card.txt title=\"A B\" n=3"),
+            ("card.txt", "title=\"A B\" n=3")
+        );
+    }
+
+    fn extend(dir: &Path, main_file: &Path) -> Result<String, RenderError> {
+        let resolver = IncludeResolver::new(dir, 20);
+        let content = fs::read_to_string(main_file).unwrap();
+        let mut visited = HashSet::new();
+        let expanded = resolver.resolve(&content, main_file, &mut visited, 0)?;
+        resolver.resolve_extends(&expanded, main_file, &mut visited, 0)
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_extends_overrides_blocks() {
+        let dir = tempdir().unwrap();
+
+        let base = dir.path().join("base.txt");
+        fs::write(
+            &base,
+            "[{{#block header}}Default header{{/block}}]\n[{{#block body}}Default body{{/block}}]",
+        )
+        .unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(
+            &main_file,
+            "{{#extends \"base.txt\"}}\nignored\n{{#block body}}Custom {{ x }}{{/block}}",
+        )
+        .unwrap();
+
+        let result = extend(dir.path(), &main_file).unwrap();
+        assert_eq!(
+            result,
+            "[{{#block header}}Default header{{/block}}]\n[{{#block body}}Custom {{ x }}{{/block}}]"
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_extends_multi_level_and_nested_blocks() {
+        let dir = tempdir().unwrap();
+
+        fs::write(
+            dir.path().join("root.txt"),
+            "{{#block page}}<{{#block title}}Root{{/block}}>{{/block}}|{{#block footer}}F{{/block}}",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("layout.txt"),
+            "{{#extends root.txt}}{{#block title}}Layout{{/block}}{{#block footer}}LF{{/block}}",
+        )
+        .unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(
+            &main_file,
+            "{{#extends 'layout.txt'}}{{#block title}}Page{{/block}}",
+        )
+        .unwrap();
+
+        let result = extend(dir.path(), &main_file).unwrap();
+        assert_eq!(
+            result,
+            "{{#block page}}<{{#block title}}Page{{/block}}>{{/block}}|{{#block footer}}LF{{/block}}"
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_dependencies() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "A {{> b.txt }}").unwrap();
+        fs::write(dir.path().join("b.txt"), "B").unwrap();
+        fs::write(dir.path().join("c.txt"), "{{> nope }}").unwrap();
+        fs::write(
+            dir.path().join("layout.txt"),
+            "{{> b.txt }}{{#block x}}{{/block}}",
+        )
+        .unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        let content = "{{#extends layout.txt}}{{#block x}}{{> a.txt }}{{>raw c.txt }}{{/block}}";
+
+        let resolver = IncludeResolver::new(dir.path(), 10);
+        let mut visited = HashSet::new();
+        let expanded = resolver
+            .resolve(content, &main_file, &mut visited, 0)
+            .unwrap();
+        resolver
+            .resolve_extends(&expanded, &main_file, &mut visited, 0)
+            .unwrap();
+
+        let dependencies: Vec<(usize, PathBuf)> = resolver
+            .dependencies()
+            .into_iter()
+            .map(|d| {
+                (
+                    d.depth,
+                    d.path.strip_prefix(dir.path()).unwrap().to_path_buf(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            dependencies,
+            vec![
+                (1, PathBuf::from("a.txt")),
+                (2, PathBuf::from("b.txt")),
+                (1, PathBuf::from("c.txt")),
+                (1, PathBuf::from("layout.txt")),
+                (2, PathBuf::from("b.txt")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_extends_circular() {
+        let dir = tempdir().unwrap();
+
+        let main_file = dir.path().join("main.txt");
+        fs::write(&main_file, "{{#extends other.txt}}").unwrap();
+        fs::write(dir.path().join("other.txt"), "{{#extends main.txt}}").unwrap();
+
+        let result = extend(dir.path(), &main_file);
+        assert!(matches!(result, Err(RenderError::CircularInclude { .. })));
+    }
+
+    #[test]
+    fn test_extends_outside_root() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        fs::write(dir.path().join("base.txt"), "base").unwrap();
+
+        let main_file = sub.join("main.txt");
+        fs::write(&main_file, "{{#extends ../base.txt}}").unwrap();
+
+        let result = extend(&sub, &main_file);
+        assert!(matches!(result, Err(RenderError::PathTraversal { .. })));
+    }
+
+    /// Files kept in a map, as a library user might provide them
+    struct MapSource(std::collections::HashMap<PathBuf, String>);
+
+    impl IncludeSource for MapSource {
+        fn read(&self, path: &Path) -> io::Result<String> {
+            self.0
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        fn is_file(&self, path: &Path) -> bool {
+            self.0.contains_key(path)
+        }
+    }
+
+    #[test]
+    fn test_custom_source() {
+        let source = MapSource(
+            [
+                ("/prompts/header.txt", "[{{> parts/title.txt }}]"),
+                ("/prompts/parts/title.txt", "Title"),
+                ("/secret.txt", "secret"),
+            ]
+            .into_iter()
+            .map(|(path, content)| (PathBuf::from(path), content.to_string()))
+            .collect(),
+        );
+        let resolver = IncludeResolver::new("/prompts", 20).with_source(Arc::new(source));
+        let main_file = Path::new("/prompts/main.txt");
+        let resolve = |content: &str| resolver.resolve(content, main_file, &mut HashSet::new(), 0);
+
+        assert_eq!(
+            resolve("{{> header.txt }}{{>? missing.txt }}").unwrap(),
+            "[Title]"
+        );
+        assert!(matches!(
+            resolve("{{> missing.txt }}"),
+            Err(RenderError::IncludeFileRead { .. })
+        ));
+        assert!(matches!(
+            resolve("{{> ../secret.txt }}"),
+            Err(RenderError::PathTraversal { .. })
+        ));
+        assert!(matches!(
+            resolve("{{> parts/*.txt }}"),
+            Err(RenderError::TemplateSyntax { .. })
+        ));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_sandbox() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("prompts");
+        let shared = dir.path().join("shared");
+        fs::create_dir(&root).unwrap();
+        fs::create_dir(&shared).unwrap();
+        fs::write(root.join("a.txt"), "A").unwrap();
+        fs::write(root.join("big.txt"), "0123456789").unwrap();
+        fs::write(shared.join("footer.txt"), "F").unwrap();
+        let main_file = root.join("main.txt");
+        let resolve = |sandbox: Sandbox, content: &str| {
+            IncludeResolver::new(&root, 20)
+                .with_sandbox(Arc::new(sandbox))
+                .resolve(content, &main_file, &mut HashSet::new(), 0)
+        };
+
+        // Other roots are only reachable when allowed
+        let content = "{{> a.txt }}{{> ../shared/footer.txt }}";
+        assert!(matches!(
+            resolve(Sandbox::new(), content),
+            Err(RenderError::PathTraversal { .. })
+        ));
+        let sandbox = Sandbox::new().allow_root(&shared).allow_root("missing");
+        assert_eq!(resolve(sandbox, content).unwrap(), "AF");
+
+        let sandbox = Sandbox::new().max_includes(1);
+        assert_eq!(resolve(sandbox.clone(), "{{> a.txt }}").unwrap(), "A");
+        assert!(matches!(
+            resolve(sandbox, "{{> a.txt }}{{> a.txt }}"),
+            Err(RenderError::TooManyIncludes { max_includes: 1 })
+        ));
+
+        let sandbox = Sandbox::new().max_file_size(5);
+        assert_eq!(resolve(sandbox.clone(), "{{> a.txt }}").unwrap(), "A");
+        match resolve(sandbox, "{{> big.txt }}") {
+            Err(RenderError::IncludeFileRead { source, .. }) => {
+                assert_eq!(source.kind(), io::ErrorKind::InvalidData)
+            }
+            other => panic!("Expected IncludeFileRead error, got {:?}", other),
+        }
+    }
+
+    #[cfg(all(unix, feature = "fs"))]
+    #[test]
+    fn test_sandbox_symlinks() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "A").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("a.txt"), dir.path().join("link.txt")).unwrap();
+        let main_file = dir.path().join("main.txt");
+        let resolve = |policy| {
+            IncludeResolver::new(dir.path(), 20)
+                .with_sandbox(Arc::new(Sandbox::new().symlinks(policy)))
+                .resolve("{{> link.txt }}", &main_file, &mut HashSet::new(), 0)
+        };
+
+        assert_eq!(resolve(SymlinkPolicy::Follow).unwrap(), "A");
+        match resolve(SymlinkPolicy::Deny) {
+            Err(RenderError::IncludeFileRead { source, .. }) => {
+                assert_eq!(source.kind(), io::ErrorKind::PermissionDenied)
+            }
+            other => panic!("Expected IncludeFileRead error, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_include_chain() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "A\n{{> b.txt }}").unwrap();
+        fs::write(dir.path().join("b.txt"), "{{> missing.txt }}").unwrap();
+        fs::write(dir.path().join("self.txt"), "{{> self.txt }}").unwrap();
+        let main_file = dir.path().join("main.txt");
+        let chain = |error: &RenderError| {
+            error
+                .included_from()
+                .iter()
+                .map(|location| {
+                    let file = Path::new(&location.file).file_name().unwrap();
+                    format!("{}:{}", file.to_string_lossy(), location.line)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let resolver = IncludeResolver::new(dir.path(), 20);
+        let content = "main\n\n  {{> a.txt }}";
+        let error = resolver
+            .resolve(content, &main_file, &mut HashSet::new(), 0)
+            .unwrap_err();
+        assert!(matches!(error, RenderError::IncludeFileRead { .. }));
+        assert_eq!(chain(&error), ["b.txt:1", "a.txt:2", "main.txt:3"]);
+        assert_eq!(error.to_string().matches(", included from ").count(), 3);
+
+        let error = IncludeResolver::new(dir.path(), 1)
+            .resolve(content, &main_file, &mut HashSet::new(), 0)
+            .unwrap_err();
+        assert!(matches!(error, RenderError::IncludeDepthExceeded { .. }));
+        assert_eq!(chain(&error), ["a.txt:2", "main.txt:3"]);
+
+        let error = IncludeResolver::new(dir.path(), 2)
+            .resolve("{{> self.txt }}", &main_file, &mut HashSet::new(), 0)
+            .unwrap_err();
+        assert!(matches!(error, RenderError::CircularInclude { .. }));
+        assert_eq!(chain(&error), ["self.txt:1", "main.txt:1"]);
+
+        // Collected errors keep the whole chain too
+        let resolver = IncludeResolver::new(dir.path(), 20).with_errors_collected(true);
+        resolver
+            .resolve(
+                "{{> a.txt }}{{> a.txt }}",
+                &main_file,
+                &mut HashSet::new(),
+                0,
+            )
+            .unwrap();
+        let errors = resolver.take_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(chain(&errors[1]), ["b.txt:1", "a.txt:2", "main.txt:1"]);
+    }
 }
@@ -0,0 +1,151 @@
+use crate::error::{Location, RenderError};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::include::is_include_tag;
+
+lazy_static! {
+    // {{#raw}}...{{/raw}} blocks, whose content is not template syntax
+    static ref RAW_BLOCK_PATTERN: Regex =
+        Regex::new(r"(?s)\{\{#raw\s*\}\}.*?\{\{/raw\s*\}\}").unwrap();
+}
+
+/// Find the delimiters of `content` that the renderer would copy to the output as text:
+/// `{{` without a `}}` before the next `{{`, `}}` outside of a tag, and tags that are not
+/// template syntax (`{{}}`, `{{ name }` followed by `}}`, `{{ > file }}`)
+///
+/// Text in `{{#raw}}` blocks and escaped `\{{ ... }}` tags is skipped. `locate` gives the
+/// position of a byte offset of `content`. Used by `check` and by strict renders, as a
+/// literal `}}` (in JSON, say) is fine otherwise.
+pub fn check_delimiters(content: &str, locate: impl Fn(usize) -> Location) -> Vec<RenderError> {
+    let raw_blocks: Vec<_> = RAW_BLOCK_PATTERN
+        .find_iter(content)
+        .map(|m| m.range())
+        .collect();
+    let mut problems = Vec::new();
+    let mut problem = |offset: usize, message: &str| {
+        problems.push(RenderError::MalformedDelimiter {
+            message: message.to_string(),
+            location: locate(offset),
+        })
+    };
+
+    let mut pos = 0;
+    while let Some(offset) = next_delimiter(content, pos) {
+        if let Some(raw) = raw_blocks.iter().find(|range| range.contains(&offset)) {
+            pos = raw.end;
+            continue;
+        }
+        if content[offset..].starts_with("}}") {
+            problem(offset, "stray '}}' without an opening '{{'");
+            pos = offset + 2;
+            continue;
+        }
+
+        let rest = &content[offset + 2..];
+        let escaped = content[..offset].ends_with('\\');
+        let close = match (rest.find("}}"), rest.find("{{")) {
+            (Some(close), Some(next)) if close < next => Some(close),
+            (Some(close), None) => Some(close),
+            _ => None,
+        };
+        match close {
+            Some(close) => {
+                let tag = &content[offset..offset + close + 4];
+                if let Some(message) = tag_problem(tag).filter(|_| !escaped) {
+                    problem(offset, message);
+                }
+                pos = offset + close + 4;
+            }
+            None => {
+                if !escaped {
+                    problem(offset, "unclosed '{{' (missing '}}')");
+                }
+                pos = offset + 2;
+            }
+        }
+    }
+    problems
+}
+
+/// Offset of the first `{{` or `}}` at or after `pos`
+fn next_delimiter(content: &str, pos: usize) -> Option<usize> {
+    let rest = &content[pos..];
+    match (rest.find("{{"), rest.find("}}")) {
+        (Some(open), Some(close)) => Some(pos + open.min(close)),
+        (open, close) => open.or(close).map(|found| pos + found),
+    }
+}
+
+/// What is wrong with a `{{ ... }}` tag the renderer would not treat as a tag
+fn tag_problem(tag: &str) -> Option<&'static str> {
+    let inner = &tag[2..tag.len() - 2];
+    if inner.trim().is_empty() {
+        return Some("empty tag");
+    }
+    if inner.contains('}') {
+        return Some("unexpected '}' in tag");
+    }
+    if inner.trim_start().starts_with('>') {
+        if !inner.starts_with('>') {
+            return Some("space between '{{' and '>' in include tag");
+        }
+        if !is_include_tag(tag) {
+            return Some("include tag without a file path");
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(content: &str) -> Vec<(usize, String)> {
+        check_delimiters(content, |offset| {
+            Location::from_offset(content, offset, "main.txt")
+        })
+        .into_iter()
+        .map(|problem| match problem {
+            RenderError::MalformedDelimiter { message, location } => (location.column, message),
+            other => panic!("Expected MalformedDelimiter error, got {:?}", other),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_well_formed() {
+        let content = "{{> header.txt }}{{#if a}}{{ b }}{{/if}} \\{{ escaped }} \\{{ open";
+        assert!(problems(content).is_empty());
+        assert!(problems("{{#raw}}}} {{ {{/raw}}").is_empty());
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(
+            problems("a {{ b\n"),
+            [(3, "unclosed '{{' (missing '}}')".to_string())]
+        );
+        assert_eq!(
+            problems("{{ a }} }}"),
+            [(9, "stray '}}' without an opening '{{'".to_string())]
+        );
+        assert_eq!(
+            problems("{{ a {{ b }}"),
+            [(1, "unclosed '{{' (missing '}}')".to_string())]
+        );
+        assert_eq!(problems("{{}}"), [(1, "empty tag".to_string())]);
+        assert_eq!(
+            problems("{{ a } }}"),
+            [(1, "unexpected '}' in tag".to_string())]
+        );
+        assert_eq!(
+            problems("{{ > a.txt }}"),
+            [(1, "space between '{{' and '>' in include tag".to_string())]
+        );
+        assert_eq!(
+            problems("{{>}}"),
+            [(1, "include tag without a file path".to_string())]
+        );
+    }
+}
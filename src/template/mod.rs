@@ -1,7 +1,34 @@
+#[cfg(feature = "tokio")]
+pub mod async_engine;
+pub mod cache;
+pub mod check;
+pub mod compiled;
+pub mod delimiters;
 pub mod engine;
+pub mod expr;
+pub mod filters;
 pub mod include;
+pub mod lexer;
+pub mod parser;
+pub mod report;
+pub mod source;
+pub mod source_map;
+pub mod stats;
 pub mod variable;
+pub mod vars;
 
-pub use engine::TemplateEngine;
-pub use include::IncludeResolver;
-pub use variable::VariableSubstitutor;
+#[cfg(feature = "tokio")]
+pub use async_engine::AsyncIncludeSource;
+pub use cache::TemplateCache;
+pub use compiled::CompiledTemplate;
+pub use delimiters::Delimiters;
+pub use engine::{TemplateEngine, TemplateEngineBuilder};
+pub use filters::FilterRegistry;
+pub use include::{Dependency, IncludeResolver};
+pub use report::{RenderReport, TRACE_TARGET};
+#[cfg(feature = "fs")]
+pub use source::FileSystemSource;
+pub use source::{IncludeSource, MemoryTemplateStore};
+pub use source_map::{MappedText, SourceMap};
+pub use stats::RenderStats;
+pub use variable::{MissingFn, MissingPolicy, VariableSubstitutor};
@@ -0,0 +1,725 @@
+use crate::error::{Location, RenderError};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::expr::Expr;
+
+lazy_static! {
+    // Match {{#raw}}...{{/raw}}, {{ tag }} or \{{ (escaped)
+    // Group 1: raw block content (passed through verbatim)
+    // Group 2: optional backslash for escape
+    // Group 3: tag content (expression, block open/close, else)
+    static ref TAG_PATTERN: Regex =
+        Regex::new(r"(?s)\{\{#raw\s*\}\}(.*?)\{\{/raw\s*\}\}|(\\)?\{\{\s*([^}]+?)\s*\}\}").unwrap();
+
+    // One `name=value` binding; the value is a quoted string or runs to the next whitespace
+    static ref BINDING_PATTERN: Regex = Regex::new(
+        r#"([A-Za-z_][A-Za-z0-9_]*)=("(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'|\S+)"#
+    )
+    .unwrap();
+}
+
+/// A parsed template element
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Literal text copied to the output as-is
+    Text(String),
+    /// `{{ expr }}`
+    Output { expr: Expr, offset: usize },
+    /// `{{#if cond}}...{{else if cond}}...{{else}}...{{/if}}`
+    ///
+    /// `{{#unless cond}}` is parsed into an `If` with the condition negated.
+    If {
+        branches: Vec<Branch>,
+        otherwise: Vec<Node>,
+    },
+    /// `{{#with expr}}...{{else}}...{{/with}}`: render the body with `expr` as the context
+    With {
+        expr: Expr,
+        offset: usize,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `{{#block name}}...{{/block}}`: an overridable region (see `{{#extends}}`), rendered in place
+    Block { name: String, body: Vec<Node> },
+    /// `{{#macro name(params)}}...{{/macro}}`: renders nothing; invoked as `{{ name(args) }}`
+    Macro {
+        name: String,
+        params: Vec<String>,
+        body: Vec<Node>,
+    },
+    /// `{{#fragment name}}...{{/fragment}}`: renders nothing; inserted with `{{fragment name}}`
+    Fragment { name: String, body: Vec<Node> },
+    /// `{{fragment name}}`: render a fragment's body in place, in the current scope
+    FragmentRef { name: String, offset: usize },
+    /// `{{#let name=value ...}}...{{/let}}`: bind locals for the body only
+    Let {
+        bindings: Vec<(String, Expr)>,
+        offset: usize,
+        body: Vec<Node>,
+    },
+    /// `{{set name = expr}}` (or `let`): bind a local value for the rest of the render
+    Set {
+        name: String,
+        expr: Expr,
+        offset: usize,
+    },
+}
+
+/// One conditional branch of an `if` block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub condition: Expr,
+    pub offset: usize,
+    pub body: Vec<Node>,
+}
+
+enum Token<'a> {
+    Text(String),
+    Tag { content: &'a str, offset: usize },
+}
+
+/// Where a sequence of nodes ended
+enum Stop<'a> {
+    Eof,
+    Else { rest: &'a str, offset: usize },
+    Close { name: &'a str, offset: usize },
+}
+
+/// Parse expanded template content into a node tree
+pub fn parse(content: &str) -> Result<Vec<Node>, RenderError> {
+    let mut parser = Parser {
+        content,
+        tokens: tokenize(content),
+        pos: 0,
+        depth: 0,
+    };
+
+    let (nodes, stop) = parser.body()?;
+    match stop {
+        Stop::Eof => Ok(nodes),
+        Stop::Else { offset, .. } => Err(parser.error("{{else}} outside of a block", offset)),
+        Stop::Close { name, offset } => Err(parser.error(
+            &format!("unexpected {{{{/{}}}}} without matching block", name),
+            offset,
+        )),
+    }
+}
+
+/// The source text of the tag starting at `offset`, e.g. `{{ user.name }}`
+pub fn tag_source(content: &str, offset: usize) -> &str {
+    TAG_PATTERN
+        .find_at(content, offset)
+        .map_or("", |tag| tag.as_str())
+}
+
+fn tokenize(content: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut last_end = 0;
+
+    for cap in TAG_PATTERN.captures_iter(content) {
+        let full_match = cap.get(0).unwrap();
+        if full_match.start() > last_end {
+            tokens.push(Token::Text(
+                content[last_end..full_match.start()].to_string(),
+            ));
+        }
+
+        if let Some(raw) = cap.get(1) {
+            // Raw block: content untouched
+            tokens.push(Token::Text(raw.as_str().to_string()));
+        } else if cap.get(2).is_some() {
+            // Escaped: \{{ ... }} -> {{ ... }}
+            tokens.push(Token::Text(format!("{{{{ {} }}}}", &cap[3])));
+        } else {
+            tokens.push(Token::Tag {
+                content: cap.get(3).unwrap().as_str().trim(),
+                offset: full_match.start(),
+            });
+        }
+
+        last_end = full_match.end();
+    }
+
+    if last_end < content.len() {
+        tokens.push(Token::Text(content[last_end..].to_string()));
+    }
+
+    tokens
+}
+
+/// How deeply blocks may nest, so that a template cannot overflow the stack of the
+/// recursive parser and renderer
+const MAX_NESTING: usize = 64;
+
+struct Parser<'a> {
+    content: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    /// How many blocks are open
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str, offset: usize) -> RenderError {
+        RenderError::TemplateSyntax {
+            message: message.to_string(),
+            location: Location::from_offset(self.content, offset, "<template>"),
+        }
+    }
+
+    fn output(&self, source: &str, offset: usize) -> Result<Node, RenderError> {
+        Ok(Node::Output {
+            expr: Expr::parse_output(source).map_err(|m| self.invalid(source, m, offset))?,
+            offset,
+        })
+    }
+
+    fn invalid(&self, source: &str, message: String, offset: usize) -> RenderError {
+        self.error(
+            &format!("invalid expression '{}': {}", source, message),
+            offset,
+        )
+    }
+
+    /// Parse nodes until end of input, `{{else}}` or a closing tag
+    fn body(&mut self) -> Result<(Vec<Node>, Stop<'a>), RenderError> {
+        let mut nodes = Vec::new();
+
+        while self.pos < self.tokens.len() {
+            let index = self.pos;
+            self.pos += 1;
+
+            let (content, offset) = match &self.tokens[index] {
+                Token::Text(text) => {
+                    match nodes.last_mut() {
+                        Some(Node::Text(previous)) => previous.push_str(text),
+                        _ => nodes.push(Node::Text(text.clone())),
+                    }
+                    continue;
+                }
+                Token::Tag { content, offset } => (*content, *offset),
+            };
+
+            if let Some(open) = content.strip_prefix('#') {
+                let (name, args) = split_keyword(open);
+                if self.depth >= MAX_NESTING {
+                    return Err(self.error(
+                        &format!("blocks nested more than {} levels deep", MAX_NESTING),
+                        offset,
+                    ));
+                }
+                self.depth += 1;
+                let block = self.block(name, args, offset);
+                self.depth -= 1;
+                nodes.push(block?);
+            } else if let Some(name) = content.strip_prefix('/') {
+                return Ok((
+                    nodes,
+                    Stop::Close {
+                        name: name.trim(),
+                        offset,
+                    },
+                ));
+            } else if let ("else", rest) = split_keyword(content) {
+                return Ok((nodes, Stop::Else { rest, offset }));
+            } else if let ("fragment", name) = split_keyword(content) {
+                // Anything other than `fragment name` (e.g. `{{ fragment }}`) is a lookup
+                if is_block_name(name) {
+                    nodes.push(Node::FragmentRef {
+                        name: name.to_string(),
+                        offset,
+                    });
+                } else {
+                    nodes.push(self.output(content, offset)?);
+                }
+            } else if let ("set" | "let", assignment) = split_keyword(content) {
+                // A bare `{{ set }}` is still a lookup of the key "set"
+                if assignment.is_empty() {
+                    nodes.push(self.output(content, offset)?);
+                } else {
+                    nodes.push(self.set(assignment, offset)?);
+                }
+            } else {
+                nodes.push(self.output(content, offset)?);
+            }
+        }
+
+        Ok((nodes, Stop::Eof))
+    }
+
+    fn block(&mut self, name: &str, args: &str, offset: usize) -> Result<Node, RenderError> {
+        match name {
+            "if" => self.if_block(args, offset),
+            "unless" => {
+                let condition = self.condition(args, offset)?;
+                let (body, otherwise) = self.body_with_else("unless", offset)?;
+                Ok(Node::If {
+                    branches: vec![Branch {
+                        condition: Expr::Not(Box::new(condition)),
+                        offset,
+                        body,
+                    }],
+                    otherwise,
+                })
+            }
+            "macro" => self.macro_block(args, offset),
+            "let" => {
+                let bindings = self.bindings(args, offset)?;
+                let (body, stop) = self.body()?;
+                self.close("let", stop, offset)?;
+                Ok(Node::Let {
+                    bindings,
+                    offset,
+                    body,
+                })
+            }
+            "block" | "fragment" => {
+                if !is_block_name(args) {
+                    return Err(self.error(&format!("invalid {} name '{}'", name, args), offset));
+                }
+                let (body, stop) = self.body()?;
+                self.close(name, stop, offset)?;
+                let block_name = args.to_string();
+                Ok(if name == "block" {
+                    Node::Block {
+                        name: block_name,
+                        body,
+                    }
+                } else {
+                    Node::Fragment {
+                        name: block_name,
+                        body,
+                    }
+                })
+            }
+            // Resolved before parsing; only a misplaced tag (e.g. inside a block) gets here
+            "extends" => Err(self.error(
+                "{{#extends}} is only allowed at the top level of a template",
+                offset,
+            )),
+            "with" => {
+                let expr = self.condition(args, offset)?;
+                let (body, otherwise) = self.body_with_else("with", offset)?;
+                Ok(Node::With {
+                    expr,
+                    offset,
+                    body,
+                    otherwise,
+                })
+            }
+            _ => Err(self.error(&format!("unknown block '{{{{#{}}}}}'", name), offset)),
+        }
+    }
+
+    /// Parse `name = expr` after `set` / `let`
+    fn set(&self, assignment: &str, offset: usize) -> Result<Node, RenderError> {
+        let (name, source) = assignment
+            .split_once('=')
+            .map(|(name, source)| (name.trim(), source.trim()))
+            .ok_or_else(|| self.error("expected 'name = expression' in set", offset))?;
+
+        if !is_identifier(name) {
+            return Err(self.error(&format!("invalid variable name '{}' in set", name), offset));
+        }
+
+        Ok(Node::Set {
+            name: name.to_string(),
+            expr: self.condition(source, offset)?,
+            offset,
+        })
+    }
+
+    /// Parse `name=value ...` where each value is a quoted string or a single token
+    fn bindings(&self, source: &str, offset: usize) -> Result<Vec<(String, Expr)>, RenderError> {
+        let mut bindings = Vec::new();
+        let mut last_end = 0;
+
+        for cap in BINDING_PATTERN.captures_iter(source) {
+            let full_match = cap.get(0).unwrap();
+            if !source[last_end..full_match.start()].trim().is_empty() {
+                break;
+            }
+            let value = &cap[2];
+            let expr = Expr::parse(value).map_err(|m| self.invalid(value, m, offset))?;
+            bindings.push((cap[1].to_string(), expr));
+            last_end = full_match.end();
+        }
+
+        if bindings.is_empty() || !source[last_end..].trim().is_empty() {
+            return Err(self.error(
+                &format!("expected 'name=value' bindings, found '{}'", source),
+                offset,
+            ));
+        }
+        Ok(bindings)
+    }
+
+    /// Parse `name(param, ...)` and the macro body
+    fn macro_block(&mut self, signature: &str, offset: usize) -> Result<Node, RenderError> {
+        let (name, params) = signature
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| self.error("expected 'name(params)' after #macro", offset))?;
+
+        let name = name.trim();
+        let params: Vec<String> = params
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect();
+        for ident in std::iter::once(name).chain(params.iter().map(String::as_str)) {
+            if !is_identifier(ident) {
+                return Err(self.error(&format!("invalid name '{}' in macro", ident), offset));
+            }
+        }
+
+        let (body, stop) = self.body()?;
+        self.close("macro", stop, offset)?;
+        Ok(Node::Macro {
+            name: name.to_string(),
+            params,
+            body,
+        })
+    }
+
+    fn if_block(&mut self, condition: &str, offset: usize) -> Result<Node, RenderError> {
+        let mut branches = Vec::new();
+        let mut condition = self.condition(condition, offset)?;
+        let mut branch_offset = offset;
+
+        loop {
+            let (body, stop) = self.body()?;
+            branches.push(Branch {
+                condition,
+                offset: branch_offset,
+                body,
+            });
+
+            match stop {
+                Stop::Else { rest: "", .. } => {
+                    let (otherwise, stop) = self.body()?;
+                    self.close("if", stop, offset)?;
+                    return Ok(Node::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                Stop::Else {
+                    rest,
+                    offset: else_offset,
+                } => match split_keyword(rest) {
+                    ("if", cond) => {
+                        condition = self.condition(cond, else_offset)?;
+                        branch_offset = else_offset;
+                    }
+                    _ => {
+                        return Err(self.error(
+                            &format!("expected 'else' or 'else if', found 'else {}'", rest),
+                            else_offset,
+                        ))
+                    }
+                },
+                stop => {
+                    self.close("if", stop, offset)?;
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: Vec::new(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Parse a block body with an optional plain `{{else}}` section, up to the closing tag
+    fn body_with_else(
+        &mut self,
+        block: &str,
+        offset: usize,
+    ) -> Result<(Vec<Node>, Vec<Node>), RenderError> {
+        let (body, stop) = self.body()?;
+        match stop {
+            Stop::Else { rest: "", .. } => {
+                let (otherwise, stop) = self.body()?;
+                self.close(block, stop, offset)?;
+                Ok((body, otherwise))
+            }
+            stop => {
+                self.close(block, stop, offset)?;
+                Ok((body, Vec::new()))
+            }
+        }
+    }
+
+    fn condition(&self, source: &str, offset: usize) -> Result<Expr, RenderError> {
+        if source.is_empty() {
+            return Err(self.error("missing expression", offset));
+        }
+        Expr::parse(source).map_err(|m| self.invalid(source, m, offset))
+    }
+
+    /// Check that a block body ended with the matching closing tag
+    fn close(&self, block: &str, stop: Stop, open_offset: usize) -> Result<(), RenderError> {
+        match stop {
+            Stop::Close { name, .. } if name == block => Ok(()),
+            Stop::Close { name, offset } => Err(self.error(
+                &format!("expected {{{{/{}}}}}, found {{{{/{}}}}}", block, name),
+                offset,
+            )),
+            Stop::Else { offset, .. } => Err(self.error(
+                &format!("unexpected {{{{else}}}} in '{}' block", block),
+                offset,
+            )),
+            Stop::Eof => {
+                Err(self.error(&format!("unclosed '{{{{#{}}}}}' block", block), open_offset))
+            }
+        }
+    }
+}
+
+/// Names usable for locals and macros: ASCII letters, digits and `_`, not starting with a digit
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Names usable for blocks and fragments: identifiers that may also contain `-`
+fn is_block_name(name: &str) -> bool {
+    is_identifier(&name.replace('-', "_"))
+}
+
+/// Split `keyword rest...` at the first whitespace
+fn split_keyword(s: &str) -> (&str, &str) {
+    match s.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_text_and_output() {
+        let nodes = parse("Hello, {{ name }}!").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                text("Hello, "),
+                Node::Output {
+                    expr: Expr::Path("name".into()),
+                    offset: 7
+                },
+                text("!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_escape_and_raw_as_text() {
+        let nodes = parse(r"\{{ a }} {{#raw}}{{ b }}{{/raw}}").unwrap();
+        assert_eq!(nodes, vec![text("{{ a }} {{ b }}")]);
+    }
+
+    #[test]
+    fn test_parse_if_else_chain() {
+        let nodes = parse("{{#if a}}A{{else if b}}B{{else}}C{{/if}}").unwrap();
+        match &nodes[..] {
+            [Node::If {
+                branches,
+                otherwise,
+            }] => {
+                assert_eq!(branches.len(), 2);
+                assert_eq!(branches[0].condition, Expr::Path("a".into()));
+                assert_eq!(branches[0].body, vec![text("A")]);
+                assert_eq!(branches[1].condition, Expr::Path("b".into()));
+                assert_eq!(branches[1].body, vec![text("B")]);
+                assert_eq!(otherwise, &vec![text("C")]);
+            }
+            _ => panic!("Expected a single if node, got {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn test_parse_nested_if() {
+        let nodes = parse("{{#if a}}{{#if b}}x{{/if}}{{/if}}").unwrap();
+        match &nodes[..] {
+            [Node::If { branches, .. }] => {
+                assert!(matches!(branches[0].body[..], [Node::If { .. }]));
+            }
+            _ => panic!("Expected a single if node"),
+        }
+    }
+
+    #[test]
+    fn test_parse_unless_block() {
+        let nodes = parse("{{#unless done}}todo{{else}}ok{{/unless}}").unwrap();
+        match &nodes[..] {
+            [Node::If {
+                branches,
+                otherwise,
+            }] => {
+                assert_eq!(
+                    branches[0].condition,
+                    Expr::Not(Box::new(Expr::Path("done".into())))
+                );
+                assert_eq!(branches[0].body, vec![text("todo")]);
+                assert_eq!(otherwise, &vec![text("ok")]);
+            }
+            _ => panic!("Expected a single if node, got {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn test_parse_with_block() {
+        let nodes = parse("{{#with user}}{{ name }}{{else}}none{{/with}}").unwrap();
+        match &nodes[..] {
+            [Node::With {
+                expr,
+                body,
+                otherwise,
+                ..
+            }] => {
+                assert_eq!(expr, &Expr::Path("user".into()));
+                assert_eq!(body.len(), 1);
+                assert_eq!(otherwise, &vec![text("none")]);
+            }
+            _ => panic!("Expected a single with node, got {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let nodes = parse("{{set a = 'x'}}{{ let b = a ~ a }}{{ set }}").unwrap();
+        match &nodes[..] {
+            [Node::Set { name: a, .. }, Node::Set { name: b, expr, .. }, Node::Output { expr: set, .. }] =>
+            {
+                assert_eq!((a.as_str(), b.as_str()), ("a", "b"));
+                assert!(matches!(expr, Expr::Binary { .. }));
+                assert_eq!(set, &Expr::Path("set".into()));
+            }
+            _ => panic!("Expected two set nodes and an output, got {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn test_parse_macro() {
+        let nodes = parse("{{#macro card(title, body)}}[{{ title }}]{{/macro}}").unwrap();
+        match &nodes[..] {
+            [Node::Macro { name, params, body }] => {
+                assert_eq!(name, "card");
+                assert_eq!(params, &vec!["title".to_string(), "body".to_string()]);
+                assert_eq!(body.len(), 3);
+            }
+            _ => panic!("Expected a single macro node, got {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        let nodes =
+            parse("{{#fragment page-header}}H{{/fragment}}{{fragment page-header}}{{ fragment }}")
+                .unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node::Fragment {
+                    name: "page-header".into(),
+                    body: vec![text("H")]
+                },
+                Node::FragmentRef {
+                    name: "page-header".into(),
+                    offset: 39
+                },
+                Node::Output {
+                    expr: Expr::Path("fragment".into()),
+                    offset: 63
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_let_block() {
+        let nodes = parse(r#"{{#let title="A b" n=3 who=user.name}}x{{/let}}"#).unwrap();
+        match &nodes[..] {
+            [Node::Let { bindings, body, .. }] => {
+                assert_eq!(
+                    bindings,
+                    &vec![
+                        ("title".to_string(), Expr::Literal(json!("A b"))),
+                        ("n".to_string(), Expr::Literal(json!(3))),
+                        ("who".to_string(), Expr::Path("user.name".into())),
+                    ]
+                );
+                assert_eq!(body, &vec![text("x")]);
+            }
+            _ => panic!("Expected a single let node, got {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth| format!("{}x{}", "{{#if a}}".repeat(depth), "{{/if}}".repeat(depth));
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+        match parse(&nested(5000)) {
+            Err(RenderError::TemplateSyntax { message, .. }) => {
+                assert_eq!(message, "blocks nested more than 64 levels deep")
+            }
+            other => panic!("Expected syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for source in [
+            "{{#if a}}unclosed",
+            "{{/if}}",
+            "{{else}}",
+            "{{#if a}}x{{/unless}}",
+            "{{#if}}x{{/if}}",
+            "{{#if a}}x{{else when b}}y{{/if}}",
+            "{{#loop a}}x{{/loop}}",
+            "{{#with}}x{{/with}}",
+            "{{#unless}}x{{/unless}}",
+            "{{#unless a}}x{{else if b}}y{{/unless}}",
+            "{{#unless a}}x{{/if}}",
+            "{{#with a}}x{{else if b}}y{{/with}}",
+            "{{set a}}",
+            "{{set a.b = 1}}",
+            "{{set 1a = 1}}",
+            "{{set a = }}",
+            "{{#macro card}}x{{/macro}}",
+            "{{#macro card(a b)}}x{{/macro}}",
+            "{{#macro card(a)}}x",
+            "{{#let}}x{{/let}}",
+            "{{#fragment}}x{{/fragment}}",
+            "{{#fragment a b}}x{{/fragment}}",
+            "{{#fragment a}}x{{/block}}",
+            "{{#let a}}x{{/let}}",
+            "{{#let a=1 junk}}x{{/let}}",
+        ] {
+            match parse(source) {
+                Err(RenderError::TemplateSyntax { .. }) => {}
+                other => panic!("Expected syntax error for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        match parse("line1\n  {{#if a}}") {
+            Err(RenderError::TemplateSyntax { location, .. }) => {
+                assert_eq!((location.line, location.column), (2, 3));
+            }
+            other => panic!("Expected syntax error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::error::Location;
+
+use super::include::Dependency;
+
+/// Target of the `trace` level events a render emits as it happens, for `rp --trace`
+///
+/// Each file read has an `include` event with the fields `depth` (0 for the template
+/// itself) and `path`; each `{{ }}` tag a `substitute` event with the `expression`, its
+/// `value` (as JSON, truncated, or `undefined`) and the `file`, `line` and `column` of
+/// the tag.
+pub const TRACE_TARGET: &str = "render_prompt::trace";
+
+/// What a single render used, from [`TemplateEngine::render_with_report`]
+///
+/// Lists the files to watch or cache the output against, and the data the output was
+/// made from.
+///
+/// [`TemplateEngine::render_with_report`]: super::TemplateEngine::render_with_report
+#[derive(Debug, Clone, Default)]
+pub struct RenderReport {
+    /// Files included or extended, in depth-first order
+    pub includes: Vec<Dependency>,
+    /// The expressions of the `{{ }}` tags replaced by a value (`user.name`,
+    /// `name | upper`), in output order
+    pub substituted: Vec<String>,
+    /// Undefined variables that were rendered anyway, with where they were referenced
+    pub undefined: Vec<(String, Location)>,
+}
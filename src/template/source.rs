@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use path_clean::PathClean;
+
+/// Where included files, `{{#extends}}` parents and glob includes are read from
+///
+/// Paths are already joined to the including file's directory and cleaned of `.` and `..`
+/// when a source sees them, and confinement to the root directory is checked with
+/// [`is_within`](IncludeSource::is_within) before anything is read. The filesystem
+/// (`FileSystemSource`, with the `fs` feature) is the default; other sources (in-memory maps, embedded assets,
+/// databases) can be plugged in with `TemplateEngine::with_include_source`.
+pub trait IncludeSource: Send + Sync {
+    /// The content of the file at `path`
+    ///
+    /// A missing file must be reported as [`io::ErrorKind::NotFound`], so that optional
+    /// includes (`{{>? file }}`) can render nothing.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Whether a file exists at `path` (tells `{{> path context }}` apart from a path
+    /// containing spaces)
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Whether `path` lies inside `root`
+    ///
+    /// The default compares the paths as written, which suits sources without symlinks.
+    fn is_within(&self, path: &Path, root: &Path) -> io::Result<bool> {
+        Ok(lexically_within(path, root))
+    }
+
+    /// Whether the file at `path` is a symlink, for sandboxes that deny them
+    ///
+    /// The default suits sources without symlinks.
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+
+    /// The files matching a glob include pattern, in sorted order
+    ///
+    /// Sources that cannot list their files do not support glob includes.
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>, String> {
+        Err(format!(
+            "glob includes ('{}') are not supported by this include source",
+            pattern
+        ))
+    }
+}
+
+/// Whether `path` lies inside `root`, comparing the paths as written
+///
+/// With the root `.`, every relative path that does not start with `..` is inside.
+pub(crate) fn lexically_within(path: &Path, root: &Path) -> bool {
+    let (path, root) = (path.clean(), root.clean());
+    if root == Path::new(".") {
+        return path.is_relative()
+            && !matches!(path.components().next(), Some(Component::ParentDir));
+    }
+    path.starts_with(root)
+}
+
+/// The source of engines and resolvers that were not given one: the filesystem, or an
+/// empty store when the crate is built without the `fs` feature (e.g. for WebAssembly)
+pub(crate) fn default_source() -> Arc<dyn IncludeSource> {
+    #[cfg(feature = "fs")]
+    return Arc::new(FileSystemSource);
+    #[cfg(not(feature = "fs"))]
+    return Arc::new(MemoryTemplateStore::new());
+}
+
+/// Include files read from disk, with symlinks resolved before the root check
+#[cfg(feature = "fs")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSystemSource;
+
+#[cfg(feature = "fs")]
+impl IncludeSource for FileSystemSource {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_within(&self, path: &Path, root: &Path) -> io::Result<bool> {
+        // Canonicalize both paths to resolve symlinks and get absolute paths
+        Ok(path.canonicalize()?.starts_with(root.canonicalize()?))
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.is_symlink()
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>, String> {
+        let entries = glob::glob(pattern).map_err(|e| e.to_string())?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// Templates and partials registered by name, for rendering without touching the disk
+///
+/// Names are relative paths (`partials/header.txt`), resolved like files: a template
+/// includes its siblings by name, and `..` cannot leave the store. Give the engine the
+/// root `.` (its default) and render a stored template by its name.
+///
+/// ```
+/// use render_prompt::template::MemoryTemplateStore;
+/// use render_prompt::TemplateEngine;
+/// use serde_json::json;
+/// use std::path::Path;
+///
+/// let mut store = MemoryTemplateStore::new();
+/// store.insert("main.txt", "{{> partials/greeting.txt }}!");
+/// store.insert("partials/greeting.txt", "Hello, {{ name }}");
+///
+/// let engine = TemplateEngine::builder().include_source(store).build();
+/// let output = engine.render(Path::new("main.txt"), &json!({"name": "Alice"}));
+/// assert_eq!(output.unwrap(), "Hello, Alice!");
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MemoryTemplateStore {
+    templates: BTreeMap<PathBuf, String>,
+}
+
+impl MemoryTemplateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a template under `name`, replacing any template of the same name
+    pub fn insert(&mut self, name: impl AsRef<Path>, content: impl Into<String>) {
+        self.templates.insert(name.as_ref().clean(), content.into());
+    }
+
+    /// The template registered under `name`
+    pub fn get(&self, name: impl AsRef<Path>) -> Option<&str> {
+        self.templates
+            .get(&name.as_ref().clean())
+            .map(String::as_str)
+    }
+}
+
+impl IncludeSource for MemoryTemplateStore {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.get(path).map(str::to_string).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no template named '{}'", path.display()),
+            )
+        })
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>, String> {
+        let pattern = glob::Pattern::new(&Path::new(pattern).clean().to_string_lossy())
+            .map_err(|e| e.to_string())?;
+        Ok(self
+            .templates
+            .keys()
+            .filter(|name| pattern.matches_path(name))
+            .cloned()
+            .collect())
+    }
+}
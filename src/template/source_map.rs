@@ -0,0 +1,262 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::error::{Location, RenderError};
+
+/// File name of positions in text without a source map
+const UNMAPPED_FILE: &str = "<template>";
+
+/// Where a region of expanded template text starts in the file it came from
+#[derive(Debug, Clone)]
+struct Segment {
+    /// Byte offset of the region in the expanded text
+    start: usize,
+    file: Arc<str>,
+    line: usize,
+    column: usize,
+    /// Text written by the include resolver itself (the `{{#let}}` or `{{#with}}` around
+    /// an include with arguments), every byte of which points at the include directive
+    generated: bool,
+}
+
+/// The origin of each region of a template whose includes and layouts were expanded, so
+/// that errors point into the file they are in rather than into the expanded text
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// Ordered by `start`
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    /// The position in its own file of the byte at `offset` of the expanded `text`
+    ///
+    /// Text without a map is reported as `<template>`, with its position in `text`.
+    pub fn locate(&self, text: &str, offset: usize) -> Location {
+        let offset = offset.min(text.len());
+        let index = self.segments.partition_point(|s| s.start <= offset);
+        let Some(segment) = index.checked_sub(1).map(|i| &self.segments[i]) else {
+            return Location::from_offset(text, offset, UNMAPPED_FILE);
+        };
+        let file = segment.file.to_string();
+        if segment.generated {
+            return Location::new(file, segment.line, segment.column);
+        }
+
+        let between = &text[segment.start..offset];
+        match between.rfind('\n') {
+            Some(last) => Location::new(
+                file,
+                segment.line + between.matches('\n').count(),
+                between.len() - last,
+            ),
+            None => Location::new(file, segment.line, segment.column + between.len()),
+        }
+    }
+
+    /// Point an error the parser reported in `<template>` into the file it is in
+    pub fn relocate(&self, text: &str, error: RenderError) -> RenderError {
+        let relocate = |location: Location| match offset_of(text, &location) {
+            Some(offset) if location.file == UNMAPPED_FILE => self.locate(text, offset),
+            _ => location,
+        };
+        match error {
+            RenderError::TemplateSyntax { message, location } => RenderError::TemplateSyntax {
+                message,
+                location: relocate(location),
+            },
+            RenderError::VariableResolution { message, location } => {
+                RenderError::VariableResolution {
+                    message,
+                    location: relocate(location),
+                }
+            }
+            RenderError::UndefinedVariable { name, location } => RenderError::UndefinedVariable {
+                name,
+                location: relocate(location),
+            },
+            other => other,
+        }
+    }
+}
+
+/// The byte offset of a line and column of `text`
+fn offset_of(text: &str, location: &Location) -> Option<usize> {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(location.line.checked_sub(1)?)
+        .map(str::len)
+        .sum();
+    Some(line_start + location.column.checked_sub(1)?)
+}
+
+/// Template text assembled from pieces of files, with a source map of where each piece
+/// came from
+#[derive(Debug, Clone, Default)]
+pub struct MappedText {
+    text: String,
+    map: SourceMap,
+}
+
+impl MappedText {
+    /// `text` as it appears in `file`, starting at its first line and column
+    pub fn new(text: &str, file: &str) -> Self {
+        Self::starting_at(text, &Location::new(file.to_string(), 1, 1))
+    }
+
+    /// `text` as it appears in a file, starting at `start` (e.g. after front matter)
+    pub fn starting_at(text: &str, start: &Location) -> Self {
+        let mut mapped = Self::default();
+        mapped.push_segment(start, false);
+        mapped.text.push_str(text);
+        mapped
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// The position in its own file of the byte at `offset`
+    pub fn location(&self, offset: usize) -> Location {
+        self.map.locate(&self.text, offset)
+    }
+
+    /// The text and its source map
+    pub fn into_parts(self) -> (String, SourceMap) {
+        (self.text, self.map)
+    }
+
+    /// Point an error the parser reported in `<template>`, for this text, into the file it
+    /// is in
+    pub fn relocate(&self, error: RenderError) -> RenderError {
+        self.map.relocate(&self.text, error)
+    }
+
+    /// Append `range` of another mapped text, keeping its origin
+    pub fn push_mapped(&mut self, other: &MappedText, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let first = other
+            .map
+            .segments
+            .partition_point(|s| s.start <= range.start);
+        let generated = first
+            .checked_sub(1)
+            .is_some_and(|i| other.map.segments[i].generated);
+        self.push_segment(&other.location(range.start), generated);
+        for segment in &other.map.segments[first..] {
+            if segment.start >= range.end {
+                break;
+            }
+            self.map.segments.push(Segment {
+                start: self.text.len() + segment.start - range.start,
+                ..segment.clone()
+            });
+        }
+        self.text.push_str(&other.text[range]);
+    }
+
+    /// Append the whole of another mapped text
+    pub fn push_all(&mut self, other: &MappedText) {
+        self.push_mapped(other, 0..other.len());
+    }
+
+    /// Append text that is not in any file (written by the include resolver, or in place of
+    /// custom delimiters), reported at `location`
+    pub fn push_generated(&mut self, text: &str, location: &Location) {
+        if text.is_empty() {
+            return;
+        }
+        self.push_segment(location, true);
+        self.text.push_str(text);
+    }
+
+    /// Start a region at the end of the text
+    fn push_segment(&mut self, location: &Location, generated: bool) {
+        // A region that is still empty is replaced by the one that follows it
+        if self
+            .map
+            .segments
+            .last()
+            .is_some_and(|last| last.start == self.text.len())
+        {
+            self.map.segments.pop();
+        }
+        self.map.segments.push(Segment {
+            start: self.text.len(),
+            file: Arc::from(location.file.as_str()),
+            line: location.line,
+            column: location.column,
+            generated,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(location: Location) -> (String, usize, usize) {
+        (location.file, location.line, location.column)
+    }
+
+    #[test]
+    fn test_locate_across_pieces() {
+        let main = MappedText::new("Hello\n{{> part }}\nbye {{ x }}", "main.txt");
+        let part = MappedText::new("one\n  {{ y }}", "part.txt");
+
+        let mut expanded = MappedText::default();
+        expanded.push_mapped(&main, 0..6);
+        expanded.push_generated("{{#let a=1}}", &main.location(6));
+        expanded.push_all(&part);
+        expanded.push_generated("{{/let}}", &main.location(6));
+        expanded.push_mapped(&main, 17..main.len());
+        let text = expanded.as_str();
+        assert_eq!(
+            text,
+            "Hello\n{{#let a=1}}one\n  {{ y }}{{/let}}\nbye {{ x }}"
+        );
+
+        let at = |needle: &str| position(expanded.location(text.find(needle).unwrap()));
+        assert_eq!(at("Hello"), ("main.txt".to_string(), 1, 1));
+        assert_eq!(at("a=1"), ("main.txt".to_string(), 2, 1));
+        assert_eq!(at("{{ y }}"), ("part.txt".to_string(), 2, 3));
+        assert_eq!(at("/let"), ("main.txt".to_string(), 2, 1));
+        assert_eq!(at("{{ x }}"), ("main.txt".to_string(), 3, 5));
+    }
+
+    #[test]
+    fn test_starting_at() {
+        let body =
+            MappedText::starting_at("{{ a }}\n{{ b }}", &Location::new("t.txt".into(), 4, 1));
+        assert_eq!(position(body.location(8)), ("t.txt".to_string(), 5, 1));
+    }
+
+    #[test]
+    fn test_relocate_parser_error() {
+        let mut expanded = MappedText::new("x\n", "main.txt");
+        expanded.push_all(&MappedText::new("ab {{#if y}}", "part.txt"));
+        let (text, map) = expanded.into_parts();
+        let error = crate::template::parser::parse(&text).unwrap_err();
+        match map.relocate(&text, error) {
+            RenderError::TemplateSyntax { location, .. } => {
+                assert_eq!(position(location), ("part.txt".to_string(), 1, 4))
+            }
+            other => panic!("Expected TemplateSyntax error, got {:?}", other),
+        }
+
+        // Text without a map keeps its positions
+        assert_eq!(
+            position(SourceMap::default().locate("a\nb", 2)),
+            ("<template>".to_string(), 2, 1)
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts of what rendering did, for `--stats`
+///
+/// An engine keeps counting across all its renders; the counts of the output itself
+/// (bytes, lines, words) are left to the caller, which sees the final text.
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    includes: AtomicUsize,
+    substituted: AtomicUsize,
+    undefined: AtomicUsize,
+}
+
+impl RenderStats {
+    /// Files included or extended
+    pub fn includes(&self) -> usize {
+        self.includes.load(Ordering::Relaxed)
+    }
+
+    /// `{{ }}` tags replaced by a value
+    pub fn substituted(&self) -> usize {
+        self.substituted.load(Ordering::Relaxed)
+    }
+
+    /// References to undefined variables that were rendered anyway
+    pub fn undefined(&self) -> usize {
+        self.undefined.load(Ordering::Relaxed)
+    }
+
+    /// Add the counts of another engine's renders to these
+    pub fn add(&self, other: &RenderStats) {
+        self.add_includes(other.includes());
+        self.substituted
+            .fetch_add(other.substituted(), Ordering::Relaxed);
+        self.undefined
+            .fetch_add(other.undefined(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_includes(&self, count: usize) {
+        self.includes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_substituted(&self) {
+        self.substituted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_undefined(&self) {
+        self.undefined.fetch_add(1, Ordering::Relaxed);
+    }
+}
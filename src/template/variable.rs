@@ -1,116 +1,803 @@
 use crate::error::{Location, RenderError};
-use lazy_static::lazy_static;
-use regex::Regex;
-use serde_json::Value;
-
-lazy_static! {
-    // Match {{ var }} or \{{ (escaped)
-    // Group 1: optional backslash for escape
-    // Group 2: variable name/path
-    static ref VAR_PATTERN: Regex = Regex::new(r"(\\)?\{\{\s*([^}]+?)\s*\}\}").unwrap();
+use chrono::{Local, SecondsFormat};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use super::cache::TemplateCache;
+use super::expr::{is_truthy, EvalContext, Expr, Functions, Scope};
+use super::filters::{escape_html, FilterRegistry};
+use super::parser::{self, Node};
+use super::report::{RenderReport, TRACE_TARGET};
+use super::source_map::SourceMap;
+use super::stats::RenderStats;
+
+/// Longest value shown by a substitution trace (`rp --trace`), in characters
+const TRACE_VALUE_LENGTH: usize = 80;
+
+/// What an output tag referring to an undefined variable renders as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingPolicy {
+    /// Fail the render once it is done, reporting every undefined variable (`--strict`)
+    Error,
+    /// Render nothing
+    #[default]
+    Empty,
+    /// Leave the tag (e.g. `{{ var }}`) in the output as written, for a second renderer
+    Keep,
+    /// Render nothing and report a warning (`--warn-undefined`)
+    Warn,
+}
+
+impl std::str::FromStr for MissingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(MissingPolicy::Error),
+            "empty" => Ok(MissingPolicy::Empty),
+            "keep" => Ok(MissingPolicy::Keep),
+            "warn" => Ok(MissingPolicy::Warn),
+            _ => Err(format!(
+                "unknown missing variable policy '{}' (expected error, empty, keep or warn)",
+                s
+            )),
+        }
+    }
 }
 
 pub struct VariableSubstitutor {
-    strict: bool,
+    missing: MissingPolicy,
     warn_undefined: bool,
+    now_helper: bool,
+    escape_html: bool,
+    /// Text rendered in place of an undefined variable, with `{name}` replaced by its path
+    placeholder: Option<String>,
+    /// Maximum size of the output in bytes
+    max_output_size: Option<usize>,
+    filters: Arc<FilterRegistry>,
+    cache: Arc<TemplateCache>,
+    stats: Arc<RenderStats>,
+    report: Option<Rc<RefCell<RenderReport>>>,
+    missing_fn: Option<Arc<MissingFn>>,
+    /// Where the regions of the content came from, for positions in included files
+    source_map: Option<Arc<SourceMap>>,
+}
+
+/// A callback asked for the value of an undefined variable, with its name (`user.name`)
+/// and where it is referenced; None leaves the variable undefined
+pub type MissingFn = dyn Fn(&str, &Location) -> Option<String> + Send + Sync;
+
+/// Macro definitions by name: parameter names and body
+type Macros<'a> = HashMap<&'a str, (&'a [String], &'a [Node])>;
+
+/// Fragment definitions by name
+type Fragments<'a> = HashMap<&'a str, &'a [Node]>;
+
+/// Maximum nesting of macro calls and fragment references, so self-recursion fails instead of
+/// overflowing
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// Where rendered text goes: a string, or a writer when streaming
+enum Target<'w> {
+    Text(&'w mut String),
+    Writer(&'w mut dyn io::Write),
+}
+
+/// Rendered text and the number of bytes written so far, for the output size limit
+struct Sink<'w> {
+    target: Target<'w>,
+    written: usize,
+}
+
+impl<'w> Sink<'w> {
+    fn new(target: Target<'w>) -> Self {
+        Self { target, written: 0 }
+    }
+
+    fn push_str(&mut self, text: &str) -> Result<(), RenderError> {
+        match &mut self.target {
+            Target::Text(result) => result.push_str(text),
+            Target::Writer(writer) => writer.write_all(text.as_bytes())?,
+        }
+        self.written += text.len();
+        Ok(())
+    }
+}
+
+/// State shared by every node of a single render
+struct RenderState<'a> {
+    substitutor: &'a VariableSubstitutor,
+    content: &'a str,
+    /// Top-level data, which macro bodies are rendered against
+    root: &'a Value,
+    /// Value of the `now` helper, or None when it is disabled
+    now: Option<Value>,
+    /// Values bound with `{{set}}`, visible to everything rendered after the assignment
+    locals: Map<String, Value>,
+    macros: &'a Macros<'a>,
+    fragments: &'a Fragments<'a>,
+    /// Number of macro calls and fragments currently being rendered
+    depth: usize,
+    /// Undefined variables found by a strict render, which keeps going to report them all
+    undefined: &'a RefCell<Vec<(String, Location)>>,
+}
+
+impl RenderState<'_> {
+    fn location(&self, offset: usize) -> Location {
+        self.substitutor.locate(self.content, offset)
+    }
+
+    /// Remember an undefined variable of a strict render, once per place it is used
+    fn record_undefined(&self, name: String, location: Location) {
+        let mut undefined = self.undefined.borrow_mut();
+        if !undefined.iter().any(|(n, l)| *n == name && *l == location) {
+            undefined.push((name, location));
+        }
+    }
+
+    fn call_macro(
+        &self,
+        name: &str,
+        params: &[String],
+        body: &[Node],
+        args: &[Value],
+        ctx: &EvalContext,
+    ) -> Result<Value, RenderError> {
+        let error = |message: String| RenderError::VariableResolution {
+            message,
+            location: ctx.location.clone(),
+        };
+        if args.len() > params.len() {
+            return Err(error(format!(
+                "macro '{}' takes {} argument(s), got {}",
+                name,
+                params.len(),
+                args.len()
+            )));
+        }
+        if self.depth >= MAX_MACRO_DEPTH {
+            return Err(error(format!(
+                "macro '{}' nested more than {} levels deep",
+                name, MAX_MACRO_DEPTH
+            )));
+        }
+
+        // Parameters shadow the caller's locals; missing arguments are null
+        let mut locals = ctx.locals.clone();
+        for (i, param) in params.iter().enumerate() {
+            locals.insert(param.clone(), args.get(i).cloned().unwrap_or(Value::Null));
+        }
+
+        let mut state = RenderState {
+            substitutor: self.substitutor,
+            content: self.content,
+            root: self.root,
+            now: self.now.clone(),
+            locals,
+            macros: self.macros,
+            fragments: self.fragments,
+            depth: self.depth + 1,
+            undefined: self.undefined,
+        };
+        let mut output = String::new();
+        self.substitutor.render_nodes(
+            body,
+            &Scope::root(self.root),
+            &mut state,
+            &mut Sink::new(Target::Text(&mut output)),
+        )?;
+        Ok(Value::String(output))
+    }
+}
+
+impl Functions for RenderState<'_> {
+    fn call(
+        &self,
+        name: &str,
+        args: &[Value],
+        ctx: &EvalContext,
+    ) -> Option<Result<Value, RenderError>> {
+        let (params, body) = self.macros.get(name)?;
+        Some(self.call_macro(name, params, body, args, ctx))
+    }
+}
+
+/// The expression of the `{{ }}` tag at `offset`, such as `name | upper`
+fn tag_expression(content: &str, offset: usize) -> &str {
+    parser::tag_source(content, offset)
+        .trim_start_matches("{{")
+        .trim_end_matches("}}")
+        .trim()
+}
+
+/// Emit the `substitute` event of [`TRACE_TARGET`] for the tag at `offset`, whose value
+/// is None when it refers to an undefined variable
+fn trace_substitution(
+    state: &RenderState,
+    offset: usize,
+    location: &Location,
+    value: Option<&Value>,
+) {
+    if !tracing::enabled!(target: TRACE_TARGET, tracing::Level::TRACE) {
+        return;
+    }
+    let value = match value {
+        Some(value) => {
+            let json = value.to_string();
+            match json.char_indices().nth(TRACE_VALUE_LENGTH) {
+                Some((end, _)) => format!("{}…", &json[..end]),
+                None => json,
+            }
+        }
+        None => "undefined".to_string(),
+    };
+    tracing::trace!(
+        target: TRACE_TARGET,
+        expression = tag_expression(state.content, offset),
+        value,
+        file = %location.file,
+        line = location.line,
+        column = location.column,
+        "substitute"
+    );
+}
+
+/// Gather macro and fragment definitions from anywhere in the tree
+fn collect_definitions<'a>(
+    nodes: &'a [Node],
+    macros: &mut Macros<'a>,
+    fragments: &mut Fragments<'a>,
+) {
+    for node in nodes {
+        match node {
+            Node::Macro { name, params, body } => {
+                macros.insert(name, (params, body));
+                collect_definitions(body, macros, fragments);
+            }
+            Node::Fragment { name, body } => {
+                fragments.insert(name, body);
+                collect_definitions(body, macros, fragments);
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                for branch in branches {
+                    collect_definitions(&branch.body, macros, fragments);
+                }
+                collect_definitions(otherwise, macros, fragments);
+            }
+            Node::With {
+                body, otherwise, ..
+            } => {
+                collect_definitions(body, macros, fragments);
+                collect_definitions(otherwise, macros, fragments);
+            }
+            Node::Block { body, .. } | Node::Let { body, .. } => {
+                collect_definitions(body, macros, fragments)
+            }
+            Node::Text(_) | Node::Output { .. } | Node::Set { .. } | Node::FragmentRef { .. } => {}
+        }
+    }
 }
 
 impl VariableSubstitutor {
     pub fn new(strict: bool, warn_undefined: bool) -> Self {
         Self {
-            strict,
+            missing: if strict {
+                MissingPolicy::Error
+            } else {
+                MissingPolicy::Empty
+            },
             warn_undefined,
+            now_helper: true,
+            escape_html: false,
+            placeholder: None,
+            max_output_size: None,
+            filters: Arc::default(),
+            cache: Arc::default(),
+            stats: Arc::default(),
+            report: None,
+            missing_fn: None,
+            source_map: None,
         }
     }
 
+    /// Set what undefined variables render as, in place of the `strict` flag
+    pub fn with_missing(mut self, missing: MissingPolicy) -> Self {
+        self.missing = missing;
+        self
+    }
+
+    /// Enable or disable the `now` helper (disable for deterministic output)
+    pub fn with_now_helper(mut self, enabled: bool) -> Self {
+        self.now_helper = enabled;
+        self
+    }
+
+    /// HTML-escape every substituted value (literal template text is left as-is)
+    pub fn with_escape_html(mut self, enabled: bool) -> Self {
+        self.escape_html = enabled;
+        self
+    }
+
+    /// Render undefined variables as `placeholder` (with `{name}` replaced by the variable's
+    /// path) instead of nothing; has no effect on the `error` and `keep` policies
+    pub fn with_placeholder(mut self, placeholder: Option<String>) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    /// Make these filters (instead of just the built-in ones) available to templates
+    pub fn with_filters(mut self, filters: Arc<FilterRegistry>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Parse templates through a cache shared with other renders
+    pub fn with_cache(mut self, cache: Arc<TemplateCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Count substituted and undefined variables into `stats`
+    pub fn with_stats(mut self, stats: Arc<RenderStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Ask `callback` for the value of undefined variables before applying the missing
+    /// variable policy
+    pub fn with_missing_fn(mut self, callback: Option<Arc<MissingFn>>) -> Self {
+        self.missing_fn = callback;
+        self
+    }
+
+    /// Report positions in the files the content was expanded from (see
+    /// [`MappedText`](super::MappedText)) instead of in the content itself
+    pub fn with_source_map(mut self, source_map: Option<Arc<SourceMap>>) -> Self {
+        self.source_map = source_map;
+        self
+    }
+
+    /// Record the substituted tags and undefined variables of each render in `report`
+    pub fn with_report(mut self, report: Option<Rc<RefCell<RenderReport>>>) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Fail once the output grows past `limit` bytes
+    pub fn with_max_output_size(mut self, limit: Option<usize>) -> Self {
+        self.max_output_size = limit;
+        self
+    }
+
     /// Substitute all variables in the content
     pub fn substitute(&self, content: &str, data: &Value) -> Result<String, RenderError> {
+        let nodes = self.parse(content)?;
+        self.substitute_parsed(&nodes, content, data)
+    }
+
+    /// Substitute all variables in the content, writing the output to `writer` as it is
+    /// rendered instead of collecting it
+    ///
+    /// Many small pieces are written, so `writer` should be buffered. On an error, the output
+    /// rendered before it has already been written.
+    pub fn substitute_to_writer(
+        &self,
+        content: &str,
+        data: &Value,
+        writer: &mut dyn io::Write,
+    ) -> Result<(), RenderError> {
+        let nodes = self.parse(content)?;
+        self.substitute_parsed_to_writer(&nodes, content, data, writer)
+    }
+
+    /// Parse content through the cache, with syntax errors pointing into their file
+    fn parse(&self, content: &str) -> Result<Arc<Vec<Node>>, RenderError> {
+        self.cache
+            .parse(content)
+            .map_err(|e| match &self.source_map {
+                Some(source_map) => source_map.relocate(content, e),
+                None => e,
+            })
+    }
+
+    /// The position of the byte at `offset` of the content, in the file it came from
+    fn locate(&self, content: &str, offset: usize) -> Location {
+        match &self.source_map {
+            Some(source_map) => source_map.locate(content, offset),
+            None => Location::from_offset(content, offset, "<template>"),
+        }
+    }
+
+    /// Substitute variables in content that was already parsed into `nodes`
+    pub(crate) fn substitute_parsed(
+        &self,
+        nodes: &[Node],
+        content: &str,
+        data: &Value,
+    ) -> Result<String, RenderError> {
         let mut result = String::with_capacity(content.len());
-        let mut last_end = 0;
-
-        for cap in VAR_PATTERN.captures_iter(content) {
-            let full_match = cap.get(0).unwrap();
-            let start = full_match.start();
-            let end = full_match.end();
-
-            // Add text before this match
-            result.push_str(&content[last_end..start]);
-
-            // Check if this is escaped
-            if cap.get(1).is_some() {
-                // Escaped: \{{ ... }} -> {{ ... }}
-                result.push_str("{{");
-                if let Some(var_name) = cap.get(2) {
-                    result.push(' ');
-                    result.push_str(var_name.as_str());
-                    result.push(' ');
+        self.render(nodes, content, data, Target::Text(&mut result))?;
+        Ok(result)
+    }
+
+    /// Substitute variables in content that was already parsed into `nodes`, writing the
+    /// output to `writer`
+    pub(crate) fn substitute_parsed_to_writer(
+        &self,
+        nodes: &[Node],
+        content: &str,
+        data: &Value,
+        writer: &mut dyn io::Write,
+    ) -> Result<(), RenderError> {
+        self.render(nodes, content, data, Target::Writer(writer))
+    }
+
+    fn render(
+        &self,
+        nodes: &[Node],
+        content: &str,
+        data: &Value,
+        target: Target,
+    ) -> Result<(), RenderError> {
+        let mut macros = Macros::new();
+        let mut fragments = Fragments::new();
+        collect_definitions(nodes, &mut macros, &mut fragments);
+        let undefined = RefCell::new(Vec::new());
+
+        let mut state = RenderState {
+            substitutor: self,
+            content,
+            root: data,
+            // Capture the time once so every `now` in a render agrees
+            now: self
+                .now_helper
+                .then(|| Value::String(Local::now().to_rfc3339_opts(SecondsFormat::Secs, true))),
+            locals: Map::new(),
+            macros: &macros,
+            fragments: &fragments,
+            depth: 0,
+            undefined: &undefined,
+        };
+
+        self.render_nodes(
+            nodes,
+            &Scope::root(data),
+            &mut state,
+            &mut Sink::new(target),
+        )?;
+
+        // Strict renders fail only once every undefined variable is known
+        let mut undefined = undefined.take();
+        match undefined.len() {
+            0 => Ok(()),
+            1 => {
+                let (name, location) = undefined.remove(0);
+                Err(RenderError::UndefinedVariable { name, location })
+            }
+            _ => Err(RenderError::UndefinedVariables { undefined }),
+        }
+    }
+
+    /// Evaluate the value of a local binding (`set` or `#let`)
+    fn eval_local(
+        &self,
+        expr: &Expr,
+        offset: usize,
+        scope: &Scope,
+        state: &RenderState,
+    ) -> Result<Value, RenderError> {
+        let location = state.location(offset);
+        let ctx = self.context(scope, state, &location);
+
+        match expr.eval(&ctx) {
+            Ok(value) => Ok(value),
+            Err(RenderError::UndefinedVariable { name, location }) => {
+                if self.missing == MissingPolicy::Error {
+                    state.record_undefined(name, location);
+                } else {
+                    self.report_undefined(&name, &location);
                 }
-                result.push_str("}}");
-            } else {
-                // Not escaped: perform substitution
-                let var_path = cap.get(2).unwrap().as_str().trim();
-                let location = Location::from_offset(content, start, "<template>");
-
-                match self.resolve_variable(var_path, data, location.clone()) {
-                    Ok(value) => result.push_str(&value),
-                    Err(e) => {
-                        if self.strict {
-                            return Err(e);
-                        } else {
-                            if self.warn_undefined {
-                                eprintln!(
-                                    "Warning: undefined variable '{}' at {}",
-                                    var_path, location
-                                );
+                // The local is bound to null (renders empty)
+                Ok(Value::Null)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Count an undefined variable that was rendered anyway, warning if warnings are enabled
+    fn report_undefined(&self, name: &str, location: &Location) {
+        self.stats.add_undefined();
+        if let Some(report) = &self.report {
+            report
+                .borrow_mut()
+                .undefined
+                .push((name.to_string(), location.clone()));
+        }
+        if self.warn_undefined || self.missing == MissingPolicy::Warn {
+            tracing::warn!(
+                code = "UNDEFINED_VAR",
+                file = %location.file,
+                line = location.line,
+                column = location.column,
+                "undefined variable '{}' at {}",
+                name,
+                location
+            );
+        }
+    }
+
+    fn render_nodes(
+        &self,
+        nodes: &[Node],
+        scope: &Scope,
+        state: &mut RenderState,
+        result: &mut Sink,
+    ) -> Result<(), RenderError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => result.push_str(text)?,
+                Node::Output { expr, offset } => {
+                    let location = state.location(*offset);
+                    let ctx = self.context(scope, state, &location);
+
+                    match expr.eval(&ctx) {
+                        Ok(value) => {
+                            trace_substitution(state, *offset, &location, Some(&value));
+                            self.stats.add_substituted();
+                            if let Some(report) = &self.report {
+                                let expression = tag_expression(state.content, *offset);
+                                report.borrow_mut().substituted.push(expression.to_string());
                             }
-                            // In non-strict mode, replace with empty string
+                            let text = Self::value_to_string(&value);
+                            // An explicit trailing `escape_html` and macro output (already
+                            // escaped inside the macro) are not escaped a second time
+                            let escaped = match expr {
+                                Expr::Filter { name, .. } => name == "escape_html",
+                                Expr::Call { name, .. } => state.macros.contains_key(name.as_str()),
+                                _ => false,
+                            };
+                            if self.escape_html && !escaped {
+                                result.push_str(&escape_html(&text))?;
+                            } else {
+                                result.push_str(&text)?;
+                            }
+                        }
+                        Err(RenderError::UndefinedVariable { name, location })
+                            if self.missing == MissingPolicy::Error =>
+                        {
+                            trace_substitution(state, *offset, &location, None);
+                            state.record_undefined(name, location);
+                        }
+                        Err(RenderError::UndefinedVariable { name, location }) => {
+                            trace_substitution(state, *offset, &location, None);
+                            self.report_undefined(&name, &location);
+                            // Otherwise replaced with an empty string
+                            if self.missing == MissingPolicy::Keep {
+                                result.push_str(parser::tag_source(state.content, *offset))?;
+                            } else if let Some(placeholder) = &self.placeholder {
+                                let text = placeholder.replace("{name}", &name);
+                                if self.escape_html {
+                                    result.push_str(&escape_html(&text))?;
+                                } else {
+                                    result.push_str(&text)?;
+                                }
+                            }
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Node::Set { name, expr, offset } => {
+                    let value = self.eval_local(expr, *offset, scope, state)?;
+                    state.locals.insert(name.clone(), value);
+                }
+                Node::Let {
+                    bindings,
+                    offset,
+                    body,
+                } => {
+                    // Evaluate every binding before any of them is visible
+                    let mut values = Vec::with_capacity(bindings.len());
+                    for (name, expr) in bindings {
+                        values.push((name, self.eval_local(expr, *offset, scope, state)?));
+                    }
+                    let saved: Vec<_> = values
+                        .into_iter()
+                        .map(|(name, value)| (name, state.locals.insert(name.clone(), value)))
+                        .collect();
+
+                    let rendered = self.render_nodes(body, scope, state, result);
+
+                    // Restore the outer bindings even if the body failed
+                    for (name, previous) in saved.into_iter().rev() {
+                        match previous {
+                            Some(value) => state.locals.insert(name.clone(), value),
+                            None => state.locals.remove(name),
+                        };
+                    }
+                    rendered?;
+                }
+                Node::Macro { .. } | Node::Fragment { .. } => {}
+                Node::FragmentRef { name, offset } => {
+                    let error = |message: String| RenderError::VariableResolution {
+                        message,
+                        location: state.location(*offset),
+                    };
+                    let body = *state
+                        .fragments
+                        .get(name.as_str())
+                        .ok_or_else(|| error(format!("unknown fragment '{}'", name)))?;
+                    if state.depth >= MAX_MACRO_DEPTH {
+                        return Err(error(format!(
+                            "fragment '{}' nested more than {} levels deep",
+                            name, MAX_MACRO_DEPTH
+                        )));
+                    }
+
+                    state.depth += 1;
+                    let rendered = self.render_nodes(body, scope, state, result);
+                    state.depth -= 1;
+                    rendered?;
+                }
+                Node::Block { body, .. } => self.render_nodes(body, scope, state, result)?,
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let mut body = otherwise;
+                    for branch in branches {
+                        let location = state.location(branch.offset);
+                        let ctx = self.context(scope, state, &location);
+                        if branch.condition.eval_condition(&ctx)? {
+                            body = &branch.body;
+                            break;
                         }
                     }
+                    self.render_nodes(body, scope, state, result)?;
+                }
+                Node::With {
+                    expr,
+                    offset,
+                    body,
+                    otherwise,
+                } => {
+                    let location = state.location(*offset);
+                    let ctx = self.context(scope, state, &location);
+                    let value = expr.eval(&EvalContext {
+                        undefined_as_null: true,
+                        ..ctx
+                    })?;
+
+                    if is_truthy(&value) {
+                        self.render_nodes(body, &scope.nested(&value), state, result)?;
+                    } else {
+                        self.render_nodes(otherwise, scope, state, result)?;
+                    }
                 }
             }
 
-            last_end = end;
+            // Stop as soon as the limit is passed rather than after a runaway render
+            if let Some(limit) = self.max_output_size {
+                if result.written > limit {
+                    return Err(RenderError::OutputTooLarge { limit });
+                }
+            }
         }
 
-        // Add remaining text
-        result.push_str(&content[last_end..]);
+        Ok(())
+    }
 
-        Ok(result)
+    fn context<'a>(
+        &'a self,
+        scope: &'a Scope<'a>,
+        state: &'a RenderState,
+        location: &'a Location,
+    ) -> EvalContext<'a> {
+        EvalContext {
+            scope,
+            locals: &state.locals,
+            filters: &self.filters,
+            functions: Some(state),
+            now: state.now.as_ref(),
+            location,
+            undefined_as_null: false,
+            missing: self.missing_fn.as_deref(),
+        }
     }
 
-    /// Resolve a variable path like "user.name" or "items.0"
-    fn resolve_variable(
-        &self,
-        path: &str,
-        data: &Value,
-        location: Location,
-    ) -> Result<String, RenderError> {
-        let parts: Vec<&str> = path.split('.').collect();
+    /// Walk a dot-separated path through the data, returning None if any segment is missing
+    pub(crate) fn lookup<'a>(path: &str, data: &'a Value) -> Option<Cow<'a, Value>> {
         let mut current = data;
+        let mut rest = Some(path);
 
-        for (i, part) in parts.iter().enumerate() {
-            // Try to parse as array index first
-            if let Ok(index) = part.parse::<usize>() {
-                if let Some(value) = current.get(index) {
-                    current = value;
-                    continue;
-                }
-                // If array index fails, try as object key
+        while let Some(remaining) = rest {
+            let (part, tail) = match remaining.split_once('.') {
+                Some((part, tail)) => (part, Some(tail)),
+                None => (remaining, None),
+            };
+
+            // A slice produces a new array, so the rest of the path is resolved against it
+            if let Some(sliced) = Self::slice(current, part) {
+                return match tail {
+                    Some(tail) => Self::lookup(tail, &sliced).map(|v| Cow::Owned(v.into_owned())),
+                    None => Some(Cow::Owned(sliced)),
+                };
             }
 
-            // Treat as object key
-            current = current.get(part).ok_or_else(|| RenderError::UndefinedVariable {
-                name: path.to_string(),
-                location: location.clone(),
-            })?;
+            current = Self::step(current, part)?;
+            rest = tail;
         }
 
-        // Convert Value to String
-        Ok(Self::value_to_string(current))
+        Some(Cow::Borrowed(current))
+    }
+
+    /// Resolve a single path segment (array index or object key)
+    pub(crate) fn step<'a>(current: &'a Value, part: &str) -> Option<&'a Value> {
+        // Try to parse as array index first
+        if let Ok(index) = part.parse::<usize>() {
+            if let Some(value) = current.get(index) {
+                return Some(value);
+            }
+            // If array index fails, try as object key
+        }
+
+        // Negative index counts from the end of an array (-1 is the last element)
+        if let (Some(items), Ok(index)) = (current.as_array(), part.parse::<isize>()) {
+            if index < 0 {
+                if let Some(value) = items
+                    .len()
+                    .checked_sub(index.unsigned_abs())
+                    .and_then(|i| items.get(i))
+                {
+                    return Some(value);
+                }
+            }
+        }
+
+        // Treat as object key
+        current.get(part)
+    }
+
+    /// Slice an array with a `start:end` segment (either bound may be omitted or negative)
+    ///
+    /// Bounds are clamped to the array length, so out-of-range slices yield a shorter
+    /// (possibly empty) array rather than an error.
+    fn slice(current: &Value, part: &str) -> Option<Value> {
+        let items = current.as_array()?;
+        let (start, end) = part.split_once(':')?;
+
+        let bound = |s: &str, default: usize| -> Option<usize> {
+            if s.is_empty() {
+                return Some(default);
+            }
+            let index = s.parse::<isize>().ok()?;
+            Some(if index < 0 {
+                items.len().saturating_sub(index.unsigned_abs())
+            } else {
+                index.unsigned_abs().min(items.len())
+            })
+        };
+
+        let start = bound(start, 0)?;
+        let end = bound(end, items.len())?;
+        let sliced = if start < end {
+            items[start..end].to_vec()
+        } else {
+            Vec::new()
+        };
+
+        Some(Value::Array(sliced))
     }
 
     /// Convert a JSON value to its string representation
-    fn value_to_string(value: &Value) -> String {
+    pub(crate) fn value_to_string(value: &Value) -> String {
         match value {
             Value::String(s) => s.clone(),
             Value::Number(n) => n.to_string(),
@@ -184,6 +871,439 @@ mod tests {
         assert_eq!(result, "Value: c");
     }
 
+    #[test]
+    fn test_negative_array_index() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({
+            "items": ["apple", "banana", "cherry"]
+        });
+        let result = sub
+            .substitute("Last: {{ items.-1 }}, {{ items.-3 }}", &data)
+            .unwrap();
+        assert_eq!(result, "Last: cherry, apple");
+    }
+
+    #[test]
+    fn test_negative_array_index_out_of_bounds() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"items": ["apple"]});
+        let result = sub.substitute("{{ items.-2 }}", &data);
+        assert!(matches!(result, Err(RenderError::UndefinedVariable { .. })));
+    }
+
+    #[test]
+    fn test_array_slice() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"items": [1, 2, 3, 4, 5]});
+        let result = sub
+            .substitute(
+                "{{ items.0:3 }} {{ items.-2: }} {{ items.:1 }} {{ items.1:-1 }}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "[1,2,3] [4,5] [1] [2,3,4]");
+    }
+
+    #[test]
+    fn test_array_slice_out_of_range() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"items": ["a", "b"]});
+        let result = sub
+            .substitute("{{ items.1:10 }} {{ items.5:8 }}", &data)
+            .unwrap();
+        assert_eq!(result, r#"["b"] []"#);
+    }
+
+    #[test]
+    fn test_array_slice_then_index() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"examples": [{"q": "a"}, {"q": "b"}, {"q": "c"}]});
+        let result = sub.substitute("{{ examples.-2:.0.q }}", &data).unwrap();
+        assert_eq!(result, "b");
+    }
+
+    #[test]
+    fn test_filters() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"lang": "  EN  ", "name": "alice smith"});
+        let result = sub
+            .substitute(
+                r#"{{ lang | trim | lower }}: {{ name | replace(" ", "_") | upper }}"#,
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "en: ALICE_SMITH");
+    }
+
+    #[test]
+    fn test_filter_on_undefined_non_strict() {
+        let sub = VariableSubstitutor::new(false, false);
+        let data = json!({});
+        let result = sub.substitute("[{{ missing | upper }}]", &data).unwrap();
+        assert_eq!(result, "[]");
+    }
+
+    #[test]
+    fn test_unknown_filter_is_error() {
+        let sub = VariableSubstitutor::new(false, false);
+        let data = json!({"name": "Alice"});
+        let result = sub.substitute("{{ name | shout }}", &data);
+        assert!(matches!(
+            result,
+            Err(RenderError::VariableResolution { .. })
+        ));
+    }
+
+    #[test]
+    fn test_now_helper() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({});
+        let result = sub.substitute(r#"{{ now | date("%Y") }}"#, &data).unwrap();
+        assert_eq!(result, Local::now().format("%Y").to_string());
+    }
+
+    #[test]
+    fn test_now_helper_disabled() {
+        let sub = VariableSubstitutor::new(true, false).with_now_helper(false);
+        let data = json!({});
+        let result = sub.substitute("{{ now }}", &data);
+        assert!(matches!(result, Err(RenderError::UndefinedVariable { .. })));
+    }
+
+    #[test]
+    fn test_now_data_takes_precedence() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"now": "2024-01-02"});
+        let result = sub.substitute("{{ now }}", &data).unwrap();
+        assert_eq!(result, "2024-01-02");
+    }
+
+    #[test]
+    fn test_if_block() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = "{{#if score >= 0.8 && lang == \"en\"}}great{{else if score >= 0.5}}ok{{else}}poor{{/if}}";
+        let render = |data: Value| sub.substitute(template, &data).unwrap();
+        assert_eq!(render(json!({"score": 0.9, "lang": "en"})), "great");
+        assert_eq!(render(json!({"score": 0.9, "lang": "ja"})), "ok");
+        assert_eq!(render(json!({"score": 0.1, "lang": "en"})), "poor");
+    }
+
+    #[test]
+    fn test_if_undefined_is_false_in_strict_mode() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({});
+        let result = sub
+            .substitute("[{{#if missing}}yes{{/if}}]", &data)
+            .unwrap();
+        assert_eq!(result, "[]");
+    }
+
+    #[test]
+    fn test_if_untaken_branch_not_evaluated() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"show": false});
+        let result = sub
+            .substitute("{{#if show}}{{ missing }}{{/if}}done", &data)
+            .unwrap();
+        assert_eq!(result, "done");
+    }
+
+    #[test]
+    fn test_unclosed_if_is_syntax_error() {
+        let sub = VariableSubstitutor::new(false, false);
+        let result = sub.substitute("{{#if a}}text", &json!({}));
+        assert!(matches!(result, Err(RenderError::TemplateSyntax { .. })));
+    }
+
+    #[test]
+    fn test_unless_block() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = "{{#unless user.email}}No email on file{{else}}{{ user.email }}{{/unless}}";
+        let render = |data: Value| sub.substitute(template, &data).unwrap();
+        assert_eq!(render(json!({"user": {}})), "No email on file");
+        assert_eq!(render(json!({"user": {"email": ""}})), "No email on file");
+        assert_eq!(render(json!({"user": {"email": "a@b.c"}})), "a@b.c");
+    }
+
+    #[test]
+    fn test_with_block() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({
+            "company": {"departments": [{"name": "R&D", "head": {"name": "Alice"}}]}
+        });
+        let result = sub
+            .substitute(
+                "{{#with company.departments.0}}{{ name }}: {{ head.name }}{{/with}}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "R&D: Alice");
+    }
+
+    #[test]
+    fn test_with_block_missing_renders_else() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"name": "root"});
+        let result = sub
+            .substitute("{{#with user}}{{ name }}{{else}}no user{{/with}}", &data)
+            .unwrap();
+        assert_eq!(result, "no user");
+    }
+
+    #[test]
+    fn test_with_block_rebinds_context() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"title": "Top", "user": {"name": "Bob"}});
+        let result = sub.substitute("{{#with user}}{{ title }}{{/with}}", &data);
+        assert!(matches!(result, Err(RenderError::UndefinedVariable { .. })));
+    }
+
+    #[test]
+    fn test_ternary() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = r#"{{ premium ? "Pro plan" : "Free plan" }}"#;
+        let render = |data: Value| sub.substitute(template, &data).unwrap();
+        assert_eq!(render(json!({"premium": true})), "Pro plan");
+        assert_eq!(render(json!({"premium": false})), "Free plan");
+        assert_eq!(render(json!({})), "Free plan");
+    }
+
+    #[test]
+    fn test_escape_html_mode() {
+        let data = json!({"name": "<b>Tom & Jerry</b>"});
+        let template = "<p>{{ name }}</p><p>{{ name | escape_html }}</p>";
+
+        let plain = VariableSubstitutor::new(true, false);
+        assert_eq!(
+            plain.substitute(template, &data).unwrap(),
+            "<p><b>Tom & Jerry</b></p><p>&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;</p>"
+        );
+
+        let escaping = VariableSubstitutor::new(true, false).with_escape_html(true);
+        assert_eq!(
+            escaping.substitute(template, &data).unwrap(),
+            "<p>&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;</p><p>&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn test_set_local_value() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"user": {"name": "Ann"}});
+        let result = sub
+            .substitute(
+                r#"{{set greeting = "Hello, " ~ user.name}}{{ greeting }}! {{ greeting | upper }}"#,
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "Hello, Ann! HELLO, ANN");
+    }
+
+    #[test]
+    fn test_set_shadows_data_and_survives_blocks() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"name": "data", "user": {"name": "Bob"}});
+        let template = "{{let name = 'local'}}{{#with user}}{{ name }}{{set seen = true}}{{/with}} {{ name }} {{ seen }}";
+        let result = sub.substitute(template, &data).unwrap();
+        assert_eq!(result, "local local true");
+    }
+
+    #[test]
+    fn test_set_reassign() {
+        let sub = VariableSubstitutor::new(true, false);
+        let result = sub
+            .substitute("{{set n = 'a'}}{{set n = n ~ 'b'}}{{ n }}", &json!({}))
+            .unwrap();
+        assert_eq!(result, "ab");
+    }
+
+    #[test]
+    fn test_set_undefined() {
+        let template = "{{set x = missing}}[{{ x }}]";
+        let lenient = VariableSubstitutor::new(false, false);
+        assert_eq!(lenient.substitute(template, &json!({})).unwrap(), "[]");
+
+        let strict = VariableSubstitutor::new(true, false);
+        let result = strict.substitute(template, &json!({}));
+        assert!(matches!(result, Err(RenderError::UndefinedVariable { .. })));
+    }
+
+    #[test]
+    fn test_raw_text_with_closing_tag() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = "{{#raw}}a {{/raw}}{{ '{{/raw' }}{{#raw}}}} {{ b }}{{/raw}}";
+        assert_eq!(
+            sub.substitute(template, &json!({})).unwrap(),
+            "a {{/raw}} {{ b }}"
+        );
+    }
+
+    #[test]
+    fn test_fragment() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"name": "Ann", "user": {"name": "Bob"}});
+        let template = "{{fragment greet}}|{{#fragment greet}}Hi {{ name }}{{/fragment}}|{{#with user}}{{fragment greet}}{{/with}}";
+        assert_eq!(sub.substitute(template, &data).unwrap(), "Hi Ann||Hi Bob");
+
+        let result = sub.substitute("{{fragment missing}}", &data);
+        assert!(matches!(
+            result,
+            Err(RenderError::VariableResolution { .. })
+        ));
+
+        let result = sub.substitute(
+            "{{#fragment loop}}{{fragment loop}}{{/fragment}}{{fragment loop}}",
+            &data,
+        );
+        assert!(matches!(
+            result,
+            Err(RenderError::VariableResolution { .. })
+        ));
+    }
+
+    #[test]
+    fn test_let_block_scoped() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"title": "Data", "user": {"name": "Ann"}});
+        let template = r#"{{#let title="Intro" who=user.name n=3}}{{ title }}/{{ who }}/{{ n }}{{/let}} {{ title }}"#;
+        assert_eq!(sub.substitute(template, &data).unwrap(), "Intro/Ann/3 Data");
+
+        let template = "{{set x = 'outer'}}{{#let x=1}}{{ x }}{{#let x=2}}{{ x }}{{/let}}{{ x }}{{/let}}{{ x }}";
+        assert_eq!(sub.substitute(template, &data).unwrap(), "121outer");
+    }
+
+    #[test]
+    fn test_with_block_parent_access() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({
+            "title": "Guide",
+            "chapter": {"title": "Intro", "section": {"title": "Setup"}}
+        });
+        let result = sub
+            .substitute(
+                "{{#with chapter}}{{#with section}}{{ ../../title }} > {{ ../title }} > {{ title }}{{/with}}{{/with}}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "Guide > Intro > Setup");
+    }
+
+    #[test]
+    fn test_bracket_lookup() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({
+            "lang": "ja",
+            "greetings": {"en": {"text": "Hello"}, "ja": {"text": "こんにちは"}},
+            "models": ["a", "b", "c"],
+            "pick": 1
+        });
+        let result = sub
+            .substitute(
+                "{{ greetings[lang].text }} {{ greetings['en'].text }} {{ models[pick] }} {{ models[-1] }}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "こんにちは Hello b c");
+    }
+
+    #[test]
+    fn test_bracket_lookup_missing_key() {
+        let data = json!({"greetings": {"en": "Hello"}, "lang": "fr"});
+        let lenient = VariableSubstitutor::new(false, false);
+        assert_eq!(
+            lenient
+                .substitute("[{{ greetings[lang] }}]", &data)
+                .unwrap(),
+            "[]"
+        );
+
+        let strict = VariableSubstitutor::new(true, false);
+        match strict.substitute("{{ greetings[lang] }}", &data) {
+            Err(RenderError::UndefinedVariable { name, .. }) => {
+                assert_eq!(name, "greetings[fr]")
+            }
+            other => panic!("Expected undefined variable error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_length_in_condition() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = "{{#if items | length > 0}}{{ items | length }} items{{else}}empty{{/if}}";
+        let render = |data: Value| sub.substitute(template, &data).unwrap();
+        assert_eq!(render(json!({"items": ["a", "b"]})), "2 items");
+        assert_eq!(render(json!({"items": []})), "empty");
+        assert_eq!(render(json!({})), "empty");
+    }
+
+    #[test]
+    fn test_split_index_and_join() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"csv_line": "b,c,a"});
+        let result = sub
+            .substitute(
+                r#"{{ (csv_line | split(","))[0] }} {{ csv_line | split(",") | sort | join(", ") }}"#,
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "b a, b, c");
+    }
+
+    #[test]
+    fn test_macro_call() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"title": "Guide", "summary": "Short"});
+        let template = "{{#macro card(title, body)}}## {{ title }}\n{{ body }}\n{{/macro}}\
+{{ card('Intro', summary) }}{{ card(title | upper, 'Body') }}";
+        let result = sub.substitute(template, &data).unwrap();
+        assert_eq!(result, "## Intro\nShort\n## GUIDE\nBody\n");
+    }
+
+    #[test]
+    fn test_macro_missing_args_are_null() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = "{{ tag('b') }}{{#macro tag(name, text)}}<{{ name }}>{{ text }}{{/macro}}";
+        assert_eq!(sub.substitute(template, &json!({})).unwrap(), "<b>");
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let sub = VariableSubstitutor::new(false, false);
+        for template in [
+            "{{ nope() }}",
+            "{{#macro one(a)}}{{/macro}}{{ one(1, 2) }}",
+            "{{#macro loop()}}{{ loop() }}{{/macro}}{{ loop() }}",
+        ] {
+            let result = sub.substitute(template, &json!({}));
+            assert!(
+                matches!(result, Err(RenderError::VariableResolution { .. })),
+                "{}: {:?}",
+                template,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_macro_output_not_escaped_twice() {
+        let sub = VariableSubstitutor::new(true, false).with_escape_html(true);
+        let template = "{{#macro b(text)}}<b>{{ text }}</b>{{/macro}}{{ b('x & y') }}";
+        assert_eq!(
+            sub.substitute(template, &json!({})).unwrap(),
+            "<b>x &amp; y</b>"
+        );
+    }
+
+    #[test]
+    fn test_block_renders_body() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"name": "Ann"});
+        let result = sub
+            .substitute("[{{#block greeting}}Hi {{ name }}{{/block}}]", &data)
+            .unwrap();
+        assert_eq!(result, "[Hi Ann]");
+    }
+
     #[test]
     fn test_number_value() {
         let sub = VariableSubstitutor::new(false, false);
@@ -266,6 +1386,83 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_strict_reports_every_undefined_variable() {
+        let sub = VariableSubstitutor::new(true, false);
+        let template = "{{ a }} {{ name }}\n{{#let x=b}}{{ x }}{{/let}}\n{{#macro m()}}{{ c }}{{/macro}}{{ m() }}{{ m() }}";
+        let result = sub.substitute(template, &json!({"name": "n"}));
+        match result {
+            Err(RenderError::UndefinedVariables { undefined }) => {
+                // Once per place a variable is used, however often it is rendered
+                let found: Vec<_> = undefined
+                    .iter()
+                    .map(|(name, l)| (name.as_str(), l.line, l.column))
+                    .collect();
+                assert_eq!(found, [("a", 1, 1), ("b", 2, 1), ("c", 3, 15)]);
+            }
+            other => panic!("Expected UndefinedVariables error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_policy() {
+        let data = json!({"name": "Alice"});
+        let template = "{{ name }} {{ plan }} {{  user.email | upper }}{{#if x}}!{{/if}}";
+        let render = |missing: MissingPolicy| {
+            VariableSubstitutor::new(false, false)
+                .with_missing(missing)
+                .substitute(template, &data)
+        };
+
+        assert_eq!(render(MissingPolicy::Empty).unwrap(), "Alice  ");
+        assert_eq!(render(MissingPolicy::Warn).unwrap(), "Alice  ");
+        assert_eq!(
+            render(MissingPolicy::Keep).unwrap(),
+            "Alice {{ plan }} {{  user.email | upper }}"
+        );
+        assert!(matches!(
+            render(MissingPolicy::Error),
+            Err(RenderError::UndefinedVariables { .. })
+        ));
+        assert_eq!("keep".parse(), Ok(MissingPolicy::Keep));
+        assert!("ignore".parse::<MissingPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_placeholder_for_undefined() {
+        let sub = VariableSubstitutor::new(false, false)
+            .with_placeholder(Some("<MISSING:{name}>".to_string()));
+        let data = json!({"name": "Alice"});
+        let result = sub
+            .substitute(
+                "{{ name }} {{ user.email }} {{set x = nope}}[{{ x }}]",
+                &data,
+            )
+            .unwrap();
+        // A local bound to an undefined value is null, not the placeholder
+        assert_eq!(result, "Alice <MISSING:user.email> []");
+
+        let strict = VariableSubstitutor::new(true, false).with_placeholder(Some("N/A".into()));
+        assert!(strict.substitute("{{ nope }}", &data).is_err());
+    }
+
+    #[test]
+    fn test_max_output_size() {
+        let data = json!({"word": "abcd"});
+        let template = "{{#macro twice(s)}}{{ s }}{{ s }}{{/macro}}{{ twice(twice(twice(word))) }}";
+        let limited = |limit| {
+            VariableSubstitutor::new(false, false)
+                .with_max_output_size(Some(limit))
+                .substitute(template, &data)
+        };
+
+        assert_eq!(limited(32).unwrap().len(), 32);
+        assert!(matches!(
+            limited(31),
+            Err(RenderError::OutputTooLarge { limit: 31 })
+        ));
+    }
+
     #[test]
     fn test_multiple_substitutions() {
         let sub = VariableSubstitutor::new(false, false);
@@ -276,6 +1473,29 @@ mod tests {
         assert_eq!(result, "Name: Alice Smith");
     }
 
+    #[test]
+    fn test_raw_block() {
+        let sub = VariableSubstitutor::new(true, false);
+        let data = json!({"name": "Alice"});
+        let result = sub
+            .substitute(
+                "{{ name }}: {{#raw}}Hello {{ name }}, \\{{ x }}{{/raw}}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(result, "Alice: Hello {{ name }}, \\{{ x }}");
+    }
+
+    #[test]
+    fn test_raw_block_multiline() {
+        let sub = VariableSubstitutor::new(false, false);
+        let data = json!({});
+        let result = sub
+            .substitute("{{#raw}}\n{{ a }}\n{{ b }}\n{{/raw}}", &data)
+            .unwrap();
+        assert_eq!(result, "\n{{ a }}\n{{ b }}\n");
+    }
+
     #[test]
     fn test_no_substitution() {
         let sub = VariableSubstitutor::new(false, false);
@@ -0,0 +1,212 @@
+use std::collections::{BTreeSet, HashSet};
+
+use super::expr::Expr;
+use super::parser::Node;
+
+/// List the data paths a parsed template refers to, without rendering it
+///
+/// Paths inside `{{#with}}` blocks are reported relative to the data root, and names bound
+/// with `set`, `let` or as macro parameters are left out. Paths under a context that can
+/// only be known at render time (e.g. `{{#with items[key]}}`) cannot be resolved and are
+/// skipped. The result is sorted and free of duplicates.
+pub fn referenced_variables(nodes: &[Node]) -> Vec<String> {
+    let mut collector = Collector::default();
+    collector.nodes(nodes, &[Some(String::new())], &HashSet::new());
+    collector.paths.into_iter().collect()
+}
+
+#[derive(Default)]
+struct Collector {
+    paths: BTreeSet<String>,
+    /// Names assigned with `{{set}}`, visible for the rest of the template
+    assigned: HashSet<String>,
+}
+
+/// The context path of each enclosing scope, innermost last (None if not known statically)
+type Scopes = [Option<String>];
+
+impl Collector {
+    fn nodes(&mut self, nodes: &[Node], scopes: &Scopes, locals: &HashSet<String>) {
+        for node in nodes {
+            match node {
+                Node::Text(_) | Node::FragmentRef { .. } => {}
+                Node::Output { expr, .. } => self.expr(expr, scopes, locals),
+                Node::If {
+                    branches,
+                    otherwise,
+                } => {
+                    for branch in branches {
+                        self.expr(&branch.condition, scopes, locals);
+                        self.nodes(&branch.body, scopes, locals);
+                    }
+                    self.nodes(otherwise, scopes, locals);
+                }
+                Node::With {
+                    expr,
+                    body,
+                    otherwise,
+                    ..
+                } => {
+                    self.expr(expr, scopes, locals);
+                    let context = match expr {
+                        Expr::Path(path) => self.resolve(path, scopes, locals),
+                        _ => None,
+                    };
+                    let mut nested = scopes.to_vec();
+                    nested.push(context);
+                    self.nodes(body, &nested, locals);
+                    self.nodes(otherwise, scopes, locals);
+                }
+                Node::Block { body, .. } | Node::Fragment { body, .. } => {
+                    self.nodes(body, scopes, locals)
+                }
+                Node::Macro { params, body, .. } => {
+                    // Macro bodies see the data root, with their parameters shadowing it
+                    let mut locals = locals.clone();
+                    locals.extend(params.iter().cloned());
+                    self.nodes(body, &[Some(String::new())], &locals);
+                }
+                Node::Let { bindings, body, .. } => {
+                    let mut inner = locals.clone();
+                    for (name, expr) in bindings {
+                        self.expr(expr, scopes, locals);
+                        inner.insert(name.clone());
+                    }
+                    self.nodes(body, scopes, &inner);
+                }
+                Node::Set { name, expr, .. } => {
+                    self.expr(expr, scopes, locals);
+                    self.assigned.insert(name.clone());
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, scopes: &Scopes, locals: &HashSet<String>) {
+        match expr {
+            Expr::Literal(_) => {}
+            Expr::Path(path) => {
+                // `now` is a built-in helper, so the data does not need to define it
+                if path == "now" {
+                    return;
+                }
+                if let Some(path) = self.resolve(path, scopes, locals) {
+                    self.paths.insert(path);
+                }
+            }
+            Expr::Index { target, key } => {
+                self.expr(target, scopes, locals);
+                self.expr(key, scopes, locals);
+            }
+            Expr::Member { target, .. } | Expr::Not(target) => self.expr(target, scopes, locals),
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    self.expr(arg, scopes, locals);
+                }
+            }
+            Expr::Filter { input, args, .. } => {
+                self.expr(input, scopes, locals);
+                for arg in args {
+                    self.expr(arg, scopes, locals);
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expr(condition, scopes, locals);
+                self.expr(then, scopes, locals);
+                self.expr(otherwise, scopes, locals);
+            }
+            Expr::Binary { left, right, .. } => {
+                self.expr(left, scopes, locals);
+                self.expr(right, scopes, locals);
+            }
+        }
+    }
+
+    /// The full data path a path in the given scope refers to, or None if it names a local
+    /// or its context is not known
+    fn resolve(&self, path: &str, scopes: &Scopes, locals: &HashSet<String>) -> Option<String> {
+        let mut rest = path;
+        let mut up = 0;
+        while let Some(tail) = rest.strip_prefix("../") {
+            rest = tail;
+            up += 1;
+        }
+
+        if up == 0 {
+            let head = rest.split('.').next().unwrap_or(rest);
+            if locals.contains(head) || self.assigned.contains(head) {
+                return None;
+            }
+        }
+        if rest.is_empty() {
+            return None;
+        }
+
+        let context = scopes.len().checked_sub(up + 1)?;
+        match scopes[context].as_deref()? {
+            "" => Some(rest.to_string()),
+            prefix => Some(format!("{}.{}", prefix, rest)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::parser;
+
+    fn vars(content: &str) -> Vec<String> {
+        referenced_variables(&parser::parse(content).unwrap())
+    }
+
+    #[test]
+    fn test_paths_in_outputs_and_expressions() {
+        let content =
+            "{{ user.name | upper }} {{ greetings[lang] }} {{ premium ? plan : \"free\" }}\
+                       {{#if items | length > limit}}{{ now | date(\"%Y\") }}{{/if}}";
+        assert_eq!(
+            vars(content),
+            vec![
+                "greetings",
+                "items",
+                "lang",
+                "limit",
+                "plan",
+                "premium",
+                "user.name"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paths_in_with_are_absolute() {
+        let content = "{{#with user}}{{ name }} {{ ../title }}{{#with address}}{{ city }}{{/with}}\
+                       {{/with}}{{#with items[0]}}{{ unknown }}{{/with}}";
+        assert_eq!(
+            vars(content),
+            vec![
+                "items",
+                "title",
+                "user",
+                "user.address",
+                "user.address.city",
+                "user.name"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locals_are_not_reported() {
+        let content = "{{ greeting }}{{set greeting = \"Hi \" ~ user.name}}{{ greeting }}\
+                       {{#let n=count}}{{ n }}{{/let}}\
+                       {{#macro card(title)}}{{ title }} {{ footer }}{{/macro}}{{ card(heading) }}";
+        assert_eq!(
+            vars(content),
+            vec!["count", "footer", "greeting", "heading", "user.name"]
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! JavaScript bindings for previewing templates in the browser (the `wasm` feature)
+//!
+//! Build with `wasm-pack build --no-default-features --features wasm`; everything is
+//! rendered in memory, with partials given by name instead of read from disk.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+use crate::template::{MemoryTemplateStore, TemplateEngine};
+
+/// Render `template` with `data` (an object), resolving includes from `partials` (an object
+/// mapping names such as `partials/header.txt` to template text)
+///
+/// Either object may be `undefined` or `null`. Errors are thrown with the same message
+/// `rp` prints.
+#[wasm_bindgen]
+pub fn render(template: &str, partials: JsValue, data: JsValue) -> Result<String, JsError> {
+    let partials: BTreeMap<String, String> = from_js(&partials, "partials")?.unwrap_or_default();
+    let data: Value = from_js(&data, "data")?.unwrap_or_else(|| serde_json::json!({}));
+
+    let mut store = MemoryTemplateStore::new();
+    for (name, content) in partials {
+        store.insert(name, content);
+    }
+    let engine = TemplateEngine::builder().include_source(store).build();
+    engine
+        .render_source(template, Path::new("<template>"), &data)
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Convert a JavaScript value through JSON, so numbers keep the form they have in data files
+fn from_js<T: DeserializeOwned>(value: &JsValue, name: &str) -> Result<Option<T>, JsError> {
+    if value.is_undefined() || value.is_null() {
+        return Ok(None);
+    }
+    let error = |message: String| JsError::new(&format!("invalid {}: {}", name, message));
+    let json: String = js_sys::JSON::stringify(value)
+        .map_err(|_| error("not serializable as JSON".to_string()))?
+        .into();
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| error(e.to_string()))
+}
@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Modification time and size of every watched file, to detect changes by polling
+#[derive(Debug, PartialEq)]
+struct Snapshot(Vec<(PathBuf, Option<(SystemTime, u64)>)>);
+
+impl Snapshot {
+    /// Record the state of the files; directories are scanned recursively
+    fn take(paths: &[PathBuf]) -> Self {
+        let mut entries = Vec::new();
+        for path in paths {
+            Self::add(path, &mut entries);
+        }
+        Snapshot(entries)
+    }
+
+    fn add(path: &Path, entries: &mut Vec<(PathBuf, Option<(SystemTime, u64)>)>) {
+        if path.is_dir() {
+            if let Ok(dir) = std::fs::read_dir(path) {
+                let mut children: Vec<PathBuf> = dir
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .collect();
+                children.sort();
+                for child in children {
+                    Self::add(&child, entries);
+                }
+            }
+        }
+
+        // A missing file is recorded too, so that creating it counts as a change
+        let state = std::fs::metadata(path)
+            .ok()
+            .and_then(|m| Some((m.modified().ok()?, m.len())));
+        entries.push((path.to_path_buf(), state));
+    }
+
+    /// The files created, modified or removed since `earlier`
+    fn changed_since(&self, earlier: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .0
+            .iter()
+            .filter(|entry| !earlier.0.contains(entry))
+            .map(|(path, _)| path.clone())
+            .collect();
+        // Removed files of a watched directory are no longer listed at all
+        for (path, _) in &earlier.0 {
+            if !self.0.iter().any(|(p, _)| p == path) {
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+/// Block until one of the files (or a file in one of the directories) is created,
+/// modified or removed, returning the files that changed
+pub fn wait_for_change(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let initial = Snapshot::take(paths);
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let current = Snapshot::take(paths);
+        if current != initial {
+            return current.changed_since(&initial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot_detects_changes() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.yaml");
+        fs::write(&file, "a: 1").unwrap();
+        let sub = dir.path().join("context");
+        fs::create_dir(&sub).unwrap();
+
+        let paths = vec![file.clone(), sub.clone(), dir.path().join("missing.txt")];
+        let before = Snapshot::take(&paths);
+        assert_eq!(Snapshot::take(&paths), before);
+
+        fs::write(&file, "a: 12").unwrap();
+        let after_edit = Snapshot::take(&paths);
+        assert_ne!(after_edit, before);
+
+        fs::write(sub.join("users.yaml"), "[]").unwrap();
+        let after_add = Snapshot::take(&paths);
+        assert_ne!(after_add, after_edit);
+
+        fs::write(dir.path().join("missing.txt"), "").unwrap();
+        assert_ne!(Snapshot::take(&paths), after_add);
+    }
+
+    #[test]
+    fn test_changed_files() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.yaml");
+        fs::write(&file, "a: 1").unwrap();
+        let sub = dir.path().join("context");
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("old.yaml"), "[]").unwrap();
+
+        let paths = vec![file.clone(), sub.clone()];
+        let before = Snapshot::take(&paths);
+        fs::write(&file, "a: 12").unwrap();
+        fs::remove_file(sub.join("old.yaml")).unwrap();
+
+        let changed = Snapshot::take(&paths).changed_since(&before);
+        assert!(changed.contains(&file));
+        assert!(changed.contains(&sub.join("old.yaml")));
+        assert!(!changed.contains(&sub.join("missing.yaml")));
+    }
+}
@@ -1,4 +1,4 @@
-use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin_cmd;
 use std::fs;
 use tempfile::tempdir;
 
@@ -19,8 +19,7 @@ fn test_merge_three_files() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ a }},{{ b }},{{ c }},{{ d }},{{ e }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -72,8 +71,7 @@ user:
     )
     .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -99,8 +97,7 @@ fn test_merge_array_override() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ items.0 }},{{ items.1 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -126,8 +123,7 @@ fn test_merge_type_change() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ value }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -153,8 +149,7 @@ fn test_merge_object_to_array() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ data.0 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -209,8 +204,7 @@ app:
     )
     .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -236,8 +230,7 @@ fn test_merge_with_null() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "[{{ value }}]").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -263,8 +256,7 @@ fn test_merge_with_empty_object() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ a }},{{ b }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -298,8 +290,7 @@ fn test_merge_very_deep_nesting() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ a.b.c.d.e.value }},{{ a.b.c.d.e.extra }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -325,8 +316,7 @@ fn test_merge_mixed_type_arrays() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ items.0 }},{{ items.1 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -352,8 +342,7 @@ fn test_merge_boolean_and_null() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "[{{ flag }}][{{ opt }}]").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -385,10 +374,13 @@ fn test_merge_many_keys() {
     fs::write(&data2, data2_content).unwrap();
 
     let template = dir.path().join("template.txt");
-    fs::write(&template, "{{ key0 }},{{ key250 }},{{ key500 }},{{ key749 }}").unwrap();
+    fs::write(
+        &template,
+        "{{ key0 }},{{ key250 }},{{ key500 }},{{ key749 }}",
+    )
+    .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -400,10 +392,65 @@ fn test_merge_many_keys() {
         .stdout("0,2500,5000,7490\n");
 }
 
-// YAMLのアンカーとエイリアス（<<: *anchor）はYAML 1.1の機能で、
-// YAML 1.2では非推奨。serde_yaml 0.9はYAML 1.2ベースのため、
-// マージキーは正しくサポートされない。これはライブラリの制限であり、
-// rpの機能テストとしては適切ではないため、このテストは削除。
+/// データマージ: 名前空間を指定したファイルはキーの下に格納され衝突しない
+#[test]
+fn test_namespaced_data_files() {
+    let dir = tempdir().unwrap();
+
+    let profile = dir.path().join("profile.yaml");
+    fs::write(&profile, "name: Alice").unwrap();
+
+    let task = dir.path().join("task.yaml");
+    fs::write(&task, "name: Summarize").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ profile.name }} / {{ task.name }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(format!("profile={}", profile.display()))
+        .arg("-d")
+        .arg(format!("task={}", task.display()))
+        .assert()
+        .success()
+        .stdout("Alice / Summarize\n");
+}
+
+/// データマージ: YAMLのアンカー・エイリアスとマージキー（<<: *anchor）
+#[test]
+fn test_yaml_anchor_merge_keys() {
+    let dir = tempdir().unwrap();
+
+    let data1 = dir.path().join("data1.yaml");
+    fs::write(
+        &data1,
+        "defaults: &defaults\n  model: small\n  temperature: 0.2\nprod:\n  <<: *defaults\n  model: large\nfallback: *defaults\n",
+    )
+    .unwrap();
+
+    let data2 = dir.path().join("data2.yaml");
+    fs::write(&data2, "prod:\n  temperature: 0.0").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ prod.model }},{{ prod.temperature }},{{ fallback.model }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .assert()
+        .success()
+        .stdout("large,0.0,small\n");
+}
 
 /// データマージ: 配列内のオブジェクトのマージ（完全上書き）
 #[test]
@@ -437,8 +484,7 @@ users:
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ users.0.name }},{{ users.0.age }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -450,6 +496,155 @@ users:
         .stdout("Charlie,35\n");
 }
 
+/// データマージ: --array-mergeによる配列の結合
+#[test]
+fn test_array_merge_strategies() {
+    let dir = tempdir().unwrap();
+
+    let data1 = dir.path().join("base.yaml");
+    fs::write(
+        &data1,
+        r#"
+examples:
+  - id: greet
+    output: hello
+  - id: bye
+    output: goodbye
+"#,
+    )
+    .unwrap();
+
+    let data2 = dir.path().join("extra.yaml");
+    fs::write(
+        &data2,
+        r#"
+examples:
+  - id: bye
+    output: see you
+  - id: thanks
+    output: welcome
+"#,
+    )
+    .unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ examples | length }}: {{ examples.1.output }}, {{ examples.-1.id }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .arg("--array-merge")
+        .arg("concat")
+        .assert()
+        .success()
+        .stdout("4: goodbye, thanks\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .arg("--array-merge")
+        .arg("merge-by-key=id")
+        .assert()
+        .success()
+        .stdout("3: see you, thanks\n");
+}
+
+/// データマージ: --merge-strategy shallowによるトップレベルの置き換え
+#[test]
+fn test_shallow_merge_strategy() {
+    let dir = tempdir().unwrap();
+
+    let data1 = dir.path().join("base.yaml");
+    fs::write(&data1, "model:\n  name: base\n  temperature: 0.2\nlang: ja").unwrap();
+
+    let data2 = dir.path().join("override.yaml");
+    fs::write(&data2, "model:\n  name: custom").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ model.name }}/{{ model.temperature }}/{{ lang }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .assert()
+        .success()
+        .stdout("custom/0.2/ja\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .arg("--merge-strategy")
+        .arg("shallow")
+        .assert()
+        .success()
+        .stdout("custom//ja\n");
+}
+
+/// データマージ: --null-deletesによるキーの削除
+#[test]
+fn test_null_deletes() {
+    let dir = tempdir().unwrap();
+
+    let data1 = dir.path().join("base.yaml");
+    fs::write(&data1, "name: Alice\nsystem_note: internal").unwrap();
+
+    let data2 = dir.path().join("public.yaml");
+    fs::write(&data2, "system_note: null").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ name }}[{{ system_note }}]").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .arg("--strict")
+        .assert()
+        .success()
+        .stdout("Alice[]\n");
+
+    // 削除されたキーはstrictモードで未定義エラーになる
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data1)
+        .arg("-d")
+        .arg(&data2)
+        .arg("--strict")
+        .arg("--null-deletes")
+        .assert()
+        .failure()
+        .code(6);
+}
+
 // ドット付きキー名（"key.with.dots"）は、変数置換のドットパス記法
 // （{{ key.with.dots }}）と矛盾するため、正しく動作しない。
 // {{ key.with.dots }} は "key" → "with" → "dots" というネストされた
@@ -1,4 +1,4 @@
-use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin_cmd;
 use predicates::prelude::*;
 use std::fs;
 use tempfile::tempdir;
@@ -15,8 +15,7 @@ fn test_very_long_variable_name() {
     let template = dir.path().join("template.txt");
     fs::write(&template, format!("{{{{ {} }}}}", long_name)).unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -38,8 +37,7 @@ fn test_very_long_variable_value() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ var }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -60,8 +58,7 @@ fn test_whitespace_only_variable() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{    }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -81,8 +78,7 @@ fn test_dot_only_path() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ . }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -102,8 +98,7 @@ fn test_consecutive_dots() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ a..b }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -123,8 +118,7 @@ fn test_array_out_of_bounds() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ items.10 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -145,14 +139,14 @@ fn test_negative_array_index() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ items.-1 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
         .arg(&data)
         .assert()
-        .success();
+        .success()
+        .stdout("one\n"); // 末尾から数える
 }
 
 /// エッジケース: 改行を含む変数値
@@ -166,8 +160,7 @@ fn test_multiline_variable_value() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ text }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -189,8 +182,7 @@ fn test_tab_characters() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ text }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -211,8 +203,7 @@ fn test_braces_in_value() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ text }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -230,8 +221,7 @@ fn test_consecutive_escapes() {
     let template = dir.path().join("template.txt");
     fs::write(&template, r"\{{ var1 }} \{{ var2 }} \{{ var3 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -250,8 +240,7 @@ fn test_mixed_escape_and_variables() {
     let template = dir.path().join("template.txt");
     fs::write(&template, r"{{ real }} and \{{ fake }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -276,8 +265,7 @@ fn test_deeply_nested_includes() {
         }
     }
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(dir.path().join("level0.txt"))
         .assert()
@@ -295,13 +283,16 @@ fn test_same_file_multiple_includes() {
     fs::write(&partial, "{{ value }}").unwrap();
 
     let template = dir.path().join("template.txt");
-    fs::write(&template, "{{> partial.txt }}, {{> partial.txt }}, {{> partial.txt }}").unwrap();
+    fs::write(
+        &template,
+        "{{> partial.txt }}, {{> partial.txt }}, {{> partial.txt }}",
+    )
+    .unwrap();
 
     let data = dir.path().join("data.yaml");
     fs::write(&data, "value: X").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -322,8 +313,7 @@ fn test_empty_include_file() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Before{{> empty.txt }}After").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -342,8 +332,7 @@ fn test_include_path_with_spaces() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> file with spaces.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -365,10 +354,13 @@ fn test_large_data_file() {
     fs::write(&data, data_content).unwrap();
 
     let template = dir.path().join("template.txt");
-    fs::write(&template, "{{ vars.var0 }}, {{ vars.var500 }}, {{ vars.var999 }}").unwrap();
+    fs::write(
+        &template,
+        "{{ vars.var0 }}, {{ vars.var500 }}, {{ vars.var999 }}",
+    )
+    .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -393,8 +385,7 @@ fn test_deeply_nested_object() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ a.b.c.d.e.f.g }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -410,7 +401,11 @@ fn test_null_values() {
     let dir = tempdir().unwrap();
 
     let data = dir.path().join("data.yaml");
-    fs::write(&data, "nullval: null\nemptyval: \"\"\nzero: 0\nfalse: false").unwrap();
+    fs::write(
+        &data,
+        "nullval: null\nemptyval: \"\"\nzero: 0\nfalse: false",
+    )
+    .unwrap();
 
     let template = dir.path().join("template.txt");
     fs::write(
@@ -419,8 +414,7 @@ fn test_null_values() {
     )
     .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -430,11 +424,6 @@ fn test_null_values() {
         .stdout("[][][0][false]\n");
 }
 
-// YAMLのアンカーとエイリアス（<<: *anchor）はYAML 1.1の機能で、
-// YAML 1.2では非推奨。serde_yaml 0.9はYAML 1.2ベースのため、
-// マージキーは正しくサポートされない。これはライブラリの制限。
-// （data_merge_test.rsのコメントも参照）
-
 /// エッジケース: 数値のみの変数名
 #[test]
 fn test_numeric_key_names() {
@@ -446,8 +435,7 @@ fn test_numeric_key_names() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ 123 }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -468,8 +456,7 @@ fn test_floating_point_numbers() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ pi }}, {{ large }}, {{ small }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -490,8 +477,7 @@ fn test_large_numbers() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ big }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -512,8 +498,7 @@ fn test_base64_data() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ binary }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -531,8 +516,7 @@ fn test_mixed_line_endings() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Line1\nLine2\rLine3\r\nLine4").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -549,8 +533,7 @@ fn test_utf8_bom() {
     content.extend_from_slice(b"Test");
     fs::write(&template, content).unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -568,8 +551,7 @@ fn test_emoji_sequences() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ emoji }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -1,4 +1,4 @@
-use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin_cmd;
 use predicates::prelude::*;
 use std::fs;
 use tempfile::tempdir;
@@ -14,8 +14,7 @@ fn test_basic_execution() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Hello, {{ name }}! Age: {{ age }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("--template")
         .arg(&template)
         .arg("--data")
@@ -43,8 +42,7 @@ fn test_multiple_data_files_merge() {
     )
     .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -69,8 +67,7 @@ fn test_output_file() {
 
     let output = dir.path().join("output.txt");
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -95,8 +92,7 @@ fn test_strict_mode_undefined_variable() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ name }} {{ undefined }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -119,8 +115,7 @@ fn test_non_strict_mode_undefined_variable() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Hello {{ name }}{{ undefined }}!").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -144,8 +139,7 @@ fn test_include_basic() {
     let data = dir.path().join("data.yaml");
     fs::write(&data, "value: 42").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -175,8 +169,7 @@ fn test_nested_includes() {
     let data = dir.path().join("data.yaml");
     fs::write(&data, "var1: A\nvar2: B\nvar3: C").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -197,8 +190,7 @@ fn test_circular_include_error() {
     fs::write(&file_a, "A {{> b.txt }}").unwrap();
     fs::write(&file_b, "B {{> a.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&file_a)
         .assert()
@@ -224,8 +216,7 @@ fn test_depth_limit_exceeded() {
 
     let template = dir.path().join("0.txt");
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--max-include-depth")
@@ -239,8 +230,7 @@ fn test_depth_limit_exceeded() {
 /// CLI統合テスト: 存在しないテンプレートファイル
 #[test]
 fn test_nonexistent_template() {
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg("/nonexistent/template.txt")
         .assert()
@@ -255,8 +245,7 @@ fn test_nonexistent_data_file() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Hello").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -277,8 +266,7 @@ fn test_invalid_yaml() {
     let data = dir.path().join("data.yaml");
     fs::write(&data, "invalid: yaml: syntax: error:").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -299,8 +287,7 @@ fn test_invalid_json() {
     let data = dir.path().join("data.json");
     fs::write(&data, "{invalid json}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -321,8 +308,7 @@ fn test_escape_syntax() {
     let data = dir.path().join("data.yaml");
     fs::write(&data, "var: Hello").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -367,8 +353,7 @@ Location: {{ company.location.city }}, {{ company.location.country }}"#,
     )
     .unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -399,8 +384,7 @@ fn test_json_data_format() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ user.name }}, {{ user.age }}, {{ active }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -424,8 +408,7 @@ fn test_mixed_yaml_and_json() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ from_yaml }} and {{ from_json }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -448,8 +431,7 @@ fn test_empty_data_file() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "No data: {{ missing }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -467,8 +449,7 @@ fn test_no_data_file() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "No variables").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -484,8 +465,7 @@ fn test_empty_template() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -518,8 +498,7 @@ fn test_many_variables() {
     }
     expected.push('\n');
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -545,8 +524,7 @@ fn test_root_option() {
     let data = dir.path().join("data.yaml");
     fs::write(&data, "value: 123").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -573,8 +551,7 @@ fn test_special_characters() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ special }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -594,8 +571,7 @@ fn test_unicode_characters() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ message }} {{ emoji }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -616,8 +592,7 @@ fn test_object_as_json() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Object: {{ obj }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -639,8 +614,7 @@ fn test_array_as_json() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Array: {{ items }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -654,8 +628,7 @@ fn test_array_as_json() {
 /// CLI統合テスト: バージョン表示
 #[test]
 fn test_version_flag() {
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("--version")
         .assert()
         .success()
@@ -665,8 +638,7 @@ fn test_version_flag() {
 /// CLI統合テスト: ヘルプ表示
 #[test]
 fn test_help_flag() {
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("--help")
         .assert()
         .success()
@@ -677,11 +649,7 @@ fn test_help_flag() {
 /// CLI統合テスト: 引数不足
 #[test]
 fn test_missing_required_args() {
-    Command::cargo_bin("rp")
-        .unwrap()
-        .assert()
-        .failure()
-        .code(2); // EXIT_USAGE_ERROR
+    cargo_bin_cmd!("rp").assert().failure().code(2); // EXIT_USAGE_ERROR
 }
 
 /// CLI統合テスト: max-include-depthの検証
@@ -691,8 +659,7 @@ fn test_invalid_max_depth_zero() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "test").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--max-include-depth")
@@ -709,8 +676,7 @@ fn test_invalid_max_depth_too_large() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "test").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--max-include-depth")
@@ -719,3 +685,2882 @@ fn test_invalid_max_depth_too_large() {
         .failure()
         .code(2);
 }
+
+/// CLI統合テスト: rawブロックはインクルードも変数置換もされない
+#[test]
+fn test_raw_block() {
+    let dir = tempdir().unwrap();
+
+    let example = dir.path().join("example.txt");
+    fs::write(&example, "{{#raw}}{{> header.txt }} {{ name }}{{/raw}}").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Example: {{> example.txt }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .success()
+        .stdout("Example: {{> header.txt }} {{ name }}\n");
+}
+
+/// CLI統合テスト: dateフィルターとnowヘルパーの無効化
+#[test]
+fn test_date_filter_and_no_now() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "released: 2024-03-05").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        r#"{{ released | date("%d/%m/%Y") }} [{{ now }}]"#,
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--no-now")
+        .assert()
+        .success()
+        .stdout("05/03/2024 []\n");
+}
+
+/// CLI統合テスト: --escape-htmlで置換値のみエスケープ
+#[test]
+fn test_escape_html_option() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "comment: \"<script>alert('x')</script>\"").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "<div>{{ comment }}</div>").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--escape-html")
+        .assert()
+        .success()
+        .stdout("<div>&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;</div>\n");
+}
+
+/// CLI統合テスト: 条件分岐と比較・論理演算子
+#[test]
+fn test_if_block_with_operators() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "score: 0.85\nlang: en").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        r#"{{#if score >= 0.8 && lang == "en"}}High{{else}}Low{{/if}}"#,
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("High\n");
+}
+
+/// CLI統合テスト: 空白を含むキーの参照と、不正な式の構文エラー
+#[test]
+fn test_key_with_spaces_and_invalid_expression() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "first name: B\nscore: 1").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ first name }}").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("B\n");
+
+    // 式として読めないタグは終了コード3
+    fs::write(&template, "{{ score + }}").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("invalid expression"));
+}
+
+/// CLI統合テスト: setで定義したローカル変数を再利用
+#[test]
+fn test_set_local_variable() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "user:\n  name: Alice").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        r#"{{set greeting = "Hello, " ~ user.name}}{{ greeting }} / {{ greeting | upper }}"#,
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("Hello, Alice / HELLO, ALICE\n");
+}
+
+/// CLI統合テスト: 引数付きインクルード
+#[test]
+fn test_include_with_params() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("card.txt"), "[{{ title }}: {{ n }}]").unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "title: Data\ncount: 7").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{> card.txt title=\"Intro\" n=3 }}{{> card.txt title=\"Outro\" n=count }} {{ title }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("[Intro: 3][Outro: 7] Data\n");
+}
+
+/// CLI統合テスト: コンテキストを指定したインクルード
+#[test]
+fn test_include_with_context() {
+    let dir = tempdir().unwrap();
+
+    fs::write(
+        dir.path().join("user_card.txt"),
+        "{{ name }} ({{ role }}) @ {{ ../team }}",
+    )
+    .unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(
+        &data,
+        "team: core\nusers:\n  - name: Alice\n    role: dev\n  - name: Bob\n    role: ops",
+    )
+    .unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{> user_card.txt users.0 }} / {{> user_card.txt users.1 }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("Alice (dev) @ core / Bob (ops) @ core\n");
+}
+
+/// CLI統合テスト: 存在しないファイルの任意インクルードは空になる
+#[test]
+fn test_optional_include() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "before[{{>? local.txt }}]after").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .success()
+        .stdout("before[]after\n");
+
+    fs::write(dir.path().join("local.txt"), "LOCAL").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .success()
+        .stdout("before[LOCAL]after\n");
+}
+
+/// CLI統合テスト: globパターンのインクルードはソート順に連結される
+#[test]
+fn test_glob_include() {
+    let dir = tempdir().unwrap();
+
+    let sections = dir.path().join("sections");
+    fs::create_dir(&sections).unwrap();
+    fs::write(sections.join("02-rules.md"), "Rules for {{ name }}\n").unwrap();
+    fs::write(sections.join("01-intro.md"), "Intro\n").unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "name: Bot").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> sections/*.md }}-- end").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("Intro\nRules for Bot\n-- end\n");
+}
+
+/// CLI統合テスト: rawインクルードは内容をそのまま挿入する
+#[test]
+fn test_raw_include() {
+    let dir = tempdir().unwrap();
+
+    fs::write(
+        dir.path().join("example.txt"),
+        "Hello, {{ name }}! {{> missing.txt }} \\{{ x }}",
+    )
+    .unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "name: Alice").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ name }}: {{>raw example.txt }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("Alice: Hello, {{ name }}! {{> missing.txt }} \\{{ x }}\n");
+}
+
+/// CLI統合テスト: データから計算したパスのインクルード
+#[test]
+fn test_dynamic_include() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("claude.txt"), "Hi {{ name }} from Claude").unwrap();
+    fs::write(dir.path().join("gpt.txt"), "Hi {{ name }} from GPT").unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(
+        &data,
+        "name: Alice\nmodel: gpt\npartials:\n  claude: claude.txt\n  gpt: gpt.txt",
+    )
+    .unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> (partials[model]) }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("Hi Alice from GPT\n");
+}
+
+/// CLI統合テスト: ファイル内フラグメントの定義と挿入
+#[test]
+fn test_fragment() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "tone: polite").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{#fragment rules}}Be {{ tone }}.{{/fragment}}A: {{fragment rules}}\nB: {{fragment rules}}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("A: Be polite.\nB: Be polite.\n");
+}
+
+/// CLI統合テスト: CSVデータファイルは行オブジェクトの配列になる
+#[test]
+fn test_csv_data_file() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("cases.csv");
+    fs::write(&data, "input,expected\n2+2,4\n\"Hello, world\",greeting\n").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ 0.input }} => {{ 0.expected }}; {{ -1.input }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("2+2 => 4; Hello, world\n");
+}
+
+/// CLI統合テスト: TSVと区切り文字を指定したCSV
+#[test]
+fn test_tsv_and_csv_delimiter() {
+    let dir = tempdir().unwrap();
+
+    let tsv = dir.path().join("rows.tsv");
+    fs::write(&tsv, "name\tscore\nAnn\t1,5\n").unwrap();
+
+    let csv = dir.path().join("rows.csv");
+    fs::write(&csv, "name;score\nBob;2,5\n").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ 0.name }}={{ 0.score }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&tsv)
+        .assert()
+        .success()
+        .stdout("Ann=1,5\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&csv)
+        .arg("--csv-delimiter")
+        .arg(";")
+        .assert()
+        .success()
+        .stdout("Bob=2,5\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&csv)
+        .arg("--csv-delimiter")
+        .arg(";;")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: JSONLデータファイルはキー配下の配列になる
+#[test]
+fn test_jsonl_data_file() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("cases.jsonl");
+    fs::write(
+        &data,
+        "{\"q\": \"2+2\", \"a\": 4}\n{\"q\": \"capital of France\", \"a\": \"Paris\"}\n",
+    )
+    .unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ records.1.q }}: {{ records.1.a }} ({{ records | length }})",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("capital of France: Paris (2)\n");
+
+    fs::write(&template, "{{ cases.0.a }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--jsonl-key")
+        .arg("cases")
+        .assert()
+        .success()
+        .stdout("4\n");
+}
+
+/// CLI統合テスト: -t - による標準入力からのテンプレート読み込み
+#[test]
+fn test_template_from_stdin() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "name: World").unwrap();
+    fs::write(dir.path().join("footer.txt"), "-- {{ name }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg("-")
+        .arg("-d")
+        .arg(&data)
+        .write_stdin("Hello, {{ name }}!")
+        .assert()
+        .success()
+        .stdout("Hello, World!\n");
+
+    // インクルードには--rootが必要
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg("-")
+        .arg("-d")
+        .arg(&data)
+        .write_stdin("{{> footer.txt }}")
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("--root"));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg("-")
+        .arg("-d")
+        .arg(&data)
+        .arg("--root")
+        .arg(dir.path())
+        .write_stdin("{{> footer.txt }}")
+        .assert()
+        .success()
+        .stdout("-- World\n");
+}
+
+/// CLI統合テスト: --data-execによるコマンド出力のデータ化
+#[cfg(unix)]
+#[test]
+fn test_data_exec() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "cluster: dev\nreplicas: 1").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ cluster }}: {{ replicas }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--data-exec")
+        .arg(r#"echo '{"replicas": 3}'"#)
+        .assert()
+        .success()
+        .stdout("dev: 3\n");
+
+    // 失敗したコマンドはデータエラー（終了コード4）
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--data-exec")
+        .arg("exit 1")
+        .assert()
+        .failure()
+        .code(4);
+}
+
+/// CLI統合テスト: --data-formatによる形式の指定
+#[test]
+fn test_data_format_override() {
+    let dir = tempdir().unwrap();
+
+    // 拡張子と中身が一致しないファイル
+    let data = dir.path().join("export.json");
+    fs::write(&data, "name: Alice\nrole: admin").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ name }} ({{ role }})").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .failure()
+        .code(4);
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--data-format")
+        .arg("yaml")
+        .assert()
+        .success()
+        .stdout("Alice (admin)\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--data-format")
+        .arg("toml")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: フロントマターによるデフォルト値
+#[test]
+fn test_front_matter() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("prompt.txt");
+    fs::write(
+        &template,
+        "---\ntone: friendly\nlanguage: English\n---\nAnswer in a {{ tone }} tone, in {{ language }}.",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .success()
+        .stdout("Answer in a friendly tone, in English.\n");
+
+    // データファイルと--setはフロントマターより優先される
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "language: Japanese").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--set")
+        .arg("tone=formal")
+        .assert()
+        .success()
+        .stdout("Answer in a formal tone, in Japanese.\n");
+}
+
+/// CLI統合テスト: --setによる値の上書き
+#[test]
+fn test_set_override() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "user:\n  name: Bob\n  role: admin").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ user.name }} ({{ user.role }}) {{ model }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--set")
+        .arg("user.name=Alice")
+        .arg("--set")
+        .arg("model=gpt-4o")
+        .assert()
+        .success()
+        .stdout("Alice (admin) gpt-4o\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("no-equals-sign")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --set-jsonによる構造化データの上書き
+#[test]
+fn test_set_json_override() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "limits:\n  tokens: 100\n  temperature: 0.2").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ tags | join(\",\") }} {{ limits.tokens }} {{ limits.temperature }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--set-json")
+        .arg(r#"tags=["a","b"]"#)
+        .arg("--set-json")
+        .arg(r#"limits={"tokens": 1000}"#)
+        .assert()
+        .success()
+        .stdout("a,b 1000 0.2\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set-json")
+        .arg("tags=[unquoted]")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: データディレクトリはファイル名をキーとして読み込まれる
+#[test]
+fn test_data_directory() {
+    let dir = tempdir().unwrap();
+
+    let context = dir.path().join("context");
+    fs::create_dir(&context).unwrap();
+    fs::write(context.join("users.yaml"), "- name: Ann\n- name: Bob").unwrap();
+    fs::write(context.join("settings.json"), r#"{"model": "gpt-4o"}"#).unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{ users.1.name }} uses {{ settings.model }} ({{ extra }})",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&context)
+        .arg("--set")
+        .arg("extra=ok")
+        .assert()
+        .success()
+        .stdout("Bob uses gpt-4o (ok)\n");
+}
+
+/// CLI統合テスト: -dのglobパターンは辞書順にマージされる
+#[test]
+fn test_data_glob() {
+    let dir = tempdir().unwrap();
+
+    let configs = dir.path().join("configs");
+    fs::create_dir(&configs).unwrap();
+    fs::write(configs.join("10-base.yaml"), "env: dev\nmodel: small").unwrap();
+    fs::write(configs.join("20-prod.yaml"), "env: prod").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ env }}/{{ model }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(configs.join("*.yaml"))
+        .assert()
+        .success()
+        .stdout("prod/small\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(configs.join("*.json"))
+        .assert()
+        .failure()
+        .code(4);
+}
+
+/// CLI統合テスト: --schemaによるデータ検証
+#[test]
+fn test_schema_validation() {
+    let dir = tempdir().unwrap();
+
+    let schema = dir.path().join("schema.json");
+    fs::write(
+        &schema,
+        r#"{"type": "object", "required": ["name"], "properties": {"age": {"type": "integer"}}}"#,
+    )
+    .unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "age: old").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ name }} ({{ age }})").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--schema")
+        .arg(&schema)
+        .assert()
+        .failure()
+        .code(4)
+        .stdout("")
+        .stderr(predicate::str::contains("code=SCHEMA_VIOLATION"))
+        .stderr(predicate::str::contains("\"name\" is a required property"))
+        .stderr(predicate::str::contains(
+            "/age: \"old\" is not of type \"integer\"",
+        ));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--schema")
+        .arg(&schema)
+        .arg("--set")
+        .arg("name=Ann")
+        .arg("--set-json")
+        .arg("age=3")
+        .assert()
+        .success()
+        .stdout("Ann (3)\n");
+}
+
+/// CLI統合テスト: テンプレート化した出力パスによるレコードごとのファイル出力
+#[test]
+fn test_templated_output_path() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "# {{ title }}\nby {{ author }}").unwrap();
+    let data = dir.path().join("posts.csv");
+    fs::write(
+        &data,
+        "slug,title,author,lang\nhello,Hello,Alice,en\nbye,Goodbye,Bob,en\n",
+    )
+    .unwrap();
+
+    // 出力先のディレクトリは自動で作成される
+    let pattern = dir.path().join("out/{{ slug }}.md");
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-o")
+        .arg(&pattern)
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/hello.md")).unwrap(),
+        "# Hello\nby Alice"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/bye.md")).unwrap(),
+        "# Goodbye\nby Bob"
+    );
+
+    // 同じパスになるレコードや未定義の変数があれば何も書き込まずにエラー
+    for (pattern, message) in [
+        ("dup/{{ lang }}.md", "records 0 and 1 both render"),
+        ("dup/{{ missing }}.md", "cannot render output path"),
+    ] {
+        cargo_bin_cmd!("rp")
+            .arg("-t")
+            .arg(&template)
+            .arg("-d")
+            .arg(&data)
+            .arg("-o")
+            .arg(dir.path().join(pattern))
+            .assert()
+            .failure()
+            .code(2)
+            .stderr(predicate::str::contains(message));
+    }
+    assert!(!dir.path().join("dup").exists());
+}
+
+/// CLI統合テスト: CSVデータと--setの併用では各レコードに値を設定する
+#[test]
+fn test_csv_data_with_set() {
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("rows.csv");
+    fs::write(&data, "name,lang\nAlice,en\nBob,ja\n").unwrap();
+
+    // レコードの配列は置き換えられない
+    let template = dir.path().join("all.txt");
+    fs::write(&template, "{{ 0.name }} {{ 1.name }} {{ 1.tone }}").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--set")
+        .arg("tone=formal")
+        .arg("--strict")
+        .assert()
+        .success()
+        .stdout("Alice Bob formal\n");
+
+    // テンプレート化した出力パスでも各レコードに値が設定される
+    let template = dir.path().join("record.txt");
+    fs::write(&template, "{{ name }} ({{ tone }})").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-o")
+        .arg(dir.path().join("out/{{ name }}.txt"))
+        .arg("--set")
+        .arg("tone=formal")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/Bob.txt")).unwrap(),
+        "Bob (formal)"
+    );
+
+    // オブジェクトでないレコードにはマージできない
+    let data = dir.path().join("list.json");
+    fs::write(&data, "[1, 2]").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--set")
+        .arg("tone=formal")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("array of non-object values"));
+}
+
+/// CLI統合テスト: --dry-runによる読み書きするファイルの報告
+#[test]
+fn test_dry_run() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("header.txt"), "# {{ title }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> header.txt }}\n{{ body }}").unwrap();
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "title: Hello\nbody: World").unwrap();
+    let output = dir.path().join("out/result.txt");
+
+    let expected = format!(
+        "read: {}\nread: {}\nread: {}\nwrite: {}\n",
+        template.display(),
+        data.display(),
+        dir.path().join("header.txt").display(),
+        output.display(),
+    );
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-o")
+        .arg(&output)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(expected);
+    assert!(!output.exists());
+
+    // レンダリングも行うため、未定義変数はエラーになる
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--dry-run")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("title"));
+}
+
+/// CLI統合テスト: --output-dirによるディレクトリ構造を保った出力
+#[test]
+fn test_output_dir_mirrors_structure() {
+    let dir = tempdir().unwrap();
+
+    let prompts = dir.path().join("prompts");
+    fs::create_dir_all(prompts.join("a/nested")).unwrap();
+    fs::write(prompts.join("top.txt"), "top {{ name }}").unwrap();
+    fs::write(prompts.join("a/b.txt"), "b {{ name }}").unwrap();
+    fs::write(prompts.join("a/nested/c.txt"), "c {{ name }}").unwrap();
+    fs::write(prompts.join("a/ignored.md"), "ignored").unwrap();
+
+    let dist = dir.path().join("dist");
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(prompts.join("**/*.txt"))
+        .arg("--set")
+        .arg("name=Claude")
+        .arg("--output-dir")
+        .arg(&dist)
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(dist.join("top.txt")).unwrap(),
+        "top Claude"
+    );
+    assert_eq!(
+        fs::read_to_string(dist.join("a/b.txt")).unwrap(),
+        "b Claude"
+    );
+    assert_eq!(
+        fs::read_to_string(dist.join("a/nested/c.txt")).unwrap(),
+        "c Claude"
+    );
+    assert!(!dist.join("a/ignored.md").exists());
+
+    // 単一のファイルはそのファイル名で出力される
+    let single = dir.path().join("single");
+    cargo_bin_cmd!("rp")
+        .arg("render")
+        .arg("-t")
+        .arg(prompts.join("a/b.txt"))
+        .arg("--set")
+        .arg("name=Claude")
+        .arg("--output-dir")
+        .arg(&single)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(single.join("b.txt")).unwrap(),
+        "b Claude"
+    );
+
+    // 一致するテンプレートがなければエラー
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(prompts.join("**/*.tmpl"))
+        .arg("--output-dir")
+        .arg(&dist)
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("no template files match"));
+}
+
+/// CLI統合テスト: --print-depsによる依存ファイルの出力
+#[test]
+fn test_print_deps() {
+    let dir = tempdir().unwrap();
+
+    fs::create_dir(dir.path().join("partials")).unwrap();
+    fs::write(
+        dir.path().join("partials/header.txt"),
+        "{{> logo.txt }} {{ title }}",
+    )
+    .unwrap();
+    fs::write(dir.path().join("partials/logo.txt"), "*").unwrap();
+    fs::write(dir.path().join("footer.txt"), "bye").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{> partials/header.txt }}\n{{ body }}\n{{> footer.txt }}",
+    )
+    .unwrap();
+
+    // レンダリングはしないため、データがなくてもstrictモードで成功する
+    let expected = format!(
+        "{}\n  {}\n    {}\n  {}\n",
+        template.display(),
+        dir.path().join("partials/header.txt").display(),
+        dir.path().join("partials/logo.txt").display(),
+        dir.path().join("footer.txt").display(),
+    );
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--print-deps")
+        .assert()
+        .success()
+        .stdout(expected);
+}
+
+/// CLI統合テスト: --list-varsによる参照変数の一覧
+#[test]
+fn test_list_vars() {
+    let dir = tempdir().unwrap();
+
+    fs::write(
+        dir.path().join("header.txt"),
+        "{{ title }}{{#with user}}{{ name }}{{/with}}",
+    )
+    .unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{> header.txt }}\n{{set n = items | length}}{{ n }} {{ now | date(\"%Y\") }} {{ title }}",
+    )
+    .unwrap();
+
+    // データがなくてもstrictモードで成功し、ローカル変数と`now`は含まれない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--list-vars")
+        .assert()
+        .success()
+        .stdout("items\ntitle\nuser\nuser.name\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--list-vars=json")
+        .assert()
+        .success()
+        .stdout("[\n  \"items\",\n  \"title\",\n  \"user\",\n  \"user.name\"\n]\n");
+}
+
+/// CLI統合テスト: サブコマンド（render / check / deps / vars）
+#[test]
+fn test_subcommands() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("header.txt"), "# {{ title }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> header.txt }}\n{{ body }}").unwrap();
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "title: Hello\nbody: World").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("render")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--strict")
+        .assert()
+        .success()
+        .stdout("# Hello\nWorld\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("check")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("no problems found"));
+
+    cargo_bin_cmd!("rp")
+        .arg("deps")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n  {}\n",
+            template.display(),
+            dir.path().join("header.txt").display()
+        ));
+
+    cargo_bin_cmd!("rp")
+        .arg("vars")
+        .arg("-t")
+        .arg(&template)
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout("[\n  \"body\",\n  \"title\"\n]\n");
+
+    // レンダリング専用のオプションは他のサブコマンドでは使えない
+    cargo_bin_cmd!("rp")
+        .arg("check")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --verbose / --quiet によるログ出力の切り替え
+#[test]
+fn test_verbose_and_quiet() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("header.txt"), "# {{ title }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> header.txt }}\n{{ missing }}").unwrap();
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "title: Hello").unwrap();
+
+    // -v: マージしたデータファイル、解決したインクルード、所要時間を表示
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-v")
+        .assert()
+        .success()
+        .stdout("# Hello\n\n")
+        .stderr(predicate::str::contains(format!(
+            "info: merged data file {}",
+            data.display()
+        )))
+        .stderr(predicate::str::contains("info: resolved include"))
+        .stderr(predicate::str::contains("header.txt depth=1"))
+        .stderr(predicate::str::contains("elapsed="));
+
+    // デフォルトでは警告のみ、--quietでは警告も表示しない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--warn-undefined")
+        .assert()
+        .success()
+        .stderr(format!(
+            "Warning: undefined variable 'missing' at {}:2:1\n",
+            template.display()
+        ));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--warn-undefined")
+        .arg("--quiet")
+        .assert()
+        .success()
+        .stderr("");
+}
+
+/// CLI統合テスト: --error-format jsonによるJSON形式のエラー・警告
+#[test]
+fn test_error_format_json() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello\n  {{ missing }}").unwrap();
+
+    let output = cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .failure()
+        .code(6)
+        .get_output()
+        .clone();
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["severity"], "error");
+    assert_eq!(error["code"], "UNDEFINED_VAR");
+    assert_eq!(error["line"], 2);
+    assert_eq!(error["column"], 3);
+    assert!(error["message"].as_str().unwrap().contains("missing"));
+
+    // 警告も同じフィールドを持つJSONとして出力される
+    let output = cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--warn-undefined")
+        .arg("--error-format=json")
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    let warning: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(warning["severity"], "warning");
+    assert_eq!(warning["code"], "UNDEFINED_VAR");
+    assert_eq!(warning["line"], 2);
+
+    // ファイル単位のエラーは行・列がnull
+    let output = cargo_bin_cmd!("rp")
+        .arg("check")
+        .arg("-t")
+        .arg(dir.path().join("missing.txt"))
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .failure()
+        .code(3)
+        .get_output()
+        .clone();
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["code"], "TEMPLATE_READ_ERROR");
+    assert!(error["file"].as_str().unwrap().ends_with("missing.txt"));
+    assert!(error["line"].is_null());
+}
+
+/// CLI統合テスト: --warn-as-errorによる警告でのエラー終了
+#[test]
+fn test_warn_as_error() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ a }} {{ b }} {{#if c}}c{{/if}}").unwrap();
+    let output = dir.path().join("output.txt");
+
+    // すべての警告を報告してから終了コード6で終了し、出力は書き込まない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--warn-as-error")
+        .assert()
+        .failure()
+        .code(6)
+        .stderr(predicate::str::contains("Warning: undefined variable 'a'"))
+        .stderr(predicate::str::contains("Warning: undefined variable 'b'"))
+        .stderr(predicate::str::contains("2 warning(s) treated as errors"));
+    assert!(!output.exists());
+
+    // --quietで警告を隠しても失敗する
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--warn-as-error")
+        .arg("--quiet")
+        .assert()
+        .failure()
+        .code(6)
+        .stderr(predicate::str::contains("Warning").not());
+
+    // 警告がなければ成功（条件の未定義変数は警告にならない）
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("a=1")
+        .arg("--set")
+        .arg("b=2")
+        .arg("--warn-as-error")
+        .assert()
+        .success()
+        .stdout("1 2 \n");
+}
+
+/// CLI統合テスト: --check-outputによる出力ファイルの差分検出
+#[test]
+fn test_check_output() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!\n").unwrap();
+    let output = dir.path().join("output.txt");
+    fs::write(&output, "Hello, Alice!\n").unwrap();
+
+    // 出力が最新なら成功し、ファイルは変更しない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--check-output")
+        .assert()
+        .success();
+
+    // 異なれば差分を出力して終了コード1で終了し、ファイルは書き換えない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--set")
+        .arg("name=Bob")
+        .arg("--check-output")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains("-Hello, Alice!"))
+        .stdout(predicate::str::contains("+Hello, Bob!"))
+        .stderr(predicate::str::contains("Output is out of date"));
+    assert_eq!(fs::read_to_string(&output).unwrap(), "Hello, Alice!\n");
+
+    // 出力ファイルがなければ最新ではない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(dir.path().join("missing.txt"))
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--check-output")
+        .assert()
+        .failure()
+        .code(1);
+    assert!(!dir.path().join("missing.txt").exists());
+
+    // -oまたは--output-dirが必要
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--check-output")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --no-trailing-newlineによるバイト単位で正確な出力
+#[test]
+fn test_no_trailing_newline() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+
+    // デフォルトでは改行が追加される
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .assert()
+        .success()
+        .stdout("Hello, Alice!\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--no-trailing-newline")
+        .assert()
+        .success()
+        .stdout("Hello, Alice!");
+
+    // テンプレート自体の末尾の改行はそのまま出力される
+    fs::write(&template, "Hello, {{ name }}!\n").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--no-trailing-newline")
+        .assert()
+        .success()
+        .stdout("Hello, Alice!\n");
+}
+
+/// CLI統合テスト: --newlineによる改行コードの統一
+#[test]
+fn test_newline_normalization() {
+    let dir = tempdir().unwrap();
+
+    // 改行コードが混在したテンプレートとインクルード
+    fs::write(dir.path().join("part.txt"), "b\r\nc").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "a\n{{> part.txt }}\r\n").unwrap();
+    let output = dir.path().join("output.txt");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--newline")
+        .arg("crlf")
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "a\r\nb\r\nc\r\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--newline")
+        .arg("lf")
+        .assert()
+        .success()
+        .stdout("a\nb\nc\n\n");
+
+    // デフォルト（preserve）ではそのまま出力する
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--no-trailing-newline")
+        .assert()
+        .success()
+        .stdout("a\nb\r\nc\r\n");
+}
+
+/// CLI統合テスト: --missingによる未定義変数の出力方法の指定
+#[test]
+fn test_missing_policy() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}! {{ order.id }}").unwrap();
+
+    // keepでは未定義変数のタグをそのまま残す
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--missing")
+        .arg("keep")
+        .assert()
+        .success()
+        .stdout("Hello, Alice! {{ order.id }}\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--missing")
+        .arg("warn")
+        .assert()
+        .success()
+        .stdout("Hello, ! \n")
+        .stderr(predicate::str::contains("undefined variable 'name'"));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--missing")
+        .arg("error")
+        .assert()
+        .failure()
+        .code(6);
+
+    // --strictとは同時に指定できない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--missing")
+        .arg("keep")
+        .arg("--strict")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --default-undefinedによる未定義変数のプレースホルダー
+#[test]
+fn test_default_undefined() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}! {{ order.id }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--default-undefined")
+        .arg("<MISSING:{name}>")
+        .assert()
+        .success()
+        .stdout("Hello, Alice! <MISSING:order.id>\n");
+
+    // keepやstrictとは同時に指定できない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--default-undefined")
+        .arg("N/A")
+        .arg("--missing")
+        .arg("keep")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --max-output-sizeによる出力サイズの制限
+#[test]
+fn test_max_output_size() {
+    let dir = tempdir().unwrap();
+
+    // インクルードのたびに出力が倍になるテンプレート
+    fs::write(dir.path().join("a.txt"), "0123456789abcdef").unwrap();
+    fs::write(dir.path().join("b.txt"), "{{> a.txt }}{{> a.txt }}").unwrap();
+    fs::write(dir.path().join("c.txt"), "{{> b.txt }}{{> b.txt }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> c.txt }}{{> c.txt }}").unwrap();
+    let output = dir.path().join("output.txt");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--max-output-size")
+        .arg("100")
+        .assert()
+        .failure()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "exceeds the maximum size of 100 bytes",
+        ));
+    assert!(!output.exists());
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--max-output-size")
+        .arg("1K")
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&output).unwrap().len(), 128);
+
+    // 複数のテンプレートは連結した出力全体で制限する
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(dir.path().join("c.txt"))
+        .arg("-t")
+        .arg(&template)
+        .arg("--max-output-size")
+        .arg("150")
+        .assert()
+        .failure()
+        .code(7)
+        .stdout("");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--max-output-size")
+        .arg("lots")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --timeoutによる処理時間の制限
+#[cfg(unix)]
+#[test]
+fn test_timeout() {
+    use std::time::{Duration, Instant};
+
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+
+    // 終わらないコマンドを待たずに終了コード7で終了する
+    let started = Instant::now();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--data-exec")
+        .arg("sleep 10; echo 'name: Alice'")
+        .arg("--timeout")
+        .arg("300ms")
+        .assert()
+        .failure()
+        .code(7)
+        .stderr(predicate::str::contains("did not finish within 300ms"));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--timeout")
+        .arg("30s")
+        .assert()
+        .success()
+        .stdout("Hello, Alice!\n");
+}
+
+/// CLI統合テスト: 出力ファイルの書き込み中に--timeoutが来ても書き込みを終えてから終了する
+#[cfg(unix)]
+#[test]
+fn test_timeout_while_writing() {
+    use std::io::Read;
+    use std::time::Duration;
+
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+    // 読み手が開くまで書き込みがブロックされるFIFO
+    let fifo = dir.path().join("out.fifo");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rp"))
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("-o")
+        .arg(&fifo)
+        .arg("--append")
+        .arg("--timeout")
+        .arg("200ms")
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // タイムアウトを過ぎても書き込み中は終了しない
+    std::thread::sleep(Duration::from_millis(800));
+    if child.try_wait().unwrap().is_some() {
+        panic!("rp exited while an output file was being written");
+    }
+
+    // 書き込みを最後まで終えてから、終了コード7で終了する
+    let mut written = String::new();
+    fs::File::open(&fifo)
+        .unwrap()
+        .read_to_string(&mut written)
+        .unwrap();
+    assert_eq!(written, "Hello, Alice!");
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("did not finish within 200ms"));
+}
+
+/// CLI統合テスト: rp buildによるマニフェストのジョブの実行
+#[test]
+fn test_build_manifest() {
+    let dir = tempdir().unwrap();
+
+    fs::create_dir(dir.path().join("prompts")).unwrap();
+    fs::write(dir.path().join("prompts/a.txt"), "A: {{ name }}").unwrap();
+    fs::write(dir.path().join("prompts/b.txt"), "B: {{ model }}").unwrap();
+    fs::write(dir.path().join("data.yaml"), "name: Alice").unwrap();
+    let manifest = dir.path().join("render.yaml");
+    fs::write(
+        &manifest,
+        "jobs:\n\
+         \x20 - name: single\n\
+         \x20   template: prompts/a.txt\n\
+         \x20   data: data.yaml\n\
+         \x20   out: dist/a.txt\n\
+         \x20 - template: \"prompts/*.txt\"\n\
+         \x20   output-dir: dist/all\n\
+         \x20   set: { model: 4, name: Bob }\n",
+    )
+    .unwrap();
+
+    // パスはマニフェストからの相対パスで、出力先のディレクトリは作成される
+    cargo_bin_cmd!("rp")
+        .arg("build")
+        .arg("-f")
+        .arg(&manifest)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("dist/a.txt")).unwrap(),
+        "A: Alice"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("dist/all/b.txt")).unwrap(),
+        "B: 4"
+    );
+
+    // --check-outputはすべてのジョブの出力を比較する
+    cargo_bin_cmd!("rp")
+        .arg("build")
+        .arg("-f")
+        .arg(&manifest)
+        .arg("--check-output")
+        .assert()
+        .success();
+    fs::write(dir.path().join("data.yaml"), "name: Carol").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("build")
+        .arg("-f")
+        .arg(&manifest)
+        .arg("--check-output")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains("+A: Carol"));
+
+    // 失敗したジョブがあっても残りのジョブは実行される
+    fs::write(
+        &manifest,
+        "jobs:\n  - template: prompts/a.txt\n    unknown-option: 1\n\
+         \x20 - template: prompts/a.txt\n    data: data.yaml\n    out: dist/c.txt\n",
+    )
+    .unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("build")
+        .arg("-f")
+        .arg(&manifest)
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("job 1 failed"))
+        .stderr(predicate::str::contains("--unknown-option"))
+        .stderr(predicate::str::contains("1 of 2 job(s) failed"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("dist/c.txt")).unwrap(),
+        "A: Carol"
+    );
+}
+
+/// CLI統合テスト: rp serveによるHTTPでのプレビュー配信
+#[test]
+fn test_serve_preview() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::{Duration, Instant};
+
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "name: Alice").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+
+    // 空いているポートを探す
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rp"))
+        .arg("serve")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--port")
+        .arg(port.to_string())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let get = |target: &str| -> Option<String> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).ok()?;
+        Some(response)
+    };
+    let wait_for = |target: &str, expected: &str| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if get(target).is_some_and(|response| response.ends_with(expected)) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    };
+
+    let initial = wait_for("/raw", "\r\n\r\nHello, Alice!");
+    let page = get("/").unwrap_or_default();
+    fs::write(&data, "name: Bob").unwrap();
+    let data_changed = wait_for("/raw", "\r\n\r\nHello, Bob!");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(initial && data_changed);
+    assert!(page.contains("Hello, Alice!"));
+    assert!(page.contains("EventSource"));
+}
+
+/// CLI統合テスト: rp serveは出力先のオプションと併用できない
+#[test]
+fn test_serve_rejects_output_options() {
+    for options in [
+        &["-o", "out.txt"][..],
+        &["--output-dir", "out"],
+        &["--append"],
+        &["--watch"],
+        &["--dry-run"],
+    ] {
+        cargo_bin_cmd!("rp")
+            .arg("serve")
+            .arg("-t")
+            .arg("template.txt")
+            .args(options)
+            .assert()
+            .failure()
+            .code(2)
+            .stderr(predicate::str::contains("`rp serve` cannot be used with"));
+    }
+}
+
+/// CLI統合テスト: --appendによる出力ファイルへの追記
+#[test]
+fn test_append_output() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{ name }}\n").unwrap();
+    let output = dir.path().join("log.txt");
+
+    for name in ["Alice", "Bob"] {
+        cargo_bin_cmd!("rp")
+            .arg("-t")
+            .arg(&template)
+            .arg("--set")
+            .arg(format!("name={}", name))
+            .arg("-o")
+            .arg(&output)
+            .arg("--append")
+            .assert()
+            .success();
+    }
+    assert_eq!(fs::read_to_string(&output).unwrap(), "Alice\nBob\n");
+
+    // 同じパスになるレコードは1つのファイルに追記される
+    let data = dir.path().join("users.csv");
+    fs::write(&data, "name,team\nCarol,a\nDave,b\nErin,a\n").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-o")
+        .arg(dir.path().join("{{ team }}.txt"))
+        .arg("--append")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+        "Carol\nErin\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+        "Dave\n"
+    );
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--append")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: 一時ファイルからのリネームによる出力ファイルの書き込み
+#[cfg(unix)]
+#[test]
+fn test_atomic_output() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+    let output = dir.path().join("output.txt");
+    fs::write(&output, "old content that is longer than the new one").unwrap();
+    fs::set_permissions(&output, fs::Permissions::from_mode(0o640)).unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    // 内容は置き換えられ、パーミッションは保たれ、一時ファイルは残らない
+    assert_eq!(fs::read_to_string(&output).unwrap(), "Hello, Alice!");
+    let mode = fs::metadata(&output).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    let mut files: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["output.txt", "template.txt"]);
+
+    // シンボリックリンクはリンク先に書き込まれる
+    let link = dir.path().join("link.txt");
+    std::os::unix::fs::symlink(&output, &link).unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Bob")
+        .arg("-o")
+        .arg(&link)
+        .assert()
+        .success();
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_to_string(&output).unwrap(), "Hello, Bob!");
+}
+
+/// CLI統合テスト: --only-if-changedによる同一内容の出力ファイルの書き込みの省略
+#[test]
+fn test_only_if_changed() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+    let output = dir.path().join("output.txt");
+    fs::write(&output, "Hello, Alice!").unwrap();
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&output)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+
+    let render = |name: &str| {
+        cargo_bin_cmd!("rp")
+            .arg("-t")
+            .arg(&template)
+            .arg("--set")
+            .arg(format!("name={}", name))
+            .arg("-o")
+            .arg(&output)
+            .arg("--only-if-changed")
+            .assert()
+            .success();
+    };
+
+    // 同じ内容なら書き込まず、更新日時を保つ
+    render("Alice");
+    assert_eq!(fs::metadata(&output).unwrap().modified().unwrap(), old);
+
+    // 内容が変われば書き込む
+    render("Bob");
+    assert_eq!(fs::read_to_string(&output).unwrap(), "Hello, Bob!");
+    assert_ne!(fs::metadata(&output).unwrap().modified().unwrap(), old);
+
+    // --appendとは併用できない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-o")
+        .arg(&output)
+        .arg("--only-if-changed")
+        .arg("--append")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --teeによる出力ファイルと標準出力の両方への出力
+#[test]
+fn test_tee_output() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello, {{ name }}!").unwrap();
+    let output = dir.path().join("output.txt");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("-o")
+        .arg(&output)
+        .arg("--tee")
+        .arg("--no-trailing-newline")
+        .assert()
+        .success()
+        .stdout("Hello, Alice!");
+    assert_eq!(fs::read_to_string(&output).unwrap(), "Hello, Alice!");
+
+    // レコードごとのファイル出力では、書き込んだ順に連結して出力する
+    let data = dir.path().join("data.json");
+    fs::write(&data, r#"[{"name": "a"}, {"name": "b"}]"#).unwrap();
+    fs::write(&template, "[{{ name }}]").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-o")
+        .arg(dir.path().join("out/{{ name }}.txt"))
+        .arg("--tee")
+        .assert()
+        .success()
+        .stdout("[a][b]\n");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/b.txt")).unwrap(),
+        "[b]"
+    );
+
+    // 出力ファイルの指定が必要
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--tee")
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("--tee requires -o"));
+}
+
+/// CLI統合テスト: --squeeze-blank-linesと--trim-trailing-whitespaceによる出力の空白の整理
+#[test]
+fn test_output_whitespace_cleanup() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "# {{ title }}\n\n{{#if draft}}\nDRAFT\n{{/if}}\n\n{{ note }}\nEnd",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("title=Intro")
+        .arg("--set")
+        .arg("note=")
+        .arg("--squeeze-blank-lines")
+        .arg("--trim-trailing-whitespace")
+        .arg("--no-trailing-newline")
+        .assert()
+        .success()
+        .stdout("# Intro\n\nEnd");
+}
+
+/// CLI統合テスト: 複数の-tによるテンプレートの連結
+#[test]
+fn test_multiple_templates() {
+    let dir = tempdir().unwrap();
+    let partials = dir.path().join("partials");
+    fs::create_dir(&partials).unwrap();
+    fs::write(partials.join("rules.txt"), "Be {{ tone }}.").unwrap();
+
+    let system = dir.path().join("system.txt");
+    fs::write(&system, "You are {{ role }}.\n").unwrap();
+    let instructions = dir.path().join("instructions.txt");
+    fs::write(
+        &instructions,
+        "---\ntone: brief\n---\n{{> partials/rules.txt }}\n",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&system)
+        .arg("-t")
+        .arg(&instructions)
+        .arg("-t")
+        .arg("-")
+        .arg("--set")
+        .arg("role=an assistant")
+        .arg("--no-trailing-newline")
+        .write_stdin("Answer: {{ role }}")
+        .assert()
+        .success()
+        .stdout("You are an assistant.\nBe brief.\nAnswer: an assistant");
+
+    // 依存ファイルと変数はすべてのテンプレートについて出力する
+    cargo_bin_cmd!("rp")
+        .arg("vars")
+        .arg("-t")
+        .arg(&system)
+        .arg("-t")
+        .arg(&instructions)
+        .assert()
+        .success()
+        .stdout("role\ntone\n");
+    cargo_bin_cmd!("rp")
+        .arg("deps")
+        .arg("-t")
+        .arg(&system)
+        .arg("-t")
+        .arg(&instructions)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("instructions.txt\n  "))
+        .stdout(predicate::str::contains("rules.txt"));
+}
+
+/// CLI統合テスト: --statsによるレンダリング結果の統計の出力
+#[test]
+fn test_stats() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("header.txt"), "# {{ title }}\n").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{> header.txt }}Hello, {{ name }}! {{ missing }}\nBye.",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("title=Intro")
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--stats")
+        .assert()
+        .success()
+        .stdout("# Intro\nHello, Alice! \nBye.\n")
+        .stderr(
+            "Stats: 27 bytes, 3 lines, 5 words, 1 includes, 2 variables substituted, \
+             1 undefined\n",
+        );
+
+    let assert = cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--stats=json")
+        .assert()
+        .success();
+    let stats: serde_json::Value = serde_json::from_slice(&assert.get_output().stderr).unwrap();
+    assert_eq!(stats["includes"], 1);
+    assert_eq!(stats["substituted"], 0);
+    assert_eq!(stats["undefined"], 3);
+}
+
+/// CLI統合テスト: 複数ファイルの出力時の進捗表示
+#[test]
+fn test_batch_progress() {
+    let dir = tempdir().unwrap();
+
+    let prompts = dir.path().join("prompts");
+    fs::create_dir(&prompts).unwrap();
+    fs::write(prompts.join("a.txt"), "A").unwrap();
+    fs::write(prompts.join("b.txt"), "B").unwrap();
+    let pattern = prompts.join("*.txt");
+    let out_dir = dir.path().join("dist");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&pattern)
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .assert()
+        .success()
+        .stderr(format!(
+            "[1/2] {}\n[2/2] {}\n",
+            prompts.join("a.txt").display(),
+            prompts.join("b.txt").display()
+        ));
+
+    // --quietでは表示しない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&pattern)
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .arg("--quiet")
+        .assert()
+        .success()
+        .stderr("");
+}
+
+/// CLI統合テスト: サンドボックスの設定（--allow-root、--max-includes、--max-file-size、--no-network）
+#[test]
+fn test_sandbox_flags() {
+    let dir = tempdir().unwrap();
+
+    let prompts = dir.path().join("prompts");
+    let shared = dir.path().join("shared");
+    fs::create_dir(&prompts).unwrap();
+    fs::create_dir(&shared).unwrap();
+    fs::write(shared.join("footer.txt"), "-- footer").unwrap();
+    let template = prompts.join("template.txt");
+    fs::write(&template, "body\n{{> ../shared/footer.txt }}").unwrap();
+
+    // ルートの外は許可したディレクトリだけ読み込める
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .code(5);
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--allow-root")
+        .arg(&shared)
+        .assert()
+        .success()
+        .stdout("body\n-- footer\n");
+
+    // インクルード数の上限を超えると終了コード7
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--allow-root")
+        .arg(&shared)
+        .arg("--max-includes")
+        .arg("0")
+        .assert()
+        .code(7)
+        .stderr(predicate::str::contains("--max-includes"));
+
+    // サイズの上限はテンプレートにも適用される
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--max-file-size")
+        .arg("8")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("--max-file-size"));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--data-exec")
+        .arg("echo '{}'")
+        .arg("--no-network")
+        .assert()
+        .code(2);
+}
+
+/// CLI統合テスト: インクルード先のエラーはそのファイルの位置で報告する
+#[test]
+fn test_error_location_in_include() {
+    let dir = tempdir().unwrap();
+
+    fs::write(
+        dir.path().join("header.txt"),
+        "# Title\n\n  {{ user.name }}",
+    )
+    .unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "---\nlang: ja\n---\n{{> header.txt }}\nbody {{ missing }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(format!(
+            "{}:3:3",
+            dir.path().join("header.txt").display()
+        )));
+
+    fs::write(dir.path().join("header.txt"), "# Title").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(format!(
+            "{}:5:6",
+            template.display()
+        )));
+
+    // 構文エラーも同様
+    fs::write(dir.path().join("header.txt"), "# Title\n{{#if lang}}").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(format!(
+            "{}:2:1",
+            dir.path().join("header.txt").display()
+        )));
+}
+
+/// CLI統合テスト: エラーの行の表示とキャレット
+#[test]
+fn test_error_snippet() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "# Title\n\nHello {{ user.email }}!\n").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(format!(
+            " --> {}:3:7\n  |\n3 | Hello {{{{ user.email }}}}!\n  |          ^^^^^^^^^^\n",
+            template.display()
+        )));
+
+    // JSON形式では表示しない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains("^^^").not());
+}
+
+/// CLI統合テスト: strictモードで未定義変数をすべて報告する
+#[test]
+fn test_strict_reports_every_undefined_variable() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("footer.txt"), "-- {{ signature }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "Hello {{ name }}, {{ plan }}\n{{ name }}\n{{> footer.txt }}",
+    )
+    .unwrap();
+    let footer = dir.path().join("footer.txt");
+
+    let output = cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .code(6)
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(output).unwrap();
+    for (name, file, line, col) in [
+        ("name", &template, 1, 7),
+        ("plan", &template, 1, 19),
+        ("name", &template, 2, 1),
+        ("signature", &footer, 1, 4),
+    ] {
+        assert!(
+            stderr.contains(&format!(
+                "ERROR code=UNDEFINED_VAR var=\"{}\" template=\"{}\" line={} col={}",
+                name,
+                file.display(),
+                line,
+                col
+            )),
+            "{}",
+            stderr
+        );
+    }
+    assert!(stderr.contains("4 undefined variables"), "{}", stderr);
+
+    // JSON形式では1行に1つずつ
+    let output = cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(6)
+        .get_output()
+        .stderr
+        .clone();
+    let lines: Vec<serde_json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines.iter().all(|line| line["code"] == "UNDEFINED_VAR"));
+    assert_eq!(lines[3]["file"], footer.display().to_string());
+}
+
+/// CLI統合テスト: --colorによるエラーと警告の色付け
+#[test]
+fn test_color() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Hello {{ name }}!").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--color")
+        .arg("always")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(
+            "\x1b[1;31mERROR\x1b[0m code=UNDEFINED_VAR",
+        ))
+        .stderr(predicate::str::contains("\x1b[1;31m^^^^\x1b[0m"));
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--warn-undefined")
+        .arg("--color=always")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("\x1b[1;33mWarning\x1b[0m: "));
+
+    // 端末でない場合やNO_COLORの指定時は色を付けない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains("\x1b[").not());
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--strict")
+        .arg("--color")
+        .arg("never")
+        .env("NO_COLOR", "1")
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains("\x1b[").not());
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--color")
+        .arg("sometimes")
+        .assert()
+        .code(2);
+}
+
+/// CLI統合テスト: 壊れた区切り文字の検出（checkとstrictモード）
+#[test]
+fn test_malformed_delimiters() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("part.txt"), "ok\n{{ name }} }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> part.txt }}\nHi {{ name").unwrap();
+
+    // 通常のレンダリングではテキストとして出力する
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .assert()
+        .success()
+        .stdout("ok\nAlice }}\nHi {{ name\n");
+
+    // strictモードでは最初の問題を元のファイルの位置とともに報告する（終了コード8）
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--strict")
+        .assert()
+        .code(8)
+        .stdout("")
+        .stderr(predicate::str::contains(format!(
+            "ERROR code=MALFORMED_DELIMITER template=\"{}\" line=2 col=12",
+            dir.path().join("part.txt").display()
+        )));
+
+    // checkではすべてのファイルの問題を報告する
+    cargo_bin_cmd!("rp")
+        .arg("check")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .code(8)
+        .stderr(predicate::str::contains("template.txt:2:4: unclosed '{{'"))
+        .stderr(predicate::str::contains(
+            "part.txt:2:12: stray '}}' without an opening '{{'",
+        ))
+        .stderr(predicate::str::contains("2 problem(s) found"));
+
+    // ファイルパスのないインクルードはどのモードでもエラー
+    fs::write(&template, "{{> }}").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .code(8)
+        .stderr(predicate::str::contains("include tag without a file path"));
+}
+
+/// CLI統合テスト: --traceによるインクルードツリーと置換の表示
+#[test]
+fn test_trace() {
+    let dir = tempdir().unwrap();
+
+    fs::create_dir(dir.path().join("parts")).unwrap();
+    fs::write(dir.path().join("header.txt"), "[{{> parts/title.txt }}]").unwrap();
+    fs::write(dir.path().join("parts/title.txt"), "{{ title | upper }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "{{> header.txt }}\nHello {{ name }}{{ missing }}\n{{ long }}",
+    )
+    .unwrap();
+    let long = "x".repeat(200);
+
+    let output = cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("title=Intro")
+        .arg("--set")
+        .arg("name=Alice")
+        .arg("--set")
+        .arg(format!("long={}", long))
+        .arg("--trace")
+        .assert()
+        .success()
+        .stdout(format!("[INTRO]\nHello Alice\n{}\n", long))
+        .get_output()
+        .stderr
+        .clone();
+    let stderr = String::from_utf8(output).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 7, "{}", stderr);
+
+    // インクルードツリー（深さに応じて字下げ）
+    assert_eq!(lines[0], format!("trace: {}", template.display()));
+    assert_eq!(
+        lines[1],
+        format!("trace:   {}", dir.path().join("header.txt").display())
+    );
+    assert_eq!(
+        lines[2],
+        format!(
+            "trace:     {}",
+            dir.path().join("parts/title.txt").display()
+        )
+    );
+
+    // 置換ごとの式と値（長い値は切り詰める）
+    assert_eq!(
+        lines[3],
+        format!(
+            "trace: {{{{ title | upper }}}} → \"INTRO\" at {}:1:1",
+            dir.path().join("parts/title.txt").display()
+        )
+    );
+    assert_eq!(
+        lines[4],
+        format!(
+            "trace: {{{{ name }}}} → \"Alice\" at {}:2:7",
+            template.display()
+        )
+    );
+    assert_eq!(
+        lines[5],
+        format!(
+            "trace: {{{{ missing }}}} → undefined at {}:2:17",
+            template.display()
+        )
+    );
+    assert!(
+        lines[6].contains(&format!("\"{}… at", &long[..79])),
+        "{}",
+        lines[6]
+    );
+
+    // --traceなしでは-vvvでも表示しない
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--set")
+        .arg("title=Intro")
+        .arg("-vvv")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("trace: {{").not());
+}
+
+/// CLI統合テスト: インクルードのエラーにインクルードの連鎖を表示
+#[test]
+fn test_include_error_chain() {
+    let dir = tempdir().unwrap();
+
+    fs::create_dir(dir.path().join("parts")).unwrap();
+    fs::write(dir.path().join("parts/a.txt"), "A\n{{> b.txt }}").unwrap();
+    fs::write(dir.path().join("parts/b.txt"), "{{> missing.txt }}").unwrap();
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "Title\n\n{{> parts/a.txt }}").unwrap();
+
+    // 内側から順に、エラーに至るインクルードの位置を表示する
+    let parts = dir.path().join("parts");
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(format!(
+            "included from {}:1, included from {}:2, included from {}:3",
+            parts.join("b.txt").display(),
+            parts.join("a.txt").display(),
+            template.display()
+        )));
+
+    // checkでも同じ連鎖を報告する
+    cargo_bin_cmd!("rp")
+        .arg("check")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains(format!(
+            "included from {}:3",
+            template.display()
+        )));
+}
+
+/// CLI統合テスト: --watchによる変更時の再レンダリング
+#[test]
+fn test_watch_rerenders_on_change() {
+    use std::time::{Duration, Instant};
+
+    let dir = tempdir().unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "name: Alice").unwrap();
+    fs::write(dir.path().join("greeting.txt"), "Hello").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "{{> greeting.txt }}, {{ name }}!").unwrap();
+
+    let output = dir.path().join("output.txt");
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_rp"))
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("-o")
+        .arg(&output)
+        .arg("--watch")
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let wait_for = |expected: &str| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if fs::read_to_string(&output).ok().as_deref() == Some(expected) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    };
+
+    let initial = wait_for("Hello, Alice!");
+    fs::write(&data, "name: Bob").unwrap();
+    let data_changed = wait_for("Hello, Bob!");
+    fs::write(dir.path().join("greeting.txt"), "Hi").unwrap();
+    let include_changed = wait_for("Hi, Bob!");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(initial && data_changed && include_changed);
+}
+
+/// CLI統合テスト: --watchは標準入力のテンプレートと併用できない
+#[test]
+fn test_watch_requires_template_file() {
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg("-")
+        .arg("--watch")
+        .assert()
+        .failure()
+        .code(2);
+}
+
+/// CLI統合テスト: --checkによるテンプレートの検証
+#[test]
+fn test_check_reports_all_problems() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("part.txt"), "ok\n{{#bogus}}").unwrap();
+    fs::write(dir.path().join("verbatim.txt"), "{{ not a tag").unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "Hi {{ name\n{{> missing.txt }}\n{{> part.txt }}\n{{>raw verbatim.txt }}",
+    )
+    .unwrap();
+
+    // 最初の問題で止まらず、すべての問題を報告する（出力はしない）
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--check")
+        .assert()
+        .failure()
+        .code(5)
+        .stdout("")
+        .stderr(predicate::str::contains("missing.txt"))
+        .stderr(predicate::str::contains("template.txt:1:4: unclosed '{{'"))
+        .stderr(predicate::str::contains("part.txt:2:1: unknown block"))
+        .stderr(predicate::str::contains("3 problem(s) found"));
+
+    // 同じファイルの複数の構文エラーと未知のフィルターもすべて報告する
+    fs::write(
+        &template,
+        "{{ a + }}\n{{#nope}}\n{{ b | nofilter }}\n{{ c | upper }}",
+    )
+    .unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--check")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "template.txt:1:1: invalid expression",
+        ))
+        .stderr(predicate::str::contains("template.txt:2:1: unknown block"))
+        .stderr(predicate::str::contains(
+            "template.txt:3:1: unknown filter 'nofilter'",
+        ))
+        .stderr(predicate::str::contains("3 problem(s) found"));
+
+    fs::write(&template, "Hi {{ name }}\n{{> part.txt }}").unwrap();
+    fs::write(dir.path().join("part.txt"), "{{#if x}}y{{/if}}").unwrap();
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--check")
+        .assert()
+        .success()
+        .stdout("");
+}
+
+/// CLI統合テスト: カレントディレクトリのテンプレートからのインクルード
+#[test]
+fn test_include_from_template_in_current_dir() {
+    let dir = tempdir().unwrap();
+
+    fs::write(dir.path().join("header.txt"), "Header").unwrap();
+    fs::write(
+        dir.path().join("template.txt"),
+        "{{> header.txt }}: {{ x }}",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .current_dir(dir.path())
+        .arg("-t")
+        .arg("template.txt")
+        .arg("--set")
+        .arg("x=1")
+        .assert()
+        .success()
+        .stdout("Header: 1\n");
+}
+
+/// CLI統合テスト: extendsによるレイアウト継承
+#[test]
+fn test_extends_layout() {
+    let dir = tempdir().unwrap();
+
+    fs::write(
+        dir.path().join("base.txt"),
+        "# {{#block title}}Untitled{{/block}}\n{{#block body}}(empty){{/block}}\n-- footer",
+    )
+    .unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "task: summarize").unwrap();
+
+    let template = dir.path().join("child.txt");
+    fs::write(
+        &template,
+        "{{#extends \"base.txt\"}}\n{{#block body}}Please {{ task }}.{{/block}}\n",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .assert()
+        .success()
+        .stdout("# Untitled\nPlease summarize.\n-- footer\n");
+}
+
+/// CLI統合テスト: 閉じられていないブロックは構文エラー
+#[test]
+fn test_unclosed_block_error() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "line1\n{{#if flag}}never closed").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .assert()
+        .failure()
+        .code(3) // EXIT_TEMPLATE_ERROR
+        .stderr(predicate::str::contains("code=SYNTAX_ERROR"));
+}
@@ -1,4 +1,4 @@
-use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin_cmd;
 use std::fs;
 use tempfile::tempdir;
 
@@ -17,8 +17,7 @@ fn test_path_traversal_parent_directory() {
     let template = subdir.join("template.txt");
     fs::write(&template, "{{> ../secret.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--root")
@@ -28,6 +27,34 @@ fn test_path_traversal_parent_directory() {
         .code(5); // EXIT_INCLUDE_ERROR
 }
 
+/// セキュリティ: パストラバーサル防止 - データから計算したインクルードパス
+#[test]
+fn test_path_traversal_dynamic_include() {
+    let dir = tempdir().unwrap();
+    let subdir = dir.path().join("templates");
+    fs::create_dir(&subdir).unwrap();
+
+    let outside = dir.path().join("secret.txt");
+    fs::write(&outside, "SECRET DATA").unwrap();
+
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "partial: ../secret.txt").unwrap();
+
+    let template = subdir.join("template.txt");
+    fs::write(&template, "{{> (partial) }}").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--root")
+        .arg(&subdir)
+        .assert()
+        .failure()
+        .code(5);
+}
+
 /// セキュリティ: パストラバーサル防止 - 絶対パス
 #[test]
 fn test_path_traversal_absolute_path() {
@@ -36,8 +63,7 @@ fn test_path_traversal_absolute_path() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> /etc/passwd }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -59,8 +85,7 @@ fn test_path_traversal_multiple_parents() {
     let template = level3.join("template.txt");
     fs::write(&template, "{{> ../../../secret.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--root")
@@ -78,8 +103,7 @@ fn test_path_traversal_url_like() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> file://etc/passwd }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -106,8 +130,7 @@ fn test_path_traversal_symlink() {
     let template = templates.join("template.txt");
     fs::write(&template, "{{> link.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--root")
@@ -125,8 +148,7 @@ fn test_dos_circular_include_immediate() {
     let file = dir.path().join("file.txt");
     fs::write(&file, "{{> file.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&file)
         .assert()
@@ -149,8 +171,7 @@ fn test_dos_depth_limit() {
         }
     }
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(dir.path().join("0.txt"))
         .arg("--max-include-depth")
@@ -170,8 +191,7 @@ fn test_large_file_handling() {
     let template = dir.path().join("template.txt");
     fs::write(&template, large_content.clone()).unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -190,8 +210,7 @@ fn test_special_characters_in_filename() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> file_with_special_!@#.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -207,8 +226,7 @@ fn test_null_character_handling() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "Before\0After").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -223,8 +241,7 @@ fn test_command_injection_filename() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> file; rm -rf / }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -242,8 +259,7 @@ fn test_script_injection_in_data() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ script }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -264,8 +280,7 @@ fn test_sql_injection_like_string() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ query }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -283,8 +298,7 @@ fn test_no_environment_variable_access() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ $HOME }}{{ env.HOME }}{{ ENV.HOME }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -292,6 +306,97 @@ fn test_no_environment_variable_access() {
         .stdout("\n"); // 全て空文字
 }
 
+/// セキュリティ: --allow-env 指定時のみ env 名前空間で環境変数を参照できる
+#[test]
+fn test_allow_env_opt_in() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "[{{ env.RP_TEST_MODEL }}]").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .env("RP_TEST_MODEL", "gpt-4o")
+        .assert()
+        .success()
+        .stdout("[]\n");
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--allow-env")
+        .env("RP_TEST_MODEL", "gpt-4o")
+        .assert()
+        .success()
+        .stdout("[gpt-4o]\n");
+}
+
+/// セキュリティ: --env で指定した環境変数だけが公開される
+#[test]
+fn test_env_selected_variables_only() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(
+        &template,
+        "[{{ env.RP_CI_BRANCH }}][{{ env.RP_CI_JOB }}][{{ env.RP_MODEL }}][{{ env.RP_SECRET }}]",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("--env")
+        .arg("RP_CI_*")
+        .arg("--env")
+        .arg("RP_MODEL")
+        .env("RP_CI_BRANCH", "main")
+        .env("RP_CI_JOB", "42")
+        .env("RP_MODEL", "gpt-4o")
+        .env("RP_SECRET", "hunter2")
+        .assert()
+        .success()
+        .stdout("[main][42][gpt-4o][]\n");
+}
+
+/// セキュリティ: --env-vars 指定時のみ RP_VAR_ で始まる環境変数がデータになる
+#[test]
+fn test_env_vars_opt_in() {
+    let dir = tempdir().unwrap();
+
+    let template = dir.path().join("template.txt");
+    fs::write(&template, "[{{ user_name }}][{{ model }}][{{ RP_SECRET }}]").unwrap();
+    let data = dir.path().join("data.yaml");
+    fs::write(&data, "model: gpt-4o\nuser_name: default").unwrap();
+
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .env("RP_VAR_USER_NAME", "Alice")
+        .assert()
+        .success()
+        .stdout("[default][gpt-4o][]\n");
+
+    // データファイルより優先され、--set はさらに優先される
+    cargo_bin_cmd!("rp")
+        .arg("-t")
+        .arg(&template)
+        .arg("-d")
+        .arg(&data)
+        .arg("--env-vars")
+        .arg("--set")
+        .arg("model=claude")
+        .env("RP_VAR_USER_NAME", "Alice")
+        .env("RP_VAR_MODEL", "gpt-4o-mini")
+        .env("RP_SECRET", "hunter2")
+        .assert()
+        .success()
+        .stdout("[Alice][claude][]\n");
+}
+
 /// セキュリティ: ファイルパスの正規化
 #[test]
 fn test_path_normalization() {
@@ -303,8 +408,7 @@ fn test_path_normalization() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> ./file.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -325,8 +429,7 @@ fn test_complex_path_manipulation() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> ./sub/../sub/./file.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -347,8 +450,7 @@ fn test_complex_directory_traversal() {
     let template = allowed.join("template.txt");
     fs::write(&template, "{{> ./../secret.txt }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("--root")
@@ -366,8 +468,7 @@ fn test_zip_slip_like_attack() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{> ../../../../../../../../etc/passwd }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .assert()
@@ -386,8 +487,7 @@ fn test_control_characters() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ text }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -408,8 +508,7 @@ fn test_backslash_handling() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ path }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")
@@ -431,8 +530,7 @@ fn test_no_recursive_object_reference() {
     let template = dir.path().join("template.txt");
     fs::write(&template, "{{ obj.self }}").unwrap();
 
-    Command::cargo_bin("rp")
-        .unwrap()
+    cargo_bin_cmd!("rp")
         .arg("-t")
         .arg(&template)
         .arg("-d")